@@ -378,6 +378,14 @@ pub struct Endpoints {
     pub dst_pid: Option<i32>,
     pub dst_tid: Option<i32>,
     pub dst_cmdline: Option<String>,
+    // the ioctl carries the uid of the local thread, the receive side of a transaction the
+    // sender's euid. the target of a send is left to the caller (it isn't on the wire).
+    pub src_uid: Option<u32>,
+    pub dst_uid: Option<u32>,
+    // SELinux domains. on the wire only when the target asked the kernel for the sender's
+    // (BR_TRANSACTION_SEC_CTX) and in the ProcessInfo packets, the caller looks up the rest.
+    pub src_selinux: Option<String>,
+    pub dst_selinux: Option<String>,
 }
 
 pub fn cmdline_to_string(buf: &[u8]) -> String {
@@ -400,6 +408,18 @@ pub fn resolve_endpoints(
         dst_pid: None,
         dst_tid: None,
         dst_cmdline: None,
+        src_uid: event
+            .ioctl_data
+            .as_ref()
+            .map(|ioctl| ioctl.uid)
+            .or_else(|| event.process_info.as_ref().map(|info| info.uid)),
+        dst_uid: None,
+        src_selinux: event
+            .process_info
+            .as_ref()
+            .map(|info| cmdline_to_string(&info.selinux))
+            .filter(|selinux| !selinux.is_empty()),
+        dst_selinux: None,
     };
 
     let Some(ioctl) = event.ioctl_data.as_ref() else {
@@ -436,6 +456,10 @@ pub fn resolve_endpoints(
         ep.dst_cmdline = (!dst_cmd.is_empty()).then_some(dst_cmd);
     } else {
         ep.src_pid = txn.sender_pid;
+        ep.src_uid = Some(txn.sender_euid);
+        ep.dst_uid = Some(ioctl.uid);
+        let secctx = cmdline_to_string(&txn.secctx);
+        ep.src_selinux = (!secctx.is_empty()).then_some(secctx);
         match caller_lookup(txn.debug_id) {
             Some((tid, cmd)) => {
                 ep.src_tid = Some(tid);
@@ -742,4 +766,66 @@ mod tests {
         assert_eq!(send_ep.src_cmdline, recv_ep.src_cmdline);
         assert_eq!(send_ep.dst_cmdline, recv_ep.dst_cmdline);
     }
+
+    #[test]
+    fn recv_frame_takes_the_sender_euid() {
+        let mut e = event_with_txn(
+            200,
+            2002,
+            "system_server",
+            BinderWriteReadType::Read,
+            Some(TransactionProtocol {
+                sender_euid: 10234,
+                ..txn(0, 42, 100, 100, 100, "")
+            }),
+        );
+        e.ioctl_data.as_mut().unwrap().uid = 1000;
+        let ep = resolve_endpoints(&e, |_| None);
+        assert_eq!(ep.src_uid, Some(10234));
+        assert_eq!(ep.dst_uid, Some(1000));
+
+        // send side: the local uid is the source, the target's isn't on the wire
+        let mut e = event_with_txn(
+            100,
+            1001,
+            "app",
+            BinderWriteReadType::Write,
+            Some(txn(0, 42, 0, 200, 2002, "system_server")),
+        );
+        e.ioctl_data.as_mut().unwrap().uid = 10234;
+        let ep = resolve_endpoints(&e, |_| None);
+        assert_eq!(ep.src_uid, Some(10234));
+        assert_eq!(ep.dst_uid, None);
+    }
+
+    #[test]
+    fn receive_side_takes_the_sender_domain_from_the_secctx() {
+        let e = event_with_txn(
+            200,
+            2002,
+            "system_server",
+            BinderWriteReadType::Read,
+            Some(TransactionProtocol {
+                secctx: b"u:r:untrusted_app:s0:c234,c256,c512,c768\0".to_vec(),
+                ..txn(0, 42, 100, 100, 100, "")
+            }),
+        );
+        let ep = resolve_endpoints(&e, |_| None);
+        assert_eq!(
+            ep.src_selinux.as_deref(),
+            Some("u:r:untrusted_app:s0:c234,c256,c512,c768")
+        );
+        // the local domain is in the thread's ProcessInfo, not in this packet
+        assert_eq!(ep.dst_selinux, None);
+
+        // a plain BR_TRANSACTION leaves both to the process table
+        let e = event_with_txn(
+            200,
+            2002,
+            "system_server",
+            BinderWriteReadType::Read,
+            Some(txn(0, 42, 100, 100, 100, "")),
+        );
+        assert_eq!(resolve_endpoints(&e, |_| None).src_selinux, None);
+    }
 }
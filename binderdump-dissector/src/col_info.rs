@@ -1,3 +1,8 @@
+use binderdump_structs::binder_types::binder_return::binder_return;
+use binderdump_structs::ioctl_layer::IoctlArgProtocol;
+use binderdump_trait::EpanProtocolEnum;
+use num_traits::FromPrimitive;
+
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum Direction {
     Bc,
@@ -9,9 +14,34 @@ pub enum Direction {
 pub enum ColEvent<'a> {
     DeadProcess,
     DeadThread,
-    // a non-BWR ioctl. we only capture the command and its return value, not
-    // the payload behind the arg pointer, so that's all we can show.
-    Ioctl { name: &'a str, result: i32 },
+    // the metadata the other packets of the thread take their cmdline from. package is empty
+    // for non-app processes.
+    ProcessInfo {
+        uid: u32,
+        package: &'a str,
+    },
+    // the packages (comma separated) of a uid, user is its Android name (u0_a234)
+    UidInfo {
+        user: &'a str,
+        packages: &'a str,
+    },
+    // the packet's pid is the child's
+    NewProcess {
+        parent_pid: i32,
+    },
+    Exec {
+        filename: &'a str,
+    },
+    // the executable mappings the stacks of the process are symbolized with
+    ProcessMaps {
+        mappings: usize,
+    },
+    // a non-BWR ioctl. arg is the struct behind the arg pointer, for the ioctls that take one.
+    Ioctl {
+        name: &'a str,
+        result: i32,
+        arg: Option<&'a IoctlArgProtocol>,
+    },
     Bwr(BwrInputs<'a>),
 }
 
@@ -33,14 +63,93 @@ pub fn format(event: &ColEvent) -> String {
     match event {
         ColEvent::DeadProcess => "process died".to_string(),
         ColEvent::DeadThread => "thread died".to_string(),
-        ColEvent::Ioctl { name, result } if *result < 0 => {
-            format!("{} (failed: {})", name, result)
+        ColEvent::ProcessInfo { uid, package } if package.is_empty() => {
+            format!("process info, uid {}", uid)
+        }
+        ColEvent::ProcessInfo { uid, package } => {
+            format!("process info, uid {} ({})", uid, package)
+        }
+        ColEvent::UidInfo { user, packages } => format!("uid info, {}: {}", user, packages),
+        ColEvent::NewProcess { parent_pid } => format!("process created by {}", parent_pid),
+        ColEvent::Exec { filename } => format!("exec {}", filename),
+        ColEvent::ProcessMaps { mappings } => format!("process maps, {} mappings", mappings),
+        ColEvent::Ioctl { name, result, arg } => {
+            let mut info = match arg.and_then(format_ioctl_arg) {
+                Some(arg) => format!("{}: {}", name, arg),
+                None => name.to_string(),
+            };
+            if *result < 0 {
+                info.push_str(&format!(" (failed: {})", result));
+            }
+            info
         }
-        ColEvent::Ioctl { name, .. } => name.to_string(),
         ColEvent::Bwr(bwr) => format_bwr(bwr),
     }
 }
 
+fn format_ioctl_arg(arg: &IoctlArgProtocol) -> Option<String> {
+    if let Some(version) = arg.protocol_version {
+        return Some(format!("protocol {}", version));
+    }
+    if let Some(max_threads) = arg.max_threads {
+        return Some(format!("{} threads", max_threads));
+    }
+    if let Some(node) = &arg.context_mgr {
+        return Some(format!("binder {:#x}", node.binder));
+    }
+    if let Some(info) = &arg.node_debug_info {
+        return Some(format!("node {:#x}", info.ptr));
+    }
+    if let Some(info) = &arg.node_info_for_ref {
+        return Some(format!(
+            "handle {:#x}, {} strong, {} weak",
+            info.handle, info.strong_count, info.weak_count
+        ));
+    }
+    if let Some(freeze) = &arg.freeze {
+        return Some(match freeze.enable {
+            0 => format!("unfreeze {}", freeze.pid),
+            _ => format!("freeze {}, timeout {}ms", freeze.pid, freeze.timeout_ms),
+        });
+    }
+    if let Some(info) = &arg.frozen_info {
+        return Some(format!(
+            "{}, sync {:#x}, async {}",
+            info.pid, info.sync_recv, info.async_recv
+        ));
+    }
+    if let Some(enable) = arg.oneway_spam_detection {
+        return Some(if enable { "on" } else { "off" }.to_string());
+    }
+    if let Some(error) = &arg.extended_error {
+        let command = binder_return::from_u32(error.command);
+        return Some(match command {
+            Some(binder_return::BR_OK) => "no error".to_string(),
+            Some(command) => format!("{} of txn {} ({})", command.to_str(), error.id, error.param),
+            None => format!("{:#x} of txn {} ({})", error.command, error.id, error.param),
+        });
+    }
+    None
+}
+
+// the capture stopped while this ioctl was still in flight (e.g. blocked waiting for a reply)
+pub fn truncated_by_shutdown(info: &str) -> String {
+    with_marker(info, "[truncated by shutdown]")
+}
+
+// the capture started while this ioctl was already blocked reading, only its read half was seen
+pub fn partial(info: &str) -> String {
+    with_marker(info, "[partial]")
+}
+
+fn with_marker(info: &str, marker: &str) -> String {
+    if info.is_empty() {
+        marker.to_string()
+    } else {
+        format!("{} {}", info, marker)
+    }
+}
+
 fn format_bwr(inputs: &BwrInputs) -> String {
     if !inputs.has_transaction {
         return inputs.raw_commands.join(", ");
@@ -109,11 +218,73 @@ mod tests {
         assert_eq!(format(&ColEvent::DeadThread), "thread died");
     }
 
+    #[test]
+    fn process_info_shows_the_package() {
+        let info = ColEvent::ProcessInfo {
+            uid: 10234,
+            package: "com.example.app",
+        };
+        assert_eq!(format(&info), "process info, uid 10234 (com.example.app)");
+        let info = ColEvent::ProcessInfo {
+            uid: 1000,
+            package: "",
+        };
+        assert_eq!(format(&info), "process info, uid 1000");
+    }
+
+    #[test]
+    fn uid_info_shows_the_packages() {
+        let info = ColEvent::UidInfo {
+            user: "u0_a234",
+            packages: "com.example.app",
+        };
+        assert_eq!(format(&info), "uid info, u0_a234: com.example.app");
+    }
+
+    #[test]
+    fn lifecycle_events() {
+        assert_eq!(
+            format(&ColEvent::NewProcess { parent_pid: 612 }),
+            "process created by 612"
+        );
+        assert_eq!(
+            format(&ColEvent::Exec {
+                filename: "/system/bin/service"
+            }),
+            "exec /system/bin/service"
+        );
+        assert_eq!(
+            format(&ColEvent::ProcessMaps { mappings: 42 }),
+            "process maps, 42 mappings"
+        );
+    }
+
+    #[test]
+    fn truncated_marker() {
+        let raw = ["BC_TRANSACTION"];
+        let info = format(&ColEvent::Bwr(base(&raw)));
+        assert_eq!(
+            truncated_by_shutdown(&info),
+            "BC_TRANSACTION [truncated by shutdown]"
+        );
+        assert_eq!(truncated_by_shutdown(""), "[truncated by shutdown]");
+    }
+
+    #[test]
+    fn partial_marker() {
+        assert_eq!(partial("\u{2190} reply"), "\u{2190} reply [partial]");
+        assert_eq!(
+            truncated_by_shutdown(&partial("BR_NOOP")),
+            "BR_NOOP [partial] [truncated by shutdown]"
+        );
+    }
+
     #[test]
     fn ioctl_success_shows_bare_name() {
         let event = ColEvent::Ioctl {
             name: "BINDER_VERSION",
             result: 0,
+            arg: None,
         };
         assert_eq!(format(&event), "BINDER_VERSION");
     }
@@ -123,10 +294,44 @@ mod tests {
         let event = ColEvent::Ioctl {
             name: "BINDER_SET_MAX_THREADS",
             result: -22,
+            arg: None,
         };
         assert_eq!(format(&event), "BINDER_SET_MAX_THREADS (failed: -22)");
     }
 
+    #[test]
+    fn ioctl_shows_its_argument() {
+        use binderdump_structs::ioctl_layer::ExtendedErrorProtocol;
+        let arg = IoctlArgProtocol {
+            max_threads: Some(15),
+            ..Default::default()
+        };
+        let event = ColEvent::Ioctl {
+            name: "BINDER_SET_MAX_THREADS",
+            result: 0,
+            arg: Some(&arg),
+        };
+        assert_eq!(format(&event), "BINDER_SET_MAX_THREADS: 15 threads");
+
+        let arg = IoctlArgProtocol {
+            extended_error: Some(ExtendedErrorProtocol {
+                id: 4321,
+                command: binder_return::BR_FAILED_REPLY as u32,
+                param: -28,
+            }),
+            ..Default::default()
+        };
+        let event = ColEvent::Ioctl {
+            name: "BINDER_GET_EXTENDED_ERROR",
+            result: 0,
+            arg: Some(&arg),
+        };
+        assert_eq!(
+            format(&event),
+            "BINDER_GET_EXTENDED_ERROR: BR_FAILED_REPLY of txn 4321 (-28)"
+        );
+    }
+
     fn txn<'a>(
         is_reply: bool,
         iface: Option<&'a str>,
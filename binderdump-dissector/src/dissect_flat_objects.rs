@@ -2,7 +2,7 @@ use anyhow::{anyhow, Context};
 use binderdump_aidl::binder_object::{self, Kind};
 use binderdump_epan_sys::epan;
 use binderdump_structs::binder_serde::FieldOffset;
-use binderdump_structs::bwr_layer::{PtrPayload, TransactionProtocol};
+use binderdump_structs::bwr_layer::{FdProtocol, PtrPayload, TransactionProtocol};
 use binderdump_structs::event_layer::EventProtocol;
 use std::ffi::{c_int, CString};
 use std::ptr::null_mut;
@@ -226,7 +226,7 @@ pub fn dissect_offsets_array(
     pinfo: *mut epan::packet_info,
     tree: *mut epan::proto_node,
 ) -> anyhow::Result<()> {
-    let _ = pinfo;
+    let frame = unsafe { (*(*pinfo).fd).num };
     let txn = match event
         .ioctl_data
         .as_ref()
@@ -284,6 +284,36 @@ pub fn dissect_offsets_array(
         )
     };
 
+    // the process that owns each binder object, from the kernel's translations of them
+    let received = event
+        .ioctl_data
+        .as_ref()
+        .and_then(|i| i.bwr.as_ref())
+        .is_some_and(|b| b.is_read());
+    let translations = if received {
+        txn.translations.clone()
+    } else {
+        crate::node_table::translations(txn.debug_id)
+    };
+    let node_label = |value: u64| {
+        crate::node_table::label(&translations, received, value, |pid| {
+            crate::process_table::cmdline(frame, pid)
+        })
+    };
+
+    // the files behind the fds, the same way
+    let fds = if received {
+        txn.fds.clone()
+    } else {
+        crate::fd_table::fds(txn.debug_id)
+    };
+    let fd_objects: Vec<usize> = iter_flat_objects(&txn.data, &txn.offsets)
+        .filter(|entry| matches!(entry.parsed, Kind::Fd))
+        .map(|entry| entry.pos)
+        .collect();
+    let mut fd_matcher = crate::fd_table::FdMatcher::new(&fds, &fd_objects);
+    let fd_label = |fd: Option<&FdProtocol>| fd.and_then(|fd| crate::fd_table::label(fd, received));
+
     let mut walked = 0usize;
     for FlatObjectEntry {
         idx,
@@ -333,13 +363,18 @@ pub fn dissect_offsets_array(
 
         match parsed {
             Kind::Binder => {
-                render_flat_binder(tvb, entry_tree, &refs.flat_binder, abs_off)?;
+                let item = render_flat_binder(tvb, entry_tree, &refs.flat_binder, abs_off)?;
+                let binder = read_u64(&txn.data, entry + 8).unwrap_or(0);
+                append_label(item, node_label(binder))?;
             }
             Kind::Handle => {
-                render_flat_handle(tvb, entry_tree, &refs.flat_handle, abs_off)?;
+                let item = render_flat_handle(tvb, entry_tree, &refs.flat_handle, abs_off)?;
+                let handle = read_u32(&txn.data, entry + 8).unwrap_or(0);
+                append_label(item, node_label(handle.into()))?;
             }
             Kind::Fd => {
-                render_flat_fd(tvb, entry_tree, &refs.flat_fd, abs_off)?;
+                let item = render_flat_fd(tvb, entry_tree, &refs.flat_fd, abs_off)?;
+                append_label(item, fd_label(fd_matcher.object(entry)))?;
             }
             Kind::Ptr => {
                 let payload_idx = txn
@@ -357,6 +392,11 @@ pub fn dissect_offsets_array(
                 )?;
             }
             Kind::Fda => {
+                // taken for every fd, rendered or not, so the next FDA gets its own
+                let num_fds = read_u64(&txn.data, entry + 8).unwrap_or(0) as usize;
+                let labels: Vec<_> = (0..num_fds.min(fds.len()))
+                    .map(|_| fd_label(fd_matcher.next_array_fd()))
+                    .collect();
                 render_flat_fda(
                     tvb,
                     entry_tree,
@@ -366,6 +406,7 @@ pub fn dissect_offsets_array(
                     entry,
                     &txn.ptr_payloads,
                     &payload_tvb,
+                    &labels,
                 )?;
             }
             Kind::Unknown => unreachable!("filtered above"),
@@ -383,12 +424,25 @@ pub fn dissect_offsets_array(
     Ok(())
 }
 
+fn append_label(item: *mut epan::proto_item, label: Option<String>) -> anyhow::Result<()> {
+    let Some(label) = label else {
+        return Ok(());
+    };
+    let label = CString::new(format!(" [{}]", label))?;
+    unsafe {
+        epan::proto_item_append_text(item, c"%s".as_ptr(), label.as_ptr());
+    }
+    Ok(())
+}
+
+// Returns the binder item, for the label of its node
 fn render_flat_binder(
     tvb: *mut epan::tvbuff,
     tree: *mut epan::proto_node,
     refs: &VariantRefs,
     abs_off: usize,
-) -> anyhow::Result<()> {
+) -> anyhow::Result<*mut epan::proto_item> {
+    let item;
     unsafe {
         epan::proto_tree_add_item(
             tree,
@@ -398,7 +452,7 @@ fn render_flat_binder(
             4,
             epan::ENC_LITTLE_ENDIAN,
         );
-        epan::proto_tree_add_item(
+        item = epan::proto_tree_add_item(
             tree,
             refs.binder,
             tvb,
@@ -415,15 +469,17 @@ fn render_flat_binder(
             epan::ENC_LITTLE_ENDIAN,
         );
     }
-    Ok(())
+    Ok(item)
 }
 
+// Returns the handle item, for the label of its node
 fn render_flat_handle(
     tvb: *mut epan::tvbuff,
     tree: *mut epan::proto_node,
     refs: &VariantHandleRefs,
     abs_off: usize,
-) -> anyhow::Result<()> {
+) -> anyhow::Result<*mut epan::proto_item> {
+    let item;
     unsafe {
         epan::proto_tree_add_item(
             tree,
@@ -435,7 +491,7 @@ fn render_flat_handle(
         );
         // The handle/binder union shares the first 4 bytes; the upper 4 bytes
         // of the 8-byte slot are padding.
-        epan::proto_tree_add_item(
+        item = epan::proto_tree_add_item(
             tree,
             refs.handle,
             tvb,
@@ -452,15 +508,17 @@ fn render_flat_handle(
             epan::ENC_LITTLE_ENDIAN,
         );
     }
-    Ok(())
+    Ok(item)
 }
 
+// Returns the fd item, for the file behind it
 fn render_flat_fd(
     tvb: *mut epan::tvbuff,
     tree: *mut epan::proto_node,
     refs: &VariantFdRefs,
     abs_off: usize,
-) -> anyhow::Result<()> {
+) -> anyhow::Result<*mut epan::proto_item> {
+    let item;
     unsafe {
         epan::proto_tree_add_item(
             tree,
@@ -471,7 +529,7 @@ fn render_flat_fd(
             epan::ENC_LITTLE_ENDIAN,
         );
         // The fd/pad_binder union: fd lives in the lower 4 bytes of the union.
-        epan::proto_tree_add_item(
+        item = epan::proto_tree_add_item(
             tree,
             refs.fd,
             tvb,
@@ -488,7 +546,7 @@ fn render_flat_fd(
             epan::ENC_LITTLE_ENDIAN,
         );
     }
-    Ok(())
+    Ok(item)
 }
 
 fn render_flat_ptr(
@@ -570,6 +628,7 @@ fn render_flat_fda(
     entry: usize,
     ptr_payloads: &[PtrPayload],
     payload_tvb: &[(usize, usize)],
+    labels: &[Option<String>],
 ) -> anyhow::Result<()> {
     unsafe {
         epan::proto_tree_add_item(
@@ -625,7 +684,7 @@ fn render_flat_fda(
             // would point at bytes that aren't in tvb, so stop here.
             break;
         }
-        unsafe {
+        let item = unsafe {
             epan::proto_tree_add_item(
                 tree,
                 refs.fds,
@@ -633,8 +692,9 @@ fn render_flat_fda(
                 (payload_data_tvb + fd_off_in_payload).try_into()?,
                 4,
                 epan::ENC_LITTLE_ENDIAN,
-            );
-        }
+            )
+        };
+        append_label(item, labels.get(k).cloned().flatten())?;
     }
 
     Ok(())
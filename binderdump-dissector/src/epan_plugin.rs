@@ -89,7 +89,7 @@ impl Protocol {
 
     fn register_prefs(&self) {
         // Wireshark 4.x rejects a second prefs_register_protocol() call for the
-        // same proto_id, so register the module once and add both prefs to it.
+        // same proto_id, so register the module once and add all the prefs to it.
         unsafe {
             let module = epan::prefs_register_protocol(self.handle, None);
             if module.is_null() {
@@ -97,6 +97,7 @@ impl Protocol {
             }
             register_aidl_overlay_pref(module);
             register_aosp_dir_pref(module);
+            register_symbols_dir_pref(module);
         }
     }
 
@@ -191,6 +192,7 @@ impl Protocol {
     fn add_endpoint_fields(
         &self,
         event: &EventProtocol,
+        frame: u32,
         tvb: *mut epan::tvbuff_t,
         tree: *mut epan::proto_tree,
     ) {
@@ -201,9 +203,24 @@ impl Protocol {
         // which the matching send frame populated earlier. safe on every pass:
         // wireshark always completes one full sequential pass before the UI is
         // interactive, so by the time any frame is re-dissected the state is set.
-        let ep = crate::binderdump::resolve_endpoints(event, |debug_id| {
+        let mut ep = crate::binderdump::resolve_endpoints(event, |debug_id| {
             crate::reply_correlation::caller_info(debug_id)
         });
+        // the target of a send (and a Dead packet) has no uid on the wire, take the one of its
+        // ProcessInfo
+        if ep.src_uid.is_none() {
+            ep.src_uid = crate::process_table::uid(frame, ep.src_pid);
+        }
+        if let (None, Some(pid)) = (ep.dst_uid, ep.dst_pid) {
+            ep.dst_uid = crate::process_table::uid(frame, pid);
+        }
+        // the same for the domains, the sender's is only on the wire with BR_TRANSACTION_SEC_CTX
+        if ep.src_selinux.is_none() {
+            ep.src_selinux = crate::process_table::selinux(frame, ep.src_pid);
+        }
+        if let (None, Some(pid)) = (&ep.dst_selinux, ep.dst_pid) {
+            ep.dst_selinux = crate::process_table::selinux(frame, pid);
+        }
 
         // emit helpers: look the abbrev up and add a generated item when both the
         // field and the (optional) value are present.
@@ -225,6 +242,30 @@ impl Protocol {
         emit_int("binderdump.dst.pid", ep.dst_pid);
         emit_int("binderdump.dst.tid", ep.dst_tid);
         emit_str("binderdump.dst.cmdline", ep.dst_cmdline.as_deref());
+        emit_str("binderdump.src_selinux", ep.src_selinux.as_deref());
+        emit_str("binderdump.dst_selinux", ep.dst_selinux.as_deref());
+
+        // user: `u0_a234 (com.example.app)`. package: one generated string per package of either
+        // end, so `binderdump.package == "com.example.app"` matches both directions.
+        let mut packages: Vec<String> = Vec::new();
+        for (abbrev, uid) in [
+            ("binderdump.src.user", ep.src_uid),
+            ("binderdump.dst.user", ep.dst_uid),
+        ] {
+            let Some(uid) = uid else { continue };
+            let uid_packages = crate::uid_table::packages(frame, uid);
+            emit_str(abbrev, Some(&crate::uid_table::label(uid, &uid_packages)));
+            for package in uid_packages {
+                if !packages.contains(&package) {
+                    packages.push(package);
+                }
+            }
+        }
+        if let Some(h) = manager.get_handle("binderdump.package") {
+            for package in &packages {
+                unsafe { add_generated_string(tree, h, tvb, package) };
+            }
+        }
 
         // bc/br: one generated string per command/return in the buffer so the
         // frame matches `== NAME` or `contains "..."` for any of them.
@@ -323,12 +364,25 @@ impl Protocol {
             let data = epan::tvb_get_ptr(tvb, 0, len.try_into()?);
             let data = slice::from_raw_parts(data, len.try_into()?);
 
-            let (event, offsets) = binderdump_structs::binder_serde::from_bytes_with_offsets::<
+            let (mut event, offsets) = binderdump_structs::binder_serde::from_bytes_with_offsets::<
                 binderdump_structs::event_layer::EventProtocol,
             >(data)?;
 
             let offsets = offsets?;
 
+            // the cmdlines are only on the wire of the ProcessInfo and Dead packets, take the
+            // others from the table before anything renders them
+            let visited = (*(*pinfo).fd).visited() != 0;
+            let frame = (*(*pinfo).fd).num;
+            if !visited {
+                crate::process_table::record(frame, &event);
+                crate::uid_table::record(frame, &event);
+                crate::node_table::record(&event);
+                crate::fd_table::record(&event);
+                crate::maps_table::record(frame, &event);
+            }
+            crate::process_table::fill(frame, &mut event);
+
             dissect_offsets::dissect_offsets(
                 &event,
                 offsets,
@@ -342,11 +396,9 @@ impl Protocol {
             // first pass only: walk the BWR data buffer to feed
             // txn_complete_tracker so BR_TRANSACTION_COMPLETE frames can be
             // attributed to the BC that they ACK.
-            let visited = (*(*pinfo).fd).visited() != 0;
             if !visited {
                 if let Some(ioctl) = event.ioctl_data.as_ref() {
                     if let Some(bwr) = ioctl.bwr.as_ref() {
-                        let frame = (*(*pinfo).fd).num;
                         let txn_debug_id = bwr.transaction.as_ref().map(|t| t.debug_id);
                         crate::txn_complete_tracker::process_bwr_data(
                             frame,
@@ -371,7 +423,15 @@ impl Protocol {
             let mut switch_src_dst = false;
 
             self.record_and_render_frame_link(&event, pinfo, tvb, tree_item);
-            self.add_endpoint_fields(&event, tvb, tree_item);
+            self.add_endpoint_fields(&event, frame, tvb, tree_item);
+            crate::prio_inheritance::emit(
+                &self.dissector.field_manager,
+                &event,
+                tree_item,
+                tvb,
+                pinfo,
+            );
+            crate::wakeup_latency::emit(&self.dissector.field_manager, &event, tree_item, tvb);
 
             let col_string = build_col_string(&event);
 
@@ -540,6 +600,11 @@ unsafe extern "C" fn binderdump_init_routine() {
     crate::reply_correlation::clear();
     crate::follow_stream::clear();
     crate::txn_complete_tracker::clear();
+    crate::process_table::clear();
+    crate::uid_table::clear();
+    crate::node_table::clear();
+    crate::fd_table::clear();
+    crate::maps_table::clear();
 }
 
 const PROTOCOL_NAME: &'static CStr = c"Android Binderdump";
@@ -554,6 +619,18 @@ pub extern "C" fn register_protoinfo() {
                 "binderdump.ioctl_data.bwr.transaction.code",
                 handle_transaction_code,
             )
+            .add_custom_handler("binderdump.cmdline", handle_cmdline)
+            .add_custom_handler(
+                "binderdump.ioctl_data.bwr.transaction.target_cmdline",
+                handle_target_cmdline,
+            )
+            .add_custom_handler("binderdump.ioctl_data.uid", handle_ioctl_uid)
+            .add_custom_handler(
+                "binderdump.ioctl_data.bwr.transaction.sender_euid",
+                handle_sender_euid,
+            )
+            .add_custom_handler("binderdump.process_info.uid", handle_process_info_uid)
+            .add_custom_handler("binderdump.uid_info.uid", handle_uid_info_uid)
             .add_custom_handler(
                 "binderdump.ioctl_data.bwr.transaction.offsets",
                 dissect_flat_objects::dissect_offsets_array,
@@ -563,6 +640,17 @@ pub extern "C" fn register_protoinfo() {
                 dissect_parcel::dissect_transaction_data,
             )
             .add_extra_subtree("binderdump.ioctl_data.bwr.transaction.parcel")
+            .add_custom_handler(
+                "binderdump.ioctl_data.bwr.transaction.user_stack",
+                crate::symbolizer::dissect_user_stack,
+            )
+            .add_extra_field(FieldInfo {
+                name: "Frame".into(),
+                abbrev: crate::symbolizer::FRAME_FIELD.into(),
+                ftype: FtEnum::String,
+                display: FieldDisplay::StrAsciis,
+                strings: None,
+            })
             // decoded parameters render through per-(interface, method, param)
             // fields registered dynamically at dissection time (see
             // dissect_parcel.rs). only the undecodable-tail field is static, so
@@ -628,6 +716,27 @@ pub extern "C" fn register_protoinfo() {
                 display: FieldDisplay::StrAsciis,
                 strings: None,
             })
+            .add_extra_field(FieldInfo {
+                name: "Priority not inherited".into(),
+                abbrev: "binderdump.prio_not_inherited".into(),
+                ftype: FtEnum::String,
+                display: FieldDisplay::StrAsciis,
+                strings: None,
+            })
+            .add_extra_field(FieldInfo {
+                name: "Queued for".into(),
+                abbrev: "binderdump.wakeup.queued".into(),
+                ftype: FtEnum::RelativeTime,
+                display: FieldDisplay::None,
+                strings: None,
+            })
+            .add_extra_field(FieldInfo {
+                name: "Runnable for".into(),
+                abbrev: "binderdump.wakeup.runnable".into(),
+                ftype: FtEnum::RelativeTime,
+                display: FieldDisplay::None,
+                strings: None,
+            })
             .add_extra_field(FieldInfo {
                 name: "Source PID".into(),
                 abbrev: "binderdump.src.pid".into(),
@@ -670,6 +779,41 @@ pub extern "C" fn register_protoinfo() {
                 display: FieldDisplay::StrAsciis,
                 strings: None,
             })
+            .add_extra_field(FieldInfo {
+                name: "Source user".into(),
+                abbrev: "binderdump.src.user".into(),
+                ftype: FtEnum::String,
+                display: FieldDisplay::StrAsciis,
+                strings: None,
+            })
+            .add_extra_field(FieldInfo {
+                name: "Destination user".into(),
+                abbrev: "binderdump.dst.user".into(),
+                ftype: FtEnum::String,
+                display: FieldDisplay::StrAsciis,
+                strings: None,
+            })
+            .add_extra_field(FieldInfo {
+                name: "Source SELinux domain".into(),
+                abbrev: "binderdump.src_selinux".into(),
+                ftype: FtEnum::String,
+                display: FieldDisplay::StrAsciis,
+                strings: None,
+            })
+            .add_extra_field(FieldInfo {
+                name: "Destination SELinux domain".into(),
+                abbrev: "binderdump.dst_selinux".into(),
+                ftype: FtEnum::String,
+                display: FieldDisplay::StrAsciis,
+                strings: None,
+            })
+            .add_extra_field(FieldInfo {
+                name: "Package".into(),
+                abbrev: "binderdump.package".into(),
+                ftype: FtEnum::String,
+                display: FieldDisplay::StrAsciis,
+                strings: None,
+            })
             .add_extra_field(FieldInfo {
                 name: "Binder command".into(),
                 abbrev: "binderdump.bc".into(),
@@ -719,6 +863,7 @@ pub extern "C" fn register_protoinfo() {
     crate::follow_stream::register(proto_id);
     crate::reply_postdissector::register();
     crate::decode_status::register(proto_id);
+    crate::prio_inheritance::register(proto_id);
 }
 
 fn comm_to_string(buf: &[u8]) -> String {
@@ -744,6 +889,169 @@ fn add_string_item(
     Ok(())
 }
 
+// The cmdline fields of the packets that don't carry one show the value process_table filled in,
+// as a generated item
+unsafe fn add_cmdline(
+    hf: c_int,
+    offset: FieldOffset,
+    value: &[u8],
+    tvb: *mut epan::tvbuff,
+    tree: *mut epan::proto_node,
+) -> anyhow::Result<()> {
+    if offset.size == 0 && !value.is_empty() {
+        crate::epan_utils::add_generated_string(
+            tree,
+            hf,
+            tvb,
+            &crate::binderdump::cmdline_to_string(value),
+        );
+    } else {
+        epan::proto_tree_add_item(
+            tree,
+            hf,
+            tvb,
+            offset.offset.try_into()?,
+            offset.size.try_into()?,
+            epan::ENC_LITTLE_ENDIAN,
+        );
+    }
+    Ok(())
+}
+
+fn handle_cmdline(
+    hf: c_int,
+    _ett: c_int,
+    _manager: &HeaderFieldsManager<EventProtocol>,
+    base: &EventProtocol,
+    offset: FieldOffset,
+    tvb: *mut epan::tvbuff,
+    _pinfo: *mut epan::packet_info,
+    tree: *mut epan::proto_node,
+) -> anyhow::Result<()> {
+    unsafe { add_cmdline(hf, offset, &base.cmdline, tvb, tree) }
+}
+
+fn handle_target_cmdline(
+    hf: c_int,
+    _ett: c_int,
+    _manager: &HeaderFieldsManager<EventProtocol>,
+    base: &EventProtocol,
+    offset: FieldOffset,
+    tvb: *mut epan::tvbuff,
+    _pinfo: *mut epan::packet_info,
+    tree: *mut epan::proto_node,
+) -> anyhow::Result<()> {
+    let target_cmdline = base
+        .ioctl_data
+        .as_ref()
+        .and_then(|ioctl| ioctl.bwr.as_ref())
+        .and_then(|bwr| bwr.transaction.as_ref())
+        .map(|txn| txn.target_cmdline.as_slice())
+        .unwrap_or_default();
+    unsafe { add_cmdline(hf, offset, target_cmdline, tvb, tree) }
+}
+
+// uid fields show the Android user and package next to the number, e.g.
+// `10234 [u0_a234 (com.example.app)]`
+unsafe fn add_uid(
+    hf: c_int,
+    offset: FieldOffset,
+    uid: u32,
+    tvb: *mut epan::tvbuff,
+    pinfo: *mut epan::packet_info,
+    tree: *mut epan::proto_node,
+) -> anyhow::Result<()> {
+    let item = epan::proto_tree_add_item(
+        tree,
+        hf,
+        tvb,
+        offset.offset.try_into()?,
+        offset.size.try_into()?,
+        epan::ENC_LITTLE_ENDIAN,
+    );
+    let frame = (*(*pinfo).fd).num;
+    let label = CString::new(format!(" [{}]", crate::uid_table::user_label(frame, uid)))?;
+    epan::proto_item_append_text(item, c"%s".as_ptr(), label.as_ptr());
+    Ok(())
+}
+
+fn handle_ioctl_uid(
+    hf: c_int,
+    _ett: c_int,
+    _manager: &HeaderFieldsManager<EventProtocol>,
+    base: &EventProtocol,
+    offset: FieldOffset,
+    tvb: *mut epan::tvbuff,
+    pinfo: *mut epan::packet_info,
+    tree: *mut epan::proto_node,
+) -> anyhow::Result<()> {
+    let uid = base.ioctl_data.as_ref().map_or(0, |ioctl| ioctl.uid);
+    unsafe { add_uid(hf, offset, uid, tvb, pinfo, tree) }
+}
+
+fn handle_sender_euid(
+    hf: c_int,
+    _ett: c_int,
+    _manager: &HeaderFieldsManager<EventProtocol>,
+    base: &EventProtocol,
+    offset: FieldOffset,
+    tvb: *mut epan::tvbuff,
+    pinfo: *mut epan::packet_info,
+    tree: *mut epan::proto_node,
+) -> anyhow::Result<()> {
+    let Some(txn) = base
+        .ioctl_data
+        .as_ref()
+        .and_then(|ioctl| ioctl.bwr.as_ref())
+        .and_then(|bwr| bwr.transaction.as_ref())
+    else {
+        return Ok(());
+    };
+    // the kernel only fills it on the receive side
+    if txn.sender_pid == 0 {
+        unsafe {
+            epan::proto_tree_add_item(
+                tree,
+                hf,
+                tvb,
+                offset.offset.try_into()?,
+                offset.size.try_into()?,
+                epan::ENC_LITTLE_ENDIAN,
+            );
+        }
+        return Ok(());
+    }
+    unsafe { add_uid(hf, offset, txn.sender_euid, tvb, pinfo, tree) }
+}
+
+fn handle_process_info_uid(
+    hf: c_int,
+    _ett: c_int,
+    _manager: &HeaderFieldsManager<EventProtocol>,
+    base: &EventProtocol,
+    offset: FieldOffset,
+    tvb: *mut epan::tvbuff,
+    pinfo: *mut epan::packet_info,
+    tree: *mut epan::proto_node,
+) -> anyhow::Result<()> {
+    let uid = base.process_info.as_ref().map_or(0, |info| info.uid);
+    unsafe { add_uid(hf, offset, uid, tvb, pinfo, tree) }
+}
+
+fn handle_uid_info_uid(
+    hf: c_int,
+    _ett: c_int,
+    _manager: &HeaderFieldsManager<EventProtocol>,
+    base: &EventProtocol,
+    offset: FieldOffset,
+    tvb: *mut epan::tvbuff,
+    pinfo: *mut epan::packet_info,
+    tree: *mut epan::proto_node,
+) -> anyhow::Result<()> {
+    let uid = base.uid_info.as_ref().map_or(0, |info| info.uid);
+    unsafe { add_uid(hf, offset, uid, tvb, pinfo, tree) }
+}
+
 fn handle_transaction_code(
     hf: c_int,
     _ett: c_int,
@@ -914,8 +1222,9 @@ pub extern "C" fn register_handoff() {
     };
 
     // Wireshark has finished reading user prefs by the time handoff runs, so
-    // OVERLAY_DIR / AOSP_DIR now point at either the defaults or the user's
-    // overrides. Build the AIDL/HIDL Registry from both.
+    // OVERLAY_DIR / AOSP_DIR / SYMBOLS_DIR now point at either the defaults or
+    // the user's overrides. Build the AIDL/HIDL Registry from the first two and
+    // point the symbolizer at the last.
     let aosp = unsafe {
         if AOSP_DIR.is_null() {
             default_aosp_dir()
@@ -937,6 +1246,17 @@ pub extern "C" fn register_handoff() {
         }
     };
     aidl_resolve::init_registry(&aosp, &overlay);
+    let symbols = unsafe {
+        if SYMBOLS_DIR.is_null() {
+            default_symbols_dir()
+        } else {
+            CStr::from_ptr(SYMBOLS_DIR)
+                .to_str()
+                .map(std::path::PathBuf::from)
+                .unwrap_or_else(|_| default_symbols_dir())
+        }
+    };
+    crate::symbolizer::init(&symbols);
     crate::reply_postdissector::register_handoff();
 }
 
@@ -999,27 +1319,109 @@ unsafe fn register_aosp_dir_pref(module: *mut epan::module_t) {
     epan::prefs_register_directory_preference(module, name, title, descr, &raw mut AOSP_DIR);
 }
 
+static mut SYMBOLS_DIR: *const std::os::raw::c_char = std::ptr::null();
+
+fn default_symbols_dir() -> std::path::PathBuf {
+    dirs::config_dir()
+        .map(|c| c.join("wireshark").join("binderdump").join("symbols"))
+        .unwrap_or_else(|| std::path::PathBuf::from("."))
+}
+
+unsafe fn register_symbols_dir_pref(module: *mut epan::module_t) {
+    let name = CString::new("symbols_dir").unwrap().into_raw();
+    let title = CString::new("Symbols directory").unwrap().into_raw();
+    let descr = CString::new(
+        "Directory of unstripped libraries (by device path, file name or .build-id/) the caller stacks are symbolized with.",
+    )
+    .unwrap()
+    .into_raw();
+    let default = CString::new(default_symbols_dir().to_string_lossy().as_ref())
+        .unwrap()
+        .into_raw();
+    SYMBOLS_DIR = default as *const _;
+
+    epan::prefs_register_directory_preference(module, name, title, descr, &raw mut SYMBOLS_DIR);
+}
+
 fn build_col_string(event: &binderdump_structs::event_layer::EventProtocol) -> String {
     use binderdump_structs::event_layer::EventType;
+
+    let mut info = build_event_col_string(event);
+    if event.partial {
+        info = col_info::partial(&info);
+    }
+    match event.event_type {
+        EventType::TruncatedIoctl => col_info::truncated_by_shutdown(&info),
+        _ => info,
+    }
+}
+
+fn build_event_col_string(event: &binderdump_structs::event_layer::EventProtocol) -> String {
+    use binderdump_structs::event_layer::EventType;
     use binderdump_trait::EpanProtocolEnum;
 
     let Some(ioctl) = event.ioctl_data.as_ref() else {
-        // no ioctl payload — these are the process/thread death events.
+        // no ioctl payload — these are the process/thread death and metadata events.
         return match &event.event_type {
             EventType::DeadProcess => col_info::format(&ColEvent::DeadProcess),
             EventType::DeadThread => col_info::format(&ColEvent::DeadThread),
+            EventType::ProcessInfo => {
+                let info = event.process_info.as_ref();
+                let package = info
+                    .map(|info| crate::binderdump::cmdline_to_string(&info.package))
+                    .unwrap_or_default();
+                col_info::format(&ColEvent::ProcessInfo {
+                    uid: info.map_or(0, |info| info.uid),
+                    package: &package,
+                })
+            }
+            EventType::UidInfo => {
+                let info = event.uid_info.as_ref();
+                let user = crate::uid_table::uid_name(info.map_or(0, |info| info.uid));
+                let packages = info
+                    .map(|info| crate::binderdump::cmdline_to_string(&info.packages))
+                    .unwrap_or_default();
+                col_info::format(&ColEvent::UidInfo {
+                    user: &user,
+                    packages: &packages,
+                })
+            }
+            EventType::NewProcess => col_info::format(&ColEvent::NewProcess {
+                parent_pid: event.new_process.as_ref().map_or(0, |info| info.parent_pid),
+            }),
+            EventType::Exec => {
+                let filename = event
+                    .exec
+                    .as_ref()
+                    .map(|exec| crate::binderdump::cmdline_to_string(&exec.filename))
+                    .unwrap_or_default();
+                col_info::format(&ColEvent::Exec {
+                    filename: &filename,
+                })
+            }
+            EventType::ProcessMaps => col_info::format(&ColEvent::ProcessMaps {
+                mappings: event.maps.as_ref().map_or(0, |maps| maps.mappings.len()),
+            }),
             // split ioctls are a fragment of a following event, and a
             // finished/invalid event with no ioctl payload carries nothing to
             // summarize — leave the column blank.
-            EventType::SplitIoctl | EventType::FinishedIoctl | EventType::Invalid => String::new(),
+            EventType::SplitIoctl
+            | EventType::FinishedIoctl
+            | EventType::TruncatedIoctl
+            | EventType::Invalid => String::new(),
         };
     };
     let Some(bwr) = ioctl.bwr.as_ref() else {
-        // a non-BWR ioctl — only the command and its return value are captured,
-        // not the payload behind the arg pointer.
+        // a non-BWR ioctl. what the kernel answered is more interesting than what was asked,
+        // unless it failed.
+        let arg = match ioctl.result {
+            0.. => ioctl.arg_out.as_ref().or(ioctl.arg_in.as_ref()),
+            _ => ioctl.arg_in.as_ref(),
+        };
         return col_info::format(&ColEvent::Ioctl {
             name: ioctl.cmd.to_str(),
             result: ioctl.result,
+            arg,
         });
     };
 
@@ -0,0 +1,159 @@
+// transaction debug id -> the fds of its FD and FDA objects, from the packets of the receiving side
+// (the capture attaches them there, like the translations of node_table). the sending side's
+// packet only gets them once the receiving side was dissected, i.e. on the second pass.
+
+use binderdump_structs::bwr_layer::FdProtocol;
+use binderdump_structs::event_layer::EventProtocol;
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+
+#[derive(Default)]
+struct State {
+    fds: HashMap<i32, Vec<FdProtocol>>,
+}
+
+static STATE: OnceLock<Mutex<State>> = OnceLock::new();
+
+fn state() -> &'static Mutex<State> {
+    STATE.get_or_init(|| Mutex::new(State::default()))
+}
+
+pub fn clear() {
+    if let Ok(mut s) = state().lock() {
+        s.fds.clear();
+    }
+}
+
+// first pass only
+pub fn record(event: &EventProtocol) {
+    let txn = event
+        .ioctl_data
+        .as_ref()
+        .and_then(|ioctl| ioctl.bwr.as_ref())
+        .and_then(|bwr| bwr.transaction.as_ref());
+    let Some(txn) = txn else { return };
+    if txn.fds.is_empty() {
+        return;
+    }
+    let Ok(mut s) = state().lock() else { return };
+    s.fds.insert(txn.debug_id, txn.fds.clone());
+}
+
+pub fn fds(debug_id: i32) -> Vec<FdProtocol> {
+    let Ok(s) = state().lock() else {
+        return Vec::new();
+    };
+    s.fds.get(&debug_id).cloned().unwrap_or_default()
+}
+
+// Matches the fds of a transaction to its objects. The kernel traces the fd of a FD object at
+// its position in the data + 8 (the fd field), and the fds of a FDA object where they ended up in
+// the target's copy of the parent buffer, which the capture doesn't know; those are taken in
+// order, the kernel translates the objects in the order of the offsets array.
+pub struct FdMatcher<'a> {
+    fds: &'a [FdProtocol],
+    // fds that aren't a FD object's, in order
+    array_fds: Vec<&'a FdProtocol>,
+    next_array_fd: usize,
+}
+
+const FD_FIELD_OFFSET: u64 = 8;
+
+impl<'a> FdMatcher<'a> {
+    // `fd_objects`: the positions of the FD objects in the data
+    pub fn new(fds: &'a [FdProtocol], fd_objects: &[usize]) -> Self {
+        let is_object_fd = |fd: &FdProtocol| {
+            fd_objects
+                .iter()
+                .any(|&pos| pos as u64 + FD_FIELD_OFFSET == fd.offset)
+        };
+        Self {
+            fds,
+            array_fds: fds.iter().filter(|fd| !is_object_fd(fd)).collect(),
+            next_array_fd: 0,
+        }
+    }
+
+    // the fd of the FD object at `pos`
+    pub fn object(&self, pos: usize) -> Option<&'a FdProtocol> {
+        self.fds
+            .iter()
+            .find(|fd| fd.offset == pos as u64 + FD_FIELD_OFFSET)
+    }
+
+    // the next fd of a FDA object
+    pub fn next_array_fd(&mut self) -> Option<&'a FdProtocol> {
+        let fd = self.array_fds.get(self.next_array_fd).copied();
+        self.next_array_fd += 1;
+        fd
+    }
+}
+
+// `/dev/ashmem/CursorWindow, fd 12 in the sender`. the data of a received transaction holds the
+// target's fd, a sent one the sender's, so it names the fd of the other side.
+pub fn label(fd: &FdProtocol, received: bool) -> Option<String> {
+    let path = crate::binderdump::cmdline_to_string(&fd.path);
+    let (other_fd, other) = if received {
+        (fd.sender_fd, "sender")
+    } else {
+        (fd.receiver_fd, "target")
+    };
+    match (path.is_empty(), other_fd) {
+        (true, -1) => None,
+        (true, other_fd) => Some(format!("fd {} in the {}", other_fd, other)),
+        (false, -1) => Some(path),
+        (false, other_fd) => Some(format!("{}, fd {} in the {}", path, other_fd, other)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fd(offset: u64, sender_fd: i32, receiver_fd: i32, path: &str) -> FdProtocol {
+        FdProtocol {
+            offset,
+            sender_fd,
+            receiver_fd,
+            path: path.as_bytes().to_vec(),
+        }
+    }
+
+    #[test]
+    fn objects_match_by_offset_and_arrays_in_order() {
+        // a FDA of two fds, then a FD object at 0x50 whose fd is traced after them
+        let fds = [
+            fd(0x100, 11, 41, "memfd:a"),
+            fd(0x104, 12, 42, "memfd:b"),
+            fd(0x58, 10, 40, "socket:[1]"),
+        ];
+        let mut matcher = FdMatcher::new(&fds, &[0x50]);
+        assert_eq!(matcher.object(0x50).map(|fd| fd.sender_fd), Some(10));
+        assert_eq!(matcher.object(0x18).map(|fd| fd.sender_fd), None);
+        assert_eq!(matcher.next_array_fd().map(|fd| fd.sender_fd), Some(11));
+        assert_eq!(matcher.next_array_fd().map(|fd| fd.sender_fd), Some(12));
+        assert!(matcher.next_array_fd().is_none());
+    }
+
+    #[test]
+    fn labels_name_the_other_side() {
+        let both = fd(0x58, 10, 40, "/dev/ashmem/CursorWindow");
+        assert_eq!(
+            label(&both, true).as_deref(),
+            Some("/dev/ashmem/CursorWindow, fd 10 in the sender")
+        );
+        assert_eq!(
+            label(&both, false).as_deref(),
+            Some("/dev/ashmem/CursorWindow, fd 40 in the target")
+        );
+        assert_eq!(
+            label(&fd(0x58, 10, -1, ""), true).as_deref(),
+            Some("fd 10 in the sender")
+        );
+        assert_eq!(
+            label(&fd(0x58, -1, 40, "pipe:[7]"), true).as_deref(),
+            Some("pipe:[7]")
+        );
+        assert_eq!(label(&fd(0x58, 10, -1, ""), false), None);
+    }
+}
@@ -7,13 +7,21 @@ mod dissect_offsets;
 mod dissect_parcel;
 mod epan_plugin;
 mod epan_utils;
+mod fd_table;
 pub mod follow_stream;
 mod header_fields_manager;
+mod maps_table;
+mod node_table;
+mod prio_inheritance;
+mod process_table;
 pub mod reply_correlation;
 pub mod reply_postdissector;
+mod symbolizer;
 pub mod txn_complete_tracker;
 mod txn_link;
+mod uid_table;
 pub mod version_check;
+mod wakeup_latency;
 
 use binderdump_epan_sys::epan;
 use std::ffi::c_int;
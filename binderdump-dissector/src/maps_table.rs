@@ -0,0 +1,86 @@
+// pid -> the executable mappings of the process, from the ProcessMaps packets the capture writes
+// before the first stack of a process (--stacks). symbolizer takes the mapping and file offset of
+// every frame from here.
+
+use binderdump_structs::event_layer::{EventProtocol, ProcessMapsProtocol};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+
+#[derive(Default)]
+struct State {
+    // pid -> (frame, mappings), in frame order. a pid that exec'd or was reused gets a new one.
+    processes: HashMap<i32, Vec<(u32, Arc<ProcessMapsProtocol>)>>,
+}
+
+impl State {
+    fn record(&mut self, frame: u32, pid: i32, maps: &ProcessMapsProtocol) {
+        let snapshots = self.processes.entry(pid).or_default();
+        // frames are only recorded on the first pass, in order
+        if snapshots.last().is_some_and(|(last, _)| *last >= frame) {
+            return;
+        }
+        snapshots.push((frame, Arc::new(maps.clone())));
+    }
+
+    // the last snapshot at or before `frame`, else the first one after it
+    fn lookup(&self, frame: u32, pid: i32) -> Option<Arc<ProcessMapsProtocol>> {
+        let snapshots = self.processes.get(&pid)?;
+        let before = snapshots.partition_point(|(f, _)| *f <= frame);
+        let (_, maps) = match before {
+            0 => snapshots.first()?,
+            _ => &snapshots[before - 1],
+        };
+        Some(maps.clone())
+    }
+}
+
+static STATE: OnceLock<Mutex<State>> = OnceLock::new();
+
+fn state() -> &'static Mutex<State> {
+    STATE.get_or_init(|| Mutex::new(State::default()))
+}
+
+pub fn clear() {
+    if let Ok(mut s) = state().lock() {
+        s.processes.clear();
+    }
+}
+
+// first pass only
+pub fn record(frame: u32, event: &EventProtocol) {
+    let Some(maps) = &event.maps else { return };
+    let Ok(mut s) = state().lock() else { return };
+    s.record(frame, event.pid, maps);
+}
+
+pub fn lookup(frame: u32, pid: i32) -> Option<Arc<ProcessMapsProtocol>> {
+    state().lock().ok()?.lookup(frame, pid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use binderdump_structs::event_layer::MappingProtocol;
+
+    fn maps(path: &str) -> ProcessMapsProtocol {
+        ProcessMapsProtocol {
+            mappings: vec![MappingProtocol {
+                path: path.as_bytes().to_vec(),
+                ..Default::default()
+            }],
+        }
+    }
+
+    #[test]
+    fn stacks_use_the_snapshot_of_their_frame() {
+        let mut state = State::default();
+        state.record(10, 100, &maps("/system/bin/app_process64"));
+        state.record(50, 100, &maps("/system/bin/dumpsys"));
+        let path = |frame| state.lookup(frame, 100).unwrap().mappings[0].path.clone();
+
+        assert_eq!(path(5), b"/system/bin/app_process64");
+        assert_eq!(path(49), b"/system/bin/app_process64");
+        assert_eq!(path(60), b"/system/bin/dumpsys");
+        assert!(state.lookup(60, 200).is_none());
+    }
+}
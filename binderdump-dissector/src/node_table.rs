@@ -0,0 +1,129 @@
+// transaction debug id -> the translations of its binder objects, from the packets of the
+// receiving side (the capture attaches them there). the objects of the sending side's packet are
+// named from the same translations, so they only get a label once the receiving side was
+// dissected, i.e. on the second pass.
+
+use binderdump_structs::bwr_layer::{TranslationProtocol, TranslationType};
+use binderdump_structs::event_layer::EventProtocol;
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+
+#[derive(Default)]
+struct State {
+    translations: HashMap<i32, Vec<TranslationProtocol>>,
+}
+
+static STATE: OnceLock<Mutex<State>> = OnceLock::new();
+
+fn state() -> &'static Mutex<State> {
+    STATE.get_or_init(|| Mutex::new(State::default()))
+}
+
+pub fn clear() {
+    if let Ok(mut s) = state().lock() {
+        s.translations.clear();
+    }
+}
+
+// first pass only
+pub fn record(event: &EventProtocol) {
+    let txn = event
+        .ioctl_data
+        .as_ref()
+        .and_then(|ioctl| ioctl.bwr.as_ref())
+        .and_then(|bwr| bwr.transaction.as_ref());
+    let Some(txn) = txn else { return };
+    if txn.translations.is_empty() {
+        return;
+    }
+    let Ok(mut s) = state().lock() else { return };
+    s.translations
+        .insert(txn.debug_id, txn.translations.clone());
+}
+
+pub fn translations(debug_id: i32) -> Vec<TranslationProtocol> {
+    let Ok(s) = state().lock() else {
+        return Vec::new();
+    };
+    s.translations.get(&debug_id).cloned().unwrap_or_default()
+}
+
+// `node 7 of 1234 (system_server)` for the object whose binder ptr or handle is `value`. The
+// objects of a received transaction are the target's (`dest`), the sent ones the sender's (`src`).
+pub fn label(
+    translations: &[TranslationProtocol],
+    received: bool,
+    value: u64,
+    cmdline_of: impl Fn(i32) -> Option<String>,
+) -> Option<String> {
+    let translation = translations.iter().find(|translation| {
+        let side = if received {
+            translation.dest
+        } else {
+            translation.src
+        };
+        side == value
+    })?;
+    let node = format!("node {}", translation.node_debug_id);
+    let label = match (translation.owner_pid, cmdline_of(translation.owner_pid)) {
+        (0, _) => node,
+        (owner, Some(cmdline)) => format!("{} of {} ({})", node, owner, cmdline),
+        (owner, None) => format!("{} of {}", node, owner),
+    };
+    Some(match (translation.translation_type, received) {
+        (TranslationType::NodeToRef, false) => {
+            format!("{}, handle {} in the target", label, translation.dest)
+        }
+        _ => label,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn translations() -> Vec<TranslationProtocol> {
+        vec![
+            TranslationProtocol {
+                translation_type: TranslationType::NodeToRef,
+                node_debug_id: 7,
+                owner_pid: 100,
+                src: 0x7000,
+                dest: 3,
+            },
+            TranslationProtocol {
+                translation_type: TranslationType::RefToRef,
+                node_debug_id: 9,
+                owner_pid: 0,
+                src: 4,
+                dest: 6,
+            },
+        ]
+    }
+
+    #[test]
+    fn received_objects_match_the_target_side() {
+        let cmdline_of = |pid| (pid == 100).then(|| "system_server".to_string());
+        assert_eq!(
+            label(&translations(), true, 3, cmdline_of).as_deref(),
+            Some("node 7 of 100 (system_server)")
+        );
+        assert_eq!(
+            label(&translations(), true, 6, cmdline_of).as_deref(),
+            Some("node 9")
+        );
+        assert_eq!(label(&translations(), true, 4, cmdline_of), None);
+    }
+
+    #[test]
+    fn sent_objects_match_the_sender_side() {
+        assert_eq!(
+            label(&translations(), false, 0x7000, |_| None).as_deref(),
+            Some("node 7 of 100, handle 3 in the target")
+        );
+        assert_eq!(
+            label(&translations(), false, 4, |_| None).as_deref(),
+            Some("node 9")
+        );
+    }
+}
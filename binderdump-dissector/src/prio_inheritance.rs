@@ -0,0 +1,127 @@
+// Flags the transactions whose receiving thread ran less urgently than its caller. check is pure
+// and unit-testable; register/emit hold the epan FFI side, like decode_status.
+
+use crate::header_fields_manager::HeaderFieldsManager;
+use binderdump_epan_sys::epan;
+use binderdump_structs::bwr_layer::TransactionProtocol;
+use binderdump_structs::event_layer::EventProtocol;
+use binderdump_trait::EpanProtocolEnum;
+use std::ffi::{c_int, CString};
+
+static mut EI_NOT_INHERITED: epan::expert_field = epan::expert_field { ei: -1, hf: -1 };
+
+// binder doesn't pass the caller's priority on with one-way transactions
+const TF_ONE_WAY: u32 = 0x01;
+
+#[allow(static_mut_refs)]
+pub fn register(proto_id: c_int) {
+    unsafe {
+        static mut EI: [epan::ei_register_info; 1] = unsafe { std::mem::zeroed() };
+        EI[0] = epan::ei_register_info {
+            ids: &raw mut EI_NOT_INHERITED,
+            eiinfo: epan::expert_field_info {
+                name: c"binderdump.prio_not_inherited".as_ptr(),
+                group: epan::PI_SEQUENCE as c_int,
+                severity: epan::PI_WARN as c_int,
+                summary: c"Receiving thread ran below the caller's priority".as_ptr(),
+                id: 0,
+                protocol: std::ptr::null(),
+                orig_severity: 0,
+                hf_info: std::mem::zeroed(),
+            },
+        };
+        let em = epan::expert_register_protocol(proto_id);
+        epan::expert_register_field_array(em, EI.as_mut_ptr(), EI.len() as c_int);
+    }
+}
+
+// Only the receiving side carries both priorities. Without a change binder made to the thread
+// there is nothing to compare with.
+pub fn check(txn: &TransactionProtocol) -> Option<String> {
+    if txn.flags & TF_ONE_WAY != 0 {
+        return None;
+    }
+    let caller = txn.caller_prio.as_ref()?;
+    let inherited = txn.inherited_prio.as_ref()?;
+    if !inherited.is_below(caller) {
+        return None;
+    }
+    Some(format!(
+        "caller ran at prio {} ({}), thread got {} (binder wanted {})",
+        caller.prio,
+        caller.policy.to_str(),
+        inherited.new_prio,
+        inherited.desired_prio
+    ))
+}
+
+pub fn emit(
+    manager: &HeaderFieldsManager<EventProtocol>,
+    event: &EventProtocol,
+    tree: *mut epan::proto_node,
+    tvb: *mut epan::tvbuff_t,
+    pinfo: *mut epan::packet_info,
+) {
+    let Some(txn) = event
+        .ioctl_data
+        .as_ref()
+        .and_then(|ioctl| ioctl.bwr.as_ref())
+        .and_then(|bwr| bwr.transaction.as_ref())
+    else {
+        return;
+    };
+    let Some(hf) = manager.get_handle("binderdump.prio_not_inherited") else {
+        return;
+    };
+    let Some(Ok(text)) = check(txn).map(CString::new) else {
+        return;
+    };
+    unsafe {
+        let item = epan::proto_tree_add_string(tree, hf, tvb, 0, 0, text.as_ptr());
+        epan::binderdump_proto_item_set_generated(item);
+        epan::expert_add_info(pinfo, item, &raw mut EI_NOT_INHERITED);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use binderdump_structs::bwr_layer::{InheritedPrioProtocol, SchedPolicy, SchedPrioProtocol};
+
+    fn txn(caller: u32, new_prio: u32) -> TransactionProtocol {
+        TransactionProtocol {
+            caller_prio: Some(SchedPrioProtocol {
+                policy: SchedPolicy::SCHED_FIFO,
+                prio: caller,
+            }),
+            inherited_prio: Some(InheritedPrioProtocol {
+                old_prio: 120,
+                desired_prio: caller,
+                new_prio,
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn flags_threads_below_their_caller() {
+        assert_eq!(
+            check(&txn(97, 120)).as_deref(),
+            Some("caller ran at prio 97 (SCHED_FIFO), thread got 120 (binder wanted 97)")
+        );
+        assert!(check(&txn(97, 97)).is_none());
+        // the node's minimum priority can put the thread above its caller
+        assert!(check(&txn(120, 100)).is_none());
+    }
+
+    #[test]
+    fn needs_both_sides_and_a_two_way_call() {
+        let mut oneway = txn(97, 120);
+        oneway.flags = TF_ONE_WAY;
+        assert!(check(&oneway).is_none());
+
+        let mut unchanged = txn(97, 120);
+        unchanged.inherited_prio = None;
+        assert!(check(&unchanged).is_none());
+    }
+}
@@ -0,0 +1,280 @@
+// pid -> cmdline and uid, from the ProcessInfo packets (and the Dead, NewProcess and Exec packets,
+// which carry the cmdline too).
+// the capture writes the cmdline of a process once, before its first packet, and again when one of
+// its threads shows another one, and leaves it empty in the other packets of its threads and in the
+// transactions sent to it. fill() puts it back into the
+// event before anything renders it, so the columns, the endpoint fields and the binderdump.cmdline
+// / target_cmdline filters behave as if every packet carried it. the uid is what the endpoint
+// fields take the user of a transaction's target from, and the SELinux domain the src_selinux /
+// dst_selinux fields take theirs from when the transaction doesn't carry the sender's.
+
+use binderdump_structs::event_layer::{EventProtocol, EventType, ProcessInfoProtocol};
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+
+struct Entry {
+    frame: u32,
+    cmdline: Vec<u8>,
+    // None on the Dead packets
+    uid: Option<u32>,
+    // empty when unknown
+    selinux: Vec<u8>,
+}
+
+#[derive(Default)]
+struct State {
+    // pid -> entries of all its threads, in frame order
+    processes: HashMap<i32, Vec<Entry>>,
+}
+
+impl State {
+    fn clear(&mut self) {
+        self.processes.clear();
+    }
+
+    fn record(&mut self, frame: u32, pid: i32, cmdline: &[u8], info: Option<&ProcessInfoProtocol>) {
+        let entries = self.processes.entry(pid).or_default();
+        // frames are only recorded on the first pass, in order
+        if entries.last().is_some_and(|last| last.frame >= frame) {
+            return;
+        }
+        entries.push(Entry {
+            frame,
+            cmdline: cmdline.to_vec(),
+            uid: info.map(|info| info.uid),
+            selinux: info.map(|info| info.selinux.clone()).unwrap_or_default(),
+        });
+    }
+
+    // what the process had at `frame`, whichever of its threads wrote it: the last entry at or
+    // before it that has it, else the first one after it (a flight recorder window may start after
+    // the process' ProcessInfo was dropped, and its Dead packet is the only one left)
+    fn lookup_by<'a, T>(
+        &'a self,
+        pid: i32,
+        frame: u32,
+        get: impl Fn(&'a Entry) -> Option<T>,
+    ) -> Option<T> {
+        let entries = self.processes.get(&pid)?;
+        let before = entries.partition_point(|entry| entry.frame <= frame);
+        entries[..before]
+            .iter()
+            .rev()
+            .find_map(&get)
+            .or_else(|| entries[before..].iter().find_map(&get))
+    }
+
+    fn lookup(&self, pid: i32, frame: u32) -> Option<&[u8]> {
+        self.lookup_by(pid, frame, |entry| {
+            (!entry.cmdline.is_empty()).then_some(entry.cmdline.as_slice())
+        })
+    }
+
+    fn lookup_uid(&self, pid: i32, frame: u32) -> Option<u32> {
+        self.lookup_by(pid, frame, |entry| entry.uid)
+    }
+
+    fn lookup_selinux(&self, pid: i32, frame: u32) -> Option<&[u8]> {
+        self.lookup_by(pid, frame, |entry| {
+            (!entry.selinux.is_empty()).then_some(entry.selinux.as_slice())
+        })
+    }
+
+    fn fill(&self, frame: u32, event: &mut EventProtocol) {
+        if event.cmdline.is_empty() {
+            if let Some(cmdline) = self.lookup(event.pid, frame) {
+                event.cmdline = cmdline.to_vec();
+            }
+        }
+        let txn = event
+            .ioctl_data
+            .as_mut()
+            .and_then(|ioctl| ioctl.bwr.as_mut())
+            .and_then(|bwr| bwr.transaction.as_mut());
+        if let Some(txn) = txn {
+            if txn.target_cmdline.is_empty() {
+                if let Some(cmdline) = self.lookup(txn.to_proc, frame) {
+                    txn.target_cmdline = cmdline.to_vec();
+                }
+            }
+        }
+    }
+}
+
+static STATE: OnceLock<Mutex<State>> = OnceLock::new();
+
+fn state() -> &'static Mutex<State> {
+    STATE.get_or_init(|| Mutex::new(State::default()))
+}
+
+pub fn clear() {
+    if let Ok(mut s) = state().lock() {
+        s.clear();
+    }
+}
+
+// first pass only
+pub fn record(frame: u32, event: &EventProtocol) {
+    let carries_cmdline = matches!(
+        event.event_type,
+        EventType::ProcessInfo
+            | EventType::DeadProcess
+            | EventType::DeadThread
+            | EventType::NewProcess
+            | EventType::Exec
+    );
+    let info = event.process_info.as_ref();
+    if !carries_cmdline || (event.cmdline.is_empty() && info.is_none()) {
+        return;
+    }
+    let Ok(mut s) = state().lock() else { return };
+    s.record(frame, event.pid, &event.cmdline, info);
+}
+
+pub fn fill(frame: u32, event: &mut EventProtocol) {
+    let Ok(s) = state().lock() else { return };
+    s.fill(frame, event);
+}
+
+// the cmdline of a process at `frame`
+pub fn cmdline(frame: u32, pid: i32) -> Option<String> {
+    let Ok(s) = state().lock() else { return None };
+    s.lookup(pid, frame)
+        .map(crate::binderdump::cmdline_to_string)
+}
+
+// the uid of a process at `frame`, for the packets that don't carry one (Dead packets, the target
+// of a transaction)
+pub fn uid(frame: u32, pid: i32) -> Option<u32> {
+    let Ok(s) = state().lock() else { return None };
+    s.lookup_uid(pid, frame)
+}
+
+// the SELinux domain of a process at `frame`
+pub fn selinux(frame: u32, pid: i32) -> Option<String> {
+    let Ok(s) = state().lock() else { return None };
+    s.lookup_selinux(pid, frame)
+        .map(crate::binderdump::cmdline_to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use binderdump_structs::bwr_layer::{BinderWriteReadProtocol, TransactionProtocol};
+    use binderdump_structs::event_layer::IoctlProtocol;
+
+    #[test]
+    fn lookup_takes_the_last_cmdline_before_the_frame() {
+        let mut s = State::default();
+        s.record(1, 100, b"zygote64", None);
+        s.record(5, 100, b"com.example.app", None);
+        assert_eq!(s.lookup(100, 3), Some(&b"zygote64"[..]));
+        assert_eq!(s.lookup(100, 5), Some(&b"com.example.app"[..]));
+        assert_eq!(s.lookup(100, 9), Some(&b"com.example.app"[..]));
+    }
+
+    #[test]
+    fn lookup_before_the_first_record_takes_the_first() {
+        let mut s = State::default();
+        s.record(7, 100, b"system_server", None);
+        assert_eq!(s.lookup(100, 2), Some(&b"system_server"[..]));
+    }
+
+    #[test]
+    fn threads_share_the_latest_cmdline_of_their_process() {
+        let mut s = State::default();
+        s.record(1, 100, b"zygote64", None);
+        // written by another thread, after the zygote child specialized
+        s.record(4, 100, b"com.example.app", None);
+        assert_eq!(s.lookup(100, 3), Some(&b"zygote64"[..]));
+        assert_eq!(s.lookup(100, 5), Some(&b"com.example.app"[..]));
+        assert_eq!(s.lookup(200, 5), None);
+    }
+
+    #[test]
+    fn revisited_frames_are_not_recorded_twice() {
+        let mut s = State::default();
+        s.record(3, 100, b"a", None);
+        s.record(3, 100, b"a", None);
+        s.record(1, 100, b"b", None);
+        assert_eq!(s.processes[&100].len(), 1);
+    }
+
+    fn info(uid: u32, selinux: &str) -> ProcessInfoProtocol {
+        ProcessInfoProtocol {
+            uid,
+            selinux: selinux.as_bytes().to_vec(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn dead_packets_keep_the_uid_of_the_process_info() {
+        let mut s = State::default();
+        s.record(1, 100, b"com.example.app", Some(&info(10234, "")));
+        s.record(4, 100, b"com.example.app", None);
+        assert_eq!(s.lookup_uid(100, 5), Some(10234));
+        assert_eq!(s.lookup_uid(200, 5), None);
+    }
+
+    #[test]
+    fn selinux_domains_follow_exec() {
+        let mut s = State::default();
+        s.record(1, 100, b"zygote64", Some(&info(0, "u:r:zygote:s0")));
+        s.record(4, 100, b"com.example.app", None);
+        s.record(6, 100, b"sh", Some(&info(2000, "u:r:shell:s0")));
+        assert_eq!(s.lookup_selinux(100, 5), Some(&b"u:r:zygote:s0"[..]));
+        assert_eq!(s.lookup_selinux(100, 7), Some(&b"u:r:shell:s0"[..]));
+        // captures of kernels without SELinux leave it empty
+        s.record(2, 200, b"servicemanager", Some(&info(1000, "")));
+        assert_eq!(s.lookup_selinux(200, 3), None);
+    }
+
+    #[test]
+    fn fill_sets_both_ends_of_a_transaction() {
+        let mut s = State::default();
+        s.record(1, 100, b"com.example.app", None);
+        s.record(2, 200, b"system_server", None);
+
+        let mut event = EventProtocol {
+            pid: 100,
+            tid: 101,
+            ioctl_data: Some(IoctlProtocol {
+                bwr: Some(BinderWriteReadProtocol {
+                    transaction: Some(TransactionProtocol {
+                        to_proc: 200,
+                        to_thread: 0,
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        s.fill(3, &mut event);
+        assert_eq!(event.cmdline, b"com.example.app");
+        let txn = event
+            .ioctl_data
+            .as_ref()
+            .and_then(|ioctl| ioctl.bwr.as_ref())
+            .and_then(|bwr| bwr.transaction.as_ref())
+            .unwrap();
+        assert_eq!(txn.target_cmdline, b"system_server");
+    }
+
+    #[test]
+    fn fill_keeps_cmdlines_on_the_wire() {
+        let mut s = State::default();
+        s.record(1, 100, b"zygote64", None);
+        let mut event = EventProtocol {
+            pid: 100,
+            tid: 100,
+            cmdline: b"/system/bin/app_process64".to_vec(),
+            ..Default::default()
+        };
+        s.fill(2, &mut event);
+        assert_eq!(event.cmdline, b"/system/bin/app_process64");
+    }
+}
@@ -0,0 +1,316 @@
+// Renders the caller stacks (--stacks) as a subtree of frames, resolved against the symbol files
+// of the symbols_dir preference. The mapping and file offset of a frame come from maps_table, the
+// symbol file from the build id of the mapping (.build-id/xx/rest.debug, like a debuginfod cache)
+// or its path: the file at the same path under the directory, then one with the same name. Files
+// whose build id differs from the mapping's are skipped. .symtab is used when there is one,
+// .dynsym otherwise; names are shown as they are in the file, mangled.
+
+use crate::header_fields_manager::HeaderFieldsManager;
+use anyhow::anyhow;
+use binderdump_epan_sys::epan;
+use binderdump_structs::binder_serde::FieldOffset;
+use binderdump_structs::event_layer::{EventProtocol, MappingProtocol, ProcessMapsProtocol};
+use object::{Object, ObjectSegment, ObjectSymbol, SymbolKind};
+use std::collections::HashMap;
+use std::ffi::{c_int, CString};
+use std::path::{Path, PathBuf};
+use std::ptr::null_mut;
+use std::sync::{Arc, Mutex, OnceLock};
+
+pub const FRAME_FIELD: &str = "binderdump.ioctl_data.bwr.transaction.user_stack.frame";
+
+struct Function {
+    // file offset
+    start: u64,
+    // 0 when the symbol doesn't say
+    size: u64,
+    name: String,
+}
+
+// The functions of a file, by file offset
+struct Symbols {
+    functions: Vec<Function>,
+}
+
+impl Symbols {
+    fn new(mut functions: Vec<Function>) -> Self {
+        functions.sort_by_key(|function| function.start);
+        Self { functions }
+    }
+
+    // The function `offset` is in, and how far into it
+    fn lookup(&self, offset: u64) -> Option<(&str, u64)> {
+        let after = self
+            .functions
+            .partition_point(|function| function.start <= offset);
+        let function = self.functions[..after].last()?;
+        let delta = offset - function.start;
+        // past the end of a sized function is code without a symbol
+        if function.size != 0 && delta >= function.size {
+            return None;
+        }
+        Some((&function.name, delta))
+    }
+}
+
+#[derive(Default)]
+struct State {
+    dir: PathBuf,
+    // (path, build id) -> its functions, None when no symbol file was found for it
+    files: HashMap<(Vec<u8>, Vec<u8>), Option<Arc<Symbols>>>,
+}
+
+static STATE: OnceLock<Mutex<State>> = OnceLock::new();
+
+fn state() -> &'static Mutex<State> {
+    STATE.get_or_init(|| Mutex::new(State::default()))
+}
+
+// from the preference, when the plugin is handed off
+pub fn init(dir: &Path) {
+    if let Ok(mut s) = state().lock() {
+        s.dir = dir.to_path_buf();
+        s.files.clear();
+    }
+}
+
+fn symbols(mapping: &MappingProtocol) -> Option<Arc<Symbols>> {
+    let mut s = state().lock().ok()?;
+    let key = (mapping.path.clone(), mapping.build_id.clone());
+    if let Some(symbols) = s.files.get(&key) {
+        return symbols.clone();
+    }
+    let symbols = candidates(&s.dir, mapping)
+        .iter()
+        .find_map(|path| load(path, &mapping.build_id))
+        .map(Arc::new);
+    s.files.insert(key, symbols.clone());
+    symbols
+}
+
+fn candidates(dir: &Path, mapping: &MappingProtocol) -> Vec<PathBuf> {
+    let mut candidates = vec![];
+    if mapping.build_id.len() > 1 {
+        let hex: String = mapping
+            .build_id
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        candidates.push(
+            dir.join(".build-id")
+                .join(&hex[..2])
+                .join(format!("{}.debug", &hex[2..])),
+        );
+    }
+    let path = String::from_utf8_lossy(&mapping.path);
+    let path = path.trim_end_matches(" (deleted)");
+    candidates.push(dir.join(path.trim_start_matches('/')));
+    if let Some(name) = Path::new(path).file_name() {
+        candidates.push(dir.join(name));
+    }
+    candidates
+}
+
+fn load(path: &Path, build_id: &[u8]) -> Option<Symbols> {
+    let data = std::fs::read(path).ok()?;
+    let file = object::File::parse(&*data).ok()?;
+    if let Ok(Some(file_build_id)) = file.build_id() {
+        if !build_id.is_empty() && file_build_id != build_id {
+            return None;
+        }
+    }
+    // (address, size, file offset) of the segments, the stacks only know file offsets
+    let segments: Vec<(u64, u64, u64)> = file
+        .segments()
+        .map(|segment| (segment.address(), segment.size(), segment.file_range().0))
+        .collect();
+    let to_offset = |address: u64| {
+        segments
+            .iter()
+            .find(|(start, size, _)| (*start..start + size).contains(&address))
+            .map(|(start, _, offset)| address - start + offset)
+    };
+    let mut functions: Vec<_> = file
+        .symbols()
+        .filter_map(|symbol| function(symbol, &to_offset))
+        .collect();
+    if functions.is_empty() {
+        functions = file
+            .dynamic_symbols()
+            .filter_map(|symbol| function(symbol, &to_offset))
+            .collect();
+    }
+    Some(Symbols::new(functions))
+}
+
+fn function(
+    symbol: object::Symbol<'_, '_>,
+    to_offset: impl Fn(u64) -> Option<u64>,
+) -> Option<Function> {
+    if symbol.kind() != SymbolKind::Text || !symbol.is_definition() {
+        return None;
+    }
+    let name = symbol.name().ok().filter(|name| !name.is_empty())?;
+    Some(Function {
+        start: to_offset(symbol.address())?,
+        size: symbol.size(),
+        name: name.into(),
+    })
+}
+
+// `#1 0x7a3c24e2c0 libbinder.so+0x4d2c0 _ZN7android14IPCThreadState8transactEij...+0x1c`
+fn frame_label(
+    index: usize,
+    ip: u64,
+    maps: Option<&ProcessMapsProtocol>,
+    resolve: impl Fn(&MappingProtocol, u64) -> Option<(String, u64)>,
+) -> String {
+    let mut label = format!("#{} {:#x}", index, ip);
+    let Some((mapping, offset)) = maps.and_then(|maps| maps.find(ip)) else {
+        return label;
+    };
+    let path = String::from_utf8_lossy(&mapping.path);
+    let name = path.rsplit('/').next().unwrap_or(&path);
+    label.push_str(&format!(" {}+{:#x}", name, offset));
+    // the frames under the first are return addresses, the call is the instruction before them
+    let call = match index {
+        0 => offset,
+        _ => offset.saturating_sub(1),
+    };
+    if let Some((function, delta)) = resolve(mapping, call) {
+        label.push_str(&format!(" {}+{:#x}", function, delta + (offset - call)));
+    }
+    label
+}
+
+pub fn dissect_user_stack(
+    _hf: c_int,
+    ett: c_int,
+    manager: &HeaderFieldsManager<EventProtocol>,
+    event: &EventProtocol,
+    field: FieldOffset,
+    tvb: *mut epan::tvbuff,
+    pinfo: *mut epan::packet_info,
+    tree: *mut epan::proto_node,
+) -> anyhow::Result<()> {
+    let Some(txn) = event
+        .ioctl_data
+        .as_ref()
+        .and_then(|ioctl| ioctl.bwr.as_ref())
+        .and_then(|bwr| bwr.transaction.as_ref())
+    else {
+        return Ok(());
+    };
+    if txn.user_stack.is_empty() {
+        return Ok(());
+    }
+    let hf_frame = manager
+        .get_handle(FRAME_FIELD)
+        .ok_or_else(|| anyhow!("missing field {}", FRAME_FIELD))?;
+    let frame = unsafe { (*(*pinfo).fd).num };
+    let maps = crate::maps_table::lookup(frame, event.pid);
+    let resolve = |mapping: &MappingProtocol, offset: u64| {
+        let symbols = symbols(mapping)?;
+        let (function, delta) = symbols.lookup(offset)?;
+        Some((function.to_string(), delta))
+    };
+
+    let ips: Vec<u64> = txn.user_stack().collect();
+    let title = CString::new(format!("Stack ({} frames)", ips.len()))?;
+    let stack_tree = unsafe {
+        epan::proto_tree_add_subtree(
+            tree,
+            tvb,
+            field.offset.try_into()?,
+            field.size.try_into()?,
+            ett,
+            null_mut(),
+            title.as_ptr(),
+        )
+    };
+    for (index, ip) in ips.into_iter().enumerate() {
+        let label = CString::new(frame_label(index, ip, maps.as_deref(), resolve))?;
+        unsafe {
+            epan::proto_tree_add_string(
+                stack_tree,
+                hf_frame,
+                tvb,
+                (field.offset + index * 8).try_into()?,
+                8,
+                label.as_ptr(),
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn func(start: u64, size: u64, name: &str) -> Function {
+        Function {
+            start,
+            size,
+            name: name.into(),
+        }
+    }
+
+    fn maps() -> ProcessMapsProtocol {
+        ProcessMapsProtocol {
+            mappings: vec![MappingProtocol {
+                start: 0x7000_0000,
+                end: 0x7010_0000,
+                offset: 0x4000,
+                build_id: vec![0xab, 0xcd, 0xef],
+                path: b"/system/lib64/libbinder.so".to_vec(),
+            }],
+        }
+    }
+
+    #[test]
+    fn finds_the_function_of_an_offset() {
+        let symbols = Symbols::new(vec![
+            func(0x2000, 0x100, "transact"),
+            func(0x1000, 0, "talkWithDriver"),
+        ]);
+        assert_eq!(symbols.lookup(0x1010), Some(("talkWithDriver", 0x10)));
+        assert_eq!(symbols.lookup(0x20ff), Some(("transact", 0xff)));
+        assert_eq!(symbols.lookup(0x2100), None);
+        assert_eq!(symbols.lookup(0x10), None);
+    }
+
+    #[test]
+    fn labels_frames() {
+        let maps = maps();
+        let resolve = |mapping: &MappingProtocol, offset: u64| {
+            assert_eq!(mapping.path, b"/system/lib64/libbinder.so");
+            Some(("transact".to_string(), offset - 0x4000))
+        };
+        assert_eq!(
+            frame_label(0, 0x7000_0120, Some(&maps), resolve),
+            "#0 0x70000120 libbinder.so+0x4120 transact+0x120"
+        );
+        // a return address is looked up by the call before it, and shown as it is
+        assert_eq!(
+            frame_label(1, 0x7000_0100, Some(&maps), resolve),
+            "#1 0x70000100 libbinder.so+0x4100 transact+0x100"
+        );
+        assert_eq!(frame_label(2, 0x1234, Some(&maps), resolve), "#2 0x1234");
+        assert_eq!(frame_label(3, 0x7000_0120, None, resolve), "#3 0x70000120");
+    }
+
+    #[test]
+    fn looks_for_symbol_files_by_build_id_then_path() {
+        let dir = Path::new("/symbols");
+        let paths: Vec<_> = candidates(dir, &maps().mappings[0]);
+        assert_eq!(
+            paths,
+            [
+                PathBuf::from("/symbols/.build-id/ab/cdef.debug"),
+                PathBuf::from("/symbols/system/lib64/libbinder.so"),
+                PathBuf::from("/symbols/libbinder.so"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,211 @@
+// app id -> packages, from the UidInfo packets, and the Android names of uids (u0_a234, system,
+// ...) as `ps` prints them. the capture writes the packages of a uid once, before the first packet
+// that refers to it; the packages of an app are the same for every Android user, so they are kept
+// by app id. like process_table, a frame gets the packages of the last UidInfo packet at or before
+// it, so the first pass and a re-dissection label it the same way.
+
+use binderdump_structs::android_ids::{
+    AID_APP_END, AID_APP_START, AID_ISOLATED_END, AID_ISOLATED_START, AID_USER_OFFSET,
+};
+use binderdump_structs::event_layer::{EventProtocol, EventType};
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+
+const AID_NAMES: &[(u32, &str)] = &[
+    (0, "root"),
+    (1000, "system"),
+    (1001, "radio"),
+    (1002, "bluetooth"),
+    (1003, "graphics"),
+    (1004, "input"),
+    (1005, "audio"),
+    (1006, "camera"),
+    (1007, "log"),
+    (1010, "wifi"),
+    (1012, "install"),
+    (1013, "media"),
+    (1017, "keystore"),
+    (1018, "usb"),
+    (1019, "drm"),
+    (1021, "gps"),
+    (1027, "nfc"),
+    (1036, "logd"),
+    (1041, "audioserver"),
+    (1046, "mediacodec"),
+    (1047, "cameraserver"),
+    (1053, "webview_zygote"),
+    (1058, "tombstoned"),
+    (1066, "statsd"),
+    (1067, "incidentd"),
+    (1068, "secure_element"),
+    (1069, "lmkd"),
+    (1072, "gpu_service"),
+    (1073, "network_stack"),
+    (1076, "credstore"),
+    (1083, "uwb"),
+    (2000, "shell"),
+    (9999, "nobody"),
+];
+
+// the name `ps` shows for `uid`: the AID name of system uids, u<user>_a<n> for apps and
+// u<user>_i<n> for isolated processes
+pub fn uid_name(uid: u32) -> String {
+    let user = uid / AID_USER_OFFSET;
+    let app_id = uid % AID_USER_OFFSET;
+    if (AID_APP_START..=AID_APP_END).contains(&app_id) {
+        return format!("u{}_a{}", user, app_id - AID_APP_START);
+    }
+    if (AID_ISOLATED_START..=AID_ISOLATED_END).contains(&app_id) {
+        return format!("u{}_i{}", user, app_id - AID_ISOLATED_START);
+    }
+    let name = AID_NAMES
+        .iter()
+        .find(|(id, _)| *id == app_id)
+        .map(|(_, name)| name.to_string())
+        .unwrap_or_else(|| app_id.to_string());
+    match user {
+        0 => name,
+        _ => format!("u{}_{}", user, name),
+    }
+}
+
+// `uid_name (package)`. a shared uid (system has dozens of packages) only shows its first one
+pub fn label(uid: u32, packages: &[String]) -> String {
+    let name = uid_name(uid);
+    match packages {
+        [] => name,
+        [package] => format!("{} ({})", name, package),
+        [first, rest @ ..] => format!("{} ({}, +{} more)", name, first, rest.len()),
+    }
+}
+
+#[derive(Default)]
+struct State {
+    // app id -> (frame, packages), in frame order
+    packages: HashMap<u32, Vec<(u32, Vec<String>)>>,
+}
+
+impl State {
+    fn record(&mut self, frame: u32, uid: u32, packages: Vec<String>) {
+        let entries = self.packages.entry(uid % AID_USER_OFFSET).or_default();
+        // frames are only recorded on the first pass, in order
+        if entries.last().is_some_and(|(last, _)| *last >= frame) {
+            return;
+        }
+        entries.push((frame, packages));
+    }
+
+    fn lookup(&self, frame: u32, uid: u32) -> &[String] {
+        let Some(entries) = self.packages.get(&(uid % AID_USER_OFFSET)) else {
+            return &[];
+        };
+        let before = entries.partition_point(|(f, _)| *f <= frame);
+        entries[..before]
+            .last()
+            .map_or(&[], |(_, packages)| packages.as_slice())
+    }
+}
+
+static STATE: OnceLock<Mutex<State>> = OnceLock::new();
+
+fn state() -> &'static Mutex<State> {
+    STATE.get_or_init(|| Mutex::new(State::default()))
+}
+
+pub fn clear() {
+    if let Ok(mut s) = state().lock() {
+        s.packages.clear();
+    }
+}
+
+pub fn parse_packages(packages: &[u8]) -> Vec<String> {
+    crate::binderdump::cmdline_to_string(packages)
+        .split(',')
+        .filter(|package| !package.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+// first pass only
+pub fn record(frame: u32, event: &EventProtocol) {
+    if event.event_type != EventType::UidInfo {
+        return;
+    }
+    let Some(info) = event.uid_info.as_ref() else {
+        return;
+    };
+    let Ok(mut s) = state().lock() else { return };
+    s.record(frame, info.uid, parse_packages(&info.packages));
+}
+
+// the packages of `uid` at `frame`
+pub fn packages(frame: u32, uid: u32) -> Vec<String> {
+    let Ok(s) = state().lock() else {
+        return Vec::new();
+    };
+    s.lookup(frame, uid).to_vec()
+}
+
+pub fn user_label(frame: u32, uid: u32) -> String {
+    label(uid, &packages(frame, uid))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_follow_android() {
+        assert_eq!(uid_name(0), "root");
+        assert_eq!(uid_name(1000), "system");
+        assert_eq!(uid_name(2000), "shell");
+        assert_eq!(uid_name(10234), "u0_a234");
+        assert_eq!(uid_name(1010234), "u10_a234");
+        assert_eq!(uid_name(99012), "u0_i9012");
+        assert_eq!(uid_name(1001000), "u10_system");
+        assert_eq!(uid_name(1999), "1999");
+    }
+
+    #[test]
+    fn labels_show_the_packages() {
+        assert_eq!(
+            label(10234, &["com.example.app".to_string()]),
+            "u0_a234 (com.example.app)"
+        );
+        assert_eq!(
+            label(
+                1001,
+                &[
+                    "com.android.phone".to_string(),
+                    "com.android.stk".to_string()
+                ]
+            ),
+            "radio (com.android.phone, +1 more)"
+        );
+        assert_eq!(label(1000, &[]), "system");
+    }
+
+    #[test]
+    fn frames_before_the_uid_info_have_no_packages() {
+        let mut s = State::default();
+        s.record(4, 10234, vec!["com.example.app".to_string()]);
+        s.record(9, 10234, vec!["com.example.other".to_string()]);
+        assert!(s.lookup(2, 10234).is_empty());
+        assert_eq!(s.lookup(4, 10234), ["com.example.app"]);
+        // every Android user of the app
+        assert_eq!(s.lookup(7, 1010234), ["com.example.app"]);
+        assert_eq!(s.lookup(12, 10234), ["com.example.other"]);
+        // revisited frames are not recorded twice
+        s.record(4, 10234, vec![]);
+        assert_eq!(s.packages[&10234].len(), 2);
+    }
+
+    #[test]
+    fn packages_are_comma_separated() {
+        assert_eq!(
+            parse_packages(b"com.android.phone,com.android.stk"),
+            ["com.android.phone", "com.android.stk"]
+        );
+        assert!(parse_packages(b"").is_empty());
+    }
+}
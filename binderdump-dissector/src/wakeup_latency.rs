@@ -0,0 +1,55 @@
+// Splits the time a transaction took to reach its receiving thread into waiting for a thread to be
+// woken and waiting for a CPU, from the timestamps --wakeup-latency records. What remains up to
+// the reply is the work itself, see binderdump_reply.response_time.
+
+use crate::epan_utils::add_generated_time;
+use crate::header_fields_manager::HeaderFieldsManager;
+use binderdump_epan_sys::epan;
+use binderdump_structs::event_layer::EventProtocol;
+use std::ffi::c_int;
+
+fn nstime(ns: u64) -> epan::nstime_t {
+    epan::nstime_t {
+        secs: (ns / 1_000_000_000) as _,
+        nsecs: (ns % 1_000_000_000) as c_int,
+    }
+}
+
+pub fn emit(
+    manager: &HeaderFieldsManager<EventProtocol>,
+    event: &EventProtocol,
+    tree: *mut epan::proto_node,
+    tvb: *mut epan::tvbuff_t,
+) {
+    let Some(wakeup) = event
+        .ioctl_data
+        .as_ref()
+        .and_then(|ioctl| ioctl.bwr.as_ref())
+        .and_then(|bwr| bwr.transaction.as_ref())
+        .and_then(|txn| txn.wakeup.as_ref())
+    else {
+        return;
+    };
+    let (Some(queued), Some(runnable)) = (
+        manager.get_handle("binderdump.wakeup.queued"),
+        manager.get_handle("binderdump.wakeup.runnable"),
+    ) else {
+        return;
+    };
+    unsafe {
+        add_generated_time(tree, queued, tvb, &nstime(wakeup.queued_ns()));
+        add_generated_time(tree, runnable, tvb, &nstime(wakeup.runnable_ns()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_seconds_and_nanoseconds() {
+        let time = nstime(2_000_000_500);
+        assert_eq!(time.secs, 2);
+        assert_eq!(time.nsecs, 500);
+    }
+}
@@ -0,0 +1,7 @@
+// Copied from android_filesystem_config.h. A uid is user * AID_USER_OFFSET + app id, and the app id
+// of an app is the same for every Android user.
+pub const AID_USER_OFFSET: u32 = 100000;
+pub const AID_APP_START: u32 = 10000;
+pub const AID_APP_END: u32 = 19999;
+pub const AID_ISOLATED_START: u32 = 90000;
+pub const AID_ISOLATED_END: u32 = 99999;
@@ -46,6 +46,21 @@ impl TryFrom<PathBuf> for BinderInterface {
     }
 }
 
+impl BinderInterface {
+    // Which protocol a binder device carries, by its name. Devices created by containers and
+    // custom binderfs instances are named after the stock ones (anbox-hwbinder, ...), anything
+    // else is assumed to carry AIDL.
+    pub fn from_device_name(name: &str) -> Self {
+        if name.ends_with("hwbinder") {
+            BinderInterface::HWBINDER
+        } else if name.ends_with("vndbinder") {
+            BinderInterface::VNDBINDER
+        } else {
+            BinderInterface::BINDER
+        }
+    }
+}
+
 #[derive(Debug, FromPrimitive, EpanProtocolEnum)]
 #[allow(non_camel_case_types)]
 #[repr(u32)]
@@ -233,4 +248,24 @@ mod tests {
     fn rejects_non_binder_paths() {
         assert!(iface("/dev/null").is_err());
     }
+
+    #[test]
+    fn classifies_custom_device_names() {
+        assert!(matches!(
+            BinderInterface::from_device_name("anbox-hwbinder"),
+            BinderInterface::HWBINDER
+        ));
+        assert!(matches!(
+            BinderInterface::from_device_name("anbox-vndbinder"),
+            BinderInterface::VNDBINDER
+        ));
+        assert!(matches!(
+            BinderInterface::from_device_name("anbox-binder"),
+            BinderInterface::BINDER
+        ));
+        assert!(matches!(
+            BinderInterface::from_device_name("mydevice"),
+            BinderInterface::BINDER
+        ));
+    }
 }
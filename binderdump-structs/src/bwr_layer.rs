@@ -1,4 +1,5 @@
 use binderdump_derive::{EpanProtocol, EpanProtocolEnum};
+use num_derive::FromPrimitive;
 use serde::{Deserialize, Serialize};
 use serde_repr::{Deserialize_repr, Serialize_repr};
 
@@ -107,6 +108,55 @@ pub struct TransactionProtocol {
     // to avoid duplicate Wireshark fields.
     #[epan(skip)]
     pub ptr_payloads: Vec<PtrPayload>,
+
+    // Real sizes of `data` and `offsets`, which only hold the first bytes of them when captured
+    // with --snaplen or --metadata-only
+    pub data_size: u64,
+    pub offsets_size: u64,
+
+    // The binder objects the kernel translated for the target, only on the receiving side: the
+    // translations are traced after the sender's packet was written
+    pub translations: Vec<TranslationProtocol>,
+
+    // The fds of the FD and FDA objects with the file behind them, only on the receiving side like
+    // the translations
+    pub fds: Vec<FdProtocol>,
+
+    // The SELinux context of the sender, which the kernel passes with BR_TRANSACTION_SEC_CTX to
+    // the nodes that asked for it. Empty on the sending side and for other transactions.
+    #[epan(display = StrAsciis, ftype = String)]
+    pub secctx: Vec<u8>,
+
+    // The scheduling class of the caller when it sent the transaction, only on the receiving side
+    // like the translations. Needs kernel BTF.
+    pub caller_prio: Option<SchedPrioProtocol>,
+
+    // The last priority binder gave the receiving thread before it read the transaction, only on
+    // kernels with the binder_set_priority tracepoint (Android common kernels) and only when it
+    // changed
+    pub inherited_prio: Option<InheritedPrioProtocol>,
+
+    // When the receiving thread was woken for the transaction and when it got on a CPU, only on
+    // the receiving side and with --wakeup-latency
+    pub wakeup: Option<WakeupProtocol>,
+
+    // The return addresses of the caller's user stack when it sent the transaction, innermost
+    // first, as u64 LE. Only on the sending side of BC_TRANSACTION and with --stacks; the
+    // process's ProcessMaps packet tells which file each one is in.
+    #[epan(display = SepSpace)]
+    pub user_stack: Vec<u8>,
+}
+
+impl TransactionProtocol {
+    pub fn is_truncated(&self) -> bool {
+        (self.data.len() as u64) < self.data_size || (self.offsets.len() as u64) < self.offsets_size
+    }
+
+    pub fn user_stack(&self) -> impl Iterator<Item = u64> + '_ {
+        self.user_stack
+            .chunks_exact(8)
+            .map(|ip| u64::from_le_bytes(ip.try_into().unwrap()))
+    }
 }
 
 #[derive(Default, Serialize, Deserialize, EpanProtocol, Debug)]
@@ -119,6 +169,119 @@ pub struct PtrPayload {
     pub data: Vec<u8>,
 }
 
+#[repr(u8)]
+#[derive(
+    Default, Clone, Copy, Eq, PartialEq, Serialize_repr, Deserialize_repr, EpanProtocolEnum, Debug,
+)]
+pub enum TranslationType {
+    // a local binder sent to another process, which gets a handle to it
+    #[default]
+    NodeToRef = 0,
+    // a handle sent back to the process that owns the node
+    RefToNode,
+    // a handle sent to a third process
+    RefToRef,
+}
+
+#[derive(Default, Clone, Serialize, Deserialize, EpanProtocol, Debug)]
+pub struct TranslationProtocol {
+    pub translation_type: TranslationType,
+    pub node_debug_id: i32,
+    // the process that owns the node, 0 when the capture never saw it cross
+    pub owner_pid: i32,
+    // the binder ptr (NodeToRef) or the handle in the sender
+    #[epan(display = Hex)]
+    pub src: u64,
+    // the handle (NodeToRef, RefToRef) or the binder ptr in the target
+    #[epan(display = Hex)]
+    pub dest: u64,
+}
+
+#[derive(Default, Clone, Serialize, Deserialize, EpanProtocol, Debug)]
+pub struct FdProtocol {
+    // where the fd is in the transaction buffer, the same in the sender and the target
+    #[epan(display = Hex)]
+    pub offset: u64,
+    // -1 when the capture missed that side
+    pub sender_fd: i32,
+    pub receiver_fd: i32,
+    // as /proc/<pid>/fd shows it: a path, memfd:<name>, socket:[ino], pipe:[ino], dmabuf:[ino],
+    // anon_inode:<type>. empty when the kernel has no BTF or the send was missed
+    #[epan(display = StrAsciis, ftype = String)]
+    pub path: Vec<u8>,
+}
+
+// The scheduling policies of sched_setscheduler(2)
+#[repr(u8)]
+#[derive(
+    Default,
+    Clone,
+    Copy,
+    Eq,
+    PartialEq,
+    Serialize_repr,
+    Deserialize_repr,
+    EpanProtocolEnum,
+    FromPrimitive,
+    Debug,
+)]
+#[allow(non_camel_case_types)]
+pub enum SchedPolicy {
+    #[default]
+    SCHED_NORMAL = 0,
+    SCHED_FIFO = 1,
+    SCHED_RR = 2,
+    SCHED_BATCH = 3,
+    SCHED_IDLE = 5,
+    SCHED_DEADLINE = 6,
+}
+
+#[derive(Default, Clone, Serialize, Deserialize, EpanProtocol, Debug)]
+pub struct SchedPrioProtocol {
+    pub policy: SchedPolicy,
+    // the kernel's: 0-99 for the real-time policies, 100-139 for nice -20..19. lower is more urgent
+    pub prio: u32,
+}
+
+#[derive(Default, Clone, Serialize, Deserialize, EpanProtocol, Debug)]
+pub struct InheritedPrioProtocol {
+    pub old_prio: u32,
+    // the caller's, or the minimum priority of the node when that is more urgent
+    pub desired_prio: u32,
+    // what the thread got, less urgent than desired_prio when binder couldn't raise it that far
+    pub new_prio: u32,
+}
+
+impl InheritedPrioProtocol {
+    // the receiving thread ran less urgently than the caller
+    pub fn is_below(&self, caller: &SchedPrioProtocol) -> bool {
+        self.new_prio > caller.prio
+    }
+}
+
+// CLOCK_BOOTTIME ns, like EventProtocol::timestamp
+#[derive(Default, Clone, Serialize, Deserialize, EpanProtocol, Debug)]
+pub struct WakeupProtocol {
+    // the caller's BinderTransaction
+    pub enqueued: u64,
+    // sched_waking of the receiving thread
+    pub woken: u64,
+    // the sched_switch to the receiving thread, the same as woken when it never left the CPU
+    pub on_cpu: u64,
+}
+
+impl WakeupProtocol {
+    // how long the transaction waited for a thread to be woken
+    pub fn queued_ns(&self) -> u64 {
+        self.woken.saturating_sub(self.enqueued)
+    }
+
+    // how long the woken thread waited for a CPU
+    pub fn runnable_ns(&self) -> u64 {
+        self.on_cpu.saturating_sub(self.woken)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -175,4 +338,136 @@ mod tests {
         assert!(decoded.is_read());
         assert!(decoded.is_transaction());
     }
+
+    #[test]
+    fn transaction_truncation_follows_real_sizes() {
+        let mut txn = TransactionProtocol {
+            data: vec![0; 8],
+            data_size: 8,
+            ..Default::default()
+        };
+        assert!(!txn.is_truncated());
+        txn.offsets_size = 16;
+        assert!(txn.is_truncated());
+    }
+
+    #[test]
+    fn translations_round_trip() {
+        let txn = TransactionProtocol {
+            debug_id: 42,
+            translations: vec![
+                TranslationProtocol {
+                    translation_type: TranslationType::NodeToRef,
+                    node_debug_id: 7,
+                    owner_pid: 100,
+                    src: 0x7fff_0000_1000,
+                    dest: 3,
+                },
+                TranslationProtocol {
+                    translation_type: TranslationType::RefToRef,
+                    node_debug_id: 9,
+                    owner_pid: 200,
+                    src: 5,
+                    dest: 12,
+                },
+            ],
+            ..Default::default()
+        };
+        let bytes = to_bytes(&txn).unwrap();
+        let decoded: TransactionProtocol = from_bytes(&bytes).unwrap();
+        assert_eq!(bytes, to_bytes(&decoded).unwrap());
+        assert_eq!(decoded.translations.len(), 2);
+        assert_eq!(
+            decoded.translations[1].translation_type,
+            TranslationType::RefToRef
+        );
+    }
+
+    #[test]
+    fn fds_round_trip() {
+        let txn = TransactionProtocol {
+            debug_id: 42,
+            fds: vec![FdProtocol {
+                offset: 0x68,
+                sender_fd: 12,
+                receiver_fd: 87,
+                path: b"memfd:jit-cache".to_vec(),
+            }],
+            ..Default::default()
+        };
+        let bytes = to_bytes(&txn).unwrap();
+        let decoded: TransactionProtocol = from_bytes(&bytes).unwrap();
+        assert_eq!(bytes, to_bytes(&decoded).unwrap());
+        assert_eq!(decoded.fds[0].receiver_fd, 87);
+        assert_eq!(decoded.fds[0].path, b"memfd:jit-cache");
+    }
+
+    #[test]
+    fn secctx_round_trips() {
+        let txn = TransactionProtocol {
+            debug_id: 42,
+            secctx: b"u:r:system_server:s0".to_vec(),
+            ..Default::default()
+        };
+        let bytes = to_bytes(&txn).unwrap();
+        let decoded: TransactionProtocol = from_bytes(&bytes).unwrap();
+        assert_eq!(bytes, to_bytes(&decoded).unwrap());
+        assert_eq!(decoded.secctx, b"u:r:system_server:s0");
+    }
+
+    #[test]
+    fn priorities_round_trip() {
+        let txn = TransactionProtocol {
+            debug_id: 42,
+            caller_prio: Some(SchedPrioProtocol {
+                policy: SchedPolicy::SCHED_FIFO,
+                prio: 97,
+            }),
+            inherited_prio: Some(InheritedPrioProtocol {
+                old_prio: 120,
+                desired_prio: 97,
+                new_prio: 120,
+            }),
+            ..Default::default()
+        };
+        let bytes = to_bytes(&txn).unwrap();
+        let decoded: TransactionProtocol = from_bytes(&bytes).unwrap();
+        assert_eq!(bytes, to_bytes(&decoded).unwrap());
+        let caller = decoded.caller_prio.unwrap();
+        assert_eq!(caller.policy, SchedPolicy::SCHED_FIFO);
+        assert!(decoded.inherited_prio.unwrap().is_below(&caller));
+    }
+
+    #[test]
+    fn wakeup_round_trips() {
+        let txn = TransactionProtocol {
+            debug_id: 42,
+            wakeup: Some(WakeupProtocol {
+                enqueued: 1_000,
+                woken: 1_500,
+                on_cpu: 3_500,
+            }),
+            ..Default::default()
+        };
+        let bytes = to_bytes(&txn).unwrap();
+        let decoded: TransactionProtocol = from_bytes(&bytes).unwrap();
+        assert_eq!(bytes, to_bytes(&decoded).unwrap());
+        let wakeup = decoded.wakeup.unwrap();
+        assert_eq!(wakeup.queued_ns(), 500);
+        assert_eq!(wakeup.runnable_ns(), 2_000);
+    }
+
+    #[test]
+    fn user_stack_round_trips() {
+        let ips = [0x7a_1234_5678u64, 0x7a_1234_9abc];
+        let txn = TransactionProtocol {
+            debug_id: 42,
+            user_stack: ips.iter().flat_map(|ip| ip.to_le_bytes()).collect(),
+            ..Default::default()
+        };
+        let bytes = to_bytes(&txn).unwrap();
+        let decoded: TransactionProtocol = from_bytes(&bytes).unwrap();
+        assert_eq!(bytes, to_bytes(&decoded).unwrap());
+        assert_eq!(decoded.user_stack().collect::<Vec<_>>(), ips);
+    }
 }
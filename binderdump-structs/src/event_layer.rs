@@ -1,4 +1,5 @@
 use super::bwr_layer::BinderWriteReadProtocol;
+use super::ioctl_layer::IoctlArgProtocol;
 use crate::binder_types::{binder_ioctl, BinderInterface};
 use binderdump_derive::{EpanProtocol, EpanProtocolEnum};
 use serde::{Deserialize, Serialize};
@@ -13,6 +14,19 @@ pub enum EventType {
     DeadThread = 3,
     #[default]
     Invalid = 4,
+    // an ioctl that was still in flight when the capture stopped, the rest of it was never seen
+    TruncatedIoctl = 5,
+    // the metadata of a thread, written before the first packet that refers to it. The other
+    // packets leave their cmdline empty, see ProcessInfoProtocol.
+    ProcessInfo = 6,
+    // the packages of an app uid, written before the first packet that refers to the uid
+    UidInfo = 7,
+    // a process created by fork, see NewProcessProtocol
+    NewProcess = 8,
+    // a process that called execve, see ExecProtocol
+    Exec = 9,
+    // the executable mappings of a process, written before the first stack of it (--stacks)
+    ProcessMaps = 10,
 }
 
 #[derive(Default, Serialize, Deserialize, EpanProtocol, Debug)]
@@ -28,6 +42,24 @@ pub struct EventProtocol {
     #[epan(display = StrAsciis, ftype = String)]
     pub cmdline: Vec<u8>,
     pub ioctl_data: Option<IoctlProtocol>,
+    // the pcapng interface (binder device) of the packet. `binder_interface` is the protocol that
+    // device carries, so custom devices are still decoded as AIDL or HIDL.
+    pub interface_id: u32,
+    // the ioctl was already blocked in binder_thread_read when the capture started, so only its
+    // read half was seen, and its transaction only has what the receiving side knows about it
+    pub partial: bool,
+    // only on ProcessInfo, DeadProcess, DeadThread, NewProcess and Exec packets, which also carry
+    // the cmdline of the thread. The dissector fills the cmdline of every other packet of the thread (and
+    // target_cmdline of the transactions sent to it) from the last of these.
+    pub process_info: Option<ProcessInfoProtocol>,
+    // only on UidInfo packets
+    pub uid_info: Option<UidInfoProtocol>,
+    // only on NewProcess packets
+    pub new_process: Option<NewProcessProtocol>,
+    // only on Exec packets
+    pub exec: Option<ExecProtocol>,
+    // only on ProcessMaps packets
+    pub maps: Option<ProcessMapsProtocol>,
 }
 
 impl EventProtocol {
@@ -52,6 +84,13 @@ impl EventProtocol {
             android_sdk,
             cmdline,
             ioctl_data,
+            interface_id: 0,
+            partial: false,
+            process_info: None,
+            uid_info: None,
+            new_process: None,
+            exec: None,
+            maps: None,
         }
     }
 
@@ -66,6 +105,82 @@ impl EventProtocol {
     pub fn android_sdk(&self) -> u32 {
         self.android_sdk
     }
+
+    pub fn interface_id(&self) -> u32 {
+        self.interface_id
+    }
+}
+
+#[derive(Default, Serialize, Deserialize, EpanProtocol, Debug)]
+pub struct ProcessInfoProtocol {
+    pub uid: u32,
+    // CLOCK_BOOTTIME nanoseconds, so it can be compared with the packet timestamps
+    pub start_time: u64,
+    #[epan(display = StrAsciis, ftype = String)]
+    pub package: Vec<u8>,
+    // the SELinux domain of the process, from /proc/<pid>/attr/current
+    #[epan(display = StrAsciis, ftype = String)]
+    pub selinux: Vec<u8>,
+}
+
+#[derive(Default, Serialize, Deserialize, EpanProtocol, Debug)]
+pub struct UidInfoProtocol {
+    pub uid: u32,
+    // comma separated, several packages share a uid with android:sharedUserId
+    #[epan(display = StrAsciis, ftype = String)]
+    pub packages: Vec<u8>,
+}
+
+// The packet's pid/tid are the child's, its cmdline and process_info are inherited from the parent
+// when the capture knows it
+#[derive(Default, Serialize, Deserialize, EpanProtocol, Debug)]
+pub struct NewProcessProtocol {
+    pub parent_pid: i32,
+    pub parent_tid: i32,
+}
+
+// The packet's pid/tid are after the exec, and its cmdline is argv[0] (or the filename)
+#[derive(Default, Serialize, Deserialize, EpanProtocol, Debug)]
+pub struct ExecProtocol {
+    // differs from the tid when a thread other than the main one called execve
+    pub old_tid: i32,
+    #[epan(display = StrAsciis, ftype = String)]
+    pub filename: Vec<u8>,
+    // space separated, only the first arguments, each cut short, are captured
+    #[epan(display = StrAsciis, ftype = String)]
+    pub argv: Vec<u8>,
+}
+
+// /proc/<pid>/maps, the mappings that can hold code. The packet's tid is the pid.
+#[derive(Default, Clone, Serialize, Deserialize, EpanProtocol, Debug)]
+pub struct ProcessMapsProtocol {
+    pub mappings: Vec<MappingProtocol>,
+}
+
+#[derive(Default, Clone, Serialize, Deserialize, EpanProtocol, Debug)]
+pub struct MappingProtocol {
+    #[epan(display = Hex)]
+    pub start: u64,
+    #[epan(display = Hex)]
+    pub end: u64,
+    // in the file
+    #[epan(display = Hex)]
+    pub offset: u64,
+    // NT_GNU_BUILD_ID of the file, empty when it has none
+    pub build_id: Vec<u8>,
+    #[epan(display = StrAsciis, ftype = String)]
+    pub path: Vec<u8>,
+}
+
+impl ProcessMapsProtocol {
+    // The mapping `ip` is in, and where in its file
+    pub fn find(&self, ip: u64) -> Option<(&MappingProtocol, u64)> {
+        let mapping = self
+            .mappings
+            .iter()
+            .find(|mapping| (mapping.start..mapping.end).contains(&ip))?;
+        Some((mapping, ip - mapping.start + mapping.offset))
+    }
 }
 
 #[derive(Default, Serialize, Deserialize, EpanProtocol, Debug)]
@@ -80,6 +195,10 @@ pub struct IoctlProtocol {
     pub ioctl_id: u64,
     pub read_only: bool,
     pub bwr: Option<BinderWriteReadProtocol>,
+    // The struct `arg` points to, for the ioctls other than BINDER_WRITE_READ that take one: as
+    // the thread passed it, and as the kernel left it when the ioctl returned
+    pub arg_in: Option<IoctlArgProtocol>,
+    pub arg_out: Option<IoctlArgProtocol>,
 }
 
 impl IoctlProtocol {
@@ -104,6 +223,8 @@ impl IoctlProtocol {
             ioctl_id,
             read_only,
             bwr,
+            arg_in: None,
+            arg_out: None,
         }
     }
 
@@ -197,4 +318,153 @@ mod tests {
         assert_eq!(bytes, to_bytes(&decoded).unwrap());
         assert!(decoded.ioctl_data.is_none());
     }
+
+    #[test]
+    fn interface_id_is_appended_to_the_wire_format() {
+        let mut event = EventProtocol::new(
+            1,
+            2,
+            3,
+            comm(b"waydroid"),
+            EventType::FinishedIoctl,
+            BinderInterface::HWBINDER,
+            30,
+            Vec::new(),
+            None,
+        );
+        let before = to_bytes(&event).unwrap();
+        event.interface_id = 4;
+        let bytes = to_bytes(&event).unwrap();
+        // only the u32 changes, the other fields keep their offsets
+        let at = before.iter().zip(&bytes).position(|(a, b)| a != b).unwrap();
+        assert_eq!(bytes[at..at + 4], 4u32.to_le_bytes());
+        assert_eq!(bytes[..at], before[..at]);
+        assert_eq!(bytes[at + 4..], before[at + 4..]);
+        let decoded: EventProtocol = from_bytes(&bytes).unwrap();
+        assert_eq!(decoded.interface_id(), 4);
+    }
+
+    #[test]
+    fn truncated_ioctl_keeps_its_wire_value() {
+        let bytes = to_bytes(&EventType::TruncatedIoctl).unwrap();
+        assert_eq!(bytes, [5]);
+        let decoded: EventType = from_bytes(&bytes).unwrap();
+        assert_eq!(decoded, EventType::TruncatedIoctl);
+    }
+
+    #[test]
+    fn process_info_round_trips() {
+        let mut event = EventProtocol::new(
+            1,
+            2,
+            2,
+            comm(b"app_process64"),
+            EventType::ProcessInfo,
+            BinderInterface::BINDER,
+            34,
+            b"com.example.app".to_vec(),
+            None,
+        );
+        event.process_info = Some(ProcessInfoProtocol {
+            uid: 10234,
+            start_time: 5_000_000_000,
+            package: b"com.example.app".to_vec(),
+            selinux: b"u:r:untrusted_app:s0:c234,c256,c512,c768".to_vec(),
+        });
+        let bytes = to_bytes(&event).unwrap();
+        let decoded: EventProtocol = from_bytes(&bytes).unwrap();
+        assert_eq!(bytes, to_bytes(&decoded).unwrap());
+        assert_eq!(decoded.event_type, EventType::ProcessInfo);
+        assert_eq!(decoded.process_info.as_ref().unwrap().uid, 10234);
+        assert_eq!(
+            decoded.process_info.as_ref().unwrap().selinux,
+            b"u:r:untrusted_app:s0:c234,c256,c512,c768"
+        );
+        assert_eq!(to_bytes(&EventType::ProcessInfo).unwrap(), [6]);
+    }
+
+    #[test]
+    fn uid_info_round_trips() {
+        let mut event = EventProtocol::new(
+            1,
+            0,
+            0,
+            [0; 16],
+            EventType::UidInfo,
+            BinderInterface::BINDER,
+            34,
+            Vec::new(),
+            None,
+        );
+        event.uid_info = Some(UidInfoProtocol {
+            uid: 10057,
+            packages: b"com.android.phone,com.android.stk".to_vec(),
+        });
+        let bytes = to_bytes(&event).unwrap();
+        let decoded: EventProtocol = from_bytes(&bytes).unwrap();
+        assert_eq!(bytes, to_bytes(&decoded).unwrap());
+        assert_eq!(decoded.event_type, EventType::UidInfo);
+        assert_eq!(decoded.uid_info.as_ref().unwrap().uid, 10057);
+    }
+
+    #[test]
+    fn exec_round_trips() {
+        let mut event = EventProtocol::new(
+            1,
+            300,
+            300,
+            [0; 16],
+            EventType::Exec,
+            BinderInterface::BINDER,
+            34,
+            b"service".to_vec(),
+            None,
+        );
+        event.exec = Some(ExecProtocol {
+            old_tid: 301,
+            filename: b"/system/bin/service".to_vec(),
+            argv: b"service call activity 1".to_vec(),
+        });
+        let bytes = to_bytes(&event).unwrap();
+        let decoded: EventProtocol = from_bytes(&bytes).unwrap();
+        assert_eq!(bytes, to_bytes(&decoded).unwrap());
+        assert_eq!(decoded.event_type, EventType::Exec);
+        assert_eq!(decoded.exec.as_ref().unwrap().old_tid, 301);
+        assert!(decoded.new_process.is_none());
+        assert_eq!(to_bytes(&EventType::Exec).unwrap(), [9]);
+    }
+
+    #[test]
+    fn process_maps_round_trip() {
+        let mut event = EventProtocol::new(
+            1,
+            300,
+            300,
+            [0; 16],
+            EventType::ProcessMaps,
+            BinderInterface::BINDER,
+            34,
+            Vec::new(),
+            None,
+        );
+        event.maps = Some(ProcessMapsProtocol {
+            mappings: vec![MappingProtocol {
+                start: 0x7000_0000,
+                end: 0x7001_0000,
+                offset: 0x4000,
+                build_id: vec![0xde, 0xad, 0xbe, 0xef],
+                path: b"/system/lib64/libbinder.so".to_vec(),
+            }],
+        });
+        let bytes = to_bytes(&event).unwrap();
+        let decoded: EventProtocol = from_bytes(&bytes).unwrap();
+        assert_eq!(bytes, to_bytes(&decoded).unwrap());
+        assert_eq!(to_bytes(&EventType::ProcessMaps).unwrap(), [10]);
+
+        let maps = decoded.maps.unwrap();
+        let (mapping, offset) = maps.find(0x7000_0120).unwrap();
+        assert_eq!(mapping.path, b"/system/lib64/libbinder.so");
+        assert_eq!(offset, 0x4120);
+        assert!(maps.find(0x7001_0000).is_none());
+    }
 }
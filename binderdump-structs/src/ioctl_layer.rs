@@ -0,0 +1,206 @@
+// The arguments of the binder ioctls other than BINDER_WRITE_READ. BPF copies the struct `arg`
+// points to when the ioctl starts and again when it returns, as raw bytes; `IoctlArgProtocol::parse`
+// lays them out by the ioctl's cmd.
+use crate::binder_types::binder_ioctl;
+use binderdump_derive::EpanProtocol;
+use serde::{Deserialize, Serialize};
+
+// Only the member of the ioctl's cmd is set
+#[derive(Default, Serialize, Deserialize, EpanProtocol, Debug, Clone, PartialEq, Eq)]
+pub struct IoctlArgProtocol {
+    // BINDER_VERSION
+    pub protocol_version: Option<i32>,
+    // BINDER_SET_MAX_THREADS
+    pub max_threads: Option<u32>,
+    // BINDER_SET_CONTEXT_MGR_EXT
+    pub context_mgr: Option<ContextMgrProtocol>,
+    // BINDER_GET_NODE_DEBUG_INFO
+    pub node_debug_info: Option<NodeDebugInfoProtocol>,
+    // BINDER_GET_NODE_INFO_FOR_REF
+    pub node_info_for_ref: Option<NodeInfoForRefProtocol>,
+    // BINDER_FREEZE
+    pub freeze: Option<FreezeProtocol>,
+    // BINDER_GET_FROZEN_INFO
+    pub frozen_info: Option<FrozenInfoProtocol>,
+    // BINDER_ENABLE_ONEWAY_SPAM_DETECTION
+    pub oneway_spam_detection: Option<bool>,
+    // BINDER_GET_EXTENDED_ERROR
+    pub extended_error: Option<ExtendedErrorProtocol>,
+}
+
+// struct flat_binder_object, the node of the context manager
+#[derive(Default, Serialize, Deserialize, EpanProtocol, Debug, Clone, PartialEq, Eq)]
+pub struct ContextMgrProtocol {
+    #[epan(display = Hex)]
+    pub binder_type: u32,
+    #[epan(display = Hex)]
+    pub flags: u32,
+    #[epan(display = Hex)]
+    pub binder: u64,
+    #[epan(display = Hex)]
+    pub cookie: u64,
+}
+
+// struct binder_node_debug_info. The thread passes the ptr of the node before the one it wants
+// (0 for the first), the kernel answers with the next node of the process.
+#[derive(Default, Serialize, Deserialize, EpanProtocol, Debug, Clone, PartialEq, Eq)]
+pub struct NodeDebugInfoProtocol {
+    #[epan(display = Hex)]
+    pub ptr: u64,
+    #[epan(display = Hex)]
+    pub cookie: u64,
+    pub has_strong_ref: u32,
+    pub has_weak_ref: u32,
+}
+
+// struct binder_node_info_for_ref, only the context manager may ask
+#[derive(Default, Serialize, Deserialize, EpanProtocol, Debug, Clone, PartialEq, Eq)]
+pub struct NodeInfoForRefProtocol {
+    #[epan(display = Hex)]
+    pub handle: u32,
+    pub strong_count: u32,
+    pub weak_count: u32,
+}
+
+// struct binder_freeze_info
+#[derive(Default, Serialize, Deserialize, EpanProtocol, Debug, Clone, PartialEq, Eq)]
+pub struct FreezeProtocol {
+    pub pid: u32,
+    pub enable: u32,
+    pub timeout_ms: u32,
+}
+
+// struct binder_frozen_status_info
+#[derive(Default, Serialize, Deserialize, EpanProtocol, Debug, Clone, PartialEq, Eq)]
+pub struct FrozenInfoProtocol {
+    pub pid: u32,
+    // bit 0: received sync transactions while frozen, bit 1: has transactions pending
+    #[epan(display = Hex)]
+    pub sync_recv: u32,
+    pub async_recv: u32,
+}
+
+// struct binder_extended_error: why the last transaction of the thread failed
+#[derive(Default, Serialize, Deserialize, EpanProtocol, Debug, Clone, PartialEq, Eq)]
+pub struct ExtendedErrorProtocol {
+    // debug id of the failed transaction
+    pub id: u32,
+    // the BR_ the thread got for it, BR_OK when there was no error
+    #[epan(display = Hex)]
+    pub command: u32,
+    // a negative errno
+    pub param: i32,
+}
+
+fn u32_at(data: &[u8], pos: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(data.get(pos..pos + 4)?.try_into().ok()?))
+}
+
+fn u64_at(data: &[u8], pos: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(data.get(pos..pos + 8)?.try_into().ok()?))
+}
+
+impl IoctlArgProtocol {
+    // None for the ioctls without a struct argument and for short reads
+    pub fn parse(cmd: binder_ioctl, data: &[u8]) -> Option<Self> {
+        let mut arg = Self::default();
+        match cmd {
+            binder_ioctl::BINDER_VERSION => arg.protocol_version = Some(u32_at(data, 0)? as i32),
+            binder_ioctl::BINDER_SET_MAX_THREADS => arg.max_threads = Some(u32_at(data, 0)?),
+            binder_ioctl::BINDER_SET_CONTEXT_MGR_EXT => {
+                arg.context_mgr = Some(ContextMgrProtocol {
+                    binder_type: u32_at(data, 0)?,
+                    flags: u32_at(data, 4)?,
+                    binder: u64_at(data, 8)?,
+                    cookie: u64_at(data, 16)?,
+                })
+            }
+            binder_ioctl::BINDER_GET_NODE_DEBUG_INFO => {
+                arg.node_debug_info = Some(NodeDebugInfoProtocol {
+                    ptr: u64_at(data, 0)?,
+                    cookie: u64_at(data, 8)?,
+                    has_strong_ref: u32_at(data, 16)?,
+                    has_weak_ref: u32_at(data, 20)?,
+                })
+            }
+            binder_ioctl::BINDER_GET_NODE_INFO_FOR_REF => {
+                arg.node_info_for_ref = Some(NodeInfoForRefProtocol {
+                    handle: u32_at(data, 0)?,
+                    strong_count: u32_at(data, 4)?,
+                    weak_count: u32_at(data, 8)?,
+                })
+            }
+            binder_ioctl::BINDER_FREEZE => {
+                arg.freeze = Some(FreezeProtocol {
+                    pid: u32_at(data, 0)?,
+                    enable: u32_at(data, 4)?,
+                    timeout_ms: u32_at(data, 8)?,
+                })
+            }
+            binder_ioctl::BINDER_GET_FROZEN_INFO => {
+                arg.frozen_info = Some(FrozenInfoProtocol {
+                    pid: u32_at(data, 0)?,
+                    sync_recv: u32_at(data, 4)?,
+                    async_recv: u32_at(data, 8)?,
+                })
+            }
+            binder_ioctl::BINDER_ENABLE_ONEWAY_SPAM_DETECTION => {
+                arg.oneway_spam_detection = Some(u32_at(data, 0)? != 0)
+            }
+            binder_ioctl::BINDER_GET_EXTENDED_ERROR => {
+                arg.extended_error = Some(ExtendedErrorProtocol {
+                    id: u32_at(data, 0)?,
+                    command: u32_at(data, 4)?,
+                    param: u32_at(data, 8)? as i32,
+                })
+            }
+            _ => return None,
+        }
+        Some(arg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binder_serde::{de::from_bytes, ser::to_bytes};
+
+    #[test]
+    fn parses_by_cmd() {
+        let arg = IoctlArgProtocol::parse(binder_ioctl::BINDER_VERSION, &8i32.to_le_bytes());
+        assert_eq!(arg.unwrap().protocol_version, Some(8));
+
+        let mut data = 4321u32.to_le_bytes().to_vec();
+        data.extend(0x7211u32.to_le_bytes());
+        data.extend((-28i32).to_le_bytes());
+        let arg = IoctlArgProtocol::parse(binder_ioctl::BINDER_GET_EXTENDED_ERROR, &data).unwrap();
+        assert_eq!(
+            arg.extended_error,
+            Some(ExtendedErrorProtocol {
+                id: 4321,
+                command: 0x7211,
+                param: -28,
+            })
+        );
+        assert_eq!(arg.protocol_version, None);
+
+        // short reads and ioctls without a struct
+        assert!(IoctlArgProtocol::parse(binder_ioctl::BINDER_FREEZE, &data[..8]).is_none());
+        assert!(IoctlArgProtocol::parse(binder_ioctl::BINDER_THREAD_EXIT, &data).is_none());
+    }
+
+    #[test]
+    fn round_trips() {
+        let arg = IoctlArgProtocol {
+            freeze: Some(FreezeProtocol {
+                pid: 1234,
+                enable: 1,
+                timeout_ms: 100,
+            }),
+            ..Default::default()
+        };
+        let bytes = to_bytes(&arg).unwrap();
+        let decoded: IoctlArgProtocol = from_bytes(&bytes).unwrap();
+        assert_eq!(decoded, arg);
+    }
+}
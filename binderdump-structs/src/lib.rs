@@ -1,7 +1,9 @@
+pub mod android_ids;
 pub mod binder_serde;
 pub mod binder_types;
 pub mod bwr_layer;
 pub mod errors;
 pub mod event_layer;
+pub mod ioctl_layer;
 pub mod link_layer;
 pub mod transaction_layer;
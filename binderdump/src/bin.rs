@@ -1,24 +1,50 @@
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
-use anyhow::Result;
-use binderdump::capture::ringbuf::create_events_channel;
+use anyhow::{bail, Result};
+use binderdump::capture::calibration::calibrate_if_uncached;
+use binderdump::capture::devices::BinderDevices;
+use binderdump::capture::doctor;
+use binderdump::capture::filter::CaptureFilter;
+use binderdump::capture::limits::CaptureLimits;
+use binderdump::capture::offset_cache::OffsetCache;
+use binderdump::capture::packages::Packages;
+use binderdump::capture::proc_maps::ProcessMaps;
+use binderdump::capture::process_cache::ProcessCache;
+use binderdump::capture::raw_dump::{RawDumpReader, RawDumpWriter};
+use binderdump::capture::ringbuf::{create_events_channel, replay_events_channel};
+use binderdump::capture::snaplen::Snaplen;
 use binderdump::capture::tracepoints::{attach_tracepoints, ReplyCorrelationMode, ReplyOffsets};
+use binderdump::pcapng::capture_info::CaptureInfo;
+use binderdump::pcapng::flight_recorder::{FlightRecorder, TransactionTrigger, Triggers};
 use binderdump::pcapng::packets;
-use clap::Parser;
+use binderdump::pcapng::rotation::{FileRing, Rotation};
+use clap::{Parser, Subcommand};
 use libbpf_rs::ErrorExt;
 
 #[derive(Parser, Debug)]
-#[command(about = "tcpdump for Android binder")]
+#[command(
+    about = "tcpdump for Android binder",
+    args_conflicts_with_subcommands = true
+)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Stop after this many seconds of capture (omit for unbounded).
     #[arg(short = 't', long = "duration", value_name = "SECONDS")]
     duration_secs: Option<u64>,
 
+    /// Stop after this many packets. Unlike -t and Ctrl-C, ioctls
+    /// that are still in flight at that point are not written.
+    #[arg(short = 'c', long = "count", value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    max_packets: Option<u64>,
+
     /// Don't load the reply-correlation BPF program at all. Use when you
     /// don't want it (debugging), or when kernel BTF advertises the right
     /// structs but the offsets it reports produce wrong data (e.g. vendor
     /// backport, out-of-tree binder).
-    #[arg(long = "no-reply-correlation", conflicts_with = "reply_offsets")]
+    #[arg(long = "no-reply-correlation", conflicts_with_all = ["reply_offsets", "calibrate"])]
     no_reply_correlation: bool,
 
     /// Manually specify binder struct offsets, bypassing CO-RE. Format:
@@ -28,10 +54,86 @@ struct Args {
     #[arg(
         long = "reply-offsets",
         value_name = "OFFSETS",
-        conflicts_with = "no_reply_correlation"
+        conflicts_with_all = ["no_reply_correlation", "calibrate"]
     )]
     reply_offsets: Option<ReplyOffsets>,
 
+    /// On a kernel without BTF for the binder structs, derive the reply
+    /// offsets against live transactions before the capture, like
+    /// offset_finder, unless they are cached for this kernel build already.
+    /// Takes a few seconds, once per kernel build.
+    #[arg(long = "calibrate")]
+    calibrate: bool,
+
+    /// Record when the thread that receives a transaction was woken and
+    /// when it got on a CPU, to split the latency of a call into queueing,
+    /// wakeup and work. Hooks sched_switch, which costs on busy systems.
+    #[arg(long = "wakeup-latency")]
+    wakeup_latency: bool,
+
+    /// Record the user stack of the caller of every transaction, along
+    /// with the executable mappings of its process, for the dissector to
+    /// symbolize against the symbols directory.
+    #[arg(long = "stacks")]
+    stacks: bool,
+
+    /// Only capture this process (repeatable). Transactions it sends are
+    /// captured on the receiving side as well.
+    #[arg(long = "pid", value_name = "PID")]
+    pids: Vec<i32>,
+
+    /// Only capture processes running as this uid (repeatable).
+    #[arg(long = "uid", value_name = "UID")]
+    uids: Vec<u32>,
+
+    /// Only capture processes with this name (repeatable). Matched against
+    /// the kernel's comm, i.e. the first 15 bytes of the process name.
+    #[arg(long = "comm", value_name = "NAME")]
+    comms: Vec<String>,
+
+    /// Never capture this process (repeatable), even when it is the other
+    /// endpoint of a captured transaction.
+    #[arg(long = "exclude-pid", value_name = "PID")]
+    exclude_pids: Vec<i32>,
+
+    /// Copy at most this many bytes of every buffer (transaction data,
+    /// scatter-gather payloads, BWR buffers). Offsets are always kept, and
+    /// the packets keep the real sizes.
+    #[arg(
+        long = "snaplen",
+        value_name = "BYTES",
+        value_parser = clap::value_parser!(u32).range(1..),
+        conflicts_with = "metadata_only"
+    )]
+    snaplen: Option<u32>,
+
+    /// Don't copy any buffer contents, only the ioctl and transaction
+    /// metadata with the buffer sizes. Much cheaper than a full capture.
+    #[arg(long = "metadata-only", conflicts_with = "snaplen")]
+    metadata_only: bool,
+
+    /// Size of the ring buffer the events are sent through, in MB (64 by
+    /// default). A power of two; raise it when the capture reports events
+    /// lost to a full ring buffer.
+    #[arg(long = "ring-size", value_name = "MB")]
+    ring_size: Option<u32>,
+
+    /// Copy at most this many 32K chunks of every transaction's data, up to
+    /// 32 (the default, about 1MB, the largest transaction binder maps).
+    #[arg(long = "max-chunks", value_name = "N")]
+    max_chunks: Option<u32>,
+
+    /// Copy the scatter-gather buffers (HIDL vectors and strings) of at most
+    /// this many objects of every transaction, up to 32 (the default). 0
+    /// doesn't copy any.
+    #[arg(long = "max-ptr-objects", value_name = "N")]
+    max_ptr_objects: Option<u32>,
+
+    /// Copy at most this many bytes of every scatter-gather buffer, up to
+    /// 32712 (16384 by default).
+    #[arg(long = "max-ptr-payload", value_name = "BYTES")]
+    max_ptr_payload: Option<u32>,
+
     /// Write the pcapng here. Use '-' to stream to stdout (pipe into
     /// `wireshark -k -i -`); the stream is flushed per packet and status
     /// output goes to stderr so it can't corrupt the capture.
@@ -42,16 +144,149 @@ struct Args {
         default_value = "/data/local/tmp/out.pcapng"
     )]
     output: String,
+
+    /// Start a new file once the current one is larger than this many
+    /// megabytes (1,000,000 bytes). Files are named after the -w path with a
+    /// counter appended (out_00000.pcapng, out_00001.pcapng, ...), and each
+    /// one opens standalone in Wireshark.
+    #[arg(short = 'C', value_name = "MB", value_parser = clap::value_parser!(u64).range(1..))]
+    file_size_mb: Option<u64>,
+
+    /// Start a new file every this many seconds. Can be combined with -C.
+    #[arg(short = 'G', value_name = "SECONDS", value_parser = clap::value_parser!(u64).range(1..))]
+    rotate_secs: Option<u64>,
+
+    /// Keep only the newest N rotated files, deleting older ones. Requires -C
+    /// or -G.
+    #[arg(short = 'W', value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    ring_files: Option<u64>,
+
+    /// Flight recorder: keep only the last this many seconds of packets in
+    /// memory, and write them out when a trigger fires (see --trigger-*).
+    /// Sending SIGUSR1 to binderdump always triggers a dump.
+    #[arg(long = "flight-recorder-secs", value_name = "SECONDS", value_parser = clap::value_parser!(u64).range(1..))]
+    flight_recorder_secs: Option<u64>,
+
+    /// Flight recorder: keep only the last this many megabytes of packets in
+    /// memory. Can be combined with --flight-recorder-secs.
+    #[arg(long = "flight-recorder-mb", value_name = "MB", value_parser = clap::value_parser!(u64).range(1..))]
+    flight_recorder_mb: Option<u64>,
+
+    /// Dump the flight recorder on a transaction to this interface, e.g.
+    /// 'android.os.IServiceManager', optionally restricted to one method
+    /// code ('android.os.IServiceManager:2'). Repeatable.
+    #[arg(long = "trigger-txn", value_name = "INTERFACE[:CODE]")]
+    trigger_txns: Vec<TransactionTrigger>,
+
+    /// Dump the flight recorder when a BR_FAILED_REPLY or BR_DEAD_REPLY is
+    /// returned to a process.
+    #[arg(long = "trigger-failed-reply")]
+    trigger_failed_reply: bool,
+
+    /// Dump the flight recorder when a reply arrives this many milliseconds
+    /// or more after its request. Needs reply correlation.
+    #[arg(long = "trigger-slow-reply", value_name = "MS")]
+    trigger_slow_reply_ms: Option<u64>,
+
+    /// Also write the raw ring buffer records here, along with the device
+    /// and process information the capture reads, so the capture can be
+    /// rerun on a host with `binderdump replay`.
+    #[arg(long = "raw-dump", value_name = "FILE")]
+    raw_dump: Option<PathBuf>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Turn a --raw-dump file into a pcapng, without BPF. Runs on any host.
+    Replay {
+        /// The file written by --raw-dump.
+        #[arg(value_name = "FILE")]
+        dump: PathBuf,
+
+        /// Write the pcapng here. Use '-' to stream to stdout.
+        #[arg(short = 'w', long = "write", value_name = "PATH")]
+        output: String,
+    },
+    /// Check what a capture needs from the device (privileges, tracepoints,
+    /// BTF, ...) and try loading every BPF program. Prints one
+    /// `status<TAB>check<TAB>detail` line per check, and fails when the
+    /// capture couldn't start.
+    Doctor,
 }
 
-fn run_pcap(output: &str, duration: Option<Duration>, mode: ReplyCorrelationMode) -> Result<()> {
-    let mut binder_skel = attach_tracepoints(mode)?;
+// -C / -G / -W
+struct RotationOptions {
+    max_bytes: Option<u64>,
+    max_age: Option<Duration>,
+    max_files: Option<usize>,
+}
 
-    let event_channel = create_events_channel(&mut binder_skel)?;
+impl RotationOptions {
+    fn is_enabled(&self) -> bool {
+        self.max_bytes.is_some() || self.max_age.is_some()
+    }
+}
+
+// --flight-recorder-* / --trigger-*
+struct FlightRecorderOptions {
+    max_age: Option<Duration>,
+    max_bytes: Option<u64>,
+    triggers: Triggers,
+}
+
+impl FlightRecorderOptions {
+    fn is_enabled(&self) -> bool {
+        self.max_age.is_some() || self.max_bytes.is_some()
+    }
+
+    fn has_triggers(&self) -> bool {
+        !self.triggers.transactions.is_empty()
+            || self.triggers.failed_replies
+            || self.triggers.slow_reply.is_some()
+    }
+}
+
+fn run_pcap(
+    output: &str,
+    rotation: RotationOptions,
+    flight_recorder: FlightRecorderOptions,
+    duration: Option<Duration>,
+    max_packets: Option<u64>,
+    mode: ReplyCorrelationMode,
+    filter: &CaptureFilter,
+    snaplen: Snaplen,
+    wakeup_latency: bool,
+    stacks: bool,
+    limits: CaptureLimits,
+    raw_dump: Option<&Path>,
+) -> Result<()> {
+    let (mut binder_skel, tiers) =
+        attach_tracepoints(mode, filter, snaplen, wakeup_latency, stacks, limits)?;
+
+    let capture_info = CaptureInfo::new()?.with_tiers(&tiers.names());
+    let devices = BinderDevices::discover();
+    let raw_dump = match raw_dump {
+        Some(path) => Some(RawDumpWriter::create(
+            path,
+            &capture_info.raw_dump_header(&devices),
+        )?),
+        None => None,
+    };
+    let event_channel = create_events_channel(&mut binder_skel, &limits, raw_dump.clone())?;
+    let mut process_cache = ProcessCache::new(devices);
+    if let Some(raw_dump) = &raw_dump {
+        process_cache = process_cache.with_raw_dump(raw_dump.clone());
+    }
 
     // '-' streams pcapng to stdout (flushed per packet); anything else is a file.
+    let mut ring = None;
     let (writer, flush_each): (Box<dyn std::io::Write>, bool) = if output == "-" {
         (Box::new(std::io::stdout().lock()), true)
+    } else if rotation.is_enabled() {
+        let mut files = FileRing::new(output, rotation.max_files);
+        let file = files.open_next()?;
+        ring = Some(files);
+        (Box::new(file), false)
     } else {
         let file = std::fs::File::create(output)
             .context(format!("failed to open output file: {}", output))?;
@@ -61,17 +296,99 @@ fn run_pcap(output: &str, duration: Option<Duration>, mode: ReplyCorrelationMode
         Some(d) => eprintln!("capturing events for {}s", d.as_secs()),
         None => eprintln!("waiting for events"),
     }
-    let mut packets = packets::PacketGenerator::new(event_channel, writer, flush_each)?;
-    packets.capture(duration)?;
+    let mut packets = packets::PacketGenerator::from_sources(
+        event_channel,
+        writer,
+        flush_each,
+        capture_info,
+        process_cache,
+    )?;
+    if stacks {
+        let mut process_maps = ProcessMaps::new();
+        if let Some(raw_dump) = &raw_dump {
+            process_maps = process_maps.with_raw_dump(raw_dump.clone());
+        }
+        packets = packets.with_process_maps(process_maps);
+    }
+    if let Some(mut files) = ring {
+        packets = packets.with_rotation(Rotation::new(
+            rotation.max_bytes,
+            rotation.max_age,
+            move || Ok(Box::new(files.open_next()?) as Box<dyn std::io::Write>),
+        ));
+    }
+    if flight_recorder.is_enabled() {
+        packets = packets.with_flight_recorder(FlightRecorder::new(
+            flight_recorder.max_age,
+            flight_recorder.max_bytes,
+            flight_recorder.triggers,
+        )?);
+        eprintln!(
+            "flight recorder on, send SIGUSR1 to pid {} to dump it",
+            std::process::id()
+        );
+    }
+    packets.capture(duration, max_packets)?;
+    if let Some(raw_dump) = raw_dump {
+        raw_dump.flush()?;
+    }
     Ok(())
 }
 
+fn run_doctor() -> Result<()> {
+    let checks = doctor::run();
+    for check in &checks {
+        println!("{}", check);
+    }
+    let summary = doctor::summary(&checks);
+    println!("{}", summary);
+    if summary.status == doctor::Status::Fail {
+        bail!("{}", summary.detail);
+    }
+    Ok(())
+}
+
+fn run_replay(dump: &Path, output: &str) -> Result<()> {
+    let dump = RawDumpReader::open(dump)?;
+    let capture_info = CaptureInfo::from_raw_dump(&dump);
+    let process_cache =
+        ProcessCache::from_raw_dump(dump.header().binder_devices(), dump.processes()?)
+            .with_packages(Packages::from_list(&dump.packages()?));
+    let maps = dump.maps()?;
+    let event_channel = replay_events_channel(dump.events()?);
+
+    let (writer, flush_each): (Box<dyn std::io::Write>, bool) = if output == "-" {
+        (Box::new(std::io::stdout().lock()), true)
+    } else {
+        let file = std::fs::File::create(output)
+            .context(format!("failed to open output file: {}", output))?;
+        (Box::new(std::io::BufWriter::new(file)), false)
+    };
+    let mut packets = packets::PacketGenerator::from_sources(
+        event_channel,
+        writer,
+        flush_each,
+        capture_info,
+        process_cache,
+    )?;
+    // only captures with --stacks have maps
+    if !maps.is_empty() {
+        packets = packets.with_process_maps(ProcessMaps::from_raw_dump(maps));
+    }
+    packets.capture(None, None)
+}
+
 pub fn main() -> Result<()> {
     // Diagnostics go to stderr; RUST_LOG overrides the default. Kept quiet by
     // default so it can't drown out capture status output.
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
 
     let args = Args::parse();
+    match &args.command {
+        Some(Command::Replay { dump, output }) => return run_replay(dump, output),
+        Some(Command::Doctor) => return run_doctor(),
+        None => (),
+    }
     let duration = args.duration_secs.map(Duration::from_secs);
     let mode = if args.no_reply_correlation {
         ReplyCorrelationMode::Disabled
@@ -80,5 +397,70 @@ pub fn main() -> Result<()> {
     } else {
         ReplyCorrelationMode::Auto
     };
-    run_pcap(&args.output, duration, mode)
+    if args.calibrate {
+        // without the offsets the capture still runs, just without reply correlation
+        if let Err(err) = calibrate_if_uncached(&OffsetCache::default()) {
+            eprintln!("warning: calibration failed: {:#}", err);
+        }
+    }
+    let filter = CaptureFilter {
+        pids: args.pids,
+        uids: args.uids,
+        comms: args.comms,
+        exclude_pids: args.exclude_pids,
+    };
+    let snaplen = match (args.snaplen, args.metadata_only) {
+        (_, true) => Snaplen::MetadataOnly,
+        (Some(bytes), false) => Snaplen::Bytes(bytes),
+        (None, false) => Snaplen::Full,
+    };
+    let default_limits = CaptureLimits::default();
+    let limits = CaptureLimits {
+        ring_size: args.ring_size.unwrap_or(default_limits.ring_size),
+        max_chunks: args.max_chunks.unwrap_or(default_limits.max_chunks),
+        max_ptr_objects: args
+            .max_ptr_objects
+            .unwrap_or(default_limits.max_ptr_objects),
+        max_ptr_payload: args
+            .max_ptr_payload
+            .unwrap_or(default_limits.max_ptr_payload),
+    };
+    limits.validate()?;
+    let rotation = RotationOptions {
+        max_bytes: args.file_size_mb.map(|mb| mb * 1_000_000),
+        max_age: args.rotate_secs.map(Duration::from_secs),
+        max_files: args.ring_files.map(|n| n as usize),
+    };
+    if rotation.max_files.is_some() && !rotation.is_enabled() {
+        bail!("-W needs -C or -G");
+    }
+    if rotation.is_enabled() && args.output == "-" {
+        bail!("-C and -G need a file to write to, not '-'");
+    }
+    let flight_recorder = FlightRecorderOptions {
+        max_age: args.flight_recorder_secs.map(Duration::from_secs),
+        max_bytes: args.flight_recorder_mb.map(|mb| mb * 1_000_000),
+        triggers: Triggers {
+            transactions: args.trigger_txns,
+            failed_replies: args.trigger_failed_reply,
+            slow_reply: args.trigger_slow_reply_ms.map(Duration::from_millis),
+        },
+    };
+    if flight_recorder.has_triggers() && !flight_recorder.is_enabled() {
+        bail!("--trigger-* need --flight-recorder-secs or --flight-recorder-mb");
+    }
+    run_pcap(
+        &args.output,
+        rotation,
+        flight_recorder,
+        duration,
+        args.max_packets,
+        mode,
+        &filter,
+        snaplen,
+        args.wakeup_latency,
+        args.stacks,
+        limits,
+        args.raw_dump.as_deref(),
+    )
 }
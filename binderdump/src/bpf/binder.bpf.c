@@ -12,36 +12,47 @@
 
 #define DEBUG
 #include "common_types.h"
+#include "drops.h"
+#include "filter.h"
+#include "limits.h"
 #include "log.h"
 #include "maps.h"
+#include "output.h"
+#include "probe_read.h"
 #include "process_state.h"
+#include "snaplen.h"
 #include "trace_binder.h"
 #include "utils.h"
 
-// We send chunks of size 32KB, this lets us send up to 1MB transactions, which is the maximum size
-// Android mmaps the binder driver and therefore the maximum size of a binder transaction.
-// I never saw a transaction with offsets_size > 32KB (which makes sense, parcels don't tend to have
-// so many nested fields), so we only handle data_size > 32KB.
-#define MAX_TRANSACTION_CHUNKS 32
-
-// Maximum number of objects in a transaction's offsets array we'll walk to look for
-// BINDER_TYPE_PTR scatter-gather buffers. Most transactions have far fewer than this;
-// the cap keeps the BPF verifier happy and prevents pathological loops.
-#define MAX_PTR_OBJECTS 32
-// Maximum bytes of a single BINDER_TYPE_PTR scatter-gather buffer we copy up per chunk.
-// Anything larger gets truncated; the userspace `total_size` field still records the
-// real length so the user knows.
-#define MAX_PTR_PAYLOAD 16384
+// The handlers that copy transaction data are compiled once per tier, userspace loads the variant
+// of the highest tier the kernel accepts (see capture/tiers.rs). A tier includes the ones before
+// it.
+typedef enum {
+    TIER_CORE = 0,     // the ioctl and BINDER_WRITE_READ events only
+    TIER_TXN_DATA,     // and the data and offsets of transactions
+    TIER_PTR_PAYLOADS, // and the BINDER_TYPE_PTR scatter-gather payloads
+} capture_tier_t;
 
 #ifndef SYS_compat_ioctl
 #define SYS_compat_ioctl 54
 #endif
+#define SYS_compat_execve 11
+#define SYS_compat_clone 120
+#define SYS_compat_execveat 387
+#ifndef SYS_clone3
+#define SYS_clone3 435
+#endif
+#ifndef CLONE_THREAD
+#define CLONE_THREAD 0x00010000
+#endif
+// kthreadd creates the kernel threads
+#define KTHREADD_PID 2
 #define compat_user_mode(pstate)                                                                   \
     (((pstate) & (PSR_MODE32_BIT | PSR_MODE_MASK)) == (PSR_MODE32_BIT | PSR_MODE_EL0t))
 
 int32_t g_loader_pid = 0;
 
-int check_is_compat(struct bpf_raw_tracepoint_args *ctx) {
+static __always_inline int check_is_compat(struct bpf_raw_tracepoint_args *ctx) {
     struct my_pt_regs *regs_ptr = (struct my_pt_regs *)ctx->args[0];
     __u64 pstate;
     uint32_t map_key = 0;
@@ -71,13 +82,18 @@ int check_is_compat(struct bpf_raw_tracepoint_args *ctx) {
 }
 
 SEC("raw_tp/sys_enter")
-int sys_enter_check_compat(struct bpf_raw_tracepoint_args *ctx) { return check_is_compat(ctx); }
+int sys_enter_check_compat(struct bpf_raw_tracepoint_args *ctx) {
+    if (filter_check(GET_PID(), GET_TID()) != FILTER_MATCH) {
+        return 0;
+    }
+    return check_is_compat(ctx);
+}
 
 // we need this also on sys_exit if we started tracing a process blocked in binder_read
 SEC("raw_tp/sys_exit")
 int sys_exit_check_compat(struct bpf_raw_tracepoint_args *ctx) { return check_is_compat(ctx); }
 
-int get_is_compat() {
+static __always_inline int get_is_compat() {
     uint32_t map_key = 0;
     int *in_compat = bpf_map_lookup_elem(&in_compat_syscall_map, &map_key);
     if (!in_compat) {
@@ -87,9 +103,54 @@ int get_is_compat() {
     return *in_compat;
 }
 
+// Reads the argv of an execve into exec_args_map, sched_process_exec sends it once the exec
+// succeeded
+static __always_inline void stash_exec_args(pid_t tid, __u64 argv, int is_compat) {
+    __u32 key = 0;
+    struct exec_args *args = bpf_map_lookup_elem(&exec_args_scratch, &key);
+    if (!args) {
+        LOG("exec: failed to get exec args buffer");
+        return;
+    }
+    args->args_size = 0;
+    for (int i = 0; i < MAX_EXEC_ARGS; i++) {
+        __u64 arg = 0;
+        // 32-bit processes pass an array of 32-bit pointers
+        if (is_compat) {
+            __u32 arg32 = 0;
+            if (probe_read_user(&arg32, sizeof(arg32), UNTAG(argv + i * sizeof(arg32)))) {
+                break;
+            }
+            arg = arg32;
+        } else if (probe_read_user(&arg, sizeof(arg), UNTAG(argv + i * sizeof(arg)))) {
+            break;
+        }
+        if (!arg) {
+            break;
+        }
+        __u32 offset = args->args_size;
+        if (offset >= EXEC_ARGS_SIZE) {
+            break;
+        }
+        // the mask keeps the verifier convinced, offset is already below EXEC_ARGS_SIZE
+        long size = probe_read_user_str(&args->args[offset & (EXEC_ARGS_SIZE - 1)],
+                                        EXEC_ARG_SIZE, UNTAG(arg));
+        if (size <= 0) {
+            break;
+        }
+        args->args_size = offset + size;
+    }
+    if (bpf_map_update_elem(&exec_args_map, &tid, args, BPF_ANY)) {
+        LOG("exec: failed to stash the args of %d", tid);
+    }
+}
+
 SEC("tp/raw_syscalls/sys_enter")
 int sys_enter(struct trace_event_raw_sys_enter *ctx) {
     pid_t tid = GET_TID();
+    if (filter_check(GET_PID(), tid) != FILTER_MATCH) {
+        return 0;
+    }
     int is_compat = get_is_compat();
     if (is_compat < 0) {
         return 0;
@@ -100,20 +161,95 @@ int sys_enter(struct trace_event_raw_sys_enter *ctx) {
         if (bpf_map_update_elem(&ioctl_context_map, &tid, &ioctl_ctx, BPF_ANY)) {
             LOG("ioctl: invalid state for %d", tid);
         };
+        return 0;
+    }
+    long execve_syscall = (is_compat) ? SYS_compat_execve : SYS_execve;
+    long execveat_syscall = (is_compat) ? SYS_compat_execveat : SYS_execveat;
+    long clone_syscall = (is_compat) ? SYS_compat_clone : SYS_clone;
+    if (ctx->id == execve_syscall) {
+        stash_exec_args(tid, ctx->args[1], is_compat);
+    } else if (ctx->id == execveat_syscall) {
+        stash_exec_args(tid, ctx->args[2], is_compat);
+    } else if (ctx->id == clone_syscall || ctx->id == SYS_clone3) {
+        // clone3 takes a struct clone_args, which starts with the flags
+        __u64 flags = ctx->args[0];
+        if (ctx->id == SYS_clone3 &&
+            probe_read_user(&flags, sizeof(flags), UNTAG(ctx->args[0]))) {
+            return 0;
+        }
+        bpf_map_update_elem(&clone_flags_map, &tid, &flags, BPF_ANY);
     }
     return 0;
 }
 
-#ifdef __aarch64__
+// BR_TRANSACTION_SEC_CTX is followed by a pointer to the security context of the sender, which the
+// kernel copied into the target's buffer, after the offsets. `cmd_addr` is the address of the
+// command in the read buffer.
+static __always_inline void submit_txn_secctx(void *ctx, pid_t pid, pid_t tid, __u64 cmd_addr) {
+    __u64 secctx = 0;
+    if (probe_read_user(&secctx, sizeof(secctx),
+                        UNTAG(cmd_addr + sizeof(struct transaction_command)))) {
+        LOG("failed to read secctx pointer %px", cmd_addr + sizeof(struct transaction_command));
+        count_drop(DROP_PROBE_READ);
+        return;
+    }
+    if (!secctx) {
+        return;
+    }
+
+    struct binder_event *event = NULL;
+    struct binder_event_txn_secctx *txn_secctx = NULL;
+    event = event_reserve(sizeof(*event) + sizeof(*txn_secctx));
+    if (!event) {
+        LOG("Failed to reserve txn secctx event");
+        count_drop(DROP_RINGBUF_FULL);
+        return;
+    }
+    event->type = BINDER_TXN_SECCTX;
+    event->pid = pid;
+    event->tid = tid;
+    event->timestamp = event_timestamp();
+
+    txn_secctx = (struct binder_event_txn_secctx *)(event + 1);
+    long len = probe_read_user_str(txn_secctx->secctx, sizeof(txn_secctx->secctx), UNTAG(secctx));
+    if (len < 0) {
+        LOG("failed to read secctx %px", secctx);
+        count_drop(DROP_PROBE_READ);
+        txn_secctx->secctx[0] = '\0';
+    }
+
+    LOG_RINGBUF("submit %u %x", sizeof(*event) + sizeof(*txn_secctx), *(int *)event);
+    event_submit(ctx, event, 0);
+}
+
 int __noinline do_bc_br_transaction(pid_t pid, pid_t tid, char log_char);
+int __noinline do_bc_br_transaction_ptrs(pid_t pid, pid_t tid, char log_char);
+static __noinline int send_txn_perf(void *ctx, pid_t pid, pid_t tid, char log_char, int with_ptrs);
+
+// Sends the transaction in transaction_command_buffers, with as much of it as `tier` includes.
+// The perf buffer needs the ctx, which a global subprog can't take from a tracepoint program, and
+// the kernels without ring buffers may not have global subprogs at all (5.6), so it goes through a
+// static one there.
+static __always_inline int send_txn_data(void *ctx, pid_t pid, pid_t tid, char log_char,
+                                         const capture_tier_t tier) {
+    if (tier != TIER_CORE && cfg_perf_buffer) {
+        return send_txn_perf(ctx, pid, tid, log_char, tier == TIER_PTR_PAYLOADS);
+    }
+    switch (tier) {
+    case TIER_PTR_PAYLOADS:
+        return do_bc_br_transaction_ptrs(pid, tid, log_char);
+    case TIER_TXN_DATA:
+        return do_bc_br_transaction(pid, tid, log_char);
+    default:
+        return 0;
+    }
+}
 
-// These global (non-inlined) subprogs take only scalar args and re-fetch their
-// per-cpu map buffers internally. The kernel verifier before 5.13 only accepts
-// SCALAR and PTR_TO_CTX args for global functions (btf_prepare_func_args:
-// "Arg#N type ... is not supported yet" -> -EINVAL); pointer args to global
-// funcs landed in 5.13 (e5069b9c23b3). Passing struct pointers here breaks the
-// load on GKI 5.10. Same reasoning as the ptr_walk_state_map handoff.
-int handle_binder_return_from_ioctl(pid_t tid, pid_t pid, __u64 read_buffer) {
+#ifdef __aarch64__
+// Sends the transactions of a read that started before the capture did, once raw_sys_exit saw all
+// of it
+static __always_inline int replay_read_only_returns(void *ctx, pid_t tid, pid_t pid,
+                                                    __u64 read_buffer, const capture_tier_t tier) {
     int ret = 1;
     uint32_t map_key = 0;
 
@@ -138,17 +274,20 @@ int handle_binder_return_from_ioctl(pid_t tid, pid_t pid, __u64 read_buffer) {
         struct binder_reply_offset *reply = &reply_offsets->offsets[i];
         uint32_t cmd = reply->cmd;
         if (cmd == BR_TRANSACTION || cmd == BR_TRANSACTION_SEC_CTX || cmd == BR_REPLY) {
-            if (bpf_probe_read_user(command, sizeof(*command),
-                                    UNTAG(read_buffer + reply->offset))) {
+            if (probe_read_user(command, sizeof(*command), UNTAG(read_buffer + reply->offset))) {
                 LOG("failed to read BC data %px (cmd: %d)", read_buffer + reply->offset, cmd);
+                count_drop(DROP_PROBE_READ);
                 goto l_cleanup;
             }
 
             // LOG("b%c handling command from read_only ioctl: %x", log_char, command);
-            if (do_bc_br_transaction(pid, tid, 'r') != 0) {
+            if (send_txn_data(ctx, pid, tid, 'r', tier) != 0) {
                 LOG("br failed to handle transaction command");
                 goto l_cleanup;
             }
+            if (cmd == BR_TRANSACTION_SEC_CTX) {
+                submit_txn_secctx(ctx, pid, tid, read_buffer + reply->offset);
+            }
         }
     }
     ret = 0;
@@ -158,8 +297,27 @@ l_cleanup:
     return 0;
 }
 
-SEC("raw_tp/sys_exit")
-int raw_sys_exit(struct bpf_raw_tracepoint_args *ctx) {
+// These global (non-inlined) subprogs take only scalar args and re-fetch their
+// per-cpu map buffers internally. The kernel verifier before 5.13 only accepts
+// SCALAR and PTR_TO_CTX args for global functions (btf_prepare_func_args:
+// "Arg#N type ... is not supported yet" -> -EINVAL); pointer args to global
+// funcs landed in 5.13 (e5069b9c23b3). Passing struct pointers here breaks the
+// load on GKI 5.10. Same reasoning as the ptr_walk_state_map handoff. Without the ctx they can
+// only send through the ring buffer, do_raw_sys_exit replays inline on the perf buffer.
+int handle_binder_return_from_ioctl(pid_t tid, pid_t pid, __u64 read_buffer) {
+    return replay_read_only_returns(NULL, tid, pid, read_buffer, TIER_CORE);
+}
+
+int handle_binder_return_from_ioctl_data(pid_t tid, pid_t pid, __u64 read_buffer) {
+    return replay_read_only_returns(NULL, tid, pid, read_buffer, TIER_TXN_DATA);
+}
+
+int handle_binder_return_from_ioctl_ptrs(pid_t tid, pid_t pid, __u64 read_buffer) {
+    return replay_read_only_returns(NULL, tid, pid, read_buffer, TIER_PTR_PAYLOADS);
+}
+
+static __always_inline int do_raw_sys_exit(struct bpf_raw_tracepoint_args *ctx,
+                                           const capture_tier_t tier) {
     struct my_pt_regs *regs_ptr = (struct my_pt_regs *)ctx->args[0];
     __u32 regs_key = 0;
     // We use map here to take less stack space, as we are on the verge of exceeding our 512 byte
@@ -198,18 +356,18 @@ int raw_sys_exit(struct bpf_raw_tracepoint_args *ctx) {
             ioctl_ctx->arg = regs->user_regs.regs[2];
             ioctl_ctx->is_compat = is_compat;
 
-            struct binder_event *event = bpf_ringbuf_reserve(
-                &binder_events_buffer,
-                sizeof(struct binder_event) + sizeof(struct binder_event_ioctl), 0);
+            struct binder_event *event =
+                event_reserve(sizeof(struct binder_event) + sizeof(struct binder_event_ioctl));
             if (!event) {
                 LOG("binder_ioctl: failed to reserved event");
-                send_invalidate(tid, pid);
+                count_drop(DROP_RINGBUF_FULL);
+                send_invalidate(ctx, tid, pid);
                 return 0;
             }
             event->type = BINDER_IOCTL;
             event->pid = pid;
             event->tid = tid;
-            event->timestamp = bpf_ktime_get_boot_ns();
+            event->timestamp = event_timestamp();
 
             struct binder_event_ioctl *ioctl_event = (struct binder_event_ioctl *)(event + 1);
             __u64 creds = bpf_get_current_uid_gid();
@@ -232,9 +390,10 @@ int raw_sys_exit(struct bpf_raw_tracepoint_args *ctx) {
                 goto l_error;
             }
             struct binder_write_read bwr = {};
-            if (bpf_probe_read_user(&bwr, sizeof(bwr), UNTAG(ioctl_ctx->arg))) {
+            if (probe_read_user(&bwr, sizeof(bwr), UNTAG(ioctl_ctx->arg))) {
                 LOG("raw_sys_exit: failed to read BINDER_WRITE_READ arg from user addr: %px",
                     (const void *)ioctl_ctx->arg);
+                count_drop(DROP_PROBE_READ);
                 goto l_error;
             }
             struct binder_reply_offsets *reply_offsets =
@@ -262,29 +421,50 @@ int raw_sys_exit(struct bpf_raw_tracepoint_args *ctx) {
             //
             // we force wakeup here so we can capture the process' cmdline and fds before it can
             // exit.
-            bpf_ringbuf_submit(event, BPF_RB_FORCE_WAKEUP);
+            event_submit(ctx, event, BPF_RB_FORCE_WAKEUP);
 
             // now pass on the cmds we got from binder_return and binder_txn_received
-            handle_binder_return_from_ioctl(tid, pid, bwr.read_buffer);
+            if (cfg_perf_buffer) {
+                replay_read_only_returns(ctx, tid, pid, bwr.read_buffer, tier);
+            } else if (tier == TIER_PTR_PAYLOADS) {
+                handle_binder_return_from_ioctl_ptrs(tid, pid, bwr.read_buffer);
+            } else if (tier == TIER_TXN_DATA) {
+                handle_binder_return_from_ioctl_data(tid, pid, bwr.read_buffer);
+            } else {
+                handle_binder_return_from_ioctl(tid, pid, bwr.read_buffer);
+            }
 
             return 0;
         l_error:
             bpf_map_delete_elem(&binder_write_read_buffers, &tid);
             LOG("discarding event");
-            bpf_ringbuf_discard(event, 0);
+            event_discard(event);
         }
         // sys_exit tracepoint is running after us and will clear the ioctl context (in both
         // arm64 and x86)
     }
     return 0;
 }
+
+SEC("raw_tp/sys_exit")
+int raw_sys_exit(struct bpf_raw_tracepoint_args *ctx) { return do_raw_sys_exit(ctx, TIER_CORE); }
+
+SEC("raw_tp/sys_exit")
+int raw_sys_exit_data(struct bpf_raw_tracepoint_args *ctx) {
+    return do_raw_sys_exit(ctx, TIER_TXN_DATA);
+}
+
+SEC("raw_tp/sys_exit")
+int raw_sys_exit_ptrs(struct bpf_raw_tracepoint_args *ctx) {
+    return do_raw_sys_exit(ctx, TIER_PTR_PAYLOADS);
+}
 // TODO - x86
 #endif
 
-static __always_inline int do_binder_write_read(pid_t tid, pid_t pid,
+static __always_inline int do_binder_write_read(void *ctx, pid_t tid, pid_t pid,
                                                 struct ioctl_context *ioctl_ctx, int is_done);
 
-int is_binder_ioctl_cmd(unsigned int cmd) {
+static __always_inline int is_binder_ioctl_cmd(unsigned int cmd) {
     switch (cmd) {
     case BINDER_WRITE_READ:
     case BINDER_SET_IDLE_TIMEOUT:
@@ -306,6 +486,59 @@ int is_binder_ioctl_cmd(unsigned int cmd) {
     }
 }
 
+// The ioctls we send a BINDER_IOCTL_ARG for. BINDER_WRITE_READ has its own events, the others
+// take a plain value or nothing.
+static __always_inline int has_ioctl_arg(unsigned int cmd) {
+    switch (cmd) {
+    case BINDER_SET_MAX_THREADS:
+    case BINDER_VERSION:
+    case BINDER_GET_NODE_DEBUG_INFO:
+    case BINDER_GET_NODE_INFO_FOR_REF:
+    case BINDER_SET_CONTEXT_MGR_EXT:
+    case BINDER_FREEZE:
+    case BINDER_GET_FROZEN_INFO:
+    case BINDER_ENABLE_ONEWAY_SPAM_DETECTION:
+    case BINDER_GET_EXTENDED_ERROR:
+        return 1;
+    default:
+        return 0;
+    }
+}
+
+static __always_inline void submit_ioctl_arg(void *ctx, pid_t pid, pid_t tid, unsigned int cmd,
+                                             __u64 arg, __u32 done) {
+    __u32 size = _IOC_SIZE(cmd);
+    if (size > IOCTL_ARG_SIZE) {
+        size = IOCTL_ARG_SIZE;
+    }
+
+    struct binder_event *event = NULL;
+    struct binder_event_ioctl_arg *ioctl_arg = NULL;
+    event = event_reserve(sizeof(*event) + sizeof(*ioctl_arg));
+    if (!event) {
+        LOG("Failed to reserve ioctl arg event");
+        count_drop(DROP_RINGBUF_FULL);
+        return;
+    }
+    event->type = BINDER_IOCTL_ARG;
+    event->pid = pid;
+    event->tid = tid;
+    event->timestamp = event_timestamp();
+
+    ioctl_arg = (struct binder_event_ioctl_arg *)(event + 1);
+    ioctl_arg->done = done;
+    ioctl_arg->size = size;
+    if (probe_read_user(ioctl_arg->data, size, UNTAG(arg))) {
+        LOG("failed to read ioctl arg %px (cmd: %x)", arg, cmd);
+        count_drop(DROP_PROBE_READ);
+        event_discard(event);
+        return;
+    }
+
+    LOG_RINGBUF("submit %u %x", sizeof(*event) + sizeof(*ioctl_arg), *(int *)event);
+    event_submit(ctx, event, 0);
+}
+
 // NOTE - this must be defined after raw_sys_exit to keep the same ordering when loaded into the
 // kernel
 SEC("tp/raw_syscalls/sys_exit")
@@ -326,6 +559,7 @@ int sys_exit(struct trace_event_raw_sys_exit *ctx) {
     if (ctx->id != ioctl_syscall || tid == g_loader_pid) {
         return 0;
     }
+    filter_ioctl_done(tid);
 
     current_state = bpf_map_lookup_elem(&binder_process_state, &tid);
     if (!current_state) {
@@ -339,29 +573,30 @@ int sys_exit(struct trace_event_raw_sys_exit *ctx) {
     }
 
     if (ioctl_ctx->cmd == BINDER_WRITE_READ && ioctl_ctx->fd >= 0) {
-        if (do_binder_write_read(tid, pid, ioctl_ctx, 1)) {
-            send_invalidate(tid, pid);
+        if (do_binder_write_read(ctx, tid, pid, ioctl_ctx, 1)) {
+            send_invalidate(ctx, tid, pid);
             return 0;
         }
+    } else if (has_ioctl_arg(ioctl_ctx->cmd)) {
+        submit_ioctl_arg(ctx, pid, tid, ioctl_ctx->cmd, ioctl_ctx->arg, 1);
     }
 
-    event = bpf_ringbuf_reserve(
-        &binder_events_buffer, sizeof(struct binder_event) + sizeof(struct binder_event_ioctl_done),
-        0);
+    event = event_reserve(sizeof(struct binder_event) + sizeof(struct binder_event_ioctl_done));
     if (!event) {
         LOG("binder_ioctl_done: failed to reserved event");
+        count_drop(DROP_RINGBUF_FULL);
         return 0;
     }
     event->type = BINDER_IOCTL_DONE;
     event->pid = pid;
     event->tid = tid;
-    event->timestamp = bpf_ktime_get_boot_ns();
+    event->timestamp = event_timestamp();
     struct binder_event_ioctl_done *ioctl_event = (struct binder_event_ioctl_done *)(event + 1);
     ioctl_event->ret = ioctl_ctx->ret;
 
     LOG_RINGBUF("submit %u %x", sizeof(*event) + sizeof(struct binder_event_ioctl_done),
                 *(int *)event);
-    bpf_ringbuf_submit(event, 0);
+    event_submit(ctx, event, 0);
 
     LOG_TRANSITION("thread %d 9 -> 0", tid);
     *current_state = BINDER_INVALID;
@@ -378,6 +613,9 @@ int sched_process_exit(const struct trace_event_raw_sched_process_template *ctx)
     pid_t tid = ctx->pid;
     pid_t pid = bpf_get_current_pid_tgid() >> 32;
 
+    // check before forgetting, a filtered-out process never made it into the ProcessCache
+    filter_verdict_t verdict = filter_check(pid, tid);
+    filter_forget(pid, tid);
     if (bpf_map_lookup_elem(&binder_process_state, &tid)) {
         // LOG("binder task %d removed from map", tid);
         binder_process_state_t state = BINDER_INVALID;
@@ -389,23 +627,122 @@ int sched_process_exit(const struct trace_event_raw_sched_process_template *ctx)
         struct ioctl_context ioctl_ctx = {.fd = -1};
         bpf_map_update_elem(&ioctl_context_map, &tid, &ioctl_ctx, BPF_ANY);
     }
+    if (verdict == FILTER_DROP) {
+        return 0;
+    }
 
-    struct binder_event *event = bpf_ringbuf_reserve(&binder_events_buffer, sizeof(*event), 0);
+    struct binder_event *event = event_reserve(sizeof(*event));
     if (!event) {
         LOG("Failed to send process invalidate message");
+        count_drop(DROP_RINGBUF_FULL);
         return 0;
     }
     event->type = BINDER_INVALIDATE_PROCESS;
     event->pid = pid;
     event->tid = tid;
-    event->timestamp = bpf_ktime_get_boot_ns();
+    event->timestamp = event_timestamp();
     LOG_RINGBUF("submit %u %x", sizeof(*event), *(int *)event);
-    bpf_ringbuf_submit(event, 0);
+    event_submit(ctx, event, 0);
+
+    return 0;
+}
+
+// Runs in the parent. Lets userspace know about a process before it had the chance to exit, which
+// a short-lived one often does before its /proc entry is read.
+SEC("tp/sched/sched_process_fork")
+int sched_process_fork(const struct trace_event_raw_sched_process_fork *ctx) {
+    __u64 task_id = bpf_get_current_pid_tgid();
+    pid_t pid = task_id >> 32;
+    pid_t tid = task_id & 0xffffffff;
+
+    // fork() and vfork() don't leave flags behind, they create processes
+    __u64 *flags = bpf_map_lookup_elem(&clone_flags_map, &tid);
+    int is_thread = flags && (*flags & CLONE_THREAD);
+    if (flags) {
+        bpf_map_delete_elem(&clone_flags_map, &tid);
+    }
+    if (is_thread || pid == KTHREADD_PID || filter_check(pid, tid) == FILTER_DROP) {
+        return 0;
+    }
+
+    struct binder_event *event = NULL;
+    struct binder_event_new_process *fork_event = NULL;
+    event = event_reserve(sizeof(*event) + sizeof(*fork_event));
+    if (!event) {
+        LOG("Failed to reserve new process event");
+        count_drop(DROP_RINGBUF_FULL);
+        return 0;
+    }
+    event->type = BINDER_NEW_PROCESS;
+    event->pid = pid;
+    event->tid = tid;
+    event->timestamp = event_timestamp();
+
+    fork_event = (struct binder_event_new_process *)(event + 1);
+    fork_event->child_pid = ctx->child_pid;
+    fork_event->uid = bpf_get_current_uid_gid() & 0xffffffff;
+    if (probe_read_kernel_str(fork_event->comm, sizeof(fork_event->comm), ctx->child_comm) < 0) {
+        fork_event->comm[0] = '\0';
+    }
+
+    LOG_RINGBUF("submit %u %x", sizeof(*event) + sizeof(*fork_event), *(int *)event);
+    event_submit(ctx, event, 0);
+    return 0;
+}
+
+SEC("tp/sched/sched_process_exec")
+int sched_process_exec(const struct trace_event_raw_sched_process_exec *ctx) {
+    __u64 task_id = bpf_get_current_pid_tgid();
+    pid_t pid = task_id >> 32;
+    pid_t tid = task_id & 0xffffffff;
+    pid_t old_tid = ctx->old_pid;
+
+    struct exec_args *args = bpf_map_lookup_elem(&exec_args_map, &old_tid);
+    if (filter_check(pid, tid) == FILTER_DROP) {
+        goto l_cleanup;
+    }
+
+    struct binder_event *event = NULL;
+    struct binder_event_exec *exec_event = NULL;
+    event = event_reserve(sizeof(*event) + sizeof(*exec_event));
+    if (!event) {
+        LOG("Failed to reserve exec event");
+        count_drop(DROP_RINGBUF_FULL);
+        goto l_cleanup;
+    }
+    event->type = BINDER_EXEC;
+    event->pid = pid;
+    event->tid = tid;
+    event->timestamp = event_timestamp();
+
+    exec_event = (struct binder_event_exec *)(event + 1);
+    exec_event->old_tid = old_tid;
+    exec_event->uid = bpf_get_current_uid_gid() & 0xffffffff;
+    bpf_get_current_comm(exec_event->comm, sizeof(exec_event->comm));
+    // __data_loc: the low 16 bits are the offset of the string from the start of the record
+    unsigned short filename_offset = ctx->__data_loc_filename & 0xffff;
+    if (probe_read_kernel_str(exec_event->filename, sizeof(exec_event->filename),
+                              (const char *)ctx + filename_offset) < 0) {
+        exec_event->filename[0] = '\0';
+    }
+    exec_event->args_size = 0;
+    if (args &&
+        !probe_read_kernel(exec_event->args, sizeof(exec_event->args), args->args)) {
+        exec_event->args_size = args->args_size;
+    }
+
+    LOG_RINGBUF("submit %u %x", sizeof(*event) + sizeof(*exec_event), *(int *)event);
+    event_submit(ctx, event, 0);
 
+l_cleanup:
+    if (args) {
+        bpf_map_delete_elem(&exec_args_map, &old_tid);
+    }
     return 0;
 }
 
-int do_binder_write_read(pid_t tid, pid_t pid, struct ioctl_context *ioctl_ctx, int is_done) {
+int do_binder_write_read(void *ctx, pid_t tid, pid_t pid, struct ioctl_context *ioctl_ctx,
+                         int is_done) {
     struct write_read_buffer *buffer = NULL;
     __u32 key = 0;
     const void *addr = NULL;
@@ -421,12 +758,15 @@ int do_binder_write_read(pid_t tid, pid_t pid, struct ioctl_context *ioctl_ctx,
         return -1;
     }
 
-    if (bpf_probe_read_user(&buffer->bwr, sizeof(buffer->bwr), UNTAG(ioctl_ctx->arg))) {
+    if (probe_read_user(&buffer->bwr.bwr, sizeof(buffer->bwr.bwr), UNTAG(ioctl_ctx->arg))) {
         LOG("bwr: failed to read BINDER_WRITE_READ arg from user addr: %px (is_done %d)",
             (const void *)ioctl_ctx->arg, is_done);
+        count_drop(DROP_PROBE_READ);
         __builtin_memset(&buffer->bwr, 0, sizeof(buffer->bwr));
         return -1;
     }
+    buffer->bwr.flags = 0;
+    buffer->bwr._pad = 0;
 
     if (is_done) {
         size = (__u32)(*(volatile binder_size_t *)&buffer->bwr.bwr.read_consumed);
@@ -441,7 +781,7 @@ int do_binder_write_read(pid_t tid, pid_t pid, struct ioctl_context *ioctl_ctx,
         buffer->event.type = BINDER_WRITE;
 
         LOG_BWR_BUFFERS("do_binder_write_read (is_done == 0) - update elem");
-        if (bpf_map_update_elem(&binder_write_read_buffers, &tid, &buffer->bwr, BPF_NOEXIST)) {
+        if (bpf_map_update_elem(&binder_write_read_buffers, &tid, &buffer->bwr.bwr, BPF_NOEXIST)) {
             LOG("bwr: failed to save bwr buffer (is_done %d)", is_done);
             __builtin_memset(&buffer->bwr, 0, sizeof(buffer->bwr));
             return -1;
@@ -449,6 +789,16 @@ int do_binder_write_read(pid_t tid, pid_t pid, struct ioctl_context *ioctl_ctx,
     }
 
     original_size = size;
+    size = snap_len(size);
+    if (size < original_size) {
+        buffer->bwr.flags |= BWR_TRUNCATED;
+        if (!is_done && write_has_transaction((__u64)addr, original_size)) {
+            buffer->bwr.flags |= BWR_HAS_TRANSACTION;
+        }
+        if (size == 0) {
+            goto l_send_event;
+        }
+    }
     // tell verifier size < sizeof(*buffer)
     size &= (sizeof(*buffer) - 1);
     if (size == 0) {
@@ -463,22 +813,23 @@ int do_binder_write_read(pid_t tid, pid_t pid, struct ioctl_context *ioctl_ctx,
     }
 
     // TODO - check addr != NULL?
-    int _ret = bpf_probe_read_user(buffer->bwr.data, size, UNTAG(addr));
+    int _ret = probe_read_user(buffer->bwr.data, size, UNTAG(addr));
     if (_ret) {
         LOG("bwr: failed to read addr %px size: %u (is_done: %d)", addr, size, is_done);
         LOG("bwr: read error %d", _ret);
+        count_drop(DROP_PROBE_READ);
         return -1;
     }
 l_send_event:
     buffer->event.pid = pid;
     buffer->event.tid = tid;
-    buffer->event.timestamp = bpf_ktime_get_boot_ns();
+    buffer->event.timestamp = event_timestamp();
     LOG_RINGBUF("output %u (%u + %u) %x",
                 (__u32)offsetof(struct write_read_buffer, bwr.data) + size,
                 (__u32)offsetof(struct write_read_buffer, bwr.data), size, *(int *)buffer);
-    if (bpf_ringbuf_output(&binder_events_buffer, buffer,
-                           (__u32)offsetof(struct write_read_buffer, bwr.data) + size, 0)) {
+    if (event_output(ctx, buffer, (__u32)offsetof(struct write_read_buffer, bwr.data) + size)) {
         LOG("bwr: failed to output write_read data (is_done: %d)", is_done);
+        count_drop(DROP_RINGBUF_FULL);
         return -1;
     }
     return 0;
@@ -492,6 +843,10 @@ int binder_ioctl(struct trace_event_raw_binder_ioctl *ctx) {
     binder_process_state_t state = BINDER_IOCTL;
     struct ioctl_context *ioctl_ctx = NULL;
 
+    if (filter_check(pid, tid) != FILTER_MATCH) {
+        return 0;
+    }
+
     LOG_TRANSITION("thread %d _ -> BINDER_IOCTL", tid);
     if (bpf_map_update_elem(&binder_process_state, &tid, &state, BPF_ANY)) {
         LOG("binder_ioctl: invalid binder state for task %d", tid);
@@ -505,16 +860,17 @@ int binder_ioctl(struct trace_event_raw_binder_ioctl *ctx) {
         return 0;
     }
 
-    struct binder_event *event = bpf_ringbuf_reserve(
-        &binder_events_buffer, sizeof(struct binder_event) + sizeof(struct binder_event_ioctl), 0);
+    struct binder_event *event =
+        event_reserve(sizeof(struct binder_event) + sizeof(struct binder_event_ioctl));
     if (!event) {
         LOG("binder_ioctl: failed to reserved event");
+        count_drop(DROP_RINGBUF_FULL);
         return 0;
     }
     event->type = BINDER_IOCTL;
     event->pid = pid;
     event->tid = tid;
-    event->timestamp = bpf_ktime_get_boot_ns();
+    event->timestamp = event_timestamp();
 
     struct binder_event_ioctl *ioctl_event = (struct binder_event_ioctl *)(event + 1);
     __u64 creds = bpf_get_current_uid_gid();
@@ -531,20 +887,26 @@ int binder_ioctl(struct trace_event_raw_binder_ioctl *ctx) {
     // and fds before it can exit
     LOG_RINGBUF("submit %u %x", sizeof(struct binder_event) + sizeof(struct binder_event_ioctl),
                 *(int *)event);
-    bpf_ringbuf_submit(event, BPF_RB_FORCE_WAKEUP);
+    event_submit(ctx, event, BPF_RB_FORCE_WAKEUP);
 
+    if (has_ioctl_arg(ctx->cmd)) {
+        submit_ioctl_arg(ctx, pid, tid, ctx->cmd, ctx->arg, 0);
+        return 0;
+    }
     if (ctx->cmd != BINDER_WRITE_READ) {
         return 0;
     }
 
-    if (do_binder_write_read(tid, pid, ioctl_ctx, 0)) {
-        send_invalidate(tid, pid);
+    if (do_binder_write_read(ctx, tid, pid, ioctl_ctx, 0)) {
+        send_invalidate(ctx, tid, pid);
     }
 
     return 0;
 }
 
-int __noinline handle_transaction_chunk(__u64 chunk_size, __u64 addr, size_t chunk_index) {
+// Reads a chunk of transaction data into tmp_buffers, send_transaction_chunk sends it
+static __always_inline int do_read_transaction_chunk(__u64 chunk_size, __u64 addr,
+                                                     size_t chunk_index) {
     __u32 key = 0;
     struct write_read_buffer *buffer = bpf_map_lookup_elem(&tmp_buffers, &key);
     if (!buffer) {
@@ -553,18 +915,44 @@ int __noinline handle_transaction_chunk(__u64 chunk_size, __u64 addr, size_t chu
     if (chunk_size > (__u32)(sizeof(*buffer) - offsetof(struct write_read_buffer, bwr.data))) {
         return -1;
     }
-    if (bpf_probe_read_user(buffer->bwr.data, chunk_size, (const void *)addr)) {
+    if (probe_read_user(buffer->bwr.data, chunk_size, (const void *)addr)) {
         LOG("failed to read txn data %u", chunk_size);
+        count_drop(DROP_PROBE_READ);
         return -1;
     }
     buffer->bwr.bwr.write_consumed = chunk_size;
     buffer->bwr.bwr.write_buffer = chunk_index + 1;
+    return 0;
+}
+
+// Verified once for every caller, on the ring buffer only: kernels without ring buffers may not
+// have global functions either (5.6), send_txn_perf reads the chunks inline
+int __noinline read_transaction_chunk(__u64 chunk_size, __u64 addr, size_t chunk_index) {
+    return do_read_transaction_chunk(chunk_size, addr, chunk_index);
+}
+
+static __always_inline int send_transaction_chunk(void *ctx, __u64 chunk_size, __u64 addr,
+                                                  size_t chunk_index) {
+    __u32 key = 0;
+    int err = cfg_perf_buffer ? do_read_transaction_chunk(chunk_size, addr, chunk_index)
+                              : read_transaction_chunk(chunk_size, addr, chunk_index);
+    if (err) {
+        return -1;
+    }
+    struct write_read_buffer *buffer = bpf_map_lookup_elem(&tmp_buffers, &key);
+    if (!buffer) {
+        return -1;
+    }
+    if (chunk_size > (__u32)(sizeof(*buffer) - offsetof(struct write_read_buffer, bwr.data))) {
+        return -1;
+    }
 
     LOG_RINGBUF("output: %u %x", (__u32)offsetof(struct write_read_buffer, bwr.data) + chunk_size,
                 *(int *)buffer);
-    if (bpf_ringbuf_output(&binder_events_buffer, buffer,
-                           (__u32)offsetof(struct write_read_buffer, bwr.data) + chunk_size, 0)) {
+    if (event_output(ctx, buffer,
+                     (__u32)offsetof(struct write_read_buffer, bwr.data) + chunk_size)) {
         LOG("failed to output txn data");
+        count_drop(DROP_RINGBUF_FULL);
         return -1;
     }
     return 0;
@@ -583,15 +971,19 @@ int __noinline handle_transaction_chunk(__u64 chunk_size, __u64 addr, size_t chu
 // neither subprog needs more than one or two args. Keeps both frames small
 // enough to fit inside the 512-byte combined-stack budget.
 
-static int __always_inline emit_one_ptr_payload(void) {
+static int __always_inline emit_one_ptr_payload(void *ctx) {
     __u32 key = 0;
     struct ptr_walk_state *st = bpf_map_lookup_elem(&ptr_walk_state_map, &key);
     struct write_read_buffer *out_buffer = bpf_map_lookup_elem(&ptr_payload_buffers, &key);
     if (!st || !out_buffer) {
         return -1;
     }
-    __u32 to_read =
-        st->cur_ptr_length > MAX_PTR_PAYLOAD ? MAX_PTR_PAYLOAD : (__u32)st->cur_ptr_length;
+    __u64 length = snap_len(st->cur_ptr_length);
+    __u32 max_payload = cfg_max_ptr_payload;
+    if (max_payload > MAX_PTR_PAYLOAD_LIMIT) {
+        max_payload = MAX_PTR_PAYLOAD_LIMIT;
+    }
+    __u32 to_read = length > max_payload ? max_payload : (__u32)length;
     if (to_read == 0) {
         return 0;
     }
@@ -599,7 +991,7 @@ static int __always_inline emit_one_ptr_payload(void) {
     ev_hdr->type = BINDER_TXN_PTR_DATA;
     ev_hdr->pid = (pid_t)(st->task_id >> 32);
     ev_hdr->tid = (pid_t)(st->task_id & 0xffffffff);
-    ev_hdr->timestamp = bpf_ktime_get_boot_ns();
+    ev_hdr->timestamp = event_timestamp();
     struct binder_event_txn_ptr_data *meta =
         (struct binder_event_txn_ptr_data *)(out_buffer->_data + sizeof(struct binder_event));
     meta->offset_index = st->cur_offset_index;
@@ -607,39 +999,49 @@ static int __always_inline emit_one_ptr_payload(void) {
     meta->buffer_addr = st->cur_ptr_buffer_addr;
     meta->total_size = st->cur_ptr_length;
     meta->chunk_size = to_read;
-    if (bpf_probe_read_user(meta->data, to_read, UNTAG(st->cur_ptr_buffer_addr))) {
+    if (probe_read_user(meta->data, to_read, UNTAG(st->cur_ptr_buffer_addr))) {
+        count_drop(DROP_PROBE_READ);
+        return -1;
+    }
+    if (event_output(ctx, out_buffer,
+                     sizeof(struct binder_event) + sizeof(struct binder_event_txn_ptr_data) +
+                         to_read)) {
+        count_drop(DROP_RINGBUF_FULL);
         return -1;
     }
-    bpf_ringbuf_output(
-        &binder_events_buffer, out_buffer,
-        sizeof(struct binder_event) + sizeof(struct binder_event_txn_ptr_data) + to_read, 0);
     return 0;
 }
 
-static int __always_inline emit_ptr_payloads(struct write_read_buffer *offsets_buf) {
+static int __always_inline emit_ptr_payloads(void *ctx, struct write_read_buffer *offsets_buf) {
     __u32 key = 0;
     struct ptr_walk_state *st = bpf_map_lookup_elem(&ptr_walk_state_map, &key);
     if (!offsets_buf || !st || st->offsets_size == 0) {
         return 0;
     }
+    __u32 max_objects = cfg_max_ptr_objects;
+    // userspace loads the programs without the walk for 0, not a drop either way
+    if (max_objects == 0) {
+        return 0;
+    }
     const __u32 ptr_size = sizeof(binder_size_t);
     __u32 entry_count = st->offsets_size / ptr_size;
-    if (entry_count > MAX_PTR_OBJECTS) {
-        entry_count = MAX_PTR_OBJECTS;
+    if (entry_count > max_objects) {
+        count_drop(DROP_TOO_MANY_PTR_OBJECTS);
+        entry_count = max_objects;
     }
 
-    for (__u32 i = 0; i < MAX_PTR_OBJECTS; i++) {
+    for (__u32 i = 0; i < MAX_PTR_OBJECTS_LIMIT; i++) {
         if (i >= entry_count) {
             break;
         }
         __u64 entry = 0;
-        if (bpf_probe_read_kernel(&entry, ptr_size,
-                                  (const __u8 *)offsets_buf->bwr.data + i * ptr_size)) {
+        if (probe_read_kernel(&entry, ptr_size,
+                              (const __u8 *)offsets_buf->bwr.data + i * ptr_size)) {
             continue;
         }
         __u32 type_ = 0;
-        if (bpf_probe_read_user(&type_, sizeof(type_),
-                                (const void *)(uintptr_t)(st->data_user_addr + entry))) {
+        if (probe_read_user(&type_, sizeof(type_),
+                            (const void *)(uintptr_t)(st->data_user_addr + entry))) {
             continue;
         }
         if (type_ != BINDER_TYPE_PTR) {
@@ -647,22 +1049,22 @@ static int __always_inline emit_ptr_payloads(struct write_read_buffer *offsets_b
         }
         st->cur_ptr_buffer_addr = 0;
         st->cur_ptr_length = 0;
-        if (bpf_probe_read_user(&st->cur_ptr_buffer_addr, ptr_size,
-                                (const void *)(uintptr_t)(st->data_user_addr + entry + 8))) {
+        if (probe_read_user(&st->cur_ptr_buffer_addr, ptr_size,
+                            (const void *)(uintptr_t)(st->data_user_addr + entry + 8))) {
             continue;
         }
-        if (bpf_probe_read_user(
-                &st->cur_ptr_length, ptr_size,
-                (const void *)(uintptr_t)(st->data_user_addr + entry + 8 + ptr_size))) {
+        if (probe_read_user(&st->cur_ptr_length, ptr_size,
+                            (const void *)(uintptr_t)(st->data_user_addr + entry + 8 + ptr_size))) {
             continue;
         }
         st->cur_offset_index = i;
-        emit_one_ptr_payload();
+        emit_one_ptr_payload(ctx);
     }
     return 0;
 }
 
-int __noinline do_bc_br_transaction(pid_t pid, pid_t tid, char log_char) {
+static __always_inline int send_txn(void *ctx, pid_t pid, pid_t tid, char log_char,
+                                    const int with_ptrs) {
     struct write_read_buffer *buffer = NULL;
     __u32 key = 0;
     __u64 data_size = 0;
@@ -683,7 +1085,7 @@ int __noinline do_bc_br_transaction(pid_t pid, pid_t tid, char log_char) {
     buffer->event.type = BINDER_TXN_DATA;
     buffer->event.pid = pid;
     buffer->event.tid = tid;
-    buffer->event.timestamp = bpf_ktime_get_boot_ns();
+    buffer->event.timestamp = event_timestamp();
 
     data_size = command->txn.data_size;
     offsets_size = command->txn.offsets_size;
@@ -694,13 +1096,26 @@ int __noinline do_bc_br_transaction(pid_t pid, pid_t tid, char log_char) {
                                                  .read_size = offsets_size,
                                                  .read_consumed = 0,
                                                  .read_buffer = 0};
+    buffer->bwr.flags = 0;
+    buffer->bwr._pad = 0;
+
+    // write_size keeps the real size, only the copy is cut down to the snaplen
+    __u64 copy_size = snap_len(data_size);
+    if (copy_size < data_size) {
+        buffer->bwr.flags |= BWR_TRUNCATED;
+        if (copy_size == 0 && send_transaction_chunk(ctx, 0, (__u64)addr, 0) != 0) {
+            goto l_error;
+        }
+    }
+    data_size = copy_size;
 
-    for (size_t i = 0; i < MAX_TRANSACTION_CHUNKS && data_size > 0; i++) {
+    __u32 max_chunks = cfg_max_transaction_chunks;
+    for (size_t i = 0; i < MAX_TRANSACTION_CHUNKS_LIMIT && i < max_chunks && data_size > 0; i++) {
         __u64 chunk_size = data_size;
         if (chunk_size > (__u32)(sizeof(*buffer) - offsetof(struct write_read_buffer, bwr.data))) {
             chunk_size = (__u32)(sizeof(*buffer) - offsetof(struct write_read_buffer, bwr.data));
         }
-        if (handle_transaction_chunk(chunk_size, (__u64)addr, i) != 0) {
+        if (send_transaction_chunk(ctx, chunk_size, (__u64)addr, i) != 0) {
             goto l_error;
         }
         data_size -= chunk_size;
@@ -709,6 +1124,7 @@ int __noinline do_bc_br_transaction(pid_t pid, pid_t tid, char log_char) {
 
     if (data_size > 0) {
         LOG("b%c: failed to send all txn data, left %llu", log_char, data_size);
+        count_drop(DROP_TOO_MANY_CHUNKS);
     }
 
     // if (data_size > (__u32)(sizeof(*buffer) - offsetof(struct write_read_buffer, bwr.data))) {
@@ -718,7 +1134,7 @@ int __noinline do_bc_br_transaction(pid_t pid, pid_t tid, char log_char) {
     //     // goto l_error;
     // }
 
-    // if (bpf_probe_read_user(buffer->bwr.data, data_size, addr)) {
+    // if (probe_read_user(buffer->bwr.data, data_size, addr)) {
     //     LOG("failed to read txn data %u, %lx", data_size, command->txn.data.ptr.buffer);
     //     goto l_error;
     // }
@@ -739,6 +1155,19 @@ int __noinline do_bc_br_transaction(pid_t pid, pid_t tid, char log_char) {
         return 0;
     }
 
+    buffer->bwr.bwr.write_buffer = 0;
+    buffer->bwr.bwr.read_buffer = 1;
+    if (cfg_metadata_only) {
+        // only report the size, there is no payload to walk the objects of
+        buffer->bwr.bwr.read_consumed = 0;
+        buffer->bwr.flags = BWR_TRUNCATED;
+        if (event_output(ctx, buffer, offsetof(struct write_read_buffer, bwr.data))) {
+            count_drop(DROP_RINGBUF_FULL);
+            goto l_error;
+        }
+        return 0;
+    }
+
     if (offsets_size >
         (__u32)sizeof(*buffer) - (__u32)offsetof(struct write_read_buffer, bwr.data)) {
         LOG("offsets too big: %u + %llu > %llu", offsets_size,
@@ -746,30 +1175,29 @@ int __noinline do_bc_br_transaction(pid_t pid, pid_t tid, char log_char) {
         goto l_error;
     }
 
-    if (bpf_probe_read_user(buffer->bwr.data, offsets_size, UNTAG(command->txn.data.ptr.offsets))) {
+    if (probe_read_user(buffer->bwr.data, offsets_size, UNTAG(command->txn.data.ptr.offsets))) {
         LOG("failed to read txn offsets %u, %llx", offsets_size, command->txn.data.ptr.offsets);
+        count_drop(DROP_PROBE_READ);
         goto l_error;
     }
 
-    buffer->bwr.bwr.write_buffer = 0;
-    buffer->bwr.bwr.read_buffer = 1;
     buffer->bwr.bwr.read_consumed = offsets_size;
 
     LOG_RINGBUF("output: %u %x", offsetof(struct write_read_buffer, bwr.data) + offsets_size,
                 *(int *)buffer);
-    if (bpf_ringbuf_output(&binder_events_buffer, buffer,
-                           offsetof(struct write_read_buffer, bwr.data) + offsets_size, 0)) {
+    if (event_output(ctx, buffer, offsetof(struct write_read_buffer, bwr.data) + offsets_size)) {
         LOG("failed to output txn offsets");
+        count_drop(DROP_RINGBUF_FULL);
         goto l_error;
     }
 
-    {
+    if (with_ptrs) {
         struct ptr_walk_state *st = bpf_map_lookup_elem(&ptr_walk_state_map, &key);
         if (st) {
             st->task_id = ((__u64)pid << 32) | (__u32)tid;
             st->data_user_addr = (__u64)command->txn.data.ptr.buffer & 0xffffffffffffULL;
             st->offsets_size = offsets_size;
-            emit_ptr_payloads(buffer);
+            emit_ptr_payloads(ctx, buffer);
         }
     }
 
@@ -778,7 +1206,24 @@ l_error:
     return -1;
 }
 
-int __always_inline do_bc_br(uint32_t cmd, const bool is_return) {
+int __noinline do_bc_br_transaction(pid_t pid, pid_t tid, char log_char) {
+    return send_txn(NULL, pid, tid, log_char, 0);
+}
+
+int __noinline do_bc_br_transaction_ptrs(pid_t pid, pid_t tid, char log_char) {
+    return send_txn(NULL, pid, tid, log_char, 1);
+}
+
+// Only called on the perf buffer, verified along with its caller
+static __noinline int send_txn_perf(void *ctx, pid_t pid, pid_t tid, char log_char, int with_ptrs) {
+    if (with_ptrs) {
+        return send_txn(ctx, pid, tid, log_char, 1);
+    }
+    return send_txn(ctx, pid, tid, log_char, 0);
+}
+
+int __always_inline do_bc_br(void *ctx, uint32_t cmd, const bool is_return,
+                             const capture_tier_t tier) {
     __u64 task_id = bpf_get_current_pid_tgid();
     pid_t pid = task_id >> 32;
     pid_t tid = task_id & 0xffffffff;
@@ -797,12 +1242,12 @@ int __always_inline do_bc_br(uint32_t cmd, const bool is_return) {
     }
 
     if (is_return) {
-        if (do_transition(pid, tid, BINDER_RETURN)) {
+        if (do_transition(ctx, pid, tid, BINDER_RETURN)) {
             LOG("bad transition");
             goto l_error;
         }
     } else {
-        if (do_transition(pid, tid, BINDER_COMMAND)) {
+        if (do_transition(ctx, pid, tid, BINDER_COMMAND)) {
             LOG("bad transition");
             goto l_error;
         }
@@ -859,14 +1304,14 @@ int __always_inline do_bc_br(uint32_t cmd, const bool is_return) {
             goto cleanup;
         }
 
-        // TODO - support secctx?
         if (cmd == BR_TRANSACTION || cmd == BR_REPLY || cmd == BR_TRANSACTION_SEC_CTX) {
 
             if (bwr->read_buffer) {
-                if (bpf_probe_read_user(command, sizeof(*command),
-                                        UNTAG(bwr->read_buffer + bwr->read_consumed))) {
+                if (probe_read_user(command, sizeof(*command),
+                                    UNTAG(bwr->read_buffer + bwr->read_consumed))) {
                     LOG("failed to read BR data %px (cmd: %d)",
                         bwr->read_buffer + bwr->read_consumed, cmd);
+                    count_drop(DROP_PROBE_READ);
                     goto l_error;
                 }
             }
@@ -876,9 +1321,10 @@ int __always_inline do_bc_br(uint32_t cmd, const bool is_return) {
     } else if (cmd == BC_TRANSACTION || cmd == BC_REPLY || cmd == BC_TRANSACTION_SG ||
                cmd == BC_REPLY_SG) {
         // we don't care about the extra `buffers_size` field in `binder_transaction_data_sg`
-        if (bpf_probe_read_user(command, sizeof(*command),
-                                UNTAG(bwr->write_buffer + bwr->write_consumed))) {
+        if (probe_read_user(command, sizeof(*command),
+                            UNTAG(bwr->write_buffer + bwr->write_consumed))) {
             LOG("failed to read BC data %px", bwr->write_buffer + bwr->write_consumed);
+            count_drop(DROP_PROBE_READ);
             goto l_error;
         }
     } else {
@@ -890,10 +1336,13 @@ int __always_inline do_bc_br(uint32_t cmd, const bool is_return) {
         goto l_error;
     }
 
-    if (do_bc_br_transaction(pid, tid, log_char) != 0) {
+    if (send_txn_data(ctx, pid, tid, log_char, tier) != 0) {
         LOG("b%c failed to handle transaction command", log_char);
         goto l_error;
     }
+    if (cmd == BR_TRANSACTION_SEC_CTX) {
+        submit_txn_secctx(ctx, pid, tid, bwr->read_buffer + bwr->read_consumed);
+    }
     // LOG("txn offsets: %llu/%llu", buffer->bwr.bwr.read_consumed, buffer->bwr.bwr.read_size);
 
 cleanup:
@@ -916,10 +1365,34 @@ l_error:
 }
 
 SEC("tp/binder/binder_command")
-int binder_command(struct trace_event_raw_binder_command *ctx) { return do_bc_br(ctx->cmd, 0); }
+int binder_command(struct trace_event_raw_binder_command *ctx) {
+    return do_bc_br(ctx, ctx->cmd, 0, TIER_CORE);
+}
+
+SEC("tp/binder/binder_command")
+int binder_command_data(struct trace_event_raw_binder_command *ctx) {
+    return do_bc_br(ctx, ctx->cmd, 0, TIER_TXN_DATA);
+}
+
+SEC("tp/binder/binder_command")
+int binder_command_ptrs(struct trace_event_raw_binder_command *ctx) {
+    return do_bc_br(ctx, ctx->cmd, 0, TIER_PTR_PAYLOADS);
+}
+
+SEC("tp/binder/binder_return")
+int binder_return(struct trace_event_raw_binder_return *ctx) {
+    return do_bc_br(ctx, ctx->cmd, 1, TIER_CORE);
+}
+
+SEC("tp/binder/binder_return")
+int binder_return_data(struct trace_event_raw_binder_return *ctx) {
+    return do_bc_br(ctx, ctx->cmd, 1, TIER_TXN_DATA);
+}
 
 SEC("tp/binder/binder_return")
-int binder_return(struct trace_event_raw_binder_return *ctx) { return do_bc_br(ctx->cmd, 1); }
+int binder_return_ptrs(struct trace_event_raw_binder_return *ctx) {
+    return do_bc_br(ctx, ctx->cmd, 1, TIER_PTR_PAYLOADS);
+}
 
 // CO-RE-relocatable stubs for the three fields we touch. libbpf rewrites
 // each access to the target kernel's offset at load time using BTF;
@@ -942,10 +1415,29 @@ struct binder_transaction___local {
 const volatile __u32 cfg_off_to_thread = 0;
 const volatile __u32 cfg_off_transaction_stack = 0;
 const volatile __u32 cfg_off_debug_id = 0;
+// 4 on a 32-bit kernel, where only the low half of the pointers below is read
+const volatile __u32 cfg_ptr_size = 8;
+
+// Reads the kernel pointer at `src` into `dst`, whatever the kernel's pointer width. Returns the
+// error of bpf_probe_read_kernel, `dst` is left untouched on failure.
+static __always_inline long read_kernel_ptr(void **dst, const void *src) {
+    __u64 ptr = 0;
+    long err;
+    if (cfg_ptr_size == 4) {
+        err = probe_read_kernel(&ptr, 4, src);
+    } else {
+        err = probe_read_kernel(&ptr, 8, src);
+    }
+    if (err) {
+        return err;
+    }
+    *dst = (void *)ptr;
+    return 0;
+}
 
 // Shared submit path — debug_ids come from either branch. Inlined to
 // avoid an extra BPF helper call on a hot path.
-static __always_inline void submit_txn_stack(int request_debug_id, int reply_debug_id) {
+static __always_inline void submit_txn_stack(void *ctx, int request_debug_id, int reply_debug_id) {
     // debug_id is monotonic from 1 in the kernel; treat 0 as a failed read.
     if (request_debug_id == 0 || reply_debug_id == 0) {
         return;
@@ -957,22 +1449,23 @@ static __always_inline void submit_txn_stack(int request_debug_id, int reply_deb
     struct binder_event *event = NULL;
     struct binder_event_transaction_stack *txn_event = NULL;
 
-    event = bpf_ringbuf_reserve(&binder_events_buffer, sizeof(*event) + sizeof(*txn_event), 0);
+    event = event_reserve(sizeof(*event) + sizeof(*txn_event));
     if (!event) {
         LOG("Failed to reserved txn stack event");
+        count_drop(DROP_RINGBUF_FULL);
         return;
     }
     event->type = BINDER_TXN_STACK;
     event->pid = pid;
     event->tid = tid;
-    event->timestamp = bpf_ktime_get_boot_ns();
+    event->timestamp = event_timestamp();
 
     txn_event = (struct binder_event_transaction_stack *)(event + 1);
     txn_event->reply_debug_id = reply_debug_id;
     txn_event->request_debug_id = request_debug_id;
 
     LOG_RINGBUF("submit %u %x", sizeof(*event) + sizeof(*txn_event), *(int *)event);
-    bpf_ringbuf_submit(event, 0);
+    event_submit(ctx, event, 0);
 }
 
 // KEEP these two before binder_transaction tracepoint so they are executed first.
@@ -996,7 +1489,7 @@ int raw_binder_transaction_core(struct bpf_raw_tracepoint_args *ctx) {
 
     int request_debug_id = BPF_CORE_READ(stack, debug_id);
     int reply_debug_id = BPF_CORE_READ(transaction, debug_id);
-    submit_txn_stack(request_debug_id, reply_debug_id);
+    submit_txn_stack(ctx, request_debug_id, reply_debug_id);
     return 0;
 }
 
@@ -1012,33 +1505,31 @@ int raw_binder_transaction_manual(struct bpf_raw_tracepoint_args *ctx) {
     int request_debug_id = 0;
     int reply_debug_id = 0;
 
-    if (bpf_probe_read_kernel(&to_thread_raw, sizeof(to_thread_raw),
-                              transaction_raw + cfg_off_to_thread)) {
+    if (read_kernel_ptr(&to_thread_raw, transaction_raw + cfg_off_to_thread)) {
         LOG("raw_binder_transaction: manual read of to_thread failed");
         return 0;
     }
     if (!to_thread_raw) {
         return 0;
     }
-    if (bpf_probe_read_kernel(&stack_raw, sizeof(stack_raw),
-                              to_thread_raw + cfg_off_transaction_stack)) {
+    if (read_kernel_ptr(&stack_raw, to_thread_raw + cfg_off_transaction_stack)) {
         LOG("raw_binder_transaction: manual read of transaction_stack failed");
         return 0;
     }
     if (!stack_raw) {
         return 0;
     }
-    if (bpf_probe_read_kernel(&request_debug_id, sizeof(request_debug_id),
-                              stack_raw + cfg_off_debug_id)) {
+    if (probe_read_kernel(&request_debug_id, sizeof(request_debug_id),
+                          stack_raw + cfg_off_debug_id)) {
         LOG("raw_binder_transaction: manual read of request debug_id failed");
         return 0;
     }
-    if (bpf_probe_read_kernel(&reply_debug_id, sizeof(reply_debug_id),
-                              transaction_raw + cfg_off_debug_id)) {
+    if (probe_read_kernel(&reply_debug_id, sizeof(reply_debug_id),
+                          transaction_raw + cfg_off_debug_id)) {
         LOG("raw_binder_transaction: manual read of reply debug_id failed");
         return 0;
     }
-    submit_txn_stack(request_debug_id, reply_debug_id);
+    submit_txn_stack(ctx, request_debug_id, reply_debug_id);
     return 0;
 }
 
@@ -1049,20 +1540,20 @@ int binder_transaction(struct trace_event_raw_binder_transaction *ctx) {
     pid_t tid = task_id & 0xffffffff;
     struct binder_event *event = NULL;
     struct binder_event_transaction *txn_event = NULL;
-    if (do_transition(pid, tid, BINDER_TXN)) {
+    if (do_transition(ctx, pid, tid, BINDER_TXN)) {
         return 0;
     }
 
-    event = bpf_ringbuf_reserve(&binder_events_buffer,
-                                sizeof(*event) + sizeof(struct binder_event_transaction), 0);
+    event = event_reserve(sizeof(*event) + sizeof(struct binder_event_transaction));
     if (!event) {
         LOG("Failed to reserved txn event");
+        count_drop(DROP_RINGBUF_FULL);
         return 0;
     }
     event->type = BINDER_TXN;
     event->pid = pid;
     event->tid = tid;
-    event->timestamp = bpf_ktime_get_boot_ns();
+    event->timestamp = event_timestamp();
 
     txn_event = (struct binder_event_transaction *)(event + 1);
     txn_event->debug_id = ctx->debug_id;
@@ -1075,7 +1566,9 @@ int binder_transaction(struct trace_event_raw_binder_transaction *ctx) {
 
     LOG_RINGBUF("submit %u %x", sizeof(*event) + sizeof(struct binder_event_transaction),
                 *(int *)event);
-    bpf_ringbuf_submit(event, 0);
+    event_submit(ctx, event, 0);
+
+    filter_mark_peer(ctx->to_proc, ctx->to_thread, ctx->reply);
 
     return 0;
 }
@@ -1088,29 +1581,441 @@ int binder_transaction_received(struct trace_event_raw_binder_transaction_receiv
     pid_t tid = task_id & 0xffffffff;
     struct binder_event *event = NULL;
     struct binder_event_transaction_received *txn_event = NULL;
-    if (do_transition(pid, tid, BINDER_TXN_RECEIVED)) {
+    if (do_transition(ctx, pid, tid, BINDER_TXN_RECEIVED)) {
         return 0;
     }
 
-    event =
-        bpf_ringbuf_reserve(&binder_events_buffer,
-                            sizeof(*event) + sizeof(struct binder_event_transaction_received), 0);
+    event = event_reserve(sizeof(*event) + sizeof(struct binder_event_transaction_received));
     if (!event) {
         LOG("Failed to reserved txn event");
+        count_drop(DROP_RINGBUF_FULL);
         return 0;
     }
     event->type = BINDER_TXN_RECEIVED;
     event->pid = pid;
     event->tid = tid;
-    event->timestamp = bpf_ktime_get_boot_ns();
+    event->timestamp = event_timestamp();
 
     txn_event = (struct binder_event_transaction_received *)(event + 1);
     txn_event->debug_id = ctx->debug_id;
 
     LOG_RINGBUF("submit %u %x", sizeof(*event) + sizeof(struct binder_event_transaction_received),
                 *(int *)event);
-    bpf_ringbuf_submit(event, 0);
+    event_submit(ctx, event, 0);
+
+    return 0;
+}
+
+// Only for the transactions we sent a BINDER_TXN for, the translations run right after it
+static __always_inline int submit_translation(void *ctx, int debug_id, translation_kind_t kind,
+                                              int node_debug_id, __u64 src, __u64 dest) {
+    __u64 task_id = bpf_get_current_pid_tgid();
+    pid_t pid = task_id >> 32;
+    pid_t tid = task_id & 0xffffffff;
+    binder_process_state_t *state = get_process_state(tid);
+    if (!state || *state != BINDER_TXN) {
+        return 0;
+    }
+
+    struct binder_event *event = NULL;
+    struct binder_event_txn_translation *translation = NULL;
+    event = event_reserve(sizeof(*event) + sizeof(*translation));
+    if (!event) {
+        LOG("Failed to reserve txn translation event");
+        count_drop(DROP_RINGBUF_FULL);
+        return 0;
+    }
+    event->type = BINDER_TXN_TRANSLATION;
+    event->pid = pid;
+    event->tid = tid;
+    event->timestamp = event_timestamp();
+
+    translation = (struct binder_event_txn_translation *)(event + 1);
+    translation->debug_id = debug_id;
+    translation->kind = kind;
+    translation->node_debug_id = node_debug_id;
+    translation->_pad = 0;
+    translation->src = src;
+    translation->dest = dest;
+
+    LOG_RINGBUF("submit %u %x", sizeof(*event) + sizeof(*translation), *(int *)event);
+    event_submit(ctx, event, 0);
+    return 0;
+}
+
+SEC("tp/binder/binder_transaction_node_to_ref")
+int binder_transaction_node_to_ref(struct trace_event_raw_binder_transaction_node_to_ref *ctx) {
+    return submit_translation(ctx, ctx->debug_id, TRANSLATION_NODE_TO_REF, ctx->node_debug_id,
+                              ctx->node_ptr, ctx->ref_desc);
+}
+
+SEC("tp/binder/binder_transaction_ref_to_node")
+int binder_transaction_ref_to_node(struct trace_event_raw_binder_transaction_ref_to_node *ctx) {
+    return submit_translation(ctx, ctx->debug_id, TRANSLATION_REF_TO_NODE, ctx->node_debug_id,
+                              ctx->ref_desc, ctx->node_ptr);
+}
+
+SEC("tp/binder/binder_transaction_ref_to_ref")
+int binder_transaction_ref_to_ref(struct trace_event_raw_binder_transaction_ref_to_ref *ctx) {
+    return submit_translation(ctx, ctx->debug_id, TRANSLATION_REF_TO_REF, ctx->node_debug_id,
+                              ctx->src_ref_desc, ctx->dest_ref_desc);
+}
+
+// CO-RE stubs for resolving the file behind a fd, only loaded with kernel BTF (see
+// btf_probe::fd_paths_supported)
+struct qstr___local {
+    const unsigned char *name;
+} __attribute__((preserve_access_index));
+
+struct super_block___local {
+    unsigned long s_magic;
+} __attribute__((preserve_access_index));
+
+struct inode___local {
+    unsigned short i_mode;
+    unsigned long i_ino;
+    struct super_block___local *i_sb;
+} __attribute__((preserve_access_index));
+
+struct dentry___local {
+    struct dentry___local *d_parent;
+    struct qstr___local d_name;
+} __attribute__((preserve_access_index));
+
+struct vfsmount___local {
+    struct dentry___local *mnt_root;
+} __attribute__((preserve_access_index));
+
+struct mount___local {
+    struct mount___local *mnt_parent;
+    struct dentry___local *mnt_mountpoint;
+    struct vfsmount___local mnt;
+} __attribute__((preserve_access_index));
+
+struct path___local {
+    struct vfsmount___local *mnt;
+    struct dentry___local *dentry;
+} __attribute__((preserve_access_index));
+
+struct file___local {
+    struct path___local f_path;
+    struct inode___local *f_inode;
+} __attribute__((preserve_access_index));
+
+struct fdtable___local {
+    unsigned int max_fds;
+    struct file___local **fd;
+} __attribute__((preserve_access_index));
+
+struct files_struct___local {
+    struct fdtable___local *fdt;
+} __attribute__((preserve_access_index));
+
+struct task_struct___local {
+    struct files_struct___local *files;
+} __attribute__((preserve_access_index));
+
+// every name on the way up, plus a mount crossing per mount point
+#define FD_PATH_STEPS (FD_PATH_DEPTH + 8)
+
+// Fills the inode and the dentry names of `fd` of the current task. Walks up the dentries like
+// d_path does, jumping to the mount point at the root of every mount but the namespace's (or an
+// internal one, like sockfs or the shmem mount of memfds).
+static __always_inline void resolve_fd(int fd, struct binder_event_txn_fd *txn_fd) {
+    struct task_struct___local *task = (struct task_struct___local *)bpf_get_current_task();
+    struct fdtable___local *fdt = BPF_CORE_READ(task, files, fdt);
+    if (!fdt || fd < 0 || (unsigned int)fd >= BPF_CORE_READ(fdt, max_fds)) {
+        return;
+    }
+    struct file___local **fds = BPF_CORE_READ(fdt, fd);
+    struct file___local *file = NULL;
+    if (probe_read_kernel(&file, sizeof(file), &fds[fd]) || !file) {
+        count_drop(DROP_PROBE_READ);
+        return;
+    }
+
+    struct inode___local *inode = BPF_CORE_READ(file, f_inode);
+    txn_fd->mode = BPF_CORE_READ(inode, i_mode);
+    txn_fd->ino = BPF_CORE_READ(inode, i_ino);
+    txn_fd->magic = BPF_CORE_READ(inode, i_sb, s_magic);
+
+    struct dentry___local *dentry = BPF_CORE_READ(file, f_path.dentry);
+    struct vfsmount___local *vfsmnt = BPF_CORE_READ(file, f_path.mnt);
+    struct mount___local *mnt = (void *)vfsmnt - bpf_core_field_offset(struct mount___local, mnt);
+    __u32 depth = 0;
+    for (int i = 0; i < FD_PATH_STEPS; i++) {
+        struct dentry___local *parent = BPF_CORE_READ(dentry, d_parent);
+        if (dentry == BPF_CORE_READ(vfsmnt, mnt_root) || dentry == parent) {
+            struct mount___local *mnt_parent = BPF_CORE_READ(mnt, mnt_parent);
+            if (mnt_parent == mnt) {
+                break;
+            }
+            dentry = BPF_CORE_READ(mnt, mnt_mountpoint);
+            mnt = mnt_parent;
+            vfsmnt = __builtin_preserve_access_index(&mnt->mnt);
+            continue;
+        }
+        if (depth >= FD_PATH_DEPTH) {
+            txn_fd->truncated = 1;
+            break;
+        }
+        char *name = txn_fd->names[depth & (FD_PATH_DEPTH - 1)];
+        const unsigned char *d_name = BPF_CORE_READ(dentry, d_name.name);
+        if (probe_read_kernel_str(name, FD_NAME_SIZE, d_name) < 0) {
+            count_drop(DROP_PROBE_READ);
+            break;
+        }
+        depth++;
+        dentry = parent;
+    }
+    txn_fd->depth = depth;
+}
+
+// Sends are only for the transactions we sent a BINDER_TXN for, like the translations. The target
+// installs its fds while it reads the transaction, right before its BINDER_TXN_RECEIVED.
+static __always_inline int submit_txn_fd(void *ctx, int debug_id, int fd, __u64 offset,
+                                         txn_fd_direction_t direction, bool resolve) {
+    __u64 task_id = bpf_get_current_pid_tgid();
+    pid_t pid = task_id >> 32;
+    pid_t tid = task_id & 0xffffffff;
+    if (direction == TXN_FD_SEND) {
+        binder_process_state_t *state = get_process_state(tid);
+        if (!state || *state != BINDER_TXN) {
+            return 0;
+        }
+    } else if (filter_check(pid, tid) == FILTER_DROP) {
+        return 0;
+    }
+
+    struct binder_event *event = NULL;
+    struct binder_event_txn_fd *txn_fd = NULL;
+    event = event_reserve(sizeof(*event) + sizeof(*txn_fd));
+    if (!event) {
+        LOG("Failed to reserve txn fd event");
+        count_drop(DROP_RINGBUF_FULL);
+        return 0;
+    }
+    event->type = BINDER_TXN_FD;
+    event->pid = pid;
+    event->tid = tid;
+    event->timestamp = event_timestamp();
+
+    txn_fd = (struct binder_event_txn_fd *)(event + 1);
+    txn_fd->debug_id = debug_id;
+    txn_fd->fd = fd;
+    txn_fd->offset = offset;
+    txn_fd->direction = direction;
+    txn_fd->depth = 0;
+    txn_fd->truncated = 0;
+    txn_fd->mode = 0;
+    txn_fd->ino = 0;
+    txn_fd->magic = 0;
+    if (resolve) {
+        resolve_fd(fd, txn_fd);
+    }
+
+    LOG_RINGBUF("submit %u %x", sizeof(*event) + sizeof(*txn_fd), *(int *)event);
+    event_submit(ctx, event, 0);
+    return 0;
+}
+
+// At most one of these two is autoloaded (userspace picks), the core one with kernel BTF
+SEC("tp/binder/binder_transaction_fd_send")
+int binder_transaction_fd_send_core(struct trace_event_raw_binder_transaction_fd_send *ctx) {
+    return submit_txn_fd(ctx, ctx->debug_id, ctx->fd, ctx->offset, TXN_FD_SEND, true);
+}
+
+SEC("tp/binder/binder_transaction_fd_send")
+int binder_transaction_fd_send_plain(struct trace_event_raw_binder_transaction_fd_send *ctx) {
+    return submit_txn_fd(ctx, ctx->debug_id, ctx->fd, ctx->offset, TXN_FD_SEND, false);
+}
+
+SEC("tp/binder/binder_transaction_fd_recv")
+int binder_transaction_fd_recv(struct trace_event_raw_binder_transaction_fd_recv *ctx) {
+    return submit_txn_fd(ctx, ctx->debug_id, ctx->fd, ctx->offset, TXN_FD_RECV, false);
+}
+
+// CO-RE stub for the scheduling class of the sender, only loaded with kernel BTF (see
+// btf_probe::sched_prio_supported)
+struct task_struct___sched {
+    unsigned int policy;
+    int normal_prio;
+} __attribute__((preserve_access_index));
+
+// Runs along with binder_transaction, in the sender's context. Threads we don't trace have no
+// state, or stay BINDER_INVALID when filtered out.
+SEC("tp/binder/binder_transaction")
+int binder_transaction_prio(struct trace_event_raw_binder_transaction *ctx) {
+    __u64 task_id = bpf_get_current_pid_tgid();
+    pid_t pid = task_id >> 32;
+    pid_t tid = task_id & 0xffffffff;
+    binder_process_state_t *state = get_process_state(tid);
+    if (!state || *state == BINDER_INVALID || ctx->reply) {
+        return 0;
+    }
+
+    struct task_struct___sched *task = (struct task_struct___sched *)bpf_get_current_task();
+    struct binder_event *event = NULL;
+    struct binder_event_txn_prio *prio = NULL;
+    event = event_reserve(sizeof(*event) + sizeof(*prio));
+    if (!event) {
+        LOG("Failed to reserve txn prio event");
+        count_drop(DROP_RINGBUF_FULL);
+        return 0;
+    }
+    event->type = BINDER_TXN_PRIO;
+    event->pid = pid;
+    event->tid = tid;
+    event->timestamp = event_timestamp();
+
+    prio = (struct binder_event_txn_prio *)(event + 1);
+    prio->debug_id = ctx->debug_id;
+    prio->policy = BPF_CORE_READ(task, policy);
+    prio->prio = BPF_CORE_READ(task, normal_prio);
+
+    LOG_RINGBUF("submit %u %x", sizeof(*event) + sizeof(*prio), *(int *)event);
+    event_submit(ctx, event, 0);
+    return 0;
+}
+
+// Fires in the context of whoever changes the priority: the sender handing its own to the thread
+// that will take the transaction, or the thread itself when it picks up work or restores its
+// priority after replying.
+SEC("tp/binder/binder_set_priority")
+int binder_set_priority(struct trace_event_raw_binder_set_priority *ctx) {
+    binder_process_state_t *state = get_process_state(ctx->thread);
+    if (!state || *state == BINDER_INVALID) {
+        return 0;
+    }
+
+    struct binder_event *event = NULL;
+    struct binder_event_set_priority *set_priority = NULL;
+    event = event_reserve(sizeof(*event) + sizeof(*set_priority));
+    if (!event) {
+        LOG("Failed to reserve set priority event");
+        count_drop(DROP_RINGBUF_FULL);
+        return 0;
+    }
+    event->type = BINDER_SET_PRIORITY;
+    event->pid = ctx->proc;
+    event->tid = ctx->thread;
+    event->timestamp = event_timestamp();
+
+    set_priority = (struct binder_event_set_priority *)(event + 1);
+    set_priority->old_prio = ctx->old_prio;
+    set_priority->desired_prio = ctx->desired_prio;
+    set_priority->new_prio = ctx->new_prio;
+
+    LOG_RINGBUF("submit %u %x", sizeof(*event) + sizeof(*set_priority), *(int *)event);
+    event_submit(ctx, event, 0);
+    return 0;
+}
+
+// --stacks: the user stack of the caller of a transaction. Runs along with binder_command, before
+// binder_transaction, so the stack is part of the caller's packet.
+SEC("tp/binder/binder_command")
+int binder_user_stack(struct trace_event_raw_binder_command *ctx) {
+    if (ctx->cmd != BC_TRANSACTION && ctx->cmd != BC_TRANSACTION_SG) {
+        return 0;
+    }
+    __u64 task_id = bpf_get_current_pid_tgid();
+    pid_t pid = task_id >> 32;
+    pid_t tid = task_id & 0xffffffff;
+    binder_process_state_t *state = get_process_state(tid);
+    if (!state || *state == BINDER_INVALID) {
+        return 0;
+    }
+
+    struct binder_event *event = NULL;
+    struct binder_event_txn_user_stack *stack = NULL;
+    event = event_reserve(sizeof(*event) + sizeof(*stack));
+    if (!event) {
+        LOG("Failed to reserve user stack event");
+        count_drop(DROP_RINGBUF_FULL);
+        return 0;
+    }
+    stack = (struct binder_event_txn_user_stack *)(event + 1);
+    long size = bpf_get_stack(ctx, stack->ips, sizeof(stack->ips), BPF_F_USER_STACK);
+    if (size < 0) {
+        LOG("Failed to get user stack: %ld", size);
+        event_discard(event);
+        return 0;
+    }
+    event->type = BINDER_TXN_USER_STACK;
+    event->pid = pid;
+    event->tid = tid;
+    event->timestamp = event_timestamp();
+    stack->depth = size / sizeof(stack->ips[0]);
+    stack->_pad = 0;
+
+    LOG_RINGBUF("submit %u %x", sizeof(*event) + sizeof(*stack), *(int *)event);
+    event_submit(ctx, event, 0);
+    return 0;
+}
+
+// --wakeup-latency: when a thread sleeping in binder_wait_for_work is woken, and when it gets on a
+// CPU after that. Only threads we trace are ever in BINDER_WAIT_FOR_WORK.
+SEC("tp/sched/sched_waking")
+int binder_sched_waking(struct trace_event_raw_sched_wakeup_template *ctx) {
+    pid_t tid = ctx->pid;
+    binder_process_state_t *state = get_process_state(tid);
+    if (!state || *state != BINDER_WAIT_FOR_WORK) {
+        return 0;
+    }
+    struct wakeup_times times = {
+        .woken = event_timestamp(),
+        .on_cpu = 0,
+    };
+    bpf_map_update_elem(&wakeup_times_map, &tid, &times, BPF_ANY);
+    return 0;
+}
+
+SEC("tp/sched/sched_switch")
+int binder_sched_switch(struct trace_event_raw_sched_switch *ctx) {
+    pid_t tid = ctx->next_pid;
+    struct wakeup_times *times = bpf_map_lookup_elem(&wakeup_times_map, &tid);
+    if (times && !times->on_cpu) {
+        times->on_cpu = event_timestamp();
+    }
+    return 0;
+}
+
+// Runs along with binder_transaction_received, in the receiving thread's context
+SEC("tp/binder/binder_transaction_received")
+int binder_transaction_wakeup(struct trace_event_raw_binder_transaction_received *ctx) {
+    __u64 task_id = bpf_get_current_pid_tgid();
+    pid_t pid = task_id >> 32;
+    pid_t tid = task_id & 0xffffffff;
+    struct wakeup_times *times = bpf_map_lookup_elem(&wakeup_times_map, &tid);
+    if (!times) {
+        return 0;
+    }
+    __u64 woken = times->woken;
+    // woken before it got off the CPU, there is no sched_switch to it
+    __u64 on_cpu = times->on_cpu ? times->on_cpu : woken;
+    bpf_map_delete_elem(&wakeup_times_map, &tid);
+
+    struct binder_event *event = NULL;
+    struct binder_event_txn_wakeup *wakeup = NULL;
+    event = event_reserve(sizeof(*event) + sizeof(*wakeup));
+    if (!event) {
+        LOG("Failed to reserve txn wakeup event");
+        count_drop(DROP_RINGBUF_FULL);
+        return 0;
+    }
+    event->type = BINDER_TXN_WAKEUP;
+    event->pid = pid;
+    event->tid = tid;
+    event->timestamp = event_timestamp();
+
+    wakeup = (struct binder_event_txn_wakeup *)(event + 1);
+    wakeup->debug_id = ctx->debug_id;
+    wakeup->_pad = 0;
+    wakeup->woken = woken;
+    wakeup->on_cpu = on_cpu;
 
+    LOG_RINGBUF("submit %u %x", sizeof(*event) + sizeof(*wakeup), *(int *)event);
+    event_submit(ctx, event, 0);
     return 0;
 }
 
@@ -1118,7 +2023,7 @@ SEC("tp/binder/binder_write_done")
 int binder_write_done(void *ctx) {
     pid_t tid = GET_TID();
     pid_t pid = GET_PID();
-    do_transition(pid, tid, BINDER_WRITE_DONE);
+    do_transition(ctx, pid, tid, BINDER_WRITE_DONE);
     return 0;
 }
 
@@ -1126,7 +2031,7 @@ SEC("tp/binder/binder_wait_for_work")
 int binder_wait_for_work(void *ctx) {
     pid_t tid = GET_TID();
     pid_t pid = GET_PID();
-    do_transition(pid, tid, BINDER_WAIT_FOR_WORK);
+    do_transition(ctx, pid, tid, BINDER_WAIT_FOR_WORK);
     return 0;
 }
 
@@ -1134,7 +2039,7 @@ SEC("tp/binder/binder_read_done")
 int binder_read_done(void *ctx) {
     pid_t tid = GET_TID();
     pid_t pid = GET_PID();
-    do_transition(pid, tid, BINDER_READ_DONE);
+    do_transition(ctx, pid, tid, BINDER_READ_DONE);
     return 0;
 }
 
@@ -1144,7 +2049,7 @@ int binder_ioctl_done(struct trace_event_raw_binder_ioctl_done *ctx) {
     pid_t pid = GET_PID();
     struct ioctl_context *ioctl_ctx = NULL;
 
-    if (do_transition(pid, tid, BINDER_IOCTL_DONE)) {
+    if (do_transition(ctx, pid, tid, BINDER_IOCTL_DONE)) {
         return 0;
     }
 
@@ -28,15 +28,88 @@ typedef enum {
     BINDER_TXN_STACK, // gets sent when a reply transaction is received for another transaction.
     BINDER_TXN_PTR_DATA, // payload bytes of a BINDER_TYPE_PTR scatter-gather buffer referenced
                          // from a transaction's offsets array.
+    BINDER_NEW_PROCESS,  // psuedo state sent from sched_process_fork when a process (not a thread)
+                         // is created
+    BINDER_EXEC,         // psuedo state sent from sched_process_exec
+    BINDER_TXN_TRANSLATION, // gets sent when a binder object in a transaction is translated for
+                            // the target process (binder_transaction_{node,ref}_to_{ref,node})
+    BINDER_TXN_FD, // gets sent when a fd in a transaction is sent (binder_transaction_fd_send) and
+                   // when the target installs it (binder_transaction_fd_recv)
+    BINDER_TXN_SECCTX, // gets sent after the BINDER_TXN_DATA of a BR_TRANSACTION_SEC_CTX, with the
+                       // security context of the sender
+    BINDER_IOCTL_ARG,  // gets sent after BINDER_IOCTL and before BINDER_IOCTL_DONE, iff cmd takes a
+                       // struct and isn't BINDER_WRITE_READ
+    BINDER_TXN_PRIO,   // gets sent after the BINDER_TXN of a transaction (not a reply), with the
+                       // scheduling class of the sender
+    BINDER_SET_PRIORITY, // gets sent when binder changes the priority of a thread
+                         // (binder_set_priority, Android common kernels only)
+    BINDER_TXN_WAKEUP,   // gets sent with the BINDER_TXN_RECEIVED of a thread that slept waiting
+                         // for work, iff --wakeup-latency
+    BINDER_TXN_USER_STACK, // gets sent before the BINDER_TXN of a BC_TRANSACTION or
+                           // BC_TRANSACTION_SG, iff --stacks
 } binder_process_state_t;
 
+// Bits of the `cfg_filter_flags` rodata (set from userspace via --pid/--uid/--comm/--exclude-pid).
+// A set bit means the matching `filter_*` map was populated and has to be consulted.
+typedef enum {
+    CAPTURE_FILTER_PID = 1 << 0,
+    CAPTURE_FILTER_UID = 1 << 1,
+    CAPTURE_FILTER_COMM = 1 << 2,
+    CAPTURE_FILTER_EXCLUDE_PID = 1 << 3,
+} capture_filter_flags_t;
+
+// Why an event didn't make it to userspace. Index into the per-cpu `drop_counters` map, which
+// userspace sums up at the end of the capture.
+typedef enum {
+    DROP_RINGBUF_FULL = 0,     // bpf_ringbuf_reserve / bpf_ringbuf_output failed
+    DROP_PROBE_READ,           // failed to read the ioctl argument or a buffer from the task
+    DROP_TOO_MANY_CHUNKS,      // transaction data didn't fit in --max-chunks chunks
+    DROP_TOO_MANY_PTR_OBJECTS, // more than --max-ptr-objects objects, the rest weren't walked
+    DROP_REASON_MAX,
+} capture_drop_reason_t;
+
+// Capture limits (see limits.h). The defaults are used unless userspace says otherwise, the
+// *_LIMIT ceilings are what the loops and copies are verified against, userspace keeps the
+// options within them.
+//
+// We send chunks of size 32KB, 32 of them let us send up to 1MB transactions, which is the maximum
+// size Android mmaps the binder driver and therefore the maximum size of a binder transaction.
+// I never saw a transaction with offsets_size > 32KB (which makes sense, parcels don't tend to have
+// so many nested fields), so we only handle data_size > 32KB.
+#define MAX_TRANSACTION_CHUNKS 32
+#define MAX_TRANSACTION_CHUNKS_LIMIT 32
+// Maximum number of objects in a transaction's offsets array we'll walk to look for
+// BINDER_TYPE_PTR scatter-gather buffers. Most transactions have far fewer than this;
+// the cap keeps the BPF verifier happy and prevents pathological loops.
+#define MAX_PTR_OBJECTS 32
+#define MAX_PTR_OBJECTS_LIMIT 32
+// Maximum bytes of a single BINDER_TYPE_PTR scatter-gather buffer we copy up.
+// Anything larger gets truncated; the userspace `total_size` field still records the
+// real length so the user knows.
+#define MAX_PTR_PAYLOAD 16384
+// the 32K scratch buffer minus the binder_event and binder_event_txn_ptr_data headers
+#define MAX_PTR_PAYLOAD_LIMIT 32712
+// Size of the ring buffer, in MB. Has to be a power of two, the kernel takes up to 2GB.
+#define RING_SIZE_MB 64
+#define RING_SIZE_MB_LIMIT 2048
+
+// Bits of binder_event_write_read.flags
+typedef enum {
+    // data[] holds less than the whole buffer (--snaplen / --metadata-only)
+    BWR_TRUNCATED = 1 << 0,
+    // a truncated BINDER_WRITE buffer holds a BC_TRANSACTION or BC_REPLY, which userspace can't
+    // find out by itself anymore
+    BWR_HAS_TRANSACTION = 1 << 1,
+} bwr_event_flags_t;
+
 // header before every message
 struct binder_event {
     binder_process_state_t type;
     pid_t pid;
     pid_t tid;
-    // CLOCK_BOOTTIME at time of event capture
-    // this requires kernel >=5.8, but so does ring buffer
+    // length of the record, only set on the perf buffer, which pads its records to 8 bytes
+    __u32 size;
+    // CLOCK_BOOTTIME at time of event capture, see event_timestamp
     __u64 timestamp;
 };
 
@@ -60,9 +133,21 @@ struct binder_event_ioctl_done {
     int ret;
 };
 
+// the largest argument of the ioctls with a BINDER_IOCTL_ARG is 24 bytes
+#define IOCTL_ARG_SIZE 32
+
+// BINDER_IOCTL_ARG message
+struct binder_event_ioctl_arg {
+    __u32 done; // 0 when the ioctl starts, 1 when it returns
+    __u32 size; // bytes of data, the _IOC_SIZE of the cmd
+    char data[IOCTL_ARG_SIZE];
+};
+
 // BINDER_WRITE or BINDER_READ message
 struct binder_event_write_read {
     struct binder_write_read bwr;
+    __u32 flags; // bwr_event_flags_t
+    __u32 _pad;
     char data[];
 };
 
@@ -85,6 +170,113 @@ struct binder_event_transaction_stack {
     int request_debug_id;
 };
 
+typedef enum {
+    TRANSLATION_NODE_TO_REF = 0, // a local binder sent to another process, becomes a handle
+    TRANSLATION_REF_TO_NODE,     // a handle sent back to the process that owns the node
+    TRANSLATION_REF_TO_REF,      // a handle sent to a third process
+} translation_kind_t;
+
+// BINDER_TXN_TRANSLATION message. Sent from the sender's context, after its BINDER_TXN
+struct binder_event_txn_translation {
+    int debug_id; // of the transaction
+    __u32 kind;   // translation_kind_t
+    int node_debug_id;
+    __u32 _pad;
+    // the node ptr (NODE_TO_REF) or handle in the sender
+    __u64 src;
+    // the handle (NODE_TO_REF, REF_TO_REF) or node ptr in the target
+    __u64 dest;
+};
+
+typedef enum {
+    TXN_FD_SEND = 0, // from the sender's context, with the file behind the sender's fd
+    TXN_FD_RECV,     // from the target's context, with the fd it got
+} txn_fd_direction_t;
+
+// dentry names of the file of a sent fd, from the file up to the root of the mount namespace
+#define FD_PATH_DEPTH 16
+#define FD_NAME_SIZE 64
+
+// BINDER_TXN_FD message. One per fd of a FD object, and per element of a FDA object
+struct binder_event_txn_fd {
+    int debug_id;     // of the transaction
+    int fd;           // in the sender (TXN_FD_SEND) or the target (TXN_FD_RECV)
+    __u64 offset;     // of the fd in the transaction buffer, the same on both sides
+    __u32 direction;  // txn_fd_direction_t
+    __u32 depth;      // names[] used, 0 if the file wasn't resolved (TXN_FD_RECV, no BTF)
+    __u32 truncated;  // the path has more than FD_PATH_DEPTH names, the outermost are missing
+    __u32 mode;       // i_mode of the inode
+    __u64 ino;        // i_ino of the inode
+    __u64 magic;      // s_magic of the superblock, tells sockets, pipes and anon inodes apart
+    char names[FD_PATH_DEPTH][FD_NAME_SIZE]; // innermost first
+};
+
+// the kernel passes the context NUL terminated, the longest MLS contexts fit
+#define SECCTX_SIZE 256
+
+// BINDER_TXN_SECCTX message
+struct binder_event_txn_secctx {
+    char secctx[SECCTX_SIZE];
+};
+
+// BINDER_TXN_PRIO message
+struct binder_event_txn_prio {
+    int debug_id;
+    __u32 policy; // SCHED_NORMAL, SCHED_FIFO, ...
+    __u32 prio;   // normal_prio of the sender, what binder hands over to the target
+};
+
+// BINDER_SET_PRIORITY message. The header pid/tid are of the thread whose priority changed, which
+// isn't always the current one
+struct binder_event_set_priority {
+    __u32 old_prio;
+    __u32 desired_prio;
+    __u32 new_prio;
+};
+
+// BINDER_TXN_WAKEUP message. CLOCK_BOOTTIME, like the header timestamp
+struct binder_event_txn_wakeup {
+    int debug_id;
+    __u32 _pad;
+    __u64 woken;  // sched_waking of the thread
+    __u64 on_cpu; // the sched_switch to it after that
+};
+
+// frames of a user stack, a deeper one is cut short
+#define USER_STACK_DEPTH 64
+
+// BINDER_TXN_USER_STACK message
+struct binder_event_txn_user_stack {
+    __u32 depth; // ips[] used
+    __u32 _pad;
+    __u64 ips[USER_STACK_DEPTH]; // return addresses, innermost first
+};
+
+// BINDER_NEW_PROCESS message. The header pid/tid are the parent's
+struct binder_event_new_process {
+    pid_t child_pid;
+    char comm[16];
+    uid_t uid;
+};
+
+#define EXEC_FILENAME_SIZE 256
+// argv, NUL separated. EXEC_ARG_SIZE bytes of slack after it, so that every argument can be read
+// with a constant size
+#define EXEC_ARGS_SIZE 512
+#define EXEC_ARG_SIZE 128
+#define MAX_EXEC_ARGS 16
+
+// BINDER_EXEC message. The header pid/tid are after the exec, a non-leader thread that execs takes
+// over the pid of its process
+struct binder_event_exec {
+    pid_t old_tid;
+    char comm[16];
+    uid_t uid;
+    __u32 args_size;
+    char filename[EXEC_FILENAME_SIZE];
+    char args[EXEC_ARGS_SIZE + EXEC_ARG_SIZE];
+};
+
 // BINDER_TXN_PTR_DATA message: contents of a BINDER_TYPE_PTR scatter-gather
 // buffer captured from the sender (BC) or receiver (BR) task's address space
 // via bpf_probe_read_user. One event per chunk; if total_size > MAX_PTR_PAYLOAD
@@ -0,0 +1,17 @@
+#pragma once
+#include <linux/types.h>
+
+#include <bpf/bpf_helpers.h>
+
+#include "common_types.h"
+#include "maps.h"
+
+// Records an event we failed to deliver to userspace. The map is per-cpu, so a plain increment is
+// enough.
+static __always_inline void count_drop(capture_drop_reason_t reason) {
+    __u32 key = reason;
+    __u64 *counter = bpf_map_lookup_elem(&drop_counters, &key);
+    if (counter) {
+        (*counter)++;
+    }
+}
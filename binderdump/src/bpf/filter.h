@@ -0,0 +1,142 @@
+#pragma once
+#include <linux/types.h>
+
+#include <bpf/bpf_helpers.h>
+#include <linux/bpf.h>
+#include <stdbool.h>
+
+#include "common_types.h"
+#include "log.h"
+#include "maps.h"
+
+// Bitmask of capture_filter_flags_t, set from userspace before load. Zero (the default) captures
+// everything and keeps the unfiltered path down to a single rodata load.
+const volatile __u32 cfg_filter_flags = 0;
+
+#define CAPTURE_FILTER_INCLUDE (CAPTURE_FILTER_PID | CAPTURE_FILTER_UID | CAPTURE_FILTER_COMM)
+
+typedef enum {
+    FILTER_DROP = 0,
+    FILTER_MATCH,
+    // The task doesn't match, but a traced task sent a transaction to its process. Only allows
+    // tracing to start from the read side (see do_transition).
+    FILTER_PEER_PROC,
+} filter_verdict_t;
+
+// Decides whether the current task should be traced. Checked wherever we start tracking a
+// thread (sys_enter, binder_ioctl and the read-only path in do_transition), so a filtered-out
+// task never gets any state and never reaches the ring buffer.
+static __always_inline filter_verdict_t filter_check(pid_t pid, pid_t tid) {
+    __u32 flags = cfg_filter_flags;
+    if (!flags) {
+        return FILTER_MATCH;
+    }
+    if ((flags & CAPTURE_FILTER_EXCLUDE_PID) && bpf_map_lookup_elem(&filter_exclude_pids, &pid)) {
+        return FILTER_DROP;
+    }
+    if (!(flags & CAPTURE_FILTER_INCLUDE)) {
+        return FILTER_MATCH;
+    }
+    // --comm matches are added here as well, see below
+    if (bpf_map_lookup_elem(&filter_pids, &pid)) {
+        return FILTER_MATCH;
+    }
+    if (flags & CAPTURE_FILTER_UID) {
+        uid_t uid = bpf_get_current_uid_gid() & 0xffffffff;
+        if (bpf_map_lookup_elem(&filter_uids, &uid)) {
+            return FILTER_MATCH;
+        }
+    }
+    if (flags & CAPTURE_FILTER_COMM) {
+        struct filter_comm comm = {};
+        bpf_get_current_comm(comm.comm, sizeof(comm.comm));
+        if (bpf_map_lookup_elem(&filter_comms, &comm)) {
+            // comm is per-thread, and binder threads are named "binder:<pid>_<n>", so once any
+            // thread matches by name we select the whole process.
+            __u8 one = 1;
+            if (bpf_map_update_elem(&filter_pids, &pid, &one, BPF_ANY)) {
+                LOG("filter: failed to remember pid %d", pid);
+            }
+            return FILTER_MATCH;
+        }
+    }
+    if (bpf_map_lookup_elem(&filter_peer_threads, &tid)) {
+        return FILTER_MATCH;
+    }
+    if (bpf_map_lookup_elem(&filter_peer_procs, &pid)) {
+        return FILTER_PEER_PROC;
+    }
+    return FILTER_DROP;
+}
+
+// Called after a traced task sent a transaction, so the receiving half gets traced even if the
+// other endpoint doesn't match the filter itself.
+static __always_inline void filter_mark_peer(pid_t to_proc, pid_t to_thread, int reply) {
+    if (!(cfg_filter_flags & CAPTURE_FILTER_INCLUDE) || to_proc <= 0) {
+        return;
+    }
+    if (bpf_map_lookup_elem(&filter_pids, &to_proc)) {
+        return;
+    }
+    if (reply) {
+        // replies are delivered to the thread that is blocked waiting for them, trace the rest of
+        // its current ioctl
+        __u32 ioctls = 1;
+        if (to_thread > 0) {
+            bpf_map_update_elem(&filter_peer_threads, &to_thread, &ioctls, BPF_ANY);
+        }
+        return;
+    }
+    // requests are picked up by any thread of the target process
+    __u32 *pending = bpf_map_lookup_elem(&filter_peer_procs, &to_proc);
+    if (pending) {
+        __sync_fetch_and_add(pending, 1);
+    } else {
+        __u32 one = 1;
+        bpf_map_update_elem(&filter_peer_procs, &to_proc, &one, BPF_NOEXIST);
+    }
+}
+
+// A thread of a peer process started reading a transaction sent by a traced task. Trace it for
+// the rest of this ioctl and for the next one, which carries the reply.
+static __always_inline void filter_claim_peer(pid_t pid, pid_t tid) {
+    __u32 *pending = bpf_map_lookup_elem(&filter_peer_procs, &pid);
+    if (!pending) {
+        return;
+    }
+    if (*pending <= 1) {
+        bpf_map_delete_elem(&filter_peer_procs, &pid);
+    } else {
+        __sync_fetch_and_sub(pending, 1);
+    }
+    __u32 ioctls = 2;
+    bpf_map_update_elem(&filter_peer_threads, &tid, &ioctls, BPF_ANY);
+}
+
+// Called from sys_exit of every ioctl
+static __always_inline void filter_ioctl_done(pid_t tid) {
+    if (!(cfg_filter_flags & CAPTURE_FILTER_INCLUDE)) {
+        return;
+    }
+    __u32 *ioctls = bpf_map_lookup_elem(&filter_peer_threads, &tid);
+    if (!ioctls) {
+        return;
+    }
+    if (*ioctls <= 1) {
+        bpf_map_delete_elem(&filter_peer_threads, &tid);
+    } else {
+        __sync_fetch_and_sub(ioctls, 1);
+    }
+}
+
+// Pids get reused, don't keep selecting a new process just because it got an old pid.
+static __always_inline void filter_forget(pid_t pid, pid_t tid) {
+    if (!cfg_filter_flags) {
+        return;
+    }
+    bpf_map_delete_elem(&filter_peer_threads, &tid);
+    if (pid == tid) {
+        bpf_map_delete_elem(&filter_pids, &pid);
+        bpf_map_delete_elem(&filter_peer_procs, &pid);
+    }
+}
@@ -0,0 +1,16 @@
+#pragma once
+#include <linux/types.h>
+
+#include "common_types.h"
+#include "maps.h"
+
+// Set from userspace before load (--max-chunks / --max-ptr-objects / --max-ptr-payload). Userspace
+// keeps them within the *_LIMIT ceilings, which are the bounds the verifier sees.
+const volatile __u32 cfg_max_transaction_chunks = MAX_TRANSACTION_CHUNKS;
+const volatile __u32 cfg_max_ptr_objects = MAX_PTR_OBJECTS;
+const volatile __u32 cfg_max_ptr_payload = MAX_PTR_PAYLOAD;
+
+_Static_assert(sizeof(struct binder_event) + sizeof(struct binder_event_txn_ptr_data) +
+                       MAX_PTR_PAYLOAD_LIMIT <=
+                   sizeof(struct write_read_buffer),
+               "a scatter-gather payload of MAX_PTR_PAYLOAD_LIMIT bytes must fit in one buffer");
@@ -9,12 +9,36 @@
 #include "trace_binder.h"
 #include "utils.h"
 
-// Ring buffer for sending binder events to userspace
+// Ring buffer for sending binder events to userspace. Resized before load (--ring-size).
 struct {
     __uint(type, BPF_MAP_TYPE_RINGBUF);
-    __uint(max_entries, SZ_64M);
+    __uint(max_entries, RING_SIZE_MB << 20);
 } binder_events_buffer SEC(".maps");
 
+// What the events go through instead on kernels without ring buffers (see output.h), one buffer
+// per cpu
+struct {
+    __uint(type, BPF_MAP_TYPE_PERF_EVENT_ARRAY);
+    __uint(key_size, sizeof(__u32));
+    __uint(value_size, sizeof(__u32));
+} binder_events_perf SEC(".maps");
+
+// Per-cpu scratch a reserved event is built in before it is copied to the perf buffer
+#define EVENT_SCRATCH_SIZE 2048
+
+struct event_scratch {
+    __u32 size;
+    __u32 _pad;
+    char data[EVENT_SCRATCH_SIZE];
+};
+
+struct {
+    __uint(type, BPF_MAP_TYPE_PERCPU_ARRAY);
+    __uint(max_entries, 1);
+    __type(key, __u32);
+    __type(value, struct event_scratch);
+} event_scratch_buffers SEC(".maps");
+
 struct {
     __uint(type, BPF_MAP_TYPE_PERCPU_ARRAY);
     __uint(max_entries, 1);
@@ -22,6 +46,14 @@ struct {
     __type(value, int);
 } in_compat_syscall_map SEC(".maps");
 
+// Per-cpu counters of lost events, indexed by capture_drop_reason_t (see count_drop)
+struct {
+    __uint(type, BPF_MAP_TYPE_PERCPU_ARRAY);
+    __uint(max_entries, DROP_REASON_MAX);
+    __type(key, __u32);
+    __type(value, __u64);
+} drop_counters SEC(".maps");
+
 // Map of tid to binder_process_state_t
 struct {
     __uint(type, BPF_MAP_TYPE_ARRAY);
@@ -143,3 +175,101 @@ struct {
     __type(key, __u32);
     __type(value, struct transaction_command);
 } transaction_command_buffers SEC(".maps");
+
+// Capture filters (--pid/--uid/--comm/--exclude-pid), populated from userspace after load.
+// Only the kinds enabled in `cfg_filter_flags` are consulted (see filter.h).
+#define FILTER_MAX_ENTRIES 1024
+
+struct filter_comm {
+    char comm[16];
+};
+
+struct {
+    __uint(type, BPF_MAP_TYPE_HASH);
+    __uint(max_entries, FILTER_MAX_ENTRIES);
+    __type(key, pid_t);
+    __type(value, __u8);
+} filter_pids SEC(".maps");
+
+struct {
+    __uint(type, BPF_MAP_TYPE_HASH);
+    __uint(max_entries, FILTER_MAX_ENTRIES);
+    __type(key, uid_t);
+    __type(value, __u8);
+} filter_uids SEC(".maps");
+
+struct {
+    __uint(type, BPF_MAP_TYPE_HASH);
+    __uint(max_entries, FILTER_MAX_ENTRIES);
+    __type(key, struct filter_comm);
+    __type(value, __u8);
+} filter_comms SEC(".maps");
+
+struct {
+    __uint(type, BPF_MAP_TYPE_HASH);
+    __uint(max_entries, FILTER_MAX_ENTRIES);
+    __type(key, pid_t);
+    __type(value, __u8);
+} filter_exclude_pids SEC(".maps");
+
+// Map of pid to the number of transactions a traced task sent to it that weren't picked up yet
+struct {
+    __uint(type, BPF_MAP_TYPE_LRU_HASH);
+    __uint(max_entries, PID_MAX);
+    __type(key, pid_t);
+    __type(value, __u32);
+} filter_peer_procs SEC(".maps");
+
+// Map of tid to the number of ioctls we still trace it for, because it is handling a transaction
+// sent by a traced task
+struct {
+    __uint(type, BPF_MAP_TYPE_LRU_HASH);
+    __uint(max_entries, PID_MAX);
+    __type(key, pid_t);
+    __type(value, __u32);
+} filter_peer_threads SEC(".maps");
+
+// Map of tid to the argv of the execve it is in. Read on sys_enter, while the memory of the old
+// image is still there, and sent from sched_process_exec. LRU, a failed execve never gets there.
+struct exec_args {
+    __u32 args_size;
+    char args[EXEC_ARGS_SIZE + EXEC_ARG_SIZE];
+};
+
+struct {
+    __uint(type, BPF_MAP_TYPE_LRU_HASH);
+    __uint(max_entries, 1024);
+    __type(key, pid_t);
+    __type(value, struct exec_args);
+} exec_args_map SEC(".maps");
+
+// Per-cpu scratch to build an exec_args in, it doesn't fit the BPF stack
+struct {
+    __uint(type, BPF_MAP_TYPE_PERCPU_ARRAY);
+    __uint(max_entries, 1);
+    __type(key, __u32);
+    __type(value, struct exec_args);
+} exec_args_scratch SEC(".maps");
+
+// Map of tid to the flags of the clone it is in, sched_process_fork doesn't tell threads apart
+struct {
+    __uint(type, BPF_MAP_TYPE_LRU_HASH);
+    __uint(max_entries, 1024);
+    __type(key, pid_t);
+    __type(value, __u64);
+} clone_flags_map SEC(".maps");
+
+// Map of tid to when a thread waiting for work was woken and got on a CPU, until it reads the
+// transaction it was woken for (--wakeup-latency). LRU, a thread woken for other work never reads
+// one.
+struct wakeup_times {
+    __u64 woken;
+    __u64 on_cpu;
+};
+
+struct {
+    __uint(type, BPF_MAP_TYPE_LRU_HASH);
+    __uint(max_entries, 1024);
+    __type(key, pid_t);
+    __type(value, struct wakeup_times);
+} wakeup_times_map SEC(".maps");
@@ -3,6 +3,7 @@
 #include <bpf/bpf_helpers.h>
 #include <linux/bpf.h>
 #include <stddef.h>
+#include <sys/syscall.h>
 
 #define DEBUG
 #include "log.h"
@@ -24,16 +25,21 @@
 #define FINDER_KIND_TXN 0
 #define FINDER_KIND_DEREF 1
 
-// arm64 ioctl syscall numbers (native + compat). The tool is arm64-only, so we
-// reset the per-tid pairing slot at each ioctl boundary rather than tracking
-// every syscall.
-#define FINDER_NR_IOCTL 29
-#define FINDER_NR_IOCTL_COMPAT 54
+// ioctl syscall numbers (native from the target's headers, compat is 54 on
+// both arm and x86). We reset the per-tid pairing slot at each ioctl boundary
+// rather than tracking every syscall.
+#ifndef SYS_compat_ioctl
+#define SYS_compat_ioctl 54
+#endif
 
-// Heuristic: on arm64 with 48-bit VA (Android 5.10/6.x), kernel addresses
-// have the top 16 bits set (>= 0xffff000000000000). A 52-bit-VA kernel would
-// need a different mask; out of scope.
-#define IS_KERNEL_PTR(v) (((v) >> 48) == 0xffff)
+// The kernel's pointer layout, set by userspace (see KernelLayout in
+// offset_solver.rs). A slot is a kernel pointer when every bit of
+// cfg_kernel_mask is set in it: the bits above the VA size, without the tag
+// byte on a tagged kernel. The default is arm64 with a 48-bit VA.
+const volatile __u32 cfg_ptr_size = 8;
+const volatile __u64 cfg_kernel_mask = 0xffff000000000000ULL;
+
+#define IS_KERNEL_PTR(v) (((v) & cfg_kernel_mask) == cfg_kernel_mask)
 
 // Count of records dropped because finder_events was full. Read by userspace
 // after calibration so "too few samples" can be told apart from "ring overran".
@@ -139,10 +145,21 @@ static __always_inline void finder_emit(struct finder_inflight *st) {
     }
     // Constant trip count -> the compiler unrolls this, so every window read below
     // is at a constant offset (keeps the verifier happy, no variable map indexing).
+    // Steps by 4 for 32-bit kernels; a 64-bit one skips the slots that aren't
+    // 8-aligned, which the verifier prunes since cfg_ptr_size is read-only.
 #pragma unroll
-    for (size_t off = 0; off + 8 <= FINDER_STRUCT_WIN; off += 8) {
+    for (size_t off = 0; off + 4 <= FINDER_STRUCT_WIN; off += 4) {
         __u64 val = 0;
-        __builtin_memcpy(&val, st->window + off, 8);
+        if (cfg_ptr_size == 8) {
+            if (off & 7) {
+                continue;
+            }
+            __builtin_memcpy(&val, st->window + off, 8);
+        } else {
+            __u32 val32 = 0;
+            __builtin_memcpy(&val32, st->window + off, 4);
+            val = val32;
+        }
         if (!IS_KERNEL_PTR(val)) {
             continue;
         }
@@ -163,7 +180,7 @@ static __always_inline void finder_emit(struct finder_inflight *st) {
 SEC("raw_tp/sys_enter")
 int finder_sys_enter(struct bpf_raw_tracepoint_args *ctx) {
     long id = (long)ctx->args[1];
-    if (id != FINDER_NR_IOCTL && id != FINDER_NR_IOCTL_COMPAT) {
+    if (id != SYS_ioctl && id != SYS_compat_ioctl) {
         return 0;
     }
     __u32 tid = (__u32)bpf_get_current_pid_tgid();
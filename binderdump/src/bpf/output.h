@@ -0,0 +1,86 @@
+#pragma once
+#include <linux/types.h>
+
+#include <bpf/bpf_helpers.h>
+#include <linux/bpf.h>
+
+#include "common_types.h"
+#include "drops.h"
+#include "maps.h"
+
+// Where the events go. Kernels before 5.8 have no ring buffers, userspace sets cfg_perf_buffer
+// before load there (see capture/ringbuf.rs) and the events are copied to the per-cpu
+// binder_events_perf buffers instead. The verifier knows the rodata, so it never walks the
+// helpers of the other transport. A perf buffer can't reserve: the event is built in a per-cpu
+// scratch buffer and copied out on submit, with the ctx of the program.
+const volatile __u32 cfg_perf_buffer = 0;
+// CLOCK_BOOTTIME - CLOCK_MONOTONIC when the capture started, bpf_ktime_get_boot_ns is 5.8 too
+const volatile __u64 cfg_boot_offset_ns = 0;
+
+_Static_assert(sizeof(struct binder_event) + sizeof(struct binder_event_txn_fd) <=
+                   EVENT_SCRATCH_SIZE,
+               "the biggest reserved event must fit in the perf buffer scratch");
+_Static_assert(sizeof(struct binder_event) + sizeof(struct binder_event_exec) <= EVENT_SCRATCH_SIZE,
+               "the biggest reserved event must fit in the perf buffer scratch");
+
+static __always_inline __u64 event_timestamp(void) {
+    if (cfg_perf_buffer) {
+        return bpf_ktime_get_ns() + cfg_boot_offset_ns;
+    }
+    return bpf_ktime_get_boot_ns();
+}
+
+static __always_inline void *event_reserve(__u64 size) {
+    if (!cfg_perf_buffer) {
+        return bpf_ringbuf_reserve(&binder_events_buffer, size, 0);
+    }
+    __u32 key = 0;
+    struct event_scratch *scratch = bpf_map_lookup_elem(&event_scratch_buffers, &key);
+    if (!scratch || size > sizeof(scratch->data)) {
+        return NULL;
+    }
+    scratch->size = size;
+    return scratch->data;
+}
+
+// The global subprogs have no ctx (NULL), they only ever send through the ring buffer
+static __always_inline long perf_output(const void *ctx, void *data, __u32 size) {
+    if (!ctx) {
+        return -1;
+    }
+    ((struct binder_event *)data)->size = size;
+    return bpf_perf_event_output((void *)ctx, &binder_events_perf, BPF_F_CURRENT_CPU, data, size);
+}
+
+static __always_inline void event_submit(const void *ctx, void *event, __u64 flags) {
+    if (!cfg_perf_buffer) {
+        bpf_ringbuf_submit(event, flags);
+        return;
+    }
+    __u32 key = 0;
+    struct event_scratch *scratch = bpf_map_lookup_elem(&event_scratch_buffers, &key);
+    if (!scratch) {
+        return;
+    }
+    __u32 size = scratch->size;
+    if (size < sizeof(struct binder_event) || size > sizeof(scratch->data)) {
+        return;
+    }
+    if (perf_output(ctx, scratch->data, size)) {
+        count_drop(DROP_RINGBUF_FULL);
+    }
+}
+
+static __always_inline void event_discard(void *event) {
+    if (!cfg_perf_buffer) {
+        bpf_ringbuf_discard(event, 0);
+    }
+}
+
+// Like bpf_ringbuf_output, `data` starts with a binder_event
+static __always_inline long event_output(const void *ctx, void *data, __u32 size) {
+    if (!cfg_perf_buffer) {
+        return bpf_ringbuf_output(&binder_events_buffer, data, size, 0);
+    }
+    return perf_output(ctx, data, size);
+}
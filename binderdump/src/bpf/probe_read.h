@@ -0,0 +1,45 @@
+#pragma once
+#include <linux/types.h>
+
+#include <bpf/bpf_core_read.h>
+#include <bpf/bpf_helpers.h>
+#include <linux/bpf.h>
+
+// bpf_probe_read_user / bpf_probe_read_kernel and their _str variants are 5.5. Userspace sets
+// cfg_probe_read_legacy before load on older kernels (see capture/btf_probe.rs) and the reads go
+// through bpf_probe_read / bpf_probe_read_str there, which read either half of the address space
+// on those kernels. The verifier knows the rodata, so it never walks the helpers the kernel lacks.
+const volatile __u32 cfg_probe_read_legacy = 0;
+
+static __always_inline long probe_read_user(void *dst, __u32 size, const void *src) {
+    if (cfg_probe_read_legacy) {
+        return bpf_probe_read(dst, size, src);
+    }
+    return bpf_probe_read_user(dst, size, src);
+}
+
+static __always_inline long probe_read_kernel(void *dst, __u32 size, const void *src) {
+    if (cfg_probe_read_legacy) {
+        return bpf_probe_read(dst, size, src);
+    }
+    return bpf_probe_read_kernel(dst, size, src);
+}
+
+static __always_inline long probe_read_user_str(void *dst, __u32 size, const void *src) {
+    if (cfg_probe_read_legacy) {
+        return bpf_probe_read_str(dst, size, src);
+    }
+    return bpf_probe_read_user_str(dst, size, src);
+}
+
+static __always_inline long probe_read_kernel_str(void *dst, __u32 size, const void *src) {
+    if (cfg_probe_read_legacy) {
+        return bpf_probe_read_str(dst, size, src);
+    }
+    return bpf_probe_read_kernel_str(dst, size, src);
+}
+
+// BPF_CORE_READ reads with bpf_probe_read_kernel too
+#undef bpf_core_read
+#define bpf_core_read(dst, sz, src)                                                                \
+    probe_read_kernel(dst, sz, (const void *)__builtin_preserve_access_index(src))
@@ -1,7 +1,10 @@
 #pragma once
 #include "common_types.h"
+#include "drops.h"
+#include "filter.h"
 #include "log.h"
 #include "maps.h"
+#include "output.h"
 
 static const binder_process_state_t g_valid_transitions[BINDER_STATE_MAX][BINDER_STATE_MAX] = {
     // starting state
@@ -95,7 +98,7 @@ static __always_inline binder_process_state_t *get_process_state(pid_t tid) {
     return (binder_process_state_t *)bpf_map_lookup_elem(&binder_process_state, &tid);
 }
 
-int __noinline do_transition(pid_t pid, pid_t tid, binder_process_state_t to) {
+static __noinline int do_transition(void *ctx, pid_t pid, pid_t tid, binder_process_state_t to) {
     struct binder_event *event = NULL;
     binder_process_state_t *from = get_process_state(tid);
     if (!from) {
@@ -104,10 +107,22 @@ int __noinline do_transition(pid_t pid, pid_t tid, binder_process_state_t to) {
         return -1;
     }
     binder_process_state_t old_from = *from;
+    filter_verdict_t verdict = FILTER_MATCH;
+    if (old_from == BINDER_INVALID) {
+        // binder_ioctl checks the filter by itself, so this is the read-only path starting to
+        // track the thread. Filtered-out threads stay here, and don't need an invalidate event.
+        verdict = filter_check(pid, tid);
+        if (verdict == FILTER_DROP) {
+            return -1;
+        }
+    }
     if (!is_valid_transition(*from, to)) {
         // LOG("transition of thread %d from state %d to %d is invalid", tid, *from, to);
         goto l_error;
     }
+    if (verdict == FILTER_PEER_PROC) {
+        filter_claim_peer(pid, tid);
+    }
     // if (bpf_map_update_elem(&binder_process_state, &tid, &to, BPF_ANY)) {
     //     LOG("failed to update state of thread %d %d -> %d", tid, *from, to);
     //     goto l_error;
@@ -117,31 +132,34 @@ int __noinline do_transition(pid_t pid, pid_t tid, binder_process_state_t to) {
     return 0;
 
 l_error:
-    event = (struct binder_event *)bpf_ringbuf_reserve(&binder_events_buffer,
-                                                       sizeof(struct binder_event), 0);
+    event = (struct binder_event *)event_reserve(sizeof(struct binder_event));
     if (!event) {
         LOG("do_transition: failed to reserved event");
+        count_drop(DROP_RINGBUF_FULL);
         return -1;
     }
     event->type = BINDER_INVALID;
     event->pid = pid;
     event->tid = tid;
-    event->timestamp = bpf_ktime_get_boot_ns();
+    event->timestamp = event_timestamp();
 
     LOG_RINGBUF("submit %u %x", sizeof(struct binder_event), *(int *)event);
-    bpf_ringbuf_submit(event, 0);
+    event_submit(ctx, event, 0);
     return -1;
 }
 
-__noinline int send_invalidate(pid_t tid, pid_t pid) {
+static __noinline int send_invalidate(void *ctx, pid_t tid, pid_t pid) {
     struct binder_event invalidate = {};
     invalidate.type = BINDER_INVALID;
     invalidate.pid = pid;
     invalidate.tid = tid;
+    // perf buffer events are put back in order by it
+    invalidate.timestamp = event_timestamp();
 
     LOG_RINGBUF("output: %u %x", sizeof(invalidate), *(int *)&invalidate);
-    if (bpf_ringbuf_output(&binder_events_buffer, &invalidate, sizeof(invalidate), 0)) {
+    if (event_output(ctx, &invalidate, sizeof(invalidate))) {
         LOG("failed to invalidate ioctl");
+        count_drop(DROP_RINGBUF_FULL);
     }
     return 0;
 }
@@ -0,0 +1,51 @@
+#pragma once
+#include <linux/types.h>
+
+#include <bpf/bpf_helpers.h>
+#include <linux/android/binder.h>
+#include <linux/bpf.h>
+
+#include "common_types.h"
+#include "utils.h"
+
+// Set from userspace before load (--snaplen / --metadata-only). A zero snaplen copies everything.
+const volatile __u32 cfg_snaplen = 0;
+const volatile __u32 cfg_metadata_only = 0;
+
+// Commands we look at in a truncated write buffer before giving up and assuming it sends a
+// transaction
+#define MAX_SCANNED_COMMANDS 16
+
+// How many bytes of a user buffer of `size` bytes are copied to userspace. The events always carry
+// the real size next to the copy.
+static __always_inline __u64 snap_len(__u64 size) {
+    if (cfg_metadata_only) {
+        return 0;
+    }
+    if (cfg_snaplen && size > cfg_snaplen) {
+        return cfg_snaplen;
+    }
+    return size;
+}
+
+// Userspace decides where to split a BINDER_WRITE_READ into its send and receive halves by looking
+// for a transaction in the write buffer. When the buffer is truncated it can't, so walk the
+// command headers here instead.
+static __always_inline int write_has_transaction(__u64 addr, __u64 size) {
+    __u64 pos = 0;
+    for (int i = 0; i < MAX_SCANNED_COMMANDS; i++) {
+        if (pos >= size) {
+            return 0;
+        }
+        __u32 cmd = 0;
+        if (bpf_probe_read_user(&cmd, sizeof(cmd), UNTAG(addr + pos))) {
+            return 1;
+        }
+        if (cmd == BC_TRANSACTION || cmd == BC_REPLY || cmd == BC_TRANSACTION_SG ||
+            cmd == BC_REPLY_SG) {
+            return 1;
+        }
+        pos += sizeof(cmd) + _IOC_SIZE(cmd);
+    }
+    return 1;
+}
@@ -32,6 +32,44 @@ struct trace_event_raw_sched_process_template {
     char __data[0];
 };
 
+struct trace_event_raw_sched_wakeup_template {
+    struct trace_entry ent;
+    char comm[16];
+    pid_t pid;
+    int prio;
+    int target_cpu;
+    char __data[0];
+};
+
+struct trace_event_raw_sched_switch {
+    struct trace_entry ent;
+    char prev_comm[16];
+    pid_t prev_pid;
+    int prev_prio;
+    long prev_state;
+    char next_comm[16];
+    pid_t next_pid;
+    int next_prio;
+    char __data[0];
+};
+
+struct trace_event_raw_sched_process_fork {
+    struct trace_entry ent;
+    char parent_comm[16];
+    pid_t parent_pid;
+    char child_comm[16];
+    pid_t child_pid;
+    char __data[0];
+};
+
+struct trace_event_raw_sched_process_exec {
+    struct trace_entry ent;
+    __u32 __data_loc_filename;
+    pid_t pid;
+    pid_t old_pid;
+    char __data[0];
+};
+
 struct trace_event_raw_binder_ioctl {
     struct trace_entry ent;
     unsigned int cmd;
@@ -95,51 +133,60 @@ struct trace_event_raw_binder_return {
     char __data[0];
 };
 
-/*
 struct trace_event_raw_binder_transaction_node_to_ref {
-        struct trace_entry ent;
-        int debug_id;
-        int node_debug_id;
-        binder_uintptr_t node_ptr;
-        int ref_debug_id;
-        uint32_t ref_desc;
-        char __data[0];
+    struct trace_entry ent;
+    int debug_id;
+    int node_debug_id;
+    uint64_t node_ptr;
+    int ref_debug_id;
+    uint32_t ref_desc;
+    char __data[0];
 };
 
 struct trace_event_raw_binder_transaction_ref_to_node {
-        struct trace_entry ent;
-        int debug_id;
-        int ref_debug_id;
-        uint32_t ref_desc;
-        int node_debug_id;
-        binder_uintptr_t node_ptr;
-        char __data[0];
+    struct trace_entry ent;
+    int debug_id;
+    int ref_debug_id;
+    uint32_t ref_desc;
+    int node_debug_id;
+    uint64_t node_ptr;
+    char __data[0];
 };
 
 struct trace_event_raw_binder_transaction_ref_to_ref {
-        struct trace_entry ent;
-        int debug_id;
-        int node_debug_id;
-        int src_ref_debug_id;
-        uint32_t src_ref_desc;
-        int dest_ref_debug_id;
-        uint32_t dest_ref_desc;
-        char __data[0];
+    struct trace_entry ent;
+    int debug_id;
+    int node_debug_id;
+    int src_ref_debug_id;
+    uint32_t src_ref_desc;
+    int dest_ref_debug_id;
+    uint32_t dest_ref_desc;
+    char __data[0];
 };
 
 struct trace_event_raw_binder_transaction_fd_send {
-        struct trace_entry ent;
-        int debug_id;
-        int fd;
-        size_t offset;
-        char __data[0];
+    struct trace_entry ent;
+    int debug_id;
+    int fd;
+    size_t offset;
+    char __data[0];
 };
 
 struct trace_event_raw_binder_transaction_fd_recv {
-        struct trace_entry ent;
-        int debug_id;
-        int fd;
-        size_t offset;
-        char __data[0];
+    struct trace_entry ent;
+    int debug_id;
+    int fd;
+    size_t offset;
+    char __data[0];
+};
+
+// only on Android common kernels
+struct trace_event_raw_binder_set_priority {
+    struct trace_entry ent;
+    int proc;
+    int thread;
+    unsigned int old_prio;
+    unsigned int new_prio;
+    unsigned int desired_prio;
+    char __data[0];
 };
-*/
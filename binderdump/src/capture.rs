@@ -3,10 +3,22 @@
 //! TODO create different "backends" to handle the capture (ptrace, uprobe, tracepoints etc.)
 
 pub mod btf_probe;
-mod common_types;
+pub mod calibration;
+pub mod common_types;
+pub mod devices;
+pub mod doctor;
+pub mod drops;
 pub mod events;
+pub mod filter;
+pub mod limits;
+pub mod offset_cache;
 pub mod offset_solver;
+pub mod packages;
+pub mod proc_maps;
 pub mod process_cache;
+pub mod raw_dump;
 pub mod ringbuf;
+pub mod snaplen;
 pub mod system_property;
+pub mod tiers;
 pub mod tracepoints;
@@ -1,12 +1,21 @@
+use std::ffi::CStr;
+use std::mem::MaybeUninit;
 use std::path::{Path, PathBuf};
 use std::sync::OnceLock;
 
 use anyhow::{Context, Result};
 use libbpf_rs::btf::Btf;
+use libbpf_rs::libbpf_sys;
 
 const KERNEL_BTF_PATH: &str = "/sys/kernel/btf/vmlinux";
+// <linux/personality.h>, not in libc
+const PER_LINUX32: i32 = 0x0008;
 
 static SUPPORTED: OnceLock<bool> = OnceLock::new();
+static FD_PATHS_SUPPORTED: OnceLock<bool> = OnceLock::new();
+static SCHED_PRIO_SUPPORTED: OnceLock<bool> = OnceLock::new();
+static RINGBUF_SUPPORTED: OnceLock<bool> = OnceLock::new();
+static PROBE_READ_USER_SUPPORTED: OnceLock<bool> = OnceLock::new();
 
 // True iff the running kernel exposes BTF at all. Cheaper and broader than
 // reply_correlation_supported(): it ignores which fields the BTF contains and
@@ -17,9 +26,11 @@ pub fn kernel_btf_present() -> bool {
 }
 
 // libbpf needs a vmlinux BTF whenever the object carries CO-RE relocations.
-// Our only CO-RE program (raw_binder_transaction_core) is disabled when BTF is
-// absent, but its relocs still live in the object, so on kernels built without
-// CONFIG_DEBUG_INFO_BTF the load aborts trying to read /sys/kernel/btf/vmlinux:
+// Our CO-RE programs (raw_binder_transaction_core, binder_transaction_fd_send_core,
+// binder_transaction_prio)
+// are disabled when BTF is absent, but their relocs still live in the object, so
+// on kernels built without CONFIG_DEBUG_INFO_BTF the load aborts trying to read
+// /sys/kernel/btf/vmlinux:
 //
 //     libbpf: kernel BTF is missing at '/sys/kernel/btf/vmlinux'
 //     libbpf: Error loading vmlinux BTF: -ESRCH
@@ -27,7 +38,7 @@ pub fn kernel_btf_present() -> bool {
 // Setting btf_custom_path makes libbpf use that file as the CO-RE target BTF
 // instead of vmlinux. libbpf *parses* the file (an empty or non-BTF path such
 // as /dev/null fails with -EIO), but its contents are irrelevant here: the only
-// CO-RE relocations belong to the disabled program, so nothing is ever resolved
+// CO-RE relocations belong to the disabled programs, so nothing is ever resolved
 // against it. A minimal, types-free but structurally valid BTF blob is enough.
 //
 // Returns the path of the written blob.
@@ -58,20 +69,24 @@ pub fn reply_correlation_supported() -> bool {
     *SUPPORTED.get_or_init(probe)
 }
 
-fn probe() -> bool {
+fn kernel_btf() -> Option<Btf<'static>> {
     if !Path::new(KERNEL_BTF_PATH).exists() {
-        log::info!(
-            "kernel BTF not found at {}; reply correlation disabled",
-            KERNEL_BTF_PATH
-        );
-        return false;
+        log::info!("kernel BTF not found at {}", KERNEL_BTF_PATH);
+        return None;
     }
-    let btf = match Btf::from_path(KERNEL_BTF_PATH) {
-        Ok(b) => b,
+    match Btf::from_path(KERNEL_BTF_PATH) {
+        Ok(b) => Some(b),
         Err(e) => {
-            log::warn!("failed to parse kernel BTF: {e}; reply correlation disabled");
-            return false;
+            log::warn!("failed to parse kernel BTF: {e}");
+            None
         }
+    }
+}
+
+fn probe() -> bool {
+    let Some(btf) = kernel_btf() else {
+        log::info!("reply correlation disabled (no kernel BTF)");
+        return false;
     };
     let has_transaction = struct_has_fields(&btf, "binder_transaction", &["to_thread", "debug_id"]);
     let has_thread = struct_has_fields(&btf, "binder_thread", &["transaction_stack"]);
@@ -82,6 +97,123 @@ fn probe() -> bool {
     true
 }
 
+// returns true iff the kernel BTF describes everything the path of a sent fd is resolved from:
+// the fd table of the task, the file and its inode, and the dentries and mounts up to the root.
+// cached after first call.
+pub fn fd_paths_supported() -> bool {
+    *FD_PATHS_SUPPORTED.get_or_init(probe_fd_paths)
+}
+
+fn probe_fd_paths() -> bool {
+    let Some(btf) = kernel_btf() else {
+        return false;
+    };
+    let structs: &[(&str, &[&str])] = &[
+        ("task_struct", &["files"]),
+        ("files_struct", &["fdt"]),
+        ("fdtable", &["max_fds", "fd"]),
+        ("file", &["f_path", "f_inode"]),
+        ("inode", &["i_mode", "i_ino", "i_sb"]),
+        ("super_block", &["s_magic"]),
+        ("dentry", &["d_parent", "d_name"]),
+        ("mount", &["mnt_parent", "mnt_mountpoint", "mnt"]),
+        ("vfsmount", &["mnt_root"]),
+    ];
+    for (name, fields) in structs {
+        if !struct_has_fields(&btf, name, fields) {
+            log::info!("kernel BTF missing struct {name} fields {fields:?}; fd paths disabled");
+            return false;
+        }
+    }
+    true
+}
+
+// returns true iff the kernel BTF describes the scheduling class of a task, which the caller's
+// priority of a transaction is read from. cached after first call.
+pub fn sched_prio_supported() -> bool {
+    *SCHED_PRIO_SUPPORTED.get_or_init(probe_sched_prio)
+}
+
+fn probe_sched_prio() -> bool {
+    let Some(btf) = kernel_btf() else {
+        return false;
+    };
+    if !struct_has_fields(&btf, "task_struct", &["policy", "normal_prio"]) {
+        log::info!("kernel BTF missing task_struct scheduling fields; caller prio disabled");
+        return false;
+    }
+    true
+}
+
+// returns true iff the kernel has BPF ring buffers (5.8), the events go through per-cpu perf
+// buffers otherwise. Not a BTF question, but probed and cached the same way.
+pub fn ringbuf_supported() -> bool {
+    *RINGBUF_SUPPORTED.get_or_init(probe_ringbuf)
+}
+
+fn probe_ringbuf() -> bool {
+    let ret = unsafe {
+        libbpf_sys::libbpf_probe_bpf_map_type(libbpf_sys::BPF_MAP_TYPE_RINGBUF, std::ptr::null())
+    };
+    if ret != 1 {
+        log::info!("BPF ring buffers not supported ({ret}); events go through perf buffers");
+        return false;
+    }
+    true
+}
+
+// returns true iff the kernel has bpf_probe_read_user and friends (5.5), the programs read with
+// bpf_probe_read otherwise. cached after first call.
+pub fn probe_read_user_supported() -> bool {
+    *PROBE_READ_USER_SUPPORTED.get_or_init(probe_probe_read_user)
+}
+
+fn probe_probe_read_user() -> bool {
+    let ret = unsafe {
+        libbpf_sys::libbpf_probe_bpf_helper(
+            libbpf_sys::BPF_PROG_TYPE_TRACEPOINT,
+            libbpf_sys::BPF_FUNC_probe_read_user,
+            std::ptr::null(),
+        )
+    };
+    // an error (no privileges yet) says nothing about the helper, only 0 means the kernel lacks it
+    if ret == 0 {
+        log::info!("bpf_probe_read_user not supported; reading with bpf_probe_read");
+        return false;
+    }
+    true
+}
+
+// The architecture of the running kernel, as uname -m prints it. The pointer width of the kernel
+// comes from it when there is no BTF to read it from.
+pub fn kernel_machine() -> Result<String> {
+    let mut uts = MaybeUninit::<libc::utsname>::uninit();
+    if unsafe { libc::uname(uts.as_mut_ptr()) } != 0 {
+        return Err(std::io::Error::last_os_error()).context("uname failed");
+    }
+    let uts = unsafe { uts.assume_init() };
+    let machine = unsafe { CStr::from_ptr(uts.machine.as_ptr()) };
+    // 0xffffffff only reads the personality
+    let personality = unsafe { libc::personality(0xffffffff as _) };
+    let linux32 = personality != -1 && personality & 0xff == PER_LINUX32;
+    Ok(native_machine(&machine.to_string_lossy(), linux32).to_string())
+}
+
+// Under the PER_LINUX32 personality (setarch linux32, 32-bit processes on some devices) a 64-bit
+// kernel prints the machine of its 32-bit compat mode instead of its own
+fn native_machine(machine: &str, linux32: bool) -> &str {
+    if !linux32 {
+        return machine;
+    }
+    match machine {
+        "armv8l" => "aarch64",
+        "i686" => "x86_64",
+        "ppc" => "ppc64",
+        "ppcle" => "ppc64le",
+        machine => machine,
+    }
+}
+
 fn struct_has_fields(btf: &Btf, struct_name: &str, fields: &[&str]) -> bool {
     let ty = match btf.type_by_name::<libbpf_rs::btf::types::Struct>(struct_name) {
         Some(t) => t,
@@ -102,5 +234,18 @@ mod tests {
         let first = reply_correlation_supported();
         let second = reply_correlation_supported();
         assert_eq!(first, second);
+        assert_eq!(fd_paths_supported(), fd_paths_supported());
+        assert_eq!(sched_prio_supported(), sched_prio_supported());
+        assert_eq!(ringbuf_supported(), ringbuf_supported());
+        assert_eq!(probe_read_user_supported(), probe_read_user_supported());
+    }
+
+    #[test]
+    fn compat_machines_name_the_64_bit_kernel() {
+        assert_eq!(native_machine("armv8l", true), "aarch64");
+        assert_eq!(native_machine("i686", true), "x86_64");
+        // a 32-bit kernel on an ARMv8 CPU
+        assert_eq!(native_machine("armv8l", false), "armv8l");
+        assert_eq!(native_machine("aarch64", true), "aarch64");
     }
 }
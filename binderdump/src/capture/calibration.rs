@@ -0,0 +1,233 @@
+// Collects the calibration samples offset_solver derives the reply-correlation offsets from, on a
+// BTF-less kernel: loads offsets.bpf.c and nudges binder traffic itself while it listens. Used by
+// offset_finder and `binderdump --calibrate`, which both store the result in the offset cache.
+
+use std::collections::HashMap;
+use std::mem::MaybeUninit;
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use libbpf_rs::skel::{OpenSkel, Skel, SkelBuilder};
+use libbpf_rs::RingBufferBuilder;
+use log::info;
+
+use super::btf_probe::{self, kernel_machine};
+use super::offset_cache::{KernelBuild, OffsetCache};
+use super::offset_solver::{
+    solve, Deref, DerivedOffsets, KernelLayout, Sample, DEREF_WIN, STRUCT_WIN,
+};
+use super::tracepoints::bump_memlock_rlimit;
+
+mod offsets {
+    include!(concat!(env!("OUT_DIR"), "/offsets.skel.rs"));
+}
+use offsets::*;
+
+// long enough to collect >= MIN_REPLY_CONFIRMATIONS replies on a quiet device
+pub const CALIBRATION_SECS: u64 = 4;
+
+#[derive(Default)]
+struct Collector {
+    by_ptr: HashMap<u64, usize>,
+    samples: Vec<Sample>,
+}
+
+impl Collector {
+    fn ingest(&mut self, data: &[u8]) {
+        if data.len() < 4 {
+            return;
+        }
+        // lengths checked above
+        let kind = u32::from_le_bytes(data[0..4].try_into().unwrap());
+        match kind {
+            0 => {
+                // struct finder_txn: kind, reply, txn_ptr, debug_id, window[STRUCT_WIN]
+                if data.len() < 20 + STRUCT_WIN {
+                    return;
+                }
+                let reply = u32::from_le_bytes(data[4..8].try_into().unwrap()) != 0;
+                let txn_ptr = u64::from_le_bytes(data[8..16].try_into().unwrap());
+                let debug_id = u32::from_le_bytes(data[16..20].try_into().unwrap());
+                let window = data[20..20 + STRUCT_WIN].to_vec();
+                let idx = self.samples.len();
+                self.samples.push(Sample {
+                    txn_ptr,
+                    reply,
+                    debug_id,
+                    struct_window: window,
+                    derefs: vec![],
+                });
+                // if a binder_transaction address is reused within the calibration window
+                // (allocate/free/realloc — rare), the newer sample wins. harmless: a sample
+                // with no derefs just contributes no votes.
+                self.by_ptr.insert(txn_ptr, idx);
+            }
+            1 => {
+                if data.len() < 16 + DEREF_WIN {
+                    return;
+                }
+                let src_off = u32::from_le_bytes(data[4..8].try_into().unwrap());
+                let txn_ptr = u64::from_le_bytes(data[8..16].try_into().unwrap());
+                let window = data[16..16 + DEREF_WIN].to_vec();
+                if let Some(&idx) = self.by_ptr.get(&txn_ptr) {
+                    self.samples[idx].derefs.push(Deref { src_off, window });
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn into_samples(self) -> Vec<Sample> {
+        self.samples
+    }
+}
+
+fn nudge(stop: Arc<AtomicBool>) {
+    // well-known services that are always registered, to drive request+reply traffic
+    let services = ["activity", "package", "window", "power", "audio"];
+    while !stop.load(Ordering::Relaxed) {
+        let _ = Command::new("service").arg("list").output();
+        for s in &services {
+            let _ = Command::new("service").args(["check", s]).output();
+        }
+        let _ = Command::new("dumpsys").arg("meminfo").output();
+        // rate-limit so we don't flood the ring buffer
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
+
+pub struct Calibration {
+    pub samples: Vec<Sample>,
+    // records the calibration ring dropped under load — distinguishes a quiet
+    // device from one whose traffic overran the buffer
+    pub drops: u64,
+    // as the transaction addresses showed it
+    pub layout: KernelLayout,
+}
+
+impl Calibration {
+    pub fn replies(&self) -> usize {
+        self.samples.iter().filter(|s| s.reply).count()
+    }
+
+    pub fn solve(&self) -> Result<DerivedOffsets> {
+        let offsets = solve(&self.samples, self.layout);
+        if self.drops > 0 {
+            return offsets.with_context(|| {
+                format!(
+                    "{} records were dropped, the ring overran; this is not a quiet device",
+                    self.drops
+                )
+            });
+        }
+        offsets
+    }
+}
+
+// Listens to live transactions for `duration`, nudging traffic meanwhile
+pub fn collect(duration: Duration) -> Result<Calibration> {
+    bump_memlock_rlimit()?;
+
+    // loose enough for every layout of the architecture, the samples narrow it down
+    let machine = kernel_machine()?;
+    let loose = KernelLayout::for_machine(&machine)?;
+    info!("calibrating on {} ({})", machine, loose);
+
+    let skel_builder = OffsetsSkelBuilder::default();
+    let open_object = Box::leak(Box::new(MaybeUninit::uninit()));
+    let mut open_skel = skel_builder.open(open_object)?;
+    let rodata = open_skel
+        .maps
+        .rodata_data
+        .as_deref_mut()
+        .context("BPF rodata section unavailable")?;
+    rodata.cfg_ptr_size = loose.ptr_size as u32;
+    rodata.cfg_kernel_mask = loose.kernel_mask();
+    let mut skel = open_skel
+        .load()
+        .context("failed to load calibration skeleton")?;
+    skel.attach()
+        .context("failed to attach calibration program")?;
+
+    let collector = Arc::new(Mutex::new(Collector::default()));
+    let collector_cb = collector.clone();
+    let mut rbb = RingBufferBuilder::new();
+    rbb.add(&skel.maps.finder_events, move |data| -> i32 {
+        // a poisoned lock is fatal for this short-lived collection
+        collector_cb.lock().unwrap().ingest(data);
+        0
+    })?;
+    let rb = rbb.build()?;
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let nudger = {
+        let stop = stop.clone();
+        std::thread::spawn(move || nudge(stop))
+    };
+
+    let deadline = Instant::now() + duration;
+    let polled = loop {
+        if Instant::now() >= deadline {
+            break Ok(());
+        }
+        match rb.poll(Duration::from_millis(50)) {
+            Ok(_) => {}
+            Err(e) if e.kind() == libbpf_rs::ErrorKind::Interrupted => {}
+            Err(e) => break Err(e).context("ringbuf poll failed"),
+        }
+    };
+    stop.store(true, Ordering::Relaxed);
+    let _ = nudger.join();
+    polled?;
+
+    // release the ringbuf's borrow of skel.maps and the collector's Arc clone
+    drop(rb);
+
+    let drops = skel
+        .maps
+        .bss_data
+        .as_deref()
+        .map_or(0, |b| b.g_ringbuf_drops);
+
+    // a poisoned lock is fatal for this short-lived collection
+    let samples = std::mem::take(&mut *collector.lock().unwrap()).into_samples();
+    let layout =
+        KernelLayout::infer(loose.ptr_size, samples.iter().map(|s| s.txn_ptr)).unwrap_or(loose);
+    info!("kernel pointers: {}", layout);
+    Ok(Calibration {
+        samples,
+        drops,
+        layout,
+    })
+}
+
+// --calibrate: derives the offsets of a BTF-less kernel that isn't in the offset cache yet and
+// stores them there, for ReplyCorrelationMode::Auto to find
+pub fn calibrate_if_uncached(cache: &OffsetCache) -> Result<()> {
+    if btf_probe::reply_correlation_supported() {
+        info!("the kernel BTF describes the binder structs, nothing to calibrate");
+        return Ok(());
+    }
+    let kernel = KernelBuild::current()?;
+    if cache.lookup(&kernel).is_some() {
+        info!(
+            "reply offsets of this kernel already in {}",
+            cache.path().display()
+        );
+        return Ok(());
+    }
+    eprintln!("calibrating the reply offsets for {CALIBRATION_SECS}s (nudging binder traffic)...");
+    let offsets = collect(Duration::from_secs(CALIBRATION_SECS))?
+        .solve()
+        .context("failed to derive the reply offsets")?;
+    cache.store(&kernel, &offsets)?;
+    eprintln!(
+        "reply offsets {} saved to {}",
+        offsets.to_reply_offsets_arg(),
+        cache.path().display()
+    );
+    Ok(())
+}
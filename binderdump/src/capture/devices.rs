@@ -0,0 +1,261 @@
+// The binder devices we capture on, one pcapng interface each.
+//
+// Android has /dev/binder, /dev/hwbinder and /dev/vndbinder, but containers (Waydroid, Anbox,
+// microdroid) and custom binderfs instances add devices with other names. Those are found by
+// listing every binderfs mount at startup.
+use binderdump_structs::binder_types::BinderInterface;
+use log::{debug, warn};
+use std::fs;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::path::{Path, PathBuf};
+
+// Always interfaces 0, 1 and 2, in BinderInterface order, so captures from stock devices keep the
+// interface ids they always had
+const STOCK_DEVICES: [&str; 3] = ["/dev/binder", "/dev/hwbinder", "/dev/vndbinder"];
+
+// binderfs creates it next to the devices, it doesn't take BINDER_WRITE_READ
+const BINDER_CONTROL: &str = "binder-control";
+
+#[derive(Debug)]
+pub struct BinderDevice {
+    path: PathBuf,
+    interface: BinderInterface,
+    // other paths of the same device (e.g. /dev/binderfs/binder for /dev/binder)
+    aliases: Vec<PathBuf>,
+    rdev: Option<u64>,
+}
+
+impl BinderDevice {
+    pub fn new(path: PathBuf, rdev: Option<u64>) -> Self {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        Self {
+            interface: BinderInterface::from_device_name(&name),
+            path,
+            aliases: vec![],
+            rdev,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn interface(&self) -> BinderInterface {
+        self.interface
+    }
+
+    pub fn with_aliases(mut self, aliases: Vec<PathBuf>) -> Self {
+        self.aliases = aliases;
+        self
+    }
+
+    pub fn aliases(&self) -> &[PathBuf] {
+        &self.aliases
+    }
+
+    pub fn rdev(&self) -> Option<u64> {
+        self.rdev
+    }
+}
+
+#[derive(Debug)]
+pub struct BinderDevices {
+    devices: Vec<BinderDevice>,
+}
+
+impl BinderDevices {
+    pub fn discover() -> Self {
+        let mut devices = Self::stock();
+        match fs::read_to_string("/proc/self/mountinfo") {
+            Ok(mountinfo) => {
+                for mount in binderfs_mounts(&mountinfo) {
+                    devices.add_binderfs(&mount);
+                }
+            }
+            Err(err) => warn!(
+                "failed to read mountinfo, only using the stock devices: {}",
+                err
+            ),
+        }
+        debug!("binder devices: {:?}", devices);
+        devices
+    }
+
+    // Devices found by an earlier discovery (e.g. recorded in a raw dump), in interface id order
+    pub fn from_list(devices: Vec<BinderDevice>) -> Self {
+        Self { devices }
+    }
+
+    // The stock devices, whether they exist or not
+    fn stock() -> Self {
+        let mut devices = Self { devices: vec![] };
+        for path in STOCK_DEVICES {
+            devices.devices.push(BinderDevice::new(
+                path.into(),
+                char_device_rdev(Path::new(path)),
+            ));
+        }
+        devices
+    }
+
+    fn add_binderfs(&mut self, mount: &Path) {
+        let entries = match fs::read_dir(mount) {
+            Ok(entries) => entries,
+            Err(err) => {
+                warn!("failed to list binderfs at {}: {}", mount.display(), err);
+                return;
+            }
+        };
+        let mut paths: Vec<_> = entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.file_name().is_some_and(|name| name != BINDER_CONTROL))
+            .collect();
+        // stable interface ids between captures
+        paths.sort();
+        for path in paths {
+            if let Some(rdev) = char_device_rdev(&path) {
+                self.add(path, rdev);
+            }
+        }
+    }
+
+    fn add(&mut self, path: PathBuf, rdev: u64) {
+        match self
+            .devices
+            .iter_mut()
+            .find(|device| device.rdev == Some(rdev))
+        {
+            Some(device) => device.aliases.push(path),
+            None => self.devices.push(BinderDevice::new(path, Some(rdev))),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &BinderDevice> {
+        self.devices.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.devices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.devices.is_empty()
+    }
+
+    pub fn get(&self, interface_id: u32) -> Option<&BinderDevice> {
+        self.devices.get(interface_id as usize)
+    }
+
+    // The interface id of the device at `path`, as read from /proc/<pid>/fd
+    pub fn find_path(&self, path: &Path) -> Option<u32> {
+        self.devices
+            .iter()
+            .position(|device| device.path == path || device.aliases.iter().any(|p| p == path))
+            .map(|id| id as u32)
+    }
+
+    // The interface id of a device opened through a path we don't know (another mount namespace)
+    pub fn find_rdev(&self, rdev: u64) -> Option<u32> {
+        self.devices
+            .iter()
+            .position(|device| device.rdev == Some(rdev))
+            .map(|id| id as u32)
+    }
+}
+
+pub(crate) fn char_device_rdev(path: &Path) -> Option<u64> {
+    let metadata = fs::metadata(path).ok()?;
+    metadata
+        .file_type()
+        .is_char_device()
+        .then(|| metadata.rdev())
+}
+
+// Mount points of every binderfs instance. mountinfo lines look like
+// `36 35 0:30 / /dev/binderfs rw,relatime shared:2 - binder binder rw,max=1048576`, with the
+// optional fields ending at '-' followed by the filesystem type.
+fn binderfs_mounts(mountinfo: &str) -> Vec<PathBuf> {
+    mountinfo
+        .lines()
+        .filter_map(|line| {
+            let (mount, fs) = line.split_once(" - ")?;
+            let fstype = fs.split(' ').next()?;
+            let mount_point = mount.split(' ').nth(4)?;
+            (fstype == "binder").then(|| PathBuf::from(unescape_mountinfo(mount_point)))
+        })
+        .collect()
+}
+
+// The kernel escapes space, tab, newline and backslash in mountinfo as octal (\040)
+fn unescape_mountinfo(path: &str) -> String {
+    let mut out = String::with_capacity(path.len());
+    let mut rest = path;
+    while let Some(pos) = rest.find('\\') {
+        out.push_str(&rest[..pos]);
+        let escaped = rest.get(pos + 1..pos + 4);
+        match escaped.and_then(|octal| u8::from_str_radix(octal, 8).ok()) {
+            Some(byte) => {
+                out.push(byte as char);
+                rest = &rest[pos + 4..];
+            }
+            None => {
+                out.push('\\');
+                rest = &rest[pos + 1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_binderfs_mounts() {
+        let mountinfo = "\
+22 1 253:0 / / ro,relatime shared:1 - ext4 /dev/root ro
+36 22 0:30 / /dev/binderfs rw,relatime shared:2 - binder binder rw,max=1048576
+37 22 0:31 / /dev/anbox\\040binder rw,relatime - binder binder rw
+38 22 0:32 / /proc rw,relatime shared:3 - proc proc rw";
+        assert_eq!(
+            binderfs_mounts(mountinfo),
+            [
+                PathBuf::from("/dev/binderfs"),
+                PathBuf::from("/dev/anbox binder")
+            ]
+        );
+    }
+
+    #[test]
+    fn stock_devices_keep_their_interface_ids() {
+        let devices = BinderDevices::stock();
+        assert_eq!(devices.len(), 3);
+        assert_eq!(devices.find_path(Path::new("/dev/hwbinder")), Some(1));
+        assert!(matches!(
+            devices.get(2).unwrap().interface(),
+            BinderInterface::VNDBINDER
+        ));
+    }
+
+    #[test]
+    fn aliases_resolve_to_the_same_device() {
+        let mut devices = BinderDevices {
+            devices: vec![BinderDevice::new("/dev/binder".into(), Some(7))],
+        };
+        devices.add("/dev/binderfs/binder".into(), 7);
+        devices.add("/dev/binderfs/anbox-hwbinder".into(), 8);
+        assert_eq!(devices.len(), 2);
+        assert_eq!(
+            devices.find_path(Path::new("/dev/binderfs/binder")),
+            Some(0)
+        );
+        assert_eq!(devices.find_rdev(8), Some(1));
+        assert!(matches!(
+            devices.get(1).unwrap().interface(),
+            BinderInterface::HWBINDER
+        ));
+    }
+}
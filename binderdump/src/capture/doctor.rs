@@ -0,0 +1,399 @@
+// `binderdump doctor`: checks what a capture needs from the device and prints one line per check,
+//
+//     <ok|warn|fail>\t<check>\t<detail>
+//
+// then a last `doctor` line with the overall result. warn is something the capture goes on
+// without, fail something it can't start without. The extcap runs it before a capture and shows
+// the failures.
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::path::Path;
+
+use super::btf_probe;
+use super::tiers::{program_tier, Tier};
+use super::tracepoints::{capture_programs, dry_run_load, ProgramInfo};
+
+const TRACEFS: &str = "/sys/kernel/tracing";
+const SELINUX_ENFORCE: &str = "/sys/fs/selinux/enforce";
+
+const CAP_SYS_ADMIN: u32 = 21;
+const CAP_PERFMON: u32 = 38;
+const CAP_BPF: u32 = 39;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Ok,
+    Warn,
+    Fail,
+}
+
+impl Status {
+    fn name(&self) -> &'static str {
+        match self {
+            Status::Ok => "ok",
+            Status::Warn => "warn",
+            Status::Fail => "fail",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+    pub status: Status,
+    pub name: String,
+    pub detail: String,
+}
+
+impl Check {
+    fn new(status: Status, name: impl Into<String>, detail: impl Into<String>) -> Self {
+        Self {
+            status,
+            name: name.into(),
+            detail: detail.into(),
+        }
+    }
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // one line per check, whatever the error says
+        let detail: String = self
+            .detail
+            .chars()
+            .map(|c| if c.is_control() { ' ' } else { c })
+            .collect();
+        write!(f, "{}\t{}\t{}", self.status.name(), self.name, detail)
+    }
+}
+
+// The last line of the report
+pub fn summary(checks: &[Check]) -> Check {
+    let failed = checks.iter().filter(|c| c.status == Status::Fail).count();
+    let warned = checks.iter().filter(|c| c.status == Status::Warn).count();
+    let status = if failed > 0 { Status::Fail } else { Status::Ok };
+    Check::new(
+        status,
+        "doctor",
+        format!(
+            "{} checks, {} failed, {} warnings",
+            checks.len(),
+            failed,
+            warned
+        ),
+    )
+}
+
+pub fn run() -> Vec<Check> {
+    let privileges = privileges();
+    let privileged = privileges.status == Status::Ok;
+    let mut checks = vec![privileges, selinux(), tracefs()];
+    checks.extend(procfs());
+    checks.extend(btf());
+    checks.push(ringbuf());
+    checks.push(probe_read());
+    let programs = match capture_programs() {
+        Ok(programs) => programs,
+        Err(err) => {
+            checks.push(Check::new(
+                Status::Fail,
+                "programs",
+                format!("failed to open the BPF object: {:#}", err),
+            ));
+            return checks;
+        }
+    };
+    checks.extend(tracepoints(&programs));
+    if privileged {
+        checks.extend(dry_run(&programs));
+    } else {
+        checks.push(Check::new(
+            Status::Warn,
+            "programs",
+            "not loaded, binderdump isn't privileged",
+        ));
+    }
+    checks
+}
+
+// The CapEff mask of /proc/self/status
+fn effective_caps(status: &str) -> Option<u64> {
+    let mask = status
+        .lines()
+        .find_map(|line| line.strip_prefix("CapEff:"))?;
+    u64::from_str_radix(mask.trim(), 16).ok()
+}
+
+// The capabilities that let binderdump load and attach tracing programs, None without them
+fn bpf_capabilities(caps: u64) -> Option<&'static str> {
+    let has = |cap: u32| caps & (1 << cap) != 0;
+    if has(CAP_SYS_ADMIN) {
+        Some("CAP_SYS_ADMIN")
+    } else if has(CAP_BPF) && has(CAP_PERFMON) {
+        Some("CAP_BPF and CAP_PERFMON")
+    } else {
+        None
+    }
+}
+
+fn privileges() -> Check {
+    let uid = unsafe { libc::geteuid() };
+    let caps = fs::read_to_string("/proc/self/status")
+        .ok()
+        .and_then(|status| effective_caps(&status));
+    match caps.and_then(bpf_capabilities) {
+        Some(caps) => Check::new(Status::Ok, "privileges", format!("uid {}, {}", uid, caps)),
+        None => Check::new(
+            Status::Fail,
+            "privileges",
+            format!(
+                "uid {} without CAP_SYS_ADMIN or CAP_BPF and CAP_PERFMON, run it as root",
+                uid
+            ),
+        ),
+    }
+}
+
+fn selinux() -> Check {
+    let context = fs::read_to_string("/proc/self/attr/current")
+        .map(|context| context.trim_end_matches(['\0', '\n']).to_string())
+        .unwrap_or_default();
+    let mode = match fs::read_to_string(SELINUX_ENFORCE)
+        .as_deref()
+        .map(str::trim)
+    {
+        Ok("1") => "enforcing",
+        Ok(_) => "permissive",
+        Err(_) => "disabled",
+    };
+    // the dry run says whether the domain may load the programs
+    if context.is_empty() {
+        return Check::new(Status::Ok, "selinux", mode);
+    }
+    Check::new(Status::Ok, "selinux", format!("{}, as {}", mode, context))
+}
+
+// The capture turns tracing on before it attaches, see enable_bpf_printk_trace
+fn tracefs() -> Check {
+    let tracing_on = Path::new(TRACEFS).join("tracing_on");
+    match OpenOptions::new().write(true).open(&tracing_on) {
+        Ok(_) => Check::new(Status::Ok, "tracefs", TRACEFS),
+        Err(err) => Check::new(
+            Status::Fail,
+            "tracefs",
+            format!("can't write {}: {}", tracing_on.display(), err),
+        ),
+    }
+}
+
+// What the process cache and --stacks read, from init since it isn't ours
+fn procfs() -> Vec<Check> {
+    let cmdline = match fs::read("/proc/1/cmdline") {
+        Ok(_) => Check::new(Status::Ok, "proc cmdline", "/proc/1/cmdline"),
+        Err(err) => Check::new(
+            Status::Fail,
+            "proc cmdline",
+            format!("can't read /proc/1/cmdline: {}", err),
+        ),
+    };
+    let fds = match fs::read_dir("/proc/1/fd") {
+        Ok(_) => Check::new(Status::Ok, "proc fds", "/proc/1/fd"),
+        Err(err) => Check::new(
+            Status::Warn,
+            "proc fds",
+            format!(
+                "can't list /proc/1/fd, the binder devices of processes won't be resolved: {}",
+                err
+            ),
+        ),
+    };
+    let maps = match fs::read("/proc/1/maps") {
+        Ok(_) => Check::new(Status::Ok, "proc maps", "/proc/1/maps"),
+        Err(err) => Check::new(
+            Status::Warn,
+            "proc maps",
+            format!(
+                "can't read /proc/1/maps, --stacks won't be symbolized: {}",
+                err
+            ),
+        ),
+    };
+    vec![cmdline, fds, maps]
+}
+
+fn btf() -> Vec<Check> {
+    if !btf_probe::kernel_btf_present() {
+        return vec![Check::new(
+            Status::Warn,
+            "btf",
+            "no kernel BTF: reply correlation needs --calibrate, sent fds have no path and \
+             callers no priority",
+        )];
+    }
+    let feature = |name: &str, supported: bool, missing: &str| {
+        if supported {
+            Check::new(Status::Ok, name, "described by the kernel BTF")
+        } else {
+            Check::new(Status::Warn, name, missing)
+        }
+    };
+    vec![
+        Check::new(Status::Ok, "btf", "/sys/kernel/btf/vmlinux"),
+        feature(
+            "btf binder structs",
+            btf_probe::reply_correlation_supported(),
+            "binder_transaction/binder_thread missing, reply correlation needs --calibrate",
+        ),
+        feature(
+            "btf fd paths",
+            btf_probe::fd_paths_supported(),
+            "the file structs are missing, sent fds have no path",
+        ),
+        feature(
+            "btf sched prio",
+            btf_probe::sched_prio_supported(),
+            "task_struct scheduling fields missing, callers have no priority",
+        ),
+    ]
+}
+
+fn ringbuf() -> Check {
+    if btf_probe::ringbuf_supported() {
+        return Check::new(Status::Ok, "ringbuf", "BPF ring buffers supported");
+    }
+    Check::new(
+        Status::Warn,
+        "ringbuf",
+        "no BPF ring buffers, the events go through perf buffers",
+    )
+}
+
+fn probe_read() -> Check {
+    if btf_probe::probe_read_user_supported() {
+        return Check::new(Status::Ok, "probe_read", "bpf_probe_read_user supported");
+    }
+    Check::new(
+        Status::Warn,
+        "probe_read",
+        "no bpf_probe_read_user, the programs read with bpf_probe_read",
+    )
+}
+
+// The tracepoint of a program section, with its category when the section names it
+fn tracepoint_of(section: &str) -> Option<(Option<&str>, &str)> {
+    if let Some(tracepoint) = section.strip_prefix("tp/") {
+        let (category, name) = tracepoint.split_once('/')?;
+        return Some((Some(category), name));
+    }
+    section.strip_prefix("raw_tp/").map(|name| (None, name))
+}
+
+fn tracepoint_exists(category: Option<&str>, name: &str) -> bool {
+    let events = Path::new(TRACEFS).join("events");
+    match category {
+        Some(category) => events.join(category).join(name).exists(),
+        None => fs::read_dir(&events)
+            .map(|categories| {
+                categories
+                    .flatten()
+                    .any(|category| category.path().join(name).exists())
+            })
+            .unwrap_or(false),
+    }
+}
+
+fn tracepoints(programs: &[ProgramInfo]) -> Vec<Check> {
+    let mut checks: Vec<Check> = vec![];
+    for program in programs {
+        let Some((category, name)) = tracepoint_of(&program.section) else {
+            continue;
+        };
+        let check_name = format!("tracepoint {}", name);
+        if checks.iter().any(|check| check.name == check_name) {
+            continue;
+        }
+        // a tracepoint none of the programs of this kernel attach to is left out by the capture
+        let used = programs
+            .iter()
+            .any(|other| other.loaded && tracepoint_of(&other.section) == Some((category, name)));
+        checks.push(match (tracepoint_exists(category, name), used) {
+            (true, _) => Check::new(Status::Ok, check_name, program.section.as_str()),
+            (false, true) => Check::new(Status::Fail, check_name, "missing"),
+            (false, false) => Check::new(Status::Warn, check_name, "missing, not used"),
+        });
+    }
+    checks
+}
+
+fn dry_run(programs: &[ProgramInfo]) -> Vec<Check> {
+    let loaded: Vec<&ProgramInfo> = programs.iter().filter(|program| program.loaded).collect();
+    let names: Vec<&str> = loaded.iter().map(|program| program.name.as_str()).collect();
+    let rejected = match dry_run_load(&names) {
+        Ok(rejected) => rejected,
+        Err(err) => return vec![Check::new(Status::Fail, "programs", format!("{:#}", err))],
+    };
+    loaded
+        .into_iter()
+        .map(|program| {
+            let name = format!("program {}", program.name);
+            let tier = program_tier(&program.name);
+            match rejected.get(&program.name) {
+                None => Check::new(Status::Ok, name, "loaded"),
+                Some(err) if tier == Tier::Core => {
+                    Check::new(Status::Fail, name, format!("{:#}", err))
+                }
+                Some(err) => Check::new(
+                    Status::Warn,
+                    name,
+                    format!(
+                        "{:#}, the capture goes on without {}",
+                        err,
+                        tier.description()
+                    ),
+                ),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_the_effective_capabilities() {
+        let status = "Name:\tbinderdump\nCapPrm:\t0000000000000000\nCapEff:\t000000c000000000\n";
+        let caps = effective_caps(status).unwrap();
+        assert_eq!(bpf_capabilities(caps), Some("CAP_BPF and CAP_PERFMON"));
+        assert_eq!(bpf_capabilities(0x1fffffffff), Some("CAP_SYS_ADMIN"));
+        assert_eq!(bpf_capabilities(1 << CAP_BPF), None);
+        assert_eq!(effective_caps("Name:\tsh\n"), None);
+    }
+
+    #[test]
+    fn finds_the_tracepoint_of_a_section() {
+        assert_eq!(
+            tracepoint_of("tp/binder/binder_ioctl"),
+            Some((Some("binder"), "binder_ioctl"))
+        );
+        assert_eq!(tracepoint_of("raw_tp/sys_exit"), Some((None, "sys_exit")));
+        assert_eq!(tracepoint_of("license"), None);
+    }
+
+    #[test]
+    fn prints_one_line_per_check() {
+        let checks = vec![
+            Check::new(Status::Ok, "ringbuf", "BPF ring buffers supported"),
+            Check::new(Status::Fail, "program binder_ioctl", "failed\nto load"),
+            Check::new(Status::Warn, "btf", "no kernel BTF"),
+        ];
+        assert_eq!(
+            checks[1].to_string(),
+            "fail\tprogram binder_ioctl\tfailed to load"
+        );
+        assert_eq!(
+            summary(&checks).to_string(),
+            "fail\tdoctor\t3 checks, 1 failed, 1 warnings"
+        );
+        assert_eq!(summary(&checks[..1]).status, Status::Ok);
+    }
+}
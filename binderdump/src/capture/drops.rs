@@ -0,0 +1,150 @@
+// Accounting of events that were lost somewhere between the BPF program and the pcapng, so a
+// missing reply in the capture can be told apart from a capture loss, and of the ones that were
+// written incomplete (truncated transactions, events without their ioctl).
+use std::fmt;
+
+use anyhow::{Context, Result};
+use libbpf_rs::{MapCore, MapFlags, MapHandle};
+
+use super::common_types;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CaptureDrops {
+    // counted by the BPF program (see src/bpf/drops.h)
+    pub ringbuf_full: u64,
+    pub probe_read: u64,
+    pub too_many_chunks: u64,
+    pub too_many_ptr_objects: u64,
+    // ring buffer records we failed to parse
+    pub invalid_events: u64,
+    // events the aggregator got without the ioctl they belong to
+    pub orphan_events: u64,
+    // aggregated events we failed to turn into a packet
+    pub unhandled_events: u64,
+}
+
+impl CaptureDrops {
+    // Reads the per-cpu `drop_counters` map
+    pub fn read_bpf_counters(drop_counters: &MapHandle) -> Result<Self> {
+        let read = |reason: common_types::capture_drop_reason_t| -> Result<u64> {
+            let values = drop_counters
+                .lookup_percpu(&reason.to_ne_bytes(), MapFlags::ANY)
+                .with_context(|| format!("failed to read drop counter {}", reason))?
+                .unwrap_or_default();
+            Ok(sum_percpu(&values))
+        };
+        Ok(Self {
+            ringbuf_full: read(common_types::capture_drop_reason_t_DROP_RINGBUF_FULL)?,
+            probe_read: read(common_types::capture_drop_reason_t_DROP_PROBE_READ)?,
+            too_many_chunks: read(common_types::capture_drop_reason_t_DROP_TOO_MANY_CHUNKS)?,
+            too_many_ptr_objects: read(
+                common_types::capture_drop_reason_t_DROP_TOO_MANY_PTR_OBJECTS,
+            )?,
+            ..Default::default()
+        })
+    }
+
+    // The events that never reached the pcapng, what the ISBs report as dropped
+    pub fn lost(&self) -> u64 {
+        self.ringbuf_full + self.probe_read + self.invalid_events + self.unhandled_events
+    }
+
+    // The events that were written, but without all of their data
+    pub fn incomplete(&self) -> u64 {
+        self.too_many_chunks + self.too_many_ptr_objects + self.orphan_events
+    }
+}
+
+fn sum_percpu(values: &[Vec<u8>]) -> u64 {
+    values
+        .iter()
+        .filter_map(|value| value.get(..8)?.try_into().ok())
+        .map(u64::from_ne_bytes)
+        .sum()
+}
+
+impl fmt::Display for CaptureDrops {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.lost() == 0 {
+            write!(f, "no events were lost")?;
+        } else {
+            write!(f, "{} events were lost:", self.lost())?;
+        }
+        let lost = [
+            ("ring buffer full", self.ringbuf_full),
+            ("failed to read task memory", self.probe_read),
+            ("invalid ring buffer record", self.invalid_events),
+            ("failed to build packet", self.unhandled_events),
+        ];
+        for (cause, count) in lost.iter().filter(|(_, count)| *count > 0) {
+            write!(f, "\n  {}: {}", cause, count)?;
+        }
+        if self.incomplete() > 0 {
+            write!(f, "\n{} events were written incomplete:", self.incomplete())?;
+        }
+        let incomplete = [
+            ("transaction too big", self.too_many_chunks),
+            ("too many objects", self.too_many_ptr_objects),
+            ("missing ioctl start", self.orphan_events),
+        ];
+        for (cause, count) in incomplete.iter().filter(|(_, count)| *count > 0) {
+            write!(f, "\n  {}: {}", cause, count)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sums_all_cpus() {
+        let values = vec![
+            3u64.to_ne_bytes().to_vec(),
+            0u64.to_ne_bytes().to_vec(),
+            7u64.to_ne_bytes().to_vec(),
+        ];
+        assert_eq!(sum_percpu(&values), 10);
+    }
+
+    #[test]
+    fn ignores_short_values() {
+        let values = vec![vec![1, 2, 3], 5u64.to_ne_bytes().to_vec()];
+        assert_eq!(sum_percpu(&values), 5);
+    }
+
+    #[test]
+    fn summary_lists_only_nonzero_causes() {
+        let drops = CaptureDrops {
+            ringbuf_full: 2,
+            unhandled_events: 1,
+            ..Default::default()
+        };
+        assert_eq!(drops.lost(), 3);
+        assert_eq!(
+            drops.to_string(),
+            "3 events were lost:\n  ring buffer full: 2\n  failed to build packet: 1"
+        );
+    }
+
+    #[test]
+    fn truncated_and_orphan_events_are_not_lost() {
+        let drops = CaptureDrops {
+            too_many_chunks: 2,
+            orphan_events: 1,
+            ..Default::default()
+        };
+        assert_eq!(drops.lost(), 0);
+        assert_eq!(
+            drops.to_string(),
+            "no events were lost\n3 events were written incomplete:\n  transaction too big: 2\n  \
+             missing ioctl start: 1"
+        );
+    }
+
+    #[test]
+    fn summary_without_drops() {
+        assert_eq!(CaptureDrops::default().to_string(), "no events were lost");
+    }
+}
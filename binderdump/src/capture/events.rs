@@ -1,14 +1,18 @@
 use crate::capture::common_types::binder_event_transaction_stack;
 
 use super::common_types::{
-    self, binder_event, binder_event_ioctl, binder_event_ioctl_done, binder_event_transaction,
-    binder_event_transaction_received, binder_event_txn_ptr_data, binder_event_write_read,
+    self, binder_event, binder_event_exec, binder_event_ioctl, binder_event_ioctl_arg,
+    binder_event_ioctl_done, binder_event_new_process, binder_event_set_priority,
+    binder_event_transaction, binder_event_transaction_received, binder_event_txn_fd,
+    binder_event_txn_prio, binder_event_txn_ptr_data, binder_event_txn_secctx,
+    binder_event_txn_translation, binder_event_txn_user_stack, binder_event_txn_wakeup,
+    binder_event_write_read,
 };
 use anyhow::{anyhow, Context};
 use binderdump_structs::binder_types::{
     binder_command, binder_ioctl, binder_return, binder_write_read, bwr_trait::Bwr,
 };
-use binderdump_structs::bwr_layer::Transaction;
+use binderdump_structs::bwr_layer::{Transaction, TranslationType};
 use binderdump_structs::errors::ToAnyhow;
 use num::FromPrimitive;
 use num_derive::FromPrimitive;
@@ -26,6 +30,16 @@ unsafe impl Plain for binder_event_transaction {}
 unsafe impl Plain for binder_event_transaction_received {}
 unsafe impl Plain for binder_event_transaction_stack {}
 unsafe impl Plain for binder_event_txn_ptr_data {}
+unsafe impl Plain for binder_event_new_process {}
+unsafe impl Plain for binder_event_exec {}
+unsafe impl Plain for binder_event_txn_translation {}
+unsafe impl Plain for binder_event_txn_fd {}
+unsafe impl Plain for binder_event_txn_secctx {}
+unsafe impl Plain for binder_event_ioctl_arg {}
+unsafe impl Plain for binder_event_txn_prio {}
+unsafe impl Plain for binder_event_set_priority {}
+unsafe impl Plain for binder_event_txn_wakeup {}
+unsafe impl Plain for binder_event_txn_user_stack {}
 
 #[derive(Debug, FromPrimitive)]
 #[allow(non_camel_case_types)]
@@ -47,6 +61,16 @@ pub enum BinderProcessState {
     BINDER_TXN_DATA = common_types::binder_process_state_t_BINDER_TXN_DATA,
     BINDER_TXN_STACK = common_types::binder_process_state_t_BINDER_TXN_STACK,
     BINDER_TXN_PTR_DATA = common_types::binder_process_state_t_BINDER_TXN_PTR_DATA,
+    BINDER_NEW_PROCESS = common_types::binder_process_state_t_BINDER_NEW_PROCESS,
+    BINDER_EXEC = common_types::binder_process_state_t_BINDER_EXEC,
+    BINDER_TXN_TRANSLATION = common_types::binder_process_state_t_BINDER_TXN_TRANSLATION,
+    BINDER_TXN_FD = common_types::binder_process_state_t_BINDER_TXN_FD,
+    BINDER_TXN_SECCTX = common_types::binder_process_state_t_BINDER_TXN_SECCTX,
+    BINDER_IOCTL_ARG = common_types::binder_process_state_t_BINDER_IOCTL_ARG,
+    BINDER_TXN_PRIO = common_types::binder_process_state_t_BINDER_TXN_PRIO,
+    BINDER_SET_PRIORITY = common_types::binder_process_state_t_BINDER_SET_PRIORITY,
+    BINDER_TXN_WAKEUP = common_types::binder_process_state_t_BINDER_TXN_WAKEUP,
+    BINDER_TXN_USER_STACK = common_types::binder_process_state_t_BINDER_TXN_USER_STACK,
 }
 
 impl From<&binder_event_transaction> for Transaction {
@@ -76,6 +100,19 @@ pub enum BinderEventData {
     BinderTransactionData(BinderTransactionContents),
     BinderTransactionPtrData(BinderTransactionPtrChunk),
     BinderInvalidateProcess,
+    BinderNewProcess(BinderEventNewProcess),
+    BinderExec(BinderEventExec),
+    BinderTransactionTranslation(BinderTransactionTranslation),
+    BinderTransactionFd(BinderTransactionFd),
+    // the security context of the sender of a BR_TRANSACTION_SEC_CTX
+    BinderTransactionSecCtx(String),
+    BinderIoctlArg(BinderIoctlArg),
+    BinderTransactionPrio(BinderTransactionPrio),
+    // the pid and tid of the event are of the thread whose priority changed
+    BinderSetPriority(BinderSetPriority),
+    BinderTransactionWakeup(BinderTransactionWakeup),
+    // the return addresses of the caller of a BC_TRANSACTION, innermost first
+    BinderTransactionUserStack(Vec<u64>),
 }
 
 impl BinderEventData {
@@ -85,6 +122,17 @@ impl BinderEventData {
             _ => false,
         }
     }
+
+    // Events about the life of a process, which come from BPF on their own instead of as part of
+    // an ioctl
+    pub fn is_lifecycle(&self) -> bool {
+        matches!(
+            self,
+            BinderEventData::BinderInvalidateProcess
+                | BinderEventData::BinderNewProcess(_)
+                | BinderEventData::BinderExec(_)
+        )
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -99,6 +147,10 @@ impl BinderEvent {
     pub fn is_invalidate_process(&self) -> bool {
         self.data.is_invalidate_process()
     }
+
+    pub fn is_lifecycle(&self) -> bool {
+        self.data.is_lifecycle()
+    }
 }
 
 const HEADER_SIZE: usize = std::mem::size_of::<binder_event>();
@@ -177,6 +229,63 @@ impl TryFrom<&[u8]> for BinderEvent {
                     data,
                 )?)
             }
+            BinderProcessState::BINDER_NEW_PROCESS => {
+                let data = &value[HEADER_SIZE..];
+                let raw_event: &binder_event_new_process = plain::from_bytes(data)
+                    .map_err(|err| err.to_anyhow("Failed to parse binder_event_new_process"))?;
+                BinderEventData::BinderNewProcess(raw_event.into())
+            }
+            BinderProcessState::BINDER_EXEC => {
+                let data = &value[HEADER_SIZE..];
+                let raw_event: &binder_event_exec = plain::from_bytes(data)
+                    .map_err(|err| err.to_anyhow("Failed to parse binder_event_exec"))?;
+                BinderEventData::BinderExec(raw_event.into())
+            }
+            BinderProcessState::BINDER_TXN_TRANSLATION => {
+                let data = &value[HEADER_SIZE..];
+                BinderEventData::BinderTransactionTranslation(
+                    BinderTransactionTranslation::try_from(data)?,
+                )
+            }
+            BinderProcessState::BINDER_TXN_FD => {
+                let data = &value[HEADER_SIZE..];
+                BinderEventData::BinderTransactionFd(BinderTransactionFd::try_from(data)?)
+            }
+            BinderProcessState::BINDER_TXN_SECCTX => {
+                let data = &value[HEADER_SIZE..];
+                let raw_event: &binder_event_txn_secctx = plain::from_bytes(data)
+                    .map_err(|err| err.to_anyhow("Failed to parse binder_event_txn_secctx"))?;
+                BinderEventData::BinderTransactionSecCtx(c_chars_to_string(&raw_event.secctx))
+            }
+            BinderProcessState::BINDER_IOCTL_ARG => {
+                let data = &value[HEADER_SIZE..];
+                BinderEventData::BinderIoctlArg(BinderIoctlArg::try_from(data)?)
+            }
+            BinderProcessState::BINDER_TXN_PRIO => {
+                let data = &value[HEADER_SIZE..];
+                let raw_event: &binder_event_txn_prio = plain::from_bytes(data)
+                    .map_err(|err| err.to_anyhow("Failed to parse binder_event_txn_prio"))?;
+                BinderEventData::BinderTransactionPrio(raw_event.into())
+            }
+            BinderProcessState::BINDER_SET_PRIORITY => {
+                let data = &value[HEADER_SIZE..];
+                let raw_event: &binder_event_set_priority = plain::from_bytes(data)
+                    .map_err(|err| err.to_anyhow("Failed to parse binder_event_set_priority"))?;
+                BinderEventData::BinderSetPriority(raw_event.into())
+            }
+            BinderProcessState::BINDER_TXN_WAKEUP => {
+                let data = &value[HEADER_SIZE..];
+                let raw_event: &binder_event_txn_wakeup = plain::from_bytes(data)
+                    .map_err(|err| err.to_anyhow("Failed to parse binder_event_txn_wakeup"))?;
+                BinderEventData::BinderTransactionWakeup(raw_event.into())
+            }
+            BinderProcessState::BINDER_TXN_USER_STACK => {
+                let data = &value[HEADER_SIZE..];
+                let raw_event: &binder_event_txn_user_stack = plain::from_bytes(data)
+                    .map_err(|err| err.to_anyhow("Failed to parse binder_event_txn_user_stack"))?;
+                let depth = (raw_event.depth as usize).min(raw_event.ips.len());
+                BinderEventData::BinderTransactionUserStack(raw_event.ips[..depth].to_vec())
+            }
         };
         // println!("Parsed binder event header: {:?} data: {:?}", header, data);
         Ok(Self {
@@ -227,6 +336,8 @@ impl TryFrom<&common_types::binder_event_ioctl> for BinderEventIoctl {
 #[derive(Debug, Clone)]
 pub struct BinderEventWriteReadData {
     bwr: binder_write_read,
+    // bwr_event_flags_t
+    flags: u32,
     buffer: Vec<u8>,
 }
 
@@ -235,6 +346,11 @@ impl BinderEventWriteReadData {
         self.buffer.len()
     }
 
+    // the buffer was cut short by --snaplen / --metadata-only
+    pub fn is_truncated(&self) -> bool {
+        self.flags & common_types::bwr_event_flags_t_BWR_TRUNCATED != 0
+    }
+
     pub fn get_bwr(&self) -> &binder_write_read {
         &self.bwr
     }
@@ -309,6 +425,13 @@ impl TryFrom<&BinderEventWriteRead> for Vec<binder_return::BinderReturn> {
     }
 }
 
+// The first BC_/BR_ TRANSACTION or REPLY in a BWR buffer
+#[derive(Clone, Copy)]
+pub struct TransactionCommand {
+    pub is_reply: bool,
+    pub data: binderdump_structs::binder_types::transaction::binder_transaction_data,
+}
+
 #[derive(Clone)]
 pub enum BinderEventWriteRead {
     BinderEventRead(BinderEventWriteReadData),
@@ -359,10 +482,21 @@ impl BinderEventWriteRead {
     pub fn is_blocking(&self) -> anyhow::Result<bool> {
         match self {
             BinderEventWriteRead::BinderEventRead(_) => Ok(false),
+            // we can't parse the commands of a truncated buffer, BPF looked for us
+            BinderEventWriteRead::BinderEventWrite(bw) if bw.is_truncated() => {
+                Ok(bw.flags & common_types::bwr_event_flags_t_BWR_HAS_TRANSACTION != 0)
+            }
             BinderEventWriteRead::BinderEventWrite(_) => self.any_transaction(),
         }
     }
 
+    pub fn is_truncated(&self) -> bool {
+        match self {
+            BinderEventWriteRead::BinderEventRead(br) => br.is_truncated(),
+            BinderEventWriteRead::BinderEventWrite(bw) => bw.is_truncated(),
+        }
+    }
+
     pub fn get_bwr(&self) -> &binder_write_read {
         match self {
             BinderEventWriteRead::BinderEventRead(br) => br.get_bwr(),
@@ -375,11 +509,15 @@ impl BinderEventWriteRead {
     // would otherwise only appear inside the dissector's Commands array
     // (target.handle, cookie, sender_pid, sender_euid, ...) into the
     // TransactionProtocol layer.
-    pub fn first_transaction_command_data(
-        &self,
-    ) -> anyhow::Result<
-        Option<binderdump_structs::binder_types::transaction::binder_transaction_data>,
-    > {
+    pub fn first_transaction_command(&self) -> anyhow::Result<Option<TransactionCommand>> {
+        match self.first_transaction_command_impl() {
+            // the command we were looking for may be past the snaplen, or cut in the middle
+            Err(_) if self.is_truncated() => Ok(None),
+            result => result,
+        }
+    }
+
+    fn first_transaction_command_impl(&self) -> anyhow::Result<Option<TransactionCommand>> {
         match self {
             BinderEventWriteRead::BinderEventWrite(bw) => {
                 let data = bw.data();
@@ -387,17 +525,19 @@ impl BinderEventWriteRead {
                 while pos < data.len() {
                     let bc = binder_command::BinderCommand::try_from(&data[pos..])?;
                     pos += bc.size();
-                    match bc {
-                        binder_command::BinderCommand::Transaction(t)
-                        | binder_command::BinderCommand::Reply(t) => {
-                            return Ok(Some(*t.data()));
+                    let (is_reply, data) = match &bc {
+                        binder_command::BinderCommand::Transaction(t) => (false, t.data()),
+                        binder_command::BinderCommand::Reply(t) => (true, t.data()),
+                        binder_command::BinderCommand::TransactionSg(t) => {
+                            (false, t.transaction().data())
                         }
-                        binder_command::BinderCommand::TransactionSg(t)
-                        | binder_command::BinderCommand::ReplySg(t) => {
-                            return Ok(Some(*t.transaction().data()));
-                        }
-                        _ => {}
-                    }
+                        binder_command::BinderCommand::ReplySg(t) => (true, t.transaction().data()),
+                        _ => continue,
+                    };
+                    return Ok(Some(TransactionCommand {
+                        is_reply,
+                        data: *data,
+                    }));
                 }
                 Ok(None)
             }
@@ -407,16 +547,18 @@ impl BinderEventWriteRead {
                 while pos < data.len() {
                     let r = binder_return::BinderReturn::try_from(&data[pos..])?;
                     pos += r.size();
-                    match r {
-                        binder_return::BinderReturn::Transaction(t)
-                        | binder_return::BinderReturn::Reply(t) => {
-                            return Ok(Some(*t.data()));
-                        }
+                    let (is_reply, data) = match &r {
+                        binder_return::BinderReturn::Transaction(t) => (false, t.data()),
+                        binder_return::BinderReturn::Reply(t) => (true, t.data()),
                         binder_return::BinderReturn::TransactionSecCtx(secctx) => {
-                            return Ok(Some(*secctx.transaction().data()));
+                            (false, secctx.transaction().data())
                         }
-                        _ => {}
-                    }
+                        _ => continue,
+                    };
+                    return Ok(Some(TransactionCommand {
+                        is_reply,
+                        data: *data,
+                    }));
                 }
                 Ok(None)
             }
@@ -456,7 +598,8 @@ impl std::fmt::Debug for BinderEventWriteRead {
     }
 }
 
-const BWR_SIZE: usize = std::mem::size_of::<binder_write_read>();
+// binder_event_write_read, without the data[] that follows it
+const BWR_SIZE: usize = std::mem::size_of::<binder_event_write_read>();
 
 impl TryFrom<&[u8]> for BinderEventWriteReadData {
     type Error = anyhow::Error;
@@ -464,10 +607,13 @@ impl TryFrom<&[u8]> for BinderEventWriteReadData {
     fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
         let raw_bwr: &binder_write_read = plain::from_bytes(value)
             .map_err(|err| err.to_anyhow("Failed to parse binder_write_read struct"))?;
+        let header: &binder_event_write_read = plain::from_bytes(value)
+            .map_err(|err| err.to_anyhow("Failed to parse binder_event_write_read struct"))?;
         let buffer = &value[BWR_SIZE..];
 
         Ok(Self {
             bwr: *raw_bwr,
+            flags: header.flags,
             buffer: buffer.into(),
         })
     }
@@ -547,6 +693,168 @@ impl TryFrom<&[u8]> for BinderTransactionStack {
     }
 }
 
+// A binder object of a transaction, as the kernel translated it for the target
+#[derive(Debug, Clone)]
+pub struct BinderTransactionTranslation {
+    pub debug_id: i32,
+    pub translation_type: TranslationType,
+    pub node_debug_id: i32,
+    pub src: u64,
+    pub dest: u64,
+}
+
+impl TryFrom<&[u8]> for BinderTransactionTranslation {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        let raw_event: &binder_event_txn_translation = plain::from_bytes(value)
+            .map_err(|err| err.to_anyhow("Failed to parse binder_event_txn_translation"))?;
+        let translation_type = match raw_event.kind {
+            common_types::translation_kind_t_TRANSLATION_NODE_TO_REF => TranslationType::NodeToRef,
+            common_types::translation_kind_t_TRANSLATION_REF_TO_NODE => TranslationType::RefToNode,
+            common_types::translation_kind_t_TRANSLATION_REF_TO_REF => TranslationType::RefToRef,
+            kind => return Err(anyhow!("Unknown translation kind {}", kind)),
+        };
+        Ok(Self {
+            debug_id: raw_event.debug_id,
+            translation_type,
+            node_debug_id: raw_event.node_debug_id,
+            src: raw_event.src,
+            dest: raw_event.dest,
+        })
+    }
+}
+
+// The file behind a sent fd, as BPF found it in the sender
+#[derive(Debug, Clone, Default)]
+pub struct BinderFdFile {
+    pub magic: u64,
+    pub mode: u32,
+    pub ino: u64,
+    // dentry names, innermost first
+    pub names: Vec<String>,
+    // the outermost names didn't fit
+    pub truncated: bool,
+}
+
+// The struct the arg of an ioctl points to, as raw bytes. See IoctlArgProtocol::parse
+#[derive(Debug, Clone)]
+pub struct BinderIoctlArg {
+    // read when the ioctl returned, instead of when it started
+    pub done: bool,
+    pub data: Vec<u8>,
+}
+
+impl TryFrom<&[u8]> for BinderIoctlArg {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        let raw_event: &binder_event_ioctl_arg = plain::from_bytes(value)
+            .map_err(|err| err.to_anyhow("Failed to parse binder_event_ioctl_arg"))?;
+        let size = (raw_event.size as usize).min(raw_event.data.len());
+        Ok(Self {
+            done: raw_event.done != 0,
+            data: raw_event.data[..size].iter().map(|&c| c as u8).collect(),
+        })
+    }
+}
+
+// The scheduling class of the sender of a transaction
+#[derive(Debug, Clone)]
+pub struct BinderTransactionPrio {
+    pub debug_id: i32,
+    pub policy: u32,
+    pub prio: u32,
+}
+
+impl From<&binder_event_txn_prio> for BinderTransactionPrio {
+    fn from(value: &binder_event_txn_prio) -> Self {
+        Self {
+            debug_id: value.debug_id,
+            policy: value.policy,
+            prio: value.prio,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BinderSetPriority {
+    pub old_prio: u32,
+    pub desired_prio: u32,
+    pub new_prio: u32,
+}
+
+impl From<&binder_event_set_priority> for BinderSetPriority {
+    fn from(value: &binder_event_set_priority) -> Self {
+        Self {
+            old_prio: value.old_prio,
+            desired_prio: value.desired_prio,
+            new_prio: value.new_prio,
+        }
+    }
+}
+
+// When the receiving thread of a transaction was woken for it and got on a CPU
+#[derive(Debug, Clone)]
+pub struct BinderTransactionWakeup {
+    pub debug_id: i32,
+    pub woken: u64,
+    pub on_cpu: u64,
+}
+
+impl From<&binder_event_txn_wakeup> for BinderTransactionWakeup {
+    fn from(value: &binder_event_txn_wakeup) -> Self {
+        Self {
+            debug_id: value.debug_id,
+            woken: value.woken,
+            on_cpu: value.on_cpu,
+        }
+    }
+}
+
+// A fd of a FD or FDA object, as the sender sent it or as the target got it
+#[derive(Debug, Clone)]
+pub struct BinderTransactionFd {
+    pub debug_id: i32,
+    pub fd: i32,
+    pub offset: u64,
+    pub received: bool,
+    // only on sends, when BPF could resolve it
+    pub file: Option<BinderFdFile>,
+}
+
+impl TryFrom<&[u8]> for BinderTransactionFd {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        let raw_event: &binder_event_txn_fd = plain::from_bytes(value)
+            .map_err(|err| err.to_anyhow("Failed to parse binder_event_txn_fd"))?;
+        let received = match raw_event.direction {
+            common_types::txn_fd_direction_t_TXN_FD_SEND => false,
+            common_types::txn_fd_direction_t_TXN_FD_RECV => true,
+            direction => return Err(anyhow!("Unknown txn fd direction {}", direction)),
+        };
+        let depth = (raw_event.depth as usize).min(raw_event.names.len());
+        let file = (raw_event.magic != 0 || depth > 0).then(|| BinderFdFile {
+            magic: raw_event.magic,
+            mode: raw_event.mode,
+            ino: raw_event.ino,
+            names: raw_event.names[..depth]
+                .iter()
+                .map(|name| c_chars_to_string(name))
+                .collect(),
+            truncated: raw_event.truncated != 0,
+        });
+        Ok(Self {
+            debug_id: raw_event.debug_id,
+            fd: raw_event.fd,
+            offset: raw_event.offset,
+            received,
+            file,
+        })
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct BinderTransactionPtrChunk {
     pub offset_index: u32,
@@ -581,3 +889,70 @@ impl TryFrom<&[u8]> for BinderTransactionPtrChunk {
         })
     }
 }
+
+// A C string in a fixed size array, which BPF may not have terminated
+fn c_chars_to_string(chars: &[std::ffi::c_char]) -> String {
+    let bytes: Vec<u8> = chars
+        .iter()
+        .map(|&c| c as u8)
+        .take_while(|&c| c != 0)
+        .collect();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+// A process created by fork, reported by its parent (the pid/tid of the event)
+#[derive(Debug, Clone)]
+pub struct BinderEventNewProcess {
+    pub child_pid: i32,
+    pub comm: String,
+    pub uid: u32,
+}
+
+impl From<&binder_event_new_process> for BinderEventNewProcess {
+    fn from(value: &binder_event_new_process) -> Self {
+        Self {
+            child_pid: value.child_pid,
+            comm: c_chars_to_string(&value.comm),
+            uid: value.uid,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BinderEventExec {
+    // the tid that called execve, the event's tid is the pid when a non-leader thread execs
+    pub old_tid: i32,
+    pub comm: String,
+    pub uid: u32,
+    pub filename: String,
+    // the first MAX_EXEC_ARGS arguments, each cut to EXEC_ARG_SIZE bytes
+    pub argv: Vec<String>,
+}
+
+impl BinderEventExec {
+    // what /proc/<pid>/cmdline would have started with
+    pub fn cmdline(&self) -> &str {
+        match self.argv.first() {
+            Some(arg) if !arg.is_empty() => arg,
+            _ => &self.filename,
+        }
+    }
+}
+
+impl From<&binder_event_exec> for BinderEventExec {
+    fn from(value: &binder_event_exec) -> Self {
+        let args_size = (value.args_size as usize).min(value.args.len());
+        let argv = value.args[..args_size]
+            .split(|&c| c == 0)
+            .filter(|arg| !arg.is_empty())
+            .map(c_chars_to_string)
+            .collect();
+        Self {
+            old_tid: value.old_tid,
+            comm: c_chars_to_string(&value.comm),
+            uid: value.uid,
+            filename: c_chars_to_string(&value.filename),
+            argv,
+        }
+    }
+}
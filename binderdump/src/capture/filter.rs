@@ -0,0 +1,161 @@
+// In-kernel capture filters (--pid, --uid, --comm, --exclude-pid).
+//
+// The values are pushed into the `filter_*` BPF maps (see src/bpf/filter.h), so tasks that don't
+// match never reach the ring buffer. The other endpoint of a transaction sent by a matching task
+// is traced too, so both halves of a call into the selected processes show up in the capture.
+use anyhow::{Context, Result};
+use libbpf_rs::{MapCore, MapFlags};
+use log::{debug, warn};
+
+use super::common_types;
+use super::tracepoints::binder::{BinderSkel, OpenBinderSkel};
+
+// TASK_COMM_LEN, including the terminating NUL
+const COMM_LEN: usize = 16;
+
+#[derive(Debug, Default, Clone)]
+pub struct CaptureFilter {
+    pub pids: Vec<i32>,
+    pub uids: Vec<u32>,
+    pub comms: Vec<String>,
+    pub exclude_pids: Vec<i32>,
+}
+
+impl CaptureFilter {
+    pub fn is_empty(&self) -> bool {
+        self.flags() == 0
+    }
+
+    fn flags(&self) -> u32 {
+        let mut flags = 0;
+        if !self.pids.is_empty() {
+            flags |= common_types::capture_filter_flags_t_CAPTURE_FILTER_PID;
+        }
+        if !self.uids.is_empty() {
+            flags |= common_types::capture_filter_flags_t_CAPTURE_FILTER_UID;
+        }
+        if !self.comms.is_empty() {
+            flags |= common_types::capture_filter_flags_t_CAPTURE_FILTER_COMM;
+        }
+        if !self.exclude_pids.is_empty() {
+            flags |= common_types::capture_filter_flags_t_CAPTURE_FILTER_EXCLUDE_PID;
+        }
+        flags
+    }
+
+    // Must be called before the skeleton is loaded (rodata becomes read-only)
+    pub(crate) fn configure(&self, open_skel: &mut OpenBinderSkel<'_>) -> Result<()> {
+        let rodata = open_skel
+            .maps
+            .rodata_data
+            .as_deref_mut()
+            .context("BPF rodata section unavailable")?;
+        rodata.cfg_filter_flags = self.flags();
+        Ok(())
+    }
+
+    // Must be called after the skeleton is loaded, but before it is attached
+    pub(crate) fn populate(&self, skel: &BinderSkel<'_>) -> Result<()> {
+        if self.is_empty() {
+            return Ok(());
+        }
+        let one = [1u8];
+        let maps = &skel.maps;
+
+        // The BPF side selects a process once one of its threads matches by comm. Processes that
+        // are already running may never call binder from a thread that still has the original
+        // name, so select them up front.
+        let running = running_pids_by_comm(&self.comms);
+        for pid in self.pids.iter().chain(running.iter()) {
+            maps.filter_pids
+                .update(&pid.to_ne_bytes(), &one, MapFlags::ANY)
+                .with_context(|| format!("failed to add pid {} to the capture filter", pid))?;
+        }
+        for uid in &self.uids {
+            maps.filter_uids
+                .update(&uid.to_ne_bytes(), &one, MapFlags::ANY)
+                .with_context(|| format!("failed to add uid {} to the capture filter", uid))?;
+        }
+        for comm in &self.comms {
+            maps.filter_comms
+                .update(&comm_key(comm), &one, MapFlags::ANY)
+                .with_context(|| format!("failed to add comm {} to the capture filter", comm))?;
+        }
+        for pid in &self.exclude_pids {
+            maps.filter_exclude_pids
+                .update(&pid.to_ne_bytes(), &one, MapFlags::ANY)
+                .with_context(|| format!("failed to exclude pid {} from the capture", pid))?;
+        }
+        debug!("capture filter: {:?}, running matches: {:?}", self, running);
+        Ok(())
+    }
+}
+
+// The kernel truncates comm to 15 bytes, so match on the same prefix
+fn comm_key(comm: &str) -> [u8; COMM_LEN] {
+    let mut key = [0u8; COMM_LEN];
+    let len = comm.len().min(COMM_LEN - 1);
+    key[..len].copy_from_slice(&comm.as_bytes()[..len]);
+    key
+}
+
+fn running_pids_by_comm(comms: &[String]) -> Vec<i32> {
+    if comms.is_empty() {
+        return vec![];
+    }
+    let keys: Vec<_> = comms.iter().map(|comm| comm_key(comm)).collect();
+    let processes = match procfs::process::all_processes() {
+        Ok(processes) => processes,
+        Err(err) => {
+            warn!("failed to list processes for --comm: {}", err);
+            return vec![];
+        }
+    };
+    processes
+        .filter_map(|proc| proc.ok())
+        .filter_map(|proc| {
+            let comm = proc.stat().ok()?.comm;
+            keys.contains(&comm_key(&comm)).then_some(proc.pid)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_filter_has_no_flags() {
+        assert!(CaptureFilter::default().is_empty());
+    }
+
+    #[test]
+    fn flags_follow_populated_filters() {
+        let filter = CaptureFilter {
+            uids: vec![1000],
+            exclude_pids: vec![1],
+            ..Default::default()
+        };
+        assert_eq!(
+            filter.flags(),
+            common_types::capture_filter_flags_t_CAPTURE_FILTER_UID
+                | common_types::capture_filter_flags_t_CAPTURE_FILTER_EXCLUDE_PID
+        );
+        assert!(!filter.is_empty());
+    }
+
+    #[test]
+    fn comm_key_is_nul_padded() {
+        let key = comm_key("surfaceflinger");
+        assert_eq!(&key[..14], b"surfaceflinger");
+        assert!(key[14..].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn comm_key_truncates_like_the_kernel() {
+        let key = comm_key("com.example.longpackage");
+        assert_eq!(&key[..15], b"com.example.lon");
+        assert_eq!(key[15], 0);
+        assert_eq!(key, comm_key("com.example.longer"));
+    }
+}
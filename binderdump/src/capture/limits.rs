@@ -0,0 +1,215 @@
+// How much of a transaction the BPF programs copy, and how big the ring buffer they copy it to is
+// (--ring-size, --max-chunks, --max-ptr-objects, --max-ptr-payload).
+//
+// The counts live in the BPF rodata (see src/bpf/limits.h) and the ring buffer is resized, both
+// before load. The loops and copies are verified against the fixed ceilings of
+// src/bpf/common_types.h, so any value within them loads. The loop ceilings are the defaults: the
+// counts can only go down, raising them would cost every capture verifier complexity. The 32K scratch buffers can't grow with
+// them: they are per-cpu map values, which the kernel caps at 32K, so transaction data is still
+// copied in 32K chunks and a scatter-gather payload is at most one buffer.
+use anyhow::{bail, Context, Result};
+use libbpf_rs::libbpf_sys;
+use libbpf_rs::{MapHandle, MapType};
+
+use super::common_types::{
+    MAX_PTR_OBJECTS, MAX_PTR_OBJECTS_LIMIT, MAX_PTR_PAYLOAD, MAX_PTR_PAYLOAD_LIMIT,
+    MAX_TRANSACTION_CHUNKS, MAX_TRANSACTION_CHUNKS_LIMIT, RING_SIZE_MB, RING_SIZE_MB_LIMIT,
+};
+use super::ringbuf::Transport;
+use super::tracepoints::binder::OpenBinderSkel;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CaptureLimits {
+    // in MB
+    pub ring_size: u32,
+    // 32K chunks of transaction data
+    pub max_chunks: u32,
+    // BINDER_TYPE_PTR objects walked per transaction, 0 doesn't copy any scatter-gather payload
+    pub max_ptr_objects: u32,
+    // bytes copied of every scatter-gather payload
+    pub max_ptr_payload: u32,
+}
+
+impl Default for CaptureLimits {
+    fn default() -> Self {
+        Self {
+            ring_size: RING_SIZE_MB,
+            max_chunks: MAX_TRANSACTION_CHUNKS,
+            max_ptr_objects: MAX_PTR_OBJECTS,
+            max_ptr_payload: MAX_PTR_PAYLOAD,
+        }
+    }
+}
+
+impl CaptureLimits {
+    pub fn validate(&self) -> Result<()> {
+        if !self.ring_size.is_power_of_two() || self.ring_size > RING_SIZE_MB_LIMIT {
+            bail!(
+                "--ring-size must be a power of two of at most {} MB, got {}",
+                RING_SIZE_MB_LIMIT,
+                self.ring_size
+            );
+        }
+        check_range(
+            "--max-chunks",
+            self.max_chunks,
+            1,
+            MAX_TRANSACTION_CHUNKS_LIMIT,
+        )?;
+        check_range(
+            "--max-ptr-objects",
+            self.max_ptr_objects,
+            0,
+            MAX_PTR_OBJECTS_LIMIT,
+        )?;
+        check_range(
+            "--max-ptr-payload",
+            self.max_ptr_payload,
+            1,
+            MAX_PTR_PAYLOAD_LIMIT,
+        )?;
+        Ok(())
+    }
+
+    pub fn ring_size_bytes(&self) -> u32 {
+        self.ring_size << 20
+    }
+
+    // --max-ptr-objects 0 loads the programs that don't walk the objects at all, which would
+    // otherwise count every transaction with an object as having too many
+    pub fn copies_ptr_payloads(&self) -> bool {
+        self.max_ptr_objects > 0
+    }
+
+    // Must be called before the skeleton is loaded (rodata becomes read-only). On the perf buffer
+    // the ring size is split between the per-cpu buffers instead, when they are created.
+    pub(crate) fn configure(
+        &self,
+        open_skel: &mut OpenBinderSkel<'_>,
+        transport: Transport,
+    ) -> Result<()> {
+        self.validate()?;
+        if transport == Transport::RingBuf {
+            self.check_ring_size()?;
+            open_skel
+                .maps
+                .binder_events_buffer
+                .set_max_entries(self.ring_size_bytes())
+                .context("failed to resize the ring buffer")?;
+        }
+        let rodata = open_skel
+            .maps
+            .rodata_data
+            .as_deref_mut()
+            .context("BPF rodata section unavailable")?;
+        rodata.cfg_max_transaction_chunks = self.max_chunks;
+        rodata.cfg_max_ptr_objects = self.max_ptr_objects;
+        rodata.cfg_max_ptr_payload = self.max_ptr_payload;
+        Ok(())
+    }
+
+    // When the kernel refuses the ring buffer, load() fails with a bare errno and only libbpf's log
+    // says which map it was. Ask for one of the same size first to say so.
+    fn check_ring_size(&self) -> Result<()> {
+        let opts = libbpf_sys::bpf_map_create_opts {
+            sz: std::mem::size_of::<libbpf_sys::bpf_map_create_opts>() as _,
+            ..Default::default()
+        };
+        MapHandle::create(
+            MapType::RingBuf,
+            Some("ring_size_probe"),
+            0,
+            0,
+            self.ring_size_bytes(),
+            &opts,
+        )
+        .map(drop)
+        .with_context(|| {
+            format!(
+                "the kernel refused a {} MB ring buffer, try a smaller --ring-size",
+                self.ring_size
+            )
+        })
+    }
+
+    // The options that differ from the defaults, for the load error
+    pub fn non_default_flags(&self) -> Vec<String> {
+        let d = Self::default();
+        [
+            ("--ring-size", self.ring_size, d.ring_size),
+            ("--max-chunks", self.max_chunks, d.max_chunks),
+            ("--max-ptr-objects", self.max_ptr_objects, d.max_ptr_objects),
+            ("--max-ptr-payload", self.max_ptr_payload, d.max_ptr_payload),
+        ]
+        .into_iter()
+        .filter(|(_, value, default)| value != default)
+        .map(|(flag, value, _)| format!("{} {}", flag, value))
+        .collect()
+    }
+}
+
+fn check_range(flag: &str, value: u32, min: u32, max: u32) -> Result<()> {
+    if !(min..=max).contains(&value) {
+        bail!(
+            "{} must be between {} and {}, got {}",
+            flag,
+            min,
+            max,
+            value
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults_are_valid() {
+        let limits = CaptureLimits::default();
+        assert!(limits.validate().is_ok());
+        assert_eq!(limits.ring_size_bytes(), 64 << 20);
+        assert!(limits.non_default_flags().is_empty());
+    }
+
+    #[test]
+    fn rejects_sizes_the_programs_were_not_verified_for() {
+        let limits = |ring_size, max_chunks, max_ptr_objects, max_ptr_payload| CaptureLimits {
+            ring_size,
+            max_chunks,
+            max_ptr_objects,
+            max_ptr_payload,
+        };
+        assert!(limits(96, 32, 32, 16384).validate().is_err());
+        assert!(limits(4096, 32, 32, 16384).validate().is_err());
+        assert!(limits(64, 0, 32, 16384).validate().is_err());
+        assert!(limits(64, 33, 32, 16384).validate().is_err());
+        assert!(limits(64, 32, 33, 16384).validate().is_err());
+        assert!(limits(64, 32, 32, 32713).validate().is_err());
+        assert!(limits(1, 32, 0, 32712).validate().is_ok());
+    }
+
+    #[test]
+    fn no_ptr_objects_skips_the_payload_programs() {
+        assert!(CaptureLimits::default().copies_ptr_payloads());
+        let limits = CaptureLimits {
+            max_ptr_objects: 0,
+            ..Default::default()
+        };
+        assert!(!limits.copies_ptr_payloads());
+    }
+
+    #[test]
+    fn lists_the_changed_flags() {
+        let limits = CaptureLimits {
+            ring_size: 256,
+            max_ptr_payload: 32712,
+            ..Default::default()
+        };
+        assert_eq!(
+            limits.non_default_flags(),
+            ["--ring-size 256", "--max-ptr-payload 32712"]
+        );
+    }
+}
@@ -0,0 +1,163 @@
+// The reply-correlation offsets offset_finder or --calibrate derived, per kernel build, so a
+// BTF-less device is only calibrated once. One line per kernel:
+//
+//     <build id of the kernel, in hex>\t<contents of /proc/version>\t<--reply-offsets value>
+//
+// The build id comes from the kernel's own ELF notes: two builds of a vendor kernel can have the
+// same /proc/version. A kernel without one is keyed on /proc/version alone.
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use log::debug;
+
+use super::offset_solver::DerivedOffsets;
+use super::proc_maps::find_build_id_note;
+use super::tracepoints::ReplyOffsets;
+
+pub const OFFSET_CACHE_PATH: &str = "/data/local/tmp/binderdump_offsets";
+const KERNEL_NOTES: &str = "/sys/kernel/notes";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KernelBuild {
+    // hex, empty when the kernel has no build id
+    build_id: String,
+    version: String,
+}
+
+impl KernelBuild {
+    pub fn new(build_id: &[u8], version: &str) -> Self {
+        Self {
+            build_id: build_id
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect(),
+            // /proc/version is a single line, the cache is tab separated
+            version: version.trim().replace('\t', " "),
+        }
+    }
+
+    pub fn current() -> Result<Self> {
+        let version =
+            fs::read_to_string("/proc/version").context("failed to read /proc/version")?;
+        let build_id = fs::read(KERNEL_NOTES)
+            .ok()
+            .and_then(|notes| find_build_id_note(&notes))
+            .unwrap_or_default();
+        Ok(Self::new(&build_id, &version))
+    }
+
+    fn key(&self) -> String {
+        format!("{}\t{}", self.build_id, self.version)
+    }
+}
+
+pub struct OffsetCache {
+    path: PathBuf,
+}
+
+impl Default for OffsetCache {
+    fn default() -> Self {
+        Self::new(OFFSET_CACHE_PATH)
+    }
+}
+
+impl OffsetCache {
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // A missing or unreadable cache is an empty one
+    fn lines(&self) -> Vec<String> {
+        match fs::read_to_string(&self.path) {
+            Ok(contents) => contents.lines().map(str::to_string).collect(),
+            Err(err) => {
+                debug!("no offset cache at {}: {}", self.path.display(), err);
+                vec![]
+            }
+        }
+    }
+
+    pub fn lookup(&self, kernel: &KernelBuild) -> Option<ReplyOffsets> {
+        let key = kernel.key();
+        self.lines().iter().find_map(|line| {
+            let (line_key, offsets) = line.rsplit_once('\t')?;
+            if line_key != key {
+                return None;
+            }
+            offsets.parse().ok()
+        })
+    }
+
+    // Replaces the entry of `kernel`, the other kernels' are kept (a device that got an update)
+    pub fn store(&self, kernel: &KernelBuild, offsets: &DerivedOffsets) -> Result<()> {
+        let key = kernel.key();
+        let mut lines: Vec<String> = self
+            .lines()
+            .into_iter()
+            .filter(|line| line.rsplit_once('\t').map(|(k, _)| k) != Some(key.as_str()))
+            .collect();
+        lines.push(format!("{}\t{}", key, offsets.to_reply_offsets_arg()));
+
+        // written aside and renamed, a capture reading it never sees half of it
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, lines.join("\n") + "\n")
+            .with_context(|| format!("failed to write {}", tmp.display()))?;
+        fs::rename(&tmp, &self.path)
+            .with_context(|| format!("failed to write {}", self.path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn offsets(to_thread: u32) -> DerivedOffsets {
+        DerivedOffsets {
+            to_thread,
+            transaction_stack: 64,
+            debug_id: 0,
+        }
+    }
+
+    #[test]
+    fn keeps_one_entry_per_kernel_build() {
+        let path = std::env::temp_dir().join(format!("binderdump_offsets_{}", std::process::id()));
+        let cache = OffsetCache::new(&path);
+        let version = "Linux version 5.10.43-android12-9 (build-user@build-host) #1 SMP PREEMPT\n";
+        let kernel = KernelBuild::new(&[0xab, 0xcd], version);
+        let rebuilt = KernelBuild::new(&[0xef], version);
+        assert!(cache.lookup(&kernel).is_none());
+
+        cache.store(&kernel, &offsets(56)).unwrap();
+        cache.store(&rebuilt, &offsets(72)).unwrap();
+        cache.store(&kernel, &offsets(64)).unwrap();
+        assert_eq!(cache.lookup(&kernel).unwrap().to_thread, 64);
+        assert_eq!(cache.lookup(&rebuilt).unwrap().to_thread, 72);
+        assert!(cache
+            .lookup(&KernelBuild::new(&[], "Linux version 6.1.25"))
+            .is_none());
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 2);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn skips_lines_it_cant_parse() {
+        let path =
+            std::env::temp_dir().join(format!("binderdump_offsets_bad_{}", std::process::id()));
+        let kernel = KernelBuild::new(&[0x01], "Linux version 4.19.191");
+        fs::write(
+            &path,
+            format!("garbage\n{}\tto_thread=oops\n", kernel.key()),
+        )
+        .unwrap();
+        assert!(OffsetCache::new(&path).lookup(&kernel).is_none());
+        fs::remove_file(&path).unwrap();
+    }
+}
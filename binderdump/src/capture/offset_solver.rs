@@ -4,6 +4,7 @@
 
 use anyhow::{bail, Result};
 use std::collections::HashMap;
+use std::fmt;
 
 // window sizes; must match offsets.bpf.c
 pub const STRUCT_WIN: usize = 256;
@@ -28,6 +29,134 @@ pub struct Sample {
     pub derefs: Vec<Deref>,
 }
 
+// How the running kernel lays out its pointers. Every kernel address has the bits from `va_bits`
+// up to the pointer width set; on a tagged kernel (arm64 MTE or tag-based KASAN) the top byte is
+// a tag and left out of that.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KernelLayout {
+    // 4 or 8
+    pub ptr_size: usize,
+    pub va_bits: u32,
+    pub tagged: bool,
+}
+
+impl KernelLayout {
+    pub const ARM64_39: Self = Self::new64(39, false);
+    pub const ARM64_48: Self = Self::new64(48, false);
+    pub const ARM64_52: Self = Self::new64(52, false);
+    // 4-level paging, the kernel half starts at 0xffff800000000000
+    pub const X86_64: Self = Self::new64(47, false);
+    // the 3G/1G split, PAGE_OFFSET 0xc0000000
+    pub const ARM32: Self = Self {
+        ptr_size: 4,
+        va_bits: 30,
+        tagged: false,
+    };
+
+    const fn new64(va_bits: u32, tagged: bool) -> Self {
+        Self {
+            ptr_size: 8,
+            va_bits,
+            tagged,
+        }
+    }
+
+    // The least every layout of the architecture `machine` (uname -m) has in common, for the
+    // calibration program to pick the slots to follow before the samples tell the layout. Only the
+    // architectures whose pointer width we know, a wrong one reads every pointer wrong.
+    pub fn for_machine(machine: &str) -> Result<Self> {
+        Ok(match machine {
+            // any of 39/48/52 bits, tagged or not
+            "aarch64" | "arm64" => Self::new64(52, true),
+            // 4 or 5-level paging
+            "x86_64" => Self::new64(56, false),
+            // any kernel half
+            "riscv64" | "ppc64" | "ppc64le" => Self::new64(63, false),
+            // a 2G/2G split too. armv8l is a 32-bit kernel on an ARMv8 CPU, the 32-bit personality
+            // of an arm64 kernel is turned back into aarch64 by kernel_machine()
+            "armv7l" | "armv8l" | "i686" => Self {
+                ptr_size: 4,
+                va_bits: 31,
+                tagged: false,
+            },
+            _ => bail!("don't know the pointer width of {} kernels", machine),
+        })
+    }
+
+    // The layout the transaction addresses of the running kernel show. Any of them can sit high
+    // in the kernel half, the one with the fewest leading ones gives the VA size.
+    pub fn infer(ptr_size: usize, addrs: impl IntoIterator<Item = u64>) -> Option<Self> {
+        let bits = (ptr_size * 8) as u32;
+        let mut layout: Option<Self> = None;
+        for addr in addrs {
+            // on a 64-bit kernel the top byte is all ones unless it's a tag
+            let tagged = ptr_size == 8 && addr >> 56 != 0xff;
+            let untagged = if tagged { addr | 0xff << 56 } else { addr };
+            let leading = (untagged << (64 - bits)).leading_ones();
+            // not in the kernel half, whatever the tag
+            if leading <= if tagged { 8 } else { 0 } {
+                continue;
+            }
+            let va_bits = bits - leading;
+            layout = Some(match layout {
+                Some(l) => Self {
+                    va_bits: l.va_bits.max(va_bits),
+                    tagged: l.tagged || tagged,
+                    ..l
+                },
+                None => Self {
+                    ptr_size,
+                    va_bits,
+                    tagged,
+                },
+            });
+        }
+        layout
+    }
+
+    // The bits every kernel pointer has set, what offsets.bpf.c checks the slots against
+    pub fn kernel_mask(&self) -> u64 {
+        let bits = (self.ptr_size * 8) as u32;
+        let mut mask = (u64::MAX >> (64 - bits)) & !((1u64 << self.va_bits) - 1);
+        if self.tagged && self.ptr_size == 8 {
+            mask &= !(0xff << 56);
+        }
+        mask
+    }
+
+    // The tag of a tagged pointer is the allocation's, the same address can come with another
+    pub fn untag(&self, ptr: u64) -> u64 {
+        if self.tagged {
+            ptr | 0xff << 56
+        } else {
+            ptr
+        }
+    }
+
+    pub fn is_kernel_ptr(&self, value: u64) -> bool {
+        let mask = self.kernel_mask();
+        value & mask == mask
+    }
+
+    fn read_ptr(&self, buf: &[u8], off: usize) -> Option<u64> {
+        if self.ptr_size == 4 {
+            read_u32(buf, off).map(u64::from)
+        } else {
+            read_u64(buf, off)
+        }
+    }
+}
+
+impl fmt::Display for KernelLayout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-bit, {}-bit VA", self.ptr_size * 8, self.va_bits)?;
+        if self.tagged {
+            write!(f, ", tagged")?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct DerivedOffsets {
     pub to_thread: u32,
@@ -86,7 +215,7 @@ fn find_debug_id_offset(samples: &[Sample]) -> Result<u32> {
     winner.ok_or_else(|| anyhow::anyhow!("could not determine debug_id offset"))
 }
 
-pub fn solve(samples: &[Sample]) -> Result<DerivedOffsets> {
+pub fn solve(samples: &[Sample], layout: KernelLayout) -> Result<DerivedOffsets> {
     if samples.is_empty() {
         bail!("no calibration samples");
     }
@@ -95,7 +224,7 @@ pub fn solve(samples: &[Sample]) -> Result<DerivedOffsets> {
     let known: HashMap<u64, u32> = samples
         .iter()
         .filter(|s| s.debug_id != 0)
-        .map(|s| (s.txn_ptr, s.debug_id))
+        .map(|s| (layout.untag(s.txn_ptr), s.debug_id))
         .collect();
 
     // votes[(txn_to_thread_off, thread_txn_stack_off)] = confirming replies.
@@ -109,12 +238,18 @@ pub fn solve(samples: &[Sample]) -> Result<DerivedOffsets> {
             continue;
         }
         replies += 1;
+        let reply_ptr = layout.untag(r.txn_ptr);
         for d in &r.derefs {
-            for q in (0..d.window.len().saturating_sub(7)).step_by(8) {
-                let Some(val) = read_u64(&d.window, q) else {
+            let slots = d.window.len().saturating_sub(layout.ptr_size - 1);
+            for q in (0..slots).step_by(layout.ptr_size) {
+                let Some(val) = layout.read_ptr(&d.window, q) else {
                     continue;
                 };
-                if val == r.txn_ptr {
+                if !layout.is_kernel_ptr(val) {
+                    continue;
+                }
+                let val = layout.untag(val);
+                if val == reply_ptr {
                     continue;
                 }
                 // a slot pointing at a known earlier transaction (debug_id <
@@ -223,7 +358,7 @@ mod tests {
 
     #[test]
     fn solves_planted_offsets() {
-        let d = solve(&make_samples()).unwrap();
+        let d = solve(&make_samples(), KernelLayout::ARM64_48).unwrap();
         assert_eq!(d.debug_id, 0);
         assert_eq!(d.to_thread, 56);
         assert_eq!(d.transaction_stack, 64);
@@ -233,11 +368,135 @@ mod tests {
         );
     }
 
+    fn ptr_bytes(layout: KernelLayout, ptr: u64) -> Vec<u8> {
+        ptr.to_le_bytes()[..layout.ptr_size].to_vec()
+    }
+
+    // make_samples for any layout: requests and replies allocated from `base`, to_thread and
+    // transaction_stack in the 7th and 8th pointer slots. A tagged kernel gives every allocation
+    // its own tag, and the transaction_stack of a thread can carry another one.
+    fn make_layout_samples(layout: KernelLayout, base: u64) -> Vec<Sample> {
+        let p = layout.ptr_size;
+        let tag = |ptr: u64, tag: u64| {
+            if layout.tagged {
+                ptr & !(0xff << 56) | tag << 56
+            } else {
+                ptr
+            }
+        };
+        let req_ptrs: Vec<u64> = (0..4u64)
+            .map(|i| tag(base + 0x1000 * (i + 1), 0xf0 + i))
+            .collect();
+        let mut samples: Vec<Sample> = req_ptrs
+            .iter()
+            .enumerate()
+            .map(|(i, &txn_ptr)| {
+                let did = (i as u32) + 1;
+                Sample {
+                    txn_ptr,
+                    reply: false,
+                    debug_id: did,
+                    struct_window: win(&[(0, &did.to_le_bytes())], STRUCT_WIN),
+                    derefs: vec![],
+                }
+            })
+            .collect();
+        for (i, &req) in req_ptrs.iter().take(3).enumerate() {
+            let did = (i as u32) + 5;
+            let thread_ptr = tag(base + 0x9_0000 + (i as u64) * 0x100, 0xf8);
+            let stack = ptr_bytes(layout, tag(req, 0xfe));
+            let decoy = ptr_bytes(layout, tag(base + 0xaa_0000, 0xf1));
+            samples.push(Sample {
+                txn_ptr: tag(base + 0xb_0000 + (i as u64) * 0x100, 0xf4),
+                reply: true,
+                debug_id: did,
+                struct_window: win(
+                    &[
+                        (0, &did.to_le_bytes()),
+                        (7 * p, &ptr_bytes(layout, thread_ptr)),
+                    ],
+                    STRUCT_WIN,
+                ),
+                derefs: vec![Deref {
+                    src_off: (7 * p) as u32,
+                    window: win(&[(2 * p, &decoy), (8 * p, &stack)], DEREF_WIN),
+                }],
+            });
+        }
+        samples
+    }
+
+    #[test]
+    fn solves_every_layout() {
+        let tagged = KernelLayout {
+            tagged: true,
+            ..KernelLayout::ARM64_48
+        };
+        let layouts = [
+            (KernelLayout::ARM64_39, 0xffff_ff80_0000_0000),
+            (KernelLayout::ARM64_48, 0xffff_0000_0000_0000),
+            (KernelLayout::ARM64_52, 0xfff0_0000_0000_0000),
+            (tagged, 0xffff_0000_0000_0000),
+            (KernelLayout::X86_64, 0xffff_8880_0000_0000),
+            (KernelLayout::ARM32, 0xc000_0000),
+        ];
+        for (layout, base) in layouts {
+            let samples = make_layout_samples(layout, base);
+            let inferred = KernelLayout::infer(layout.ptr_size, samples.iter().map(|s| s.txn_ptr));
+            assert_eq!(inferred, Some(layout), "{layout}");
+            let d = solve(&samples, layout).unwrap_or_else(|e| panic!("{layout}: {e:#}"));
+            let p = layout.ptr_size as u32;
+            assert_eq!(
+                (d.to_thread, d.transaction_stack, d.debug_id),
+                (7 * p, 8 * p, 0),
+                "{layout}"
+            );
+        }
+    }
+
+    #[test]
+    fn masks_the_kernel_half() {
+        assert_eq!(KernelLayout::ARM64_39.kernel_mask(), 0xffff_ff80_0000_0000);
+        assert_eq!(KernelLayout::X86_64.kernel_mask(), 0xffff_8000_0000_0000);
+        assert_eq!(KernelLayout::ARM32.kernel_mask(), 0xc000_0000);
+        let arm64 = KernelLayout::for_machine("aarch64").unwrap();
+        assert_eq!(arm64.kernel_mask(), 0x00f0_0000_0000_0000);
+        // whatever the VA size or tag, but not a user address
+        for ptr in [
+            0xffff_ff80_0000_1000u64,
+            0xffff_0000_0000_1000,
+            0xfff0_0000_0000_1000,
+            0xf3ff_0000_0000_1000,
+        ] {
+            assert!(arm64.is_kernel_ptr(ptr), "{ptr:#x}");
+        }
+        assert!(!arm64.is_kernel_ptr(0x0000_007f_f000_0000));
+        let x86_64 = KernelLayout::for_machine("x86_64").unwrap();
+        assert!(x86_64.is_kernel_ptr(0xff11_0000_0000_1000));
+        let armv7 = KernelLayout::for_machine("armv7l").unwrap();
+        assert!(armv7.is_kernel_ptr(0x8000_1000));
+        assert!(!KernelLayout::ARM32.is_kernel_ptr(0x8000_1000));
+        assert_eq!(KernelLayout::infer(8, [0x0000_007f_f000_0000]), None);
+    }
+
+    #[test]
+    fn pointer_width_comes_from_known_machines_only() {
+        for machine in ["aarch64", "x86_64", "riscv64", "ppc64le"] {
+            assert_eq!(KernelLayout::for_machine(machine).unwrap().ptr_size, 8);
+        }
+        for machine in ["armv7l", "armv8l", "i686"] {
+            assert_eq!(KernelLayout::for_machine(machine).unwrap().ptr_size, 4);
+        }
+        // 64-bit, but kernel addresses aren't in a high half
+        assert!(KernelLayout::for_machine("s390x").is_err());
+        assert!(KernelLayout::for_machine("sparc64").is_err());
+    }
+
     #[test]
     fn errors_on_insufficient_replies() {
         let mut s = make_samples();
         s.retain(|x| !x.reply); // drop all replies
-        let err = solve(&s).unwrap_err().to_string();
+        let err = solve(&s, KernelLayout::ARM64_48).unwrap_err().to_string();
         assert!(err.contains("insufficient reply traffic"), "got: {err}");
     }
 
@@ -252,7 +511,7 @@ mod tests {
             reply.derefs[0].window[96..104]
                 .copy_from_slice(&0xffff_dead_dead_0000u64.to_le_bytes());
         }
-        let d = solve(&s).unwrap();
+        let d = solve(&s, KernelLayout::ARM64_48).unwrap();
         assert_eq!((d.to_thread, d.transaction_stack, d.debug_id), (56, 64, 0));
     }
 
@@ -282,7 +541,7 @@ mod tests {
         for sample in &mut s {
             sample.debug_id = 0;
         }
-        let err = solve(&s).unwrap_err().to_string();
+        let err = solve(&s, KernelLayout::ARM64_48).unwrap_err().to_string();
         assert!(err.contains("insufficient debug_id samples"), "got: {err}");
     }
 
@@ -295,7 +554,7 @@ mod tests {
             let did = sample.debug_id.to_le_bytes();
             sample.struct_window[8..12].copy_from_slice(&did);
         }
-        let err = solve(&s).unwrap_err().to_string();
+        let err = solve(&s, KernelLayout::ARM64_48).unwrap_err().to_string();
         assert!(err.contains("ambiguous debug_id"), "got: {err}");
     }
 }
@@ -0,0 +1,213 @@
+// uid -> package names, from /data/system/packages.list.
+//
+// Every line is `<package> <app id> <debuggable> <data dir> <seinfo> <gids> ...`. Packages with the
+// same android:sharedUserId (e.g. android.uid.phone) share an app id, and the app id of an app is
+// the same for every Android user (uid = user * AID_USER_OFFSET + app id). The list is reread when
+// an app uid we don't know shows up, in case it was installed during the capture.
+use super::raw_dump::{RawDumpPackage, RawDumpWriter};
+use binderdump_structs::android_ids::{AID_APP_END, AID_APP_START, AID_USER_OFFSET};
+use log::debug;
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime};
+
+const PACKAGES_LIST: &str = "/data/system/packages.list";
+
+// a uid of an app that was just installed is seen on every packet of the app, don't stat the list
+// for each of them
+const RELOAD_INTERVAL: Duration = Duration::from_secs(1);
+
+pub fn app_id(uid: u32) -> u32 {
+    uid % AID_USER_OFFSET
+}
+
+fn is_app(uid: u32) -> bool {
+    (AID_APP_START..=AID_APP_END).contains(&app_id(uid))
+}
+
+#[derive(Default)]
+pub struct Packages {
+    // app id -> packages, in packages.list order
+    by_app_id: HashMap<u32, Vec<String>>,
+    // None when the list came from somewhere else (e.g. a raw dump), it is never reread then
+    path: Option<PathBuf>,
+    modified: Option<SystemTime>,
+    last_check: Option<Instant>,
+    raw_dump: Option<RawDumpWriter>,
+}
+
+impl Packages {
+    pub fn load() -> Self {
+        let mut packages = Self {
+            path: Some(PACKAGES_LIST.into()),
+            ..Default::default()
+        };
+        packages.reload();
+        packages
+    }
+
+    // Packages listed somewhere else than the running system (e.g. recorded in a raw dump)
+    pub fn from_list(packages: &[RawDumpPackage]) -> Self {
+        let mut by_app_id: HashMap<u32, Vec<String>> = HashMap::new();
+        for package in packages {
+            by_app_id
+                .entry(package.app_id)
+                .or_default()
+                .push(package.name.clone());
+        }
+        Self {
+            by_app_id,
+            ..Default::default()
+        }
+    }
+
+    // Record the list, and every time it is reread, in `raw_dump`
+    pub fn with_raw_dump(mut self, raw_dump: RawDumpWriter) -> Self {
+        raw_dump.write_packages(&self.to_list());
+        self.raw_dump = Some(raw_dump);
+        self
+    }
+
+    fn to_list(&self) -> Vec<RawDumpPackage> {
+        let mut list: Vec<_> = self
+            .by_app_id
+            .iter()
+            .flat_map(|(&app_id, names)| {
+                names.iter().map(move |name| RawDumpPackage {
+                    name: name.clone(),
+                    app_id,
+                })
+            })
+            .collect();
+        list.sort_by_key(|package| package.app_id);
+        list
+    }
+
+    fn reload(&mut self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        self.last_check = Some(Instant::now());
+        let modified = fs::metadata(path).and_then(|m| m.modified()).ok();
+        if modified.is_some() && modified == self.modified {
+            return;
+        }
+        match fs::read_to_string(path) {
+            Ok(contents) => {
+                self.by_app_id = parse_packages_list(&contents);
+                self.modified = modified;
+                debug!("{} app ids in {}", self.by_app_id.len(), path.display());
+            }
+            Err(err) => {
+                debug!("failed to read {}: {}", path.display(), err);
+                return;
+            }
+        }
+        if let Some(raw_dump) = &self.raw_dump {
+            raw_dump.write_packages(&self.to_list());
+        }
+    }
+
+    // The packages running as `uid`, empty for uids that aren't an app's or a shared user's
+    pub fn get(&mut self, uid: u32) -> &[String] {
+        let app_id = app_id(uid);
+        let recently_checked = self
+            .last_check
+            .is_some_and(|last| last.elapsed() < RELOAD_INTERVAL);
+        if is_app(uid) && !self.by_app_id.contains_key(&app_id) && !recently_checked {
+            self.reload();
+        }
+        self.by_app_id
+            .get(&app_id)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    // The package of a process of `uid`. The process name of an app is its package name,
+    // optionally followed by ':' and the name of the service or activity process, so that is how a
+    // process of a shared uid is matched. Native daemons also run as the system shared uids
+    // (system, radio, nfc, ...), they only get a package by name.
+    pub fn package_of(&mut self, uid: u32, cmdline: &str) -> String {
+        let process = cmdline.split(':').next().unwrap_or_default();
+        let app = is_app(uid);
+        match self.get(uid) {
+            [] => String::new(),
+            [package] if app => package.clone(),
+            packages => packages
+                .iter()
+                .find(|package| *package == process)
+                .cloned()
+                .unwrap_or_default(),
+        }
+    }
+}
+
+fn parse_packages_list(contents: &str) -> HashMap<u32, Vec<String>> {
+    let mut by_app_id: HashMap<u32, Vec<String>> = HashMap::new();
+    for line in contents.lines() {
+        let mut fields = line.split_whitespace();
+        let (Some(name), Some(app_id)) = (fields.next(), fields.next()) else {
+            continue;
+        };
+        let Ok(app_id) = app_id.parse() else {
+            continue;
+        };
+        by_app_id.entry(app_id).or_default().push(name.to_string());
+    }
+    by_app_id
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PACKAGES: &str = "\
+com.example.app 10234 1 /data/user/0/com.example.app default:targetSdkVersion=34 3003 0 1
+com.android.phone 1001 0 /data/user_de/0/com.android.phone platform:privapp 3002,3003 0 1
+com.android.stk 1001 0 /data/user/0/com.android.stk platform:privapp 3002,3003 0 1
+broken line";
+
+    #[test]
+    fn parses_shared_uids() {
+        let mut packages = Packages {
+            by_app_id: parse_packages_list(PACKAGES),
+            ..Default::default()
+        };
+        assert_eq!(packages.get(10234), ["com.example.app"]);
+        // the same app in another user
+        assert_eq!(packages.get(1010234), ["com.example.app"]);
+        assert_eq!(packages.get(1001), ["com.android.phone", "com.android.stk"]);
+        assert!(packages.get(1000).is_empty());
+    }
+
+    #[test]
+    fn process_names_pick_the_shared_uid_package() {
+        let mut packages = Packages {
+            by_app_id: parse_packages_list(PACKAGES),
+            ..Default::default()
+        };
+        assert_eq!(
+            packages.package_of(10234, "com.example.app:remote"),
+            "com.example.app"
+        );
+        assert_eq!(
+            packages.package_of(1001, "com.android.phone"),
+            "com.android.phone"
+        );
+        assert_eq!(packages.package_of(1001, "/system/bin/rild"), "");
+        assert_eq!(packages.package_of(1027, "com.android.nfc"), "");
+        assert_eq!(packages.package_of(99001, "com.example.app:isolated"), "");
+    }
+
+    #[test]
+    fn round_trips_through_a_list() {
+        let packages = Packages {
+            by_app_id: parse_packages_list(PACKAGES),
+            ..Default::default()
+        };
+        let mut copy = Packages::from_list(&packages.to_list());
+        assert_eq!(copy.get(1001), ["com.android.phone", "com.android.stk"]);
+        assert_eq!(copy.get(10234), ["com.example.app"]);
+    }
+}
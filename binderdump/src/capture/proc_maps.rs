@@ -0,0 +1,305 @@
+// The executable mappings of the processes whose stacks are captured (--stacks).
+//
+// BPF only records the return addresses of a stack, the file and offset they fall in come from
+// /proc/<pid>/maps. It is read the first time a stack of the process is written, along with the
+// GNU build id of every file so the symbolizer can tell it has the same one. Libraries the process
+// loads after that show up as unknown frames.
+use super::raw_dump::{RawDumpMapping, RawDumpMaps, RawDumpWriter};
+use log::trace;
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::Read;
+
+// PT_NOTE segments come right after the program headers, a build id further in is not looked for
+const BUILD_ID_SEARCH: u64 = 64 * 1024;
+
+const PT_NOTE: u32 = 4;
+const NT_GNU_BUILD_ID: u32 = 3;
+
+enum MapsSource {
+    // read from /proc, and also written to the raw dump when there is one
+    Procfs(Option<RawDumpWriter>),
+    // the snapshots of a raw dump, in the order they were read during the capture
+    RawDump(HashMap<i32, VecDeque<Vec<RawDumpMapping>>>),
+}
+
+pub struct ProcessMaps {
+    source: MapsSource,
+    // pid -> its snapshot, until it exits or execs
+    snapshots: HashMap<i32, Vec<RawDumpMapping>>,
+    // path -> build id, every process maps the same libraries
+    build_ids: HashMap<String, Vec<u8>>,
+}
+
+impl Default for ProcessMaps {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ProcessMaps {
+    pub fn new() -> Self {
+        Self {
+            source: MapsSource::Procfs(None),
+            snapshots: HashMap::new(),
+            build_ids: HashMap::new(),
+        }
+    }
+
+    // Answers from the maps records of a raw dump instead of /proc
+    pub fn from_raw_dump(maps: Vec<RawDumpMaps>) -> Self {
+        let mut snapshots: HashMap<i32, VecDeque<Vec<RawDumpMapping>>> = HashMap::new();
+        for record in maps {
+            snapshots
+                .entry(record.pid)
+                .or_default()
+                .push_back(record.mappings);
+        }
+        Self {
+            source: MapsSource::RawDump(snapshots),
+            ..Self::new()
+        }
+    }
+
+    // Record every /proc read in `raw_dump`, so `from_raw_dump` can answer the same on replay
+    pub fn with_raw_dump(mut self, raw_dump: RawDumpWriter) -> Self {
+        self.source = MapsSource::Procfs(Some(raw_dump));
+        self
+    }
+
+    // The mappings of `pid`, the first time they are asked for since it started or exec'd
+    pub fn snapshot_once(&mut self, pid: i32) -> Option<&[RawDumpMapping]> {
+        if self.snapshots.contains_key(&pid) {
+            return None;
+        }
+        let mappings = match &mut self.source {
+            MapsSource::Procfs(raw_dump) => {
+                let mappings = read_procfs(&mut self.build_ids, pid);
+                if let Some(raw_dump) = raw_dump {
+                    raw_dump.write_maps(&RawDumpMaps {
+                        pid,
+                        mappings: mappings.clone(),
+                    });
+                }
+                mappings
+            }
+            MapsSource::RawDump(snapshots) => snapshots
+                .get_mut(&pid)
+                .and_then(|snapshots| snapshots.pop_front())
+                .unwrap_or_default(),
+        };
+        // a process that is already gone isn't asked again
+        let mappings = self.snapshots.entry(pid).or_insert(mappings);
+        if mappings.is_empty() {
+            return None;
+        }
+        Some(mappings.as_slice())
+    }
+
+    pub fn forget(&mut self, pid: i32) {
+        self.snapshots.remove(&pid);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (i32, &[RawDumpMapping])> {
+        self.snapshots
+            .iter()
+            .filter(|(_, mappings)| !mappings.is_empty())
+            .map(|(&pid, mappings)| (pid, mappings.as_slice()))
+    }
+}
+
+fn read_procfs(build_ids: &mut HashMap<String, Vec<u8>>, pid: i32) -> Vec<RawDumpMapping> {
+    let contents = match std::fs::read_to_string(format!("/proc/{}/maps", pid)) {
+        Ok(contents) => contents,
+        Err(err) => {
+            trace!("failed to read the maps of {}: {}", pid, err);
+            return vec![];
+        }
+    };
+    let mut mappings = parse_maps(&contents);
+    for mapping in &mut mappings {
+        // through the process' root, the file may be in a mount namespace of its own (APEXes)
+        mapping.build_id = build_ids
+            .entry(mapping.path.clone())
+            .or_insert_with(|| read_build_id(&format!("/proc/{}/root{}", pid, mapping.path)))
+            .clone();
+    }
+    mappings
+}
+
+// The executable, file backed mappings, without their build ids
+fn parse_maps(contents: &str) -> Vec<RawDumpMapping> {
+    contents.lines().filter_map(parse_maps_line).collect()
+}
+
+// `start-end perms offset dev inode` then the path, padded with spaces
+fn parse_maps_line(line: &str) -> Option<RawDumpMapping> {
+    let mut fields = line.splitn(6, ' ');
+    let (start, end) = fields.next()?.split_once('-')?;
+    let perms = fields.next()?;
+    let offset = fields.next()?;
+    let path = fields.nth(2)?.trim_start();
+    if perms.as_bytes().get(2) != Some(&b'x') || !path.starts_with('/') {
+        return None;
+    }
+    Some(RawDumpMapping {
+        start: u64::from_str_radix(start, 16).ok()?,
+        end: u64::from_str_radix(end, 16).ok()?,
+        offset: u64::from_str_radix(offset, 16).ok()?,
+        build_id: vec![],
+        path: path.into(),
+    })
+}
+
+fn read_build_id(path: &str) -> Vec<u8> {
+    let mut data = vec![];
+    let read = File::open(path).and_then(|file| file.take(BUILD_ID_SEARCH).read_to_end(&mut data));
+    if let Err(err) = read {
+        trace!("failed to read {}: {}", path, err);
+        return vec![];
+    }
+    parse_build_id(&data).unwrap_or_default()
+}
+
+fn u16_at(data: &[u8], pos: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(data.get(pos..pos + 2)?.try_into().ok()?))
+}
+
+fn u32_at(data: &[u8], pos: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(data.get(pos..pos + 4)?.try_into().ok()?))
+}
+
+fn u64_at(data: &[u8], pos: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(data.get(pos..pos + 8)?.try_into().ok()?))
+}
+
+// The NT_GNU_BUILD_ID note of a little endian ELF, found through its PT_NOTE segments
+fn parse_build_id(data: &[u8]) -> Option<Vec<u8>> {
+    if data.get(..4)? != b"\x7fELF" || *data.get(5)? != 1 {
+        return None;
+    }
+    let elf64 = *data.get(4)? == 2;
+    let (phoff, phentsize, phnum) = if elf64 {
+        (
+            u64_at(data, 0x20)?,
+            u16_at(data, 0x36)?,
+            u16_at(data, 0x38)?,
+        )
+    } else {
+        (
+            u32_at(data, 0x1c)? as u64,
+            u16_at(data, 0x2a)?,
+            u16_at(data, 0x2c)?,
+        )
+    };
+    for i in 0..phnum as usize {
+        let phdr = phoff as usize + i * phentsize as usize;
+        if u32_at(data, phdr)? != PT_NOTE {
+            continue;
+        }
+        let (offset, size) = if elf64 {
+            (u64_at(data, phdr + 8)?, u64_at(data, phdr + 32)?)
+        } else {
+            (
+                u32_at(data, phdr + 4)? as u64,
+                u32_at(data, phdr + 16)? as u64,
+            )
+        };
+        let notes = data.get(offset as usize..(offset + size) as usize)?;
+        if let Some(build_id) = find_build_id_note(notes) {
+            return Some(build_id);
+        }
+    }
+    None
+}
+
+pub(crate) fn find_build_id_note(mut notes: &[u8]) -> Option<Vec<u8>> {
+    let align = |len: usize| (len + 3) & !3;
+    while notes.len() >= 12 {
+        let namesz = u32_at(notes, 0)? as usize;
+        let descsz = u32_at(notes, 4)? as usize;
+        let kind = u32_at(notes, 8)?;
+        let name = notes.get(12..12 + namesz)?;
+        let desc_start = 12 + align(namesz);
+        let desc = notes.get(desc_start..desc_start + descsz)?;
+        if kind == NT_GNU_BUILD_ID && name == b"GNU\0" {
+            return Some(desc.to_vec());
+        }
+        notes = notes.get(desc_start + align(descsz)..)?;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_executable_file_mappings() {
+        let maps = "\
+5a0b000000-5a0b001000 r--p 00000000 fd:05 1234                       /system/bin/app_process64
+5a0b001000-5a0b003000 r-xp 00001000 fd:05 1234                       /system/bin/app_process64
+7f10000000-7f10010000 rw-p 00000000 00:00 0                          [anon:libc_malloc]
+7fe0000000-7fe0001000 r-xp 00000000 00:00 0                          [vdso]
+7f20000000-7f20100000 r-xp 00040000 fd:05 99                         /data/app/a b/lib.so (deleted)
+";
+        let mappings = parse_maps(maps);
+        assert_eq!(mappings.len(), 2);
+        assert_eq!(mappings[0].start, 0x5a0b001000);
+        assert_eq!(mappings[0].end, 0x5a0b003000);
+        assert_eq!(mappings[0].offset, 0x1000);
+        assert_eq!(mappings[0].path, "/system/bin/app_process64");
+        assert_eq!(mappings[1].path, "/data/app/a b/lib.so (deleted)");
+    }
+
+    #[test]
+    fn finds_the_gnu_build_id() {
+        // an ELF64 header with a single PT_NOTE program header, then an unrelated note and the
+        // build id
+        let mut elf = vec![0u8; 0x40];
+        elf[..6].copy_from_slice(b"\x7fELF\x02\x01");
+        elf[0x20..0x28].copy_from_slice(&0x40u64.to_le_bytes());
+        elf[0x36..0x38].copy_from_slice(&56u16.to_le_bytes());
+        elf[0x38..0x3a].copy_from_slice(&1u16.to_le_bytes());
+        let mut notes = vec![];
+        for (kind, name, desc) in [
+            (1u32, &b"Android\0"[..], &[0u8; 4][..]),
+            (3, b"GNU\0", &[0xab; 20]),
+        ] {
+            notes.extend((name.len() as u32).to_le_bytes());
+            notes.extend((desc.len() as u32).to_le_bytes());
+            notes.extend(kind.to_le_bytes());
+            notes.extend(name);
+            notes.extend(desc);
+        }
+        let mut phdr = vec![0u8; 56];
+        phdr[..4].copy_from_slice(&PT_NOTE.to_le_bytes());
+        phdr[8..16].copy_from_slice(&(0x40u64 + 56).to_le_bytes());
+        phdr[32..40].copy_from_slice(&(notes.len() as u64).to_le_bytes());
+        elf.extend(phdr);
+        elf.extend(notes);
+
+        assert_eq!(parse_build_id(&elf), Some(vec![0xab; 20]));
+        assert_eq!(parse_build_id(&elf[..0x60]), None);
+        assert_eq!(parse_build_id(b"#!/system/bin/sh"), None);
+    }
+
+    #[test]
+    fn snapshots_each_process_once() {
+        let mapping = parse_maps_line(
+            "7f20000000-7f20100000 r-xp 00000000 fd:05 99 /system/lib64/libbinder.so",
+        )
+        .unwrap();
+        let mut maps = ProcessMaps::from_raw_dump(vec![RawDumpMaps {
+            pid: 100,
+            mappings: vec![mapping.clone()],
+        }]);
+        assert_eq!(maps.snapshot_once(100), Some(&[mapping.clone()][..]));
+        assert_eq!(maps.snapshot_once(100), None);
+        assert_eq!(maps.snapshot_once(200), None);
+        assert_eq!(maps.iter().count(), 1);
+
+        maps.forget(100);
+        assert_eq!(maps.iter().count(), 0);
+    }
+}
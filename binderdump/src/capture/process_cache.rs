@@ -1,8 +1,12 @@
+use super::devices::{char_device_rdev, BinderDevices};
+use super::events::BinderEventExec;
+use super::packages::Packages;
+use super::raw_dump::{RawDumpFd, RawDumpProcess, RawDumpWriter};
 use anyhow::{anyhow, Context, Result};
-use binderdump_structs::binder_types::BinderInterface;
 use log::trace;
 use procfs;
-use std::collections::{hash_map::Entry, HashMap};
+use std::collections::{hash_map::Entry, HashMap, HashSet, VecDeque};
+use std::path::Path;
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
 struct ProcessKey {
@@ -10,40 +14,26 @@ struct ProcessKey {
     tid: i32,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ProcessInfo {
     cmdline: String,
     comm: String,
-    binder_fd: Option<i32>,
-    hwbinder_fd: Option<i32>,
-    vndbinder_fd: Option<i32>,
+    uid: u32,
+    // CLOCK_BOOTTIME nanoseconds
+    start_time: u64,
+    package: String,
+    // SELinux domain, empty when /proc/<pid>/attr/current can't be read (no SELinux, or forked and
+    // exec'd processes that are already gone)
+    selinux: String,
+    // fd -> interface id of the binder device it was opened on
+    binder_fds: HashMap<i32, u32>,
+    // fds we already rescanned the process for without finding a binder device
+    unknown_fds: HashSet<i32>,
 }
 
 impl ProcessInfo {
-    pub fn get_binder_name(&self, fd: i32) -> Option<&'static str> {
-        if self.binder_fd.is_some_and(|x| fd == x) {
-            return Some("/dev/binder");
-        }
-        if self.hwbinder_fd.is_some_and(|x| fd == x) {
-            return Some("/dev/hwbinder");
-        }
-        if self.vndbinder_fd.is_some_and(|x| fd == x) {
-            return Some("/dev/vndbinder");
-        }
-        None
-    }
-
-    pub fn get_binder_interface(&self, fd: i32) -> Option<BinderInterface> {
-        if self.binder_fd.is_some_and(|x| fd == x) {
-            return Some(BinderInterface::BINDER);
-        }
-        if self.hwbinder_fd.is_some_and(|x| fd == x) {
-            return Some(BinderInterface::HWBINDER);
-        }
-        if self.vndbinder_fd.is_some_and(|x| fd == x) {
-            return Some(BinderInterface::VNDBINDER);
-        }
-        None
+    pub fn get_interface_id(&self, fd: i32) -> Option<u32> {
+        self.binder_fds.get(&fd).copied()
     }
 
     pub fn get_cmdline(&self) -> &str {
@@ -53,20 +43,256 @@ impl ProcessInfo {
     pub fn get_comm(&self) -> &str {
         &self.comm
     }
+
+    pub fn get_uid(&self) -> u32 {
+        self.uid
+    }
+
+    pub fn get_start_time(&self) -> u64 {
+        self.start_time
+    }
+
+    pub fn get_package(&self) -> &str {
+        &self.package
+    }
+
+    pub fn get_selinux(&self) -> &str {
+        &self.selinux
+    }
+
+    // A thread of a process we have a template of, see ProcessCache::fork and exec
+    fn for_thread(&self, comm: Option<&str>) -> Self {
+        let mut proc_info = self.clone();
+        if let Some(comm) = comm {
+            proc_info.comm = comm.into();
+        }
+        proc_info.unknown_fds.clear();
+        proc_info
+    }
+
+    fn to_raw_dump(&self, pid: i32, tid: i32) -> RawDumpProcess {
+        let mut binder_fds: Vec<_> = self
+            .binder_fds
+            .iter()
+            .map(|(&fd, &interface_id)| RawDumpFd { fd, interface_id })
+            .collect();
+        binder_fds.sort_by_key(|fd| fd.fd);
+        RawDumpProcess {
+            pid,
+            tid,
+            cmdline: self.cmdline.clone(),
+            comm: self.comm.clone(),
+            uid: self.uid,
+            start_time: self.start_time,
+            binder_fds,
+            selinux: self.selinux.clone(),
+        }
+    }
+
+    fn from_raw_dump(process: &RawDumpProcess) -> Result<Self> {
+        if process.binder_fds.is_empty() {
+            return Err(anyhow!(
+                "Thread {} doesn't have any open binder fds (recorded)",
+                process.tid
+            ));
+        }
+        Ok(Self {
+            cmdline: process.cmdline.clone(),
+            comm: process.comm.clone(),
+            uid: process.uid,
+            start_time: process.start_time,
+            package: String::new(),
+            selinux: process.selinux.clone(),
+            binder_fds: process
+                .binder_fds
+                .iter()
+                .map(|fd| (fd.fd, fd.interface_id))
+                .collect(),
+            unknown_fds: HashSet::new(),
+        })
+    }
+}
+
+enum ProcessSource {
+    // read from /proc, and also written to the raw dump when there is one
+    Procfs(Option<RawDumpWriter>),
+    // the snapshots of a raw dump, in the order they were read during the capture
+    RawDump(HashMap<ProcessKey, VecDeque<RawDumpProcess>>),
 }
 
 pub struct ProcessCache {
     map: HashMap<ProcessKey, ProcessInfo>,
+    devices: BinderDevices,
+    source: ProcessSource,
+    packages: Packages,
+    // entries created (or whose cmdline changed) since the last take_new_entries
+    new_entries: Vec<ProcessKey>,
+    // pid -> the cmdline take_new_entries reported, the metadata is written once per process, and
+    // again when a thread of it shows another cmdline (a zygote child that specialized)
+    announced: HashMap<i32, String>,
+    // pid -> what it exec'd. Its threads are created from it instead of from /proc, which the
+    // process may already have left.
+    execd: HashMap<i32, ProcessInfo>,
+    // pid -> a copy of its parent at fork. Only used when /proc can't be read, as a zygote child
+    // renames itself after the fork.
+    forked: HashMap<i32, ProcessInfo>,
 }
 
 impl ProcessCache {
-    pub fn new() -> Self {
+    pub fn new(devices: BinderDevices) -> Self {
+        Self {
+            map: HashMap::new(),
+            devices,
+            source: ProcessSource::Procfs(None),
+            packages: Packages::load(),
+            new_entries: vec![],
+            announced: HashMap::new(),
+            execd: HashMap::new(),
+            forked: HashMap::new(),
+        }
+    }
+
+    // Answers from the process records of a raw dump instead of /proc
+    pub fn from_raw_dump(devices: BinderDevices, processes: Vec<RawDumpProcess>) -> Self {
+        let mut snapshots: HashMap<ProcessKey, VecDeque<RawDumpProcess>> = HashMap::new();
+        for process in processes {
+            let key = ProcessKey {
+                pid: process.pid,
+                tid: process.tid,
+            };
+            snapshots.entry(key).or_default().push_back(process);
+        }
         Self {
             map: HashMap::new(),
+            devices,
+            source: ProcessSource::RawDump(snapshots),
+            packages: Packages::default(),
+            new_entries: vec![],
+            announced: HashMap::new(),
+            execd: HashMap::new(),
+            forked: HashMap::new(),
+        }
+    }
+
+    // Record every /proc read in `raw_dump`, so `from_raw_dump` can answer the same on replay
+    pub fn with_raw_dump(mut self, raw_dump: RawDumpWriter) -> Self {
+        self.packages = self.packages.with_raw_dump(raw_dump.clone());
+        self.source = ProcessSource::Procfs(Some(raw_dump));
+        self
+    }
+
+    pub fn with_packages(mut self, packages: Packages) -> Self {
+        self.packages = packages;
+        self
+    }
+
+    // The packages running as `uid`
+    pub fn packages(&mut self, uid: u32) -> &[String] {
+        self.packages.get(uid)
+    }
+
+    pub fn devices(&self) -> &BinderDevices {
+        &self.devices
+    }
+
+    // The processes the packets written so far don't have the metadata of yet, or whose cmdline
+    // changed since, as the (pid, tid) of the thread that showed it
+    pub fn take_new_entries(&mut self) -> Vec<(i32, i32)> {
+        let mut entries = vec![];
+        for key in std::mem::take(&mut self.new_entries) {
+            let Some(proc_info) = self.map.get(&key) else {
+                continue;
+            };
+            if self.announced.get(&key.pid) == Some(&proc_info.cmdline) {
+                continue;
+            }
+            self.announced.insert(key.pid, proc_info.cmdline.clone());
+            entries.push((key.pid, key.tid));
+        }
+        entries
+    }
+
+    // One thread of every process, its main thread when we know it
+    pub fn processes(&self) -> impl Iterator<Item = (i32, i32, &ProcessInfo)> {
+        let mut processes: HashMap<i32, (i32, &ProcessInfo)> = HashMap::new();
+        for (key, proc_info) in &self.map {
+            match processes.entry(key.pid) {
+                Entry::Vacant(v) => {
+                    v.insert((key.tid, proc_info));
+                }
+                Entry::Occupied(mut o) if key.tid == key.pid => {
+                    o.insert((key.tid, proc_info));
+                }
+                Entry::Occupied(_) => {}
+            }
+        }
+        processes
+            .into_iter()
+            .map(|(pid, (tid, proc_info))| (pid, tid, proc_info))
+    }
+
+    // The process's metadata changed (exec), its next packets announce it again
+    fn reannounce(&mut self, key: ProcessKey) {
+        self.announced.remove(&key.pid);
+        self.new_entries.push(key);
+    }
+
+    fn create_process_info(
+        source: &mut ProcessSource,
+        devices: &BinderDevices,
+        packages: &mut Packages,
+        pid: i32,
+        tid: i32,
+    ) -> Result<ProcessInfo> {
+        let mut proc_info = Self::read_process_info(source, devices, pid, tid)?;
+        proc_info.package = packages.package_of(proc_info.uid, &proc_info.cmdline);
+        Ok(proc_info)
+    }
+
+    fn read_process_info(
+        source: &mut ProcessSource,
+        devices: &BinderDevices,
+        pid: i32,
+        tid: i32,
+    ) -> Result<ProcessInfo> {
+        let raw_dump = match source {
+            ProcessSource::Procfs(raw_dump) => raw_dump,
+            ProcessSource::RawDump(snapshots) => {
+                let key = ProcessKey { pid, tid };
+                let snapshots = snapshots
+                    .get_mut(&key)
+                    .with_context(|| format!("no recorded process info for tid {}", tid))?;
+                // the same lookups happen in the same order as during the capture, the last
+                // snapshot answers any extra ones
+                let snapshot = match snapshots.len() {
+                    1 => snapshots.front().cloned(),
+                    _ => snapshots.pop_front(),
+                };
+                return ProcessInfo::from_raw_dump(&snapshot.unwrap());
+            }
+        };
+        let proc_info = Self::read_procfs(devices, pid, tid);
+        if let Some(raw_dump) = raw_dump {
+            let process = match &proc_info {
+                Ok(proc_info) => proc_info.to_raw_dump(pid, tid),
+                // recorded without fds, so the replay fails the same way
+                Err(_) => RawDumpProcess {
+                    pid,
+                    tid,
+                    cmdline: String::new(),
+                    comm: String::new(),
+                    uid: 0,
+                    start_time: 0,
+                    binder_fds: vec![],
+                    selinux: String::new(),
+                },
+            };
+            raw_dump.write_process(&process);
         }
+        proc_info
     }
 
-    fn create_process_info(pid: i32, tid: i32) -> Result<ProcessInfo> {
+    fn read_procfs(devices: &BinderDevices, pid: i32, tid: i32) -> Result<ProcessInfo> {
         let proc = match procfs::process::Process::new(tid)
             .context(format!("failed to create process info of tid: {}", tid))
         {
@@ -82,13 +308,19 @@ impl ProcessCache {
         };
         let cmdline = proc.cmdline()?;
         let cmdline = cmdline.into_iter().nth(0).unwrap_or_default();
+        let stat = proc.stat()?;
+        let uid = proc.uid()?;
 
         let mut proc_info = ProcessInfo {
+            package: String::new(),
             cmdline: cmdline,
-            comm: proc.stat()?.comm,
-            binder_fd: None,
-            hwbinder_fd: None,
-            vndbinder_fd: None,
+            comm: stat.comm,
+            uid,
+            // starttime is in clock ticks since boot
+            start_time: stat.starttime * (1_000_000_000 / procfs::ticks_per_second()),
+            selinux: read_selinux(proc.pid),
+            binder_fds: HashMap::new(),
+            unknown_fds: HashSet::new(),
         };
 
         for fd in proc
@@ -99,37 +331,152 @@ impl ProcessCache {
                 continue;
             }
             let fd = fd.unwrap();
-            let binder_type = match fd.target {
-                procfs::process::FDTarget::Path(path) => BinderInterface::try_from(path).ok(),
+            let interface_id = match fd.target {
+                procfs::process::FDTarget::Path(path) => {
+                    Self::find_device(devices, proc.pid, fd.fd, &path)
+                }
                 _ => None,
             };
-            if binder_type.is_none() {
-                continue;
-            }
-            // TODO - check that there is only one fd of each type / support multiple fds (does anybody do that?)
-            match binder_type.unwrap() {
-                BinderInterface::BINDER => proc_info.binder_fd = Some(fd.fd),
-                BinderInterface::HWBINDER => proc_info.hwbinder_fd = Some(fd.fd),
-                BinderInterface::VNDBINDER => proc_info.vndbinder_fd = Some(fd.fd),
-            }
-            if proc_info.binder_fd.is_some()
-                && proc_info.hwbinder_fd.is_some()
-                && proc_info.vndbinder_fd.is_some()
-            {
-                break;
+            if let Some(interface_id) = interface_id {
+                proc_info.binder_fds.insert(fd.fd, interface_id);
             }
         }
 
-        if proc_info.binder_fd.is_none()
-            && proc_info.hwbinder_fd.is_none()
-            && proc_info.vndbinder_fd.is_none()
-        {
+        if proc_info.binder_fds.is_empty() {
             return Err(anyhow!("Thread {} doesn't have any open binder fds", tid));
         }
 
         Ok(proc_info)
     }
 
+    fn find_device(devices: &BinderDevices, pid: i32, fd: i32, path: &Path) -> Option<u32> {
+        if let Some(interface_id) = devices.find_path(path) {
+            return Some(interface_id);
+        }
+        // A device opened in another mount namespace (e.g. a container) shows up under a path we
+        // don't know, so compare the device numbers instead. Only for paths that can be one, as
+        // this runs for every fd of the process.
+        if !path.starts_with("/dev") && !path.to_string_lossy().contains("binder") {
+            return None;
+        }
+        let rdev = char_device_rdev(Path::new(&format!("/proc/{}/fd/{}", pid, fd)))?;
+        devices.find_rdev(rdev)
+    }
+
+    // The interface id of `fd` in the process. The process may have opened it after we cached its
+    // fds, so rescan once when the fd is unknown.
+    pub fn get_interface_id(&mut self, pid: i32, tid: i32, fd: i32) -> Option<u32> {
+        let key = ProcessKey { pid, tid };
+        let proc_info = self.map.get(&key)?;
+        if let Some(interface_id) = proc_info.get_interface_id(fd) {
+            return Some(interface_id);
+        }
+        if proc_info.unknown_fds.contains(&fd) {
+            return None;
+        }
+        let unknown_fds = proc_info.unknown_fds.clone();
+        let old_cmdline = proc_info.cmdline.clone();
+        let mut proc_info = Self::create_process_info(
+            &mut self.source,
+            &self.devices,
+            &mut self.packages,
+            pid,
+            tid,
+        )
+        .ok()?;
+        proc_info.unknown_fds = unknown_fds;
+        // it may have exec'd since, the following packets need the new metadata
+        if proc_info.cmdline != old_cmdline {
+            self.reannounce(key.clone());
+        }
+        let interface_id = proc_info.get_interface_id(fd);
+        if interface_id.is_none() {
+            proc_info.unknown_fds.insert(fd);
+        }
+        self.map.insert(key, proc_info);
+        interface_id
+    }
+
+    // `child_pid` was forked by `parent_tid`, it starts with its parent's cmdline and binder fds
+    pub fn fork(
+        &mut self,
+        parent_pid: i32,
+        parent_tid: i32,
+        child_pid: i32,
+        comm: &str,
+        uid: u32,
+        timestamp: u64,
+    ) -> ProcessInfo {
+        let parent = self
+            .map
+            .get(&ProcessKey {
+                pid: parent_pid,
+                tid: parent_tid,
+            })
+            .or_else(|| self.execd.get(&parent_pid))
+            .or_else(|| self.forked.get(&parent_pid))
+            .or_else(|| {
+                self.map
+                    .iter()
+                    .find(|(key, _)| key.pid == parent_pid)
+                    .map(|(_, proc_info)| proc_info)
+            });
+        let (cmdline, selinux, binder_fds) = match parent {
+            Some(parent) => (
+                parent.cmdline.clone(),
+                parent.selinux.clone(),
+                parent.binder_fds.clone(),
+            ),
+            None => (String::new(), String::new(), HashMap::new()),
+        };
+        let proc_info = ProcessInfo {
+            package: self.packages.package_of(uid, &cmdline),
+            cmdline,
+            comm: comm.into(),
+            uid,
+            start_time: timestamp,
+            selinux,
+            binder_fds,
+            unknown_fds: HashSet::new(),
+        };
+        self.forked.insert(child_pid, proc_info.clone());
+        proc_info
+    }
+
+    // `old_tid` of `pid` exec'd, which kills its other threads. binder fds are close-on-exec, so
+    // the new image has none until it opens the driver.
+    pub fn exec(&mut self, pid: i32, old_tid: i32, exec: &BinderEventExec) -> ProcessInfo {
+        let start_time = self
+            .forked
+            .remove(&pid)
+            .or_else(|| self.execd.remove(&pid))
+            .or_else(|| self.map.remove(&ProcessKey { pid, tid: pid }))
+            .or_else(|| self.map.remove(&ProcessKey { pid, tid: old_tid }))
+            .map(|proc_info| proc_info.start_time)
+            .unwrap_or_default();
+        self.map.retain(|key, _| key.pid != pid);
+        self.announced.remove(&pid);
+        let cmdline = exec.cmdline().to_string();
+        // exec is where domains transition. The process usually still runs right after it, a raw
+        // dump doesn't have it though.
+        let selinux = match self.source {
+            ProcessSource::Procfs(_) => read_selinux(pid),
+            ProcessSource::RawDump(_) => String::new(),
+        };
+        let proc_info = ProcessInfo {
+            package: self.packages.package_of(exec.uid, &cmdline),
+            cmdline,
+            comm: exec.comm.clone(),
+            uid: exec.uid,
+            start_time,
+            selinux,
+            binder_fds: HashMap::new(),
+            unknown_fds: HashSet::new(),
+        };
+        self.execd.insert(pid, proc_info.clone());
+        proc_info
+    }
+
     pub fn get_proc(&mut self, pid: i32, tid: i32, comm: Option<&str>) -> Result<&ProcessInfo> {
         let key = ProcessKey { pid, tid };
         let proc_info = match self.map.entry(key.clone()) {
@@ -137,7 +484,32 @@ impl ProcessCache {
                 if let Some(comm) = comm {
                     if comm.ne(&proc_info.get().comm) {
                         trace!("cache invalid");
-                        proc_info.insert(Self::create_process_info(pid, tid)?);
+                        let old_cmdline = proc_info.get().cmdline.clone();
+                        let created = Self::create_process_info(
+                            &mut self.source,
+                            &self.devices,
+                            &mut self.packages,
+                            pid,
+                            tid,
+                        );
+                        match created {
+                            Ok(created) => {
+                                proc_info.insert(created);
+                            }
+                            // renamed a thread, but /proc doesn't answer anymore
+                            Err(_)
+                                if self.execd.contains_key(&pid)
+                                    || self.forked.contains_key(&pid) =>
+                            {
+                                proc_info.get_mut().comm = comm.into();
+                            }
+                            Err(err) => return Err(err),
+                        }
+                        // a renamed thread only, its comm is in its own packets
+                        if proc_info.get().cmdline != old_cmdline {
+                            self.announced.remove(&pid);
+                            self.new_entries.push(key);
+                        }
                     } else {
                         trace!("cache hit");
                     }
@@ -146,14 +518,184 @@ impl ProcessCache {
             }
             Entry::Vacant(v) => {
                 trace!("cache miss");
-                v.insert(Self::create_process_info(pid, tid)?)
+                let created = match self.execd.get(&pid) {
+                    Some(template) => Ok(template.for_thread(comm)),
+                    None => Self::create_process_info(
+                        &mut self.source,
+                        &self.devices,
+                        &mut self.packages,
+                        pid,
+                        tid,
+                    )
+                    .or_else(|err| {
+                        self.forked
+                            .get(&pid)
+                            .map(|template| template.for_thread(comm))
+                            .ok_or(err)
+                    }),
+                };
+                let proc_info = v.insert(created?);
+                self.new_entries.push(key);
+                proc_info
             }
         };
 
         Ok(proc_info)
     }
 
+    pub fn get(&self, pid: i32, tid: i32) -> Option<&ProcessInfo> {
+        self.map.get(&ProcessKey { pid, tid })
+    }
+
     pub fn invalidate_proc(&mut self, pid: i32, tid: i32) -> Option<ProcessInfo> {
+        if pid == tid {
+            self.execd.remove(&pid);
+            self.forked.remove(&pid);
+            self.announced.remove(&pid);
+        }
         self.map.remove(&ProcessKey { pid, tid })
     }
 }
+
+// `u:r:system_server:s0`, without the NUL the kernel terminates it with
+fn read_selinux(pid: i32) -> String {
+    let Ok(current) = std::fs::read(format!("/proc/{}/attr/current", pid)) else {
+        return String::new();
+    };
+    let current = String::from_utf8_lossy(&current);
+    current.trim_end_matches(['\0', '\n']).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::raw_dump::RawDumpPackage;
+
+    fn snapshot(tid: i32, comm: &str, fds: &[(i32, u32)]) -> RawDumpProcess {
+        RawDumpProcess {
+            pid: 100,
+            tid,
+            cmdline: "system_server".into(),
+            comm: comm.into(),
+            uid: 1000,
+            start_time: 0,
+            binder_fds: fds
+                .iter()
+                .map(|&(fd, interface_id)| RawDumpFd { fd, interface_id })
+                .collect(),
+            selinux: "u:r:system_server:s0".into(),
+        }
+    }
+
+    #[test]
+    fn replays_recorded_lookups_in_order() {
+        let mut cache = ProcessCache::from_raw_dump(
+            BinderDevices::from_list(vec![]),
+            vec![
+                snapshot(101, "binder:100_1", &[(5, 0)]),
+                snapshot(101, "binder:100_1", &[(5, 0), (9, 1)]),
+                snapshot(102, "", &[]),
+            ],
+        );
+        assert_eq!(
+            cache.get_proc(100, 101, None).unwrap().get_comm(),
+            "binder:100_1"
+        );
+        assert_eq!(cache.get_interface_id(100, 101, 5), Some(0));
+        // rescanned for the unknown fd, which takes the second snapshot
+        assert_eq!(cache.get_interface_id(100, 101, 9), Some(1));
+        // failed lookups fail again
+        assert!(cache.get_proc(100, 102, None).is_err());
+        assert!(cache.get_proc(100, 103, None).is_err());
+    }
+
+    #[test]
+    fn new_entries_are_reported_once() {
+        let mut cache = ProcessCache::from_raw_dump(
+            BinderDevices::from_list(vec![]),
+            vec![
+                snapshot(101, "binder:100_1", &[(5, 0)]),
+                snapshot(102, "binder:100_2", &[(5, 0)]),
+            ],
+        );
+        cache.get_proc(100, 101, None).unwrap();
+        cache.get_proc(100, 101, Some("binder:100_1")).unwrap();
+        assert_eq!(cache.take_new_entries(), [(100, 101)]);
+        assert!(cache.take_new_entries().is_empty());
+        // once per process, not per thread
+        cache.get_proc(100, 102, None).unwrap();
+        assert!(cache.take_new_entries().is_empty());
+        // failed lookups don't have anything to report
+        assert!(cache.get_proc(100, 103, None).is_err());
+        assert!(cache.take_new_entries().is_empty());
+    }
+
+    #[test]
+    fn threads_with_another_cmdline_are_reported_again() {
+        let mut app_thread = snapshot(102, "binder:100_2", &[(5, 0)]);
+        app_thread.cmdline = "com.android.phone".into();
+        let mut cache = ProcessCache::from_raw_dump(
+            BinderDevices::from_list(vec![]),
+            vec![snapshot(101, "binder:100_1", &[(5, 0)]), app_thread],
+        );
+        cache.get_proc(100, 101, None).unwrap();
+        assert_eq!(cache.take_new_entries(), [(100, 101)]);
+        // started after the zygote child specialized
+        cache.get_proc(100, 102, None).unwrap();
+        assert_eq!(cache.take_new_entries(), [(100, 102)]);
+    }
+
+    #[test]
+    fn processes_get_the_package_of_their_uid() {
+        let packages = Packages::from_list(&[RawDumpPackage {
+            name: "android".into(),
+            app_id: 1000,
+        }]);
+        let mut cache = ProcessCache::from_raw_dump(
+            BinderDevices::from_list(vec![]),
+            vec![snapshot(101, "binder:100_1", &[(5, 0)])],
+        )
+        .with_packages(packages);
+        assert_eq!(
+            cache.get_proc(100, 101, None).unwrap().get_package(),
+            "android"
+        );
+    }
+
+    #[test]
+    fn exec_and_fork_answer_for_processes_that_are_gone() {
+        let mut cache = ProcessCache::from_raw_dump(
+            BinderDevices::from_list(vec![]),
+            vec![snapshot(101, "binder:100_1", &[(5, 0)])],
+        );
+        cache.get_proc(100, 101, None).unwrap();
+        let child = cache.fork(100, 101, 200, "system_server", 1000, 42);
+        assert_eq!(child.get_cmdline(), "system_server");
+        assert_eq!(child.get_interface_id(5), Some(0));
+        assert_eq!(child.get_selinux(), "u:r:system_server:s0");
+        // nothing was recorded for the child, the fork answers
+        let proc_info = cache.get_proc(200, 200, Some("sh")).unwrap();
+        assert_eq!(proc_info.get_comm(), "sh");
+        assert_eq!(proc_info.get_start_time(), 42);
+
+        let exec = BinderEventExec {
+            old_tid: 200,
+            comm: "service".into(),
+            uid: 2000,
+            filename: "/system/bin/service".into(),
+            argv: vec!["service".into(), "list".into()],
+        };
+        let execd = cache.exec(200, 200, &exec);
+        assert_eq!(execd.get_cmdline(), "service");
+        assert_eq!(execd.get_start_time(), 42);
+        // the new domain isn't in the dump
+        assert_eq!(execd.get_selinux(), "");
+        assert!(cache.get(200, 200).is_none());
+        let proc_info = cache.get_proc(200, 200, Some("service")).unwrap();
+        assert_eq!(proc_info.get_uid(), 2000);
+        assert_eq!(proc_info.get_interface_id(5), None);
+        // the templates go away with the process
+        cache.invalidate_proc(200, 200);
+        assert!(cache.get_proc(200, 200, None).is_err());
+    }
+}
@@ -0,0 +1,465 @@
+// Raw ring buffer dumps (--raw-dump) and their replay (`binderdump replay`).
+//
+// A dump holds the ring buffer records exactly as handle_binder_event got them, along with what
+// the capture otherwise reads from the device: the system properties and clocks of the section
+// header, the binder devices, the /proc entries of the processes, their executable mappings
+// (--stacks) and packages.list. Replaying it runs the same
+// parsing, aggregation and packet generation as a live capture, without BPF, on any host.
+//
+// The file is MAGIC followed by records: a u8 kind, a u32 length and the payload. The header comes
+// first, then ring buffer, process, package list and maps records in the order they were written.
+use anyhow::{bail, Context, Result};
+use binderdump_structs::binder_serde;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use super::devices::{BinderDevice, BinderDevices};
+
+const MAGIC: &[u8; 8] = b"BDRAWDMP";
+const VERSION: u32 = 1;
+
+const RECORD_HEADER: u8 = 0;
+const RECORD_EVENT: u8 = 1;
+const RECORD_PROCESS: u8 = 2;
+const RECORD_PACKAGES: u8 = 3;
+const RECORD_MAPS: u8 = 4;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RawDumpHeader {
+    pub model: String,
+    pub os: String,
+    pub fingerprint: String,
+    pub kernel_version: String,
+    // CLOCK_REALTIME - CLOCK_BOOTTIME when the capture started
+    pub timeshift_ns: u64,
+    pub android_sdk: u32,
+    // in interface id order
+    pub devices: Vec<RawDumpDevice>,
+    // the feature tiers that were loaded (see capture/tiers.rs)
+    pub tiers: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RawDumpDevice {
+    pub path: String,
+    pub aliases: Vec<String>,
+    pub rdev: Option<u64>,
+}
+
+impl RawDumpHeader {
+    pub fn dump_devices(devices: &BinderDevices) -> Vec<RawDumpDevice> {
+        devices
+            .iter()
+            .map(|device| RawDumpDevice {
+                path: device.path().to_string_lossy().into_owned(),
+                aliases: device
+                    .aliases()
+                    .iter()
+                    .map(|alias| alias.to_string_lossy().into_owned())
+                    .collect(),
+                rdev: device.rdev(),
+            })
+            .collect()
+    }
+
+    pub fn binder_devices(&self) -> BinderDevices {
+        BinderDevices::from_list(
+            self.devices
+                .iter()
+                .map(|device| {
+                    BinderDevice::new(device.path.clone().into(), device.rdev)
+                        .with_aliases(device.aliases.iter().map(PathBuf::from).collect())
+                })
+                .collect(),
+        )
+    }
+}
+
+// What ProcessCache read from /proc for a thread. No binder fds means the lookup failed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RawDumpProcess {
+    pub pid: i32,
+    pub tid: i32,
+    pub cmdline: String,
+    pub comm: String,
+    pub uid: u32,
+    // CLOCK_BOOTTIME nanoseconds
+    pub start_time: u64,
+    pub binder_fds: Vec<RawDumpFd>,
+    pub selinux: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RawDumpFd {
+    pub fd: i32,
+    pub interface_id: u32,
+}
+
+// A line of packages.list
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct RawDumpPackage {
+    pub name: String,
+    pub app_id: u32,
+}
+
+// The executable mappings of a process, see ProcessMaps
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RawDumpMaps {
+    pub pid: i32,
+    pub mappings: Vec<RawDumpMapping>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RawDumpMapping {
+    pub start: u64,
+    pub end: u64,
+    // file offset of `start`
+    pub offset: u64,
+    // empty when the file has no GNU build id or couldn't be read
+    pub build_id: Vec<u8>,
+    pub path: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RawDumpPackages {
+    packages: Vec<RawDumpPackage>,
+}
+
+struct WriterState {
+    writer: BufWriter<File>,
+    // set after the first failed write, so a full disk is reported once and stops the dump
+    failed: bool,
+}
+
+// Shared by the ring buffer thread and the ProcessCache
+#[derive(Clone)]
+pub struct RawDumpWriter {
+    state: Arc<Mutex<WriterState>>,
+}
+
+impl RawDumpWriter {
+    pub fn create(path: &Path, header: &RawDumpHeader) -> Result<Self> {
+        let file = File::create(path)
+            .with_context(|| format!("failed to create raw dump: {}", path.display()))?;
+        let mut writer = BufWriter::new(file);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        let header = binder_serde::to_bytes(header).context("failed to serialize dump header")?;
+        write_record(&mut writer, RECORD_HEADER, &header)?;
+        Ok(Self {
+            state: Arc::new(Mutex::new(WriterState {
+                writer,
+                failed: false,
+            })),
+        })
+    }
+
+    pub fn write_event(&self, data: &[u8]) {
+        self.write(RECORD_EVENT, data);
+    }
+
+    pub fn write_process(&self, process: &RawDumpProcess) {
+        match binder_serde::to_bytes(process) {
+            Ok(payload) => self.write(RECORD_PROCESS, &payload),
+            Err(err) => warn!("failed to serialize process {}: {}", process.tid, err),
+        }
+    }
+
+    pub fn write_packages(&self, packages: &[RawDumpPackage]) {
+        let packages = RawDumpPackages {
+            packages: packages.to_vec(),
+        };
+        match binder_serde::to_bytes(&packages) {
+            Ok(payload) => self.write(RECORD_PACKAGES, &payload),
+            Err(err) => warn!("failed to serialize the package list: {}", err),
+        }
+    }
+
+    pub fn write_maps(&self, maps: &RawDumpMaps) {
+        match binder_serde::to_bytes(maps) {
+            Ok(payload) => self.write(RECORD_MAPS, &payload),
+            Err(err) => warn!("failed to serialize the maps of {}: {}", maps.pid, err),
+        }
+    }
+
+    fn write(&self, kind: u8, payload: &[u8]) {
+        let mut state = self.state.lock().unwrap_or_else(|err| err.into_inner());
+        if state.failed {
+            return;
+        }
+        if let Err(err) = write_record(&mut state.writer, kind, payload) {
+            warn!("failed to write raw dump, stopping it: {}", err);
+            state.failed = true;
+        }
+    }
+
+    pub fn flush(&self) -> Result<()> {
+        let mut state = self.state.lock().unwrap_or_else(|err| err.into_inner());
+        state.writer.flush().context("failed to flush raw dump")
+    }
+}
+
+fn write_record(writer: &mut impl Write, kind: u8, payload: &[u8]) -> io::Result<()> {
+    let len = u32::try_from(payload.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "record too large"))?;
+    writer.write_all(&[kind])?;
+    writer.write_all(&len.to_le_bytes())?;
+    writer.write_all(payload)
+}
+
+// Reads the next record. A record cut short (the capture was killed mid-write) ends the dump.
+fn read_record(reader: &mut impl Read) -> Result<Option<(u8, Vec<u8>)>> {
+    let mut head = [0u8; 5];
+    match reader.read_exact(&mut head[..1]) {
+        Ok(()) => (),
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err).context("failed to read raw dump"),
+    }
+    let mut payload = vec![];
+    let read = reader.read_exact(&mut head[1..]).and_then(|()| {
+        let len = u32::from_le_bytes(head[1..].try_into().unwrap());
+        payload.resize(len as usize, 0);
+        reader.read_exact(&mut payload)
+    });
+    match read {
+        Ok(()) => Ok(Some((head[0], payload))),
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+            warn!("raw dump ends with a truncated record, ignoring it");
+            Ok(None)
+        }
+        Err(err) => Err(err).context("failed to read raw dump"),
+    }
+}
+
+pub struct RawDumpReader {
+    path: PathBuf,
+    header: RawDumpHeader,
+}
+
+impl RawDumpReader {
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut reader = Self::open_records(path)?;
+        let header = match read_record(&mut reader)? {
+            Some((RECORD_HEADER, payload)) => {
+                binder_serde::from_bytes(&payload).context("failed to deserialize dump header")?
+            }
+            _ => bail!("{} has no dump header", path.display()),
+        };
+        Ok(Self {
+            path: path.into(),
+            header,
+        })
+    }
+
+    // A reader positioned after the magic and version
+    fn open_records(path: &Path) -> Result<BufReader<File>> {
+        let file = File::open(path)
+            .with_context(|| format!("failed to open raw dump: {}", path.display()))?;
+        let mut reader = BufReader::new(file);
+        let mut magic = [0u8; 12];
+        reader
+            .read_exact(&mut magic)
+            .with_context(|| format!("{} is not a raw dump", path.display()))?;
+        if magic[..8] != MAGIC[..] {
+            bail!("{} is not a raw dump", path.display());
+        }
+        let version = u32::from_le_bytes(magic[8..].try_into().unwrap());
+        if version != VERSION {
+            bail!(
+                "unsupported raw dump version {} (expected {})",
+                version,
+                VERSION
+            );
+        }
+        Ok(reader)
+    }
+
+    pub fn header(&self) -> &RawDumpHeader {
+        &self.header
+    }
+
+    // Every process record, in the order they were written
+    pub fn processes(&self) -> Result<Vec<RawDumpProcess>> {
+        let mut reader = Self::open_records(&self.path)?;
+        let mut processes = vec![];
+        while let Some((kind, payload)) = read_record(&mut reader)? {
+            if kind == RECORD_PROCESS {
+                processes.push(
+                    binder_serde::from_bytes(&payload)
+                        .context("failed to deserialize process record")?,
+                );
+            }
+        }
+        Ok(processes)
+    }
+
+    // Every package of the package list records, the list is recorded again when it changes
+    pub fn packages(&self) -> Result<Vec<RawDumpPackage>> {
+        let mut reader = Self::open_records(&self.path)?;
+        let mut seen = HashSet::new();
+        let mut packages = vec![];
+        while let Some((kind, payload)) = read_record(&mut reader)? {
+            if kind == RECORD_PACKAGES {
+                let record: RawDumpPackages = binder_serde::from_bytes(&payload)
+                    .context("failed to deserialize package list record")?;
+                for package in record.packages {
+                    if seen.insert(package.clone()) {
+                        packages.push(package);
+                    }
+                }
+            }
+        }
+        Ok(packages)
+    }
+
+    // Every maps record, in the order they were written
+    pub fn maps(&self) -> Result<Vec<RawDumpMaps>> {
+        let mut reader = Self::open_records(&self.path)?;
+        let mut maps = vec![];
+        while let Some((kind, payload)) = read_record(&mut reader)? {
+            if kind == RECORD_MAPS {
+                maps.push(
+                    binder_serde::from_bytes(&payload)
+                        .context("failed to deserialize maps record")?,
+                );
+            }
+        }
+        Ok(maps)
+    }
+
+    // The ring buffer records, streamed from the file
+    pub fn events(&self) -> Result<RawDumpEvents> {
+        Ok(RawDumpEvents {
+            reader: Self::open_records(&self.path)?,
+        })
+    }
+}
+
+pub struct RawDumpEvents {
+    reader: BufReader<File>,
+}
+
+impl Iterator for RawDumpEvents {
+    type Item = Vec<u8>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match read_record(&mut self.reader) {
+                Ok(Some((RECORD_EVENT, payload))) => return Some(payload),
+                Ok(Some(_)) => continue,
+                Ok(None) => return None,
+                Err(err) => {
+                    warn!("{:#}", err);
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header() -> RawDumpHeader {
+        RawDumpHeader {
+            model: "Pixel 7".into(),
+            os: "Android 14".into(),
+            fingerprint: "google/panther/panther:14/UQ1A".into(),
+            kernel_version: "Linux version 5.10.157".into(),
+            timeshift_ns: 1_700_000_000_000_000_000,
+            android_sdk: 34,
+            devices: vec![
+                RawDumpDevice {
+                    path: "/dev/binder".into(),
+                    aliases: vec!["/dev/binderfs/binder".into()],
+                    rdev: Some(7),
+                },
+                RawDumpDevice {
+                    path: "/dev/hwbinder".into(),
+                    aliases: vec![],
+                    rdev: None,
+                },
+            ],
+            tiers: vec!["core".into(), "txn_data".into()],
+        }
+    }
+
+    #[test]
+    fn round_trips_records() {
+        let path = std::env::temp_dir().join(format!("binderdump_raw_{}", std::process::id()));
+        let process = RawDumpProcess {
+            pid: 100,
+            tid: 101,
+            cmdline: "system_server".into(),
+            comm: "binder:100_1".into(),
+            uid: 1000,
+            start_time: 1_000_000,
+            binder_fds: vec![RawDumpFd {
+                fd: 5,
+                interface_id: 0,
+            }],
+            selinux: "u:r:system_server:s0".into(),
+        };
+        let writer = RawDumpWriter::create(&path, &header()).unwrap();
+        writer.write_event(&[1, 2, 3]);
+        writer.write_process(&process);
+        writer.write_event(&[4]);
+        let package = RawDumpPackage {
+            name: "com.example.app".into(),
+            app_id: 10234,
+        };
+        writer.write_packages(&[package.clone()]);
+        writer.write_packages(&[package.clone()]);
+        let maps = RawDumpMaps {
+            pid: 100,
+            mappings: vec![RawDumpMapping {
+                start: 0x7000_0000,
+                end: 0x7001_0000,
+                offset: 0x1000,
+                build_id: vec![0xab, 0xcd],
+                path: "/system/lib64/libbinder.so".into(),
+            }],
+        };
+        writer.write_maps(&maps);
+        writer.flush().unwrap();
+
+        let reader = RawDumpReader::open(&path).unwrap();
+        assert_eq!(reader.header(), &header());
+        assert_eq!(reader.processes().unwrap(), [process]);
+        assert_eq!(reader.packages().unwrap(), [package]);
+        assert_eq!(reader.maps().unwrap(), [maps]);
+        assert_eq!(
+            reader.events().unwrap().collect::<Vec<_>>(),
+            [vec![1, 2, 3], vec![4]]
+        );
+
+        let devices = reader.header().binder_devices();
+        assert_eq!(
+            devices.find_path(Path::new("/dev/binderfs/binder")),
+            Some(0)
+        );
+        assert_eq!(RawDumpHeader::dump_devices(&devices), header().devices);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn truncated_record_ends_the_dump() {
+        let mut data = vec![];
+        write_record(&mut data, RECORD_EVENT, &[1, 2]).unwrap();
+        write_record(&mut data, RECORD_EVENT, &[3, 4, 5]).unwrap();
+        data.truncate(data.len() - 1);
+
+        let mut reader = data.as_slice();
+        assert_eq!(
+            read_record(&mut reader).unwrap(),
+            Some((RECORD_EVENT, vec![1, 2]))
+        );
+        assert_eq!(read_record(&mut reader).unwrap(), None);
+    }
+}
@@ -1,24 +1,228 @@
 // will handle ringbuf polling and comsuming
-use super::{events, tracepoints::binder::BinderSkel};
+//
+// Kernels before 5.8 have no BPF ring buffers, the events go through one perf buffer per cpu there
+// (see src/bpf/output.h). A thread that migrates can have its events in two of them, and they are
+// read one buffer at a time, so the records are held for REORDER_WINDOW_NS and released in
+// timestamp order: the channel sees the events of a thread in the order they were sent either way.
+use super::{
+    common_types::binder_event,
+    drops::CaptureDrops,
+    events,
+    limits::CaptureLimits,
+    raw_dump::RawDumpWriter,
+    tracepoints::binder::{BinderSkel, OpenBinderSkel},
+};
 use anyhow::{Context, Result};
 use ctrlc;
-use libbpf_rs::RingBufferBuilder;
+use libbpf_rs::{MapHandle, PerfBufferBuilder, RingBufferBuilder};
 use log::{debug, error, warn};
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{mpsc, Arc};
+use nix::time::{clock_gettime, ClockId};
+use std::cmp::{Ordering as CmpOrdering, Reverse};
+use std::collections::BinaryHeap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex, OnceLock};
 use std::thread;
 use std::time::Duration;
 
+// How long a perf buffer record waits for the ones other cpus sent before it
+const REORDER_WINDOW_NS: u64 = 50_000_000;
+
+// libbpf wants a power of two of pages per cpu
+const MIN_PERF_BUFFER_PAGES: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    RingBuf,
+    PerfBuffer,
+}
+
+impl Transport {
+    pub fn detect() -> Self {
+        if super::btf_probe::ringbuf_supported() {
+            Transport::RingBuf
+        } else {
+            Transport::PerfBuffer
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Transport::RingBuf => "ring buffer",
+            Transport::PerfBuffer => "perf buffers",
+        }
+    }
+
+    // Must be called before the skeleton is loaded (rodata becomes read-only). The maps of the
+    // other transport are not created, the programs only reference them in branches the verifier
+    // knows are dead.
+    pub(crate) fn configure(&self, open_skel: &mut OpenBinderSkel<'_>) -> Result<()> {
+        if *self == Transport::RingBuf {
+            open_skel.maps.binder_events_perf.set_autocreate(false)?;
+            open_skel.maps.event_scratch_buffers.set_autocreate(false)?;
+            return Ok(());
+        }
+        open_skel.maps.binder_events_buffer.set_autocreate(false)?;
+        let rodata = open_skel
+            .maps
+            .rodata_data
+            .as_deref_mut()
+            .context("BPF rodata section unavailable")?;
+        rodata.cfg_perf_buffer = 1;
+        rodata.cfg_boot_offset_ns = boot_offset_ns();
+        Ok(())
+    }
+}
+
+fn clock_ns(clock: ClockId) -> u64 {
+    clock_gettime(clock).map_or(0, |ts| Duration::from(ts).as_nanos() as u64)
+}
+
+// CLOCK_BOOTTIME - CLOCK_MONOTONIC, which the programs add to bpf_ktime_get_ns on the perf buffer.
+// Taken once, so the event timestamps and the reorder window agree on it.
+fn boot_offset_ns() -> u64 {
+    static OFFSET: OnceLock<u64> = OnceLock::new();
+    *OFFSET.get_or_init(|| {
+        clock_ns(ClockId::CLOCK_BOOTTIME).saturating_sub(clock_ns(ClockId::CLOCK_MONOTONIC))
+    })
+}
+
+// Now, on the clock of the perf buffer timestamps
+fn perf_clock_now() -> u64 {
+    clock_ns(ClockId::CLOCK_MONOTONIC) + boot_offset_ns()
+}
+
+// The events are parsed in place, and ring buffer records are 8 byte aligned. Perf buffer records
+// and the records of a raw dump aren't.
+struct AlignedRecord {
+    words: Vec<u64>,
+    len: usize,
+}
+
+impl AlignedRecord {
+    fn new(data: &[u8]) -> Self {
+        let mut record = Self {
+            words: vec![0u64; data.len().div_ceil(8)],
+            len: data.len(),
+        };
+        let bytes = unsafe {
+            std::slice::from_raw_parts_mut(record.words.as_mut_ptr() as *mut u8, data.len())
+        };
+        bytes.copy_from_slice(data);
+        record
+    }
+
+    fn bytes(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.words.as_ptr() as *const u8, self.len) }
+    }
+}
+
+// A perf buffer record, without the padding the kernel adds to it, and its timestamp
+fn parse_perf_record(data: &[u8]) -> Option<(u64, AlignedRecord)> {
+    let mut record = AlignedRecord::new(data);
+    let header: &binder_event = plain::from_bytes(record.bytes()).ok()?;
+    let (size, timestamp) = (header.size as usize, header.timestamp);
+    if size < std::mem::size_of::<binder_event>() || size > data.len() {
+        return None;
+    }
+    record.len = size;
+    Some((timestamp, record))
+}
+
+struct PendingRecord {
+    timestamp: u64,
+    // the order it was read in, records of the same cpu with the same timestamp keep it
+    seq: u64,
+    record: AlignedRecord,
+}
+
+impl PartialEq for PendingRecord {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == CmpOrdering::Equal
+    }
+}
+
+impl Eq for PendingRecord {}
+
+impl PartialOrd for PendingRecord {
+    fn partial_cmp(&self, other: &Self) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for PendingRecord {
+    fn cmp(&self, other: &Self) -> CmpOrdering {
+        (self.timestamp, self.seq).cmp(&(other.timestamp, other.seq))
+    }
+}
+
+#[derive(Default)]
+struct ReorderBuffer {
+    seq: u64,
+    pending: BinaryHeap<Reverse<PendingRecord>>,
+}
+
+impl ReorderBuffer {
+    fn push(&mut self, timestamp: u64, record: AlignedRecord) {
+        self.pending.push(Reverse(PendingRecord {
+            timestamp,
+            seq: self.seq,
+            record,
+        }));
+        self.seq += 1;
+    }
+
+    // The records older than the window at `now`, in timestamp order
+    fn release(&mut self, now: u64) -> Vec<AlignedRecord> {
+        let mut released = vec![];
+        while let Some(Reverse(next)) = self.pending.peek() {
+            if next.timestamp.saturating_add(REORDER_WINDOW_NS) > now {
+                break;
+            }
+            if let Some(Reverse(next)) = self.pending.pop() {
+                released.push(next.record);
+            }
+        }
+        released
+    }
+
+    fn flush(&mut self) -> Vec<AlignedRecord> {
+        self.release(u64::MAX)
+    }
+}
+
+// The pages of every per-cpu perf buffer, about --ring-size in all
+fn perf_buffer_pages(ring_size: usize, cpus: usize, page_size: usize) -> usize {
+    let pages = ring_size / cpus.max(1) / page_size.max(1);
+    if pages < MIN_PERF_BUFFER_PAGES {
+        return MIN_PERF_BUFFER_PAGES;
+    }
+    // rounded down to a power of two
+    1 << pages.ilog2()
+}
+
 pub struct EventChannel {
     consumer_thread: Option<thread::JoinHandle<()>>,
     running: Arc<AtomicBool>,
     binder_events_channel: mpsc::Receiver<events::BinderEvent>,
+    // None when replaying a raw dump
+    drop_counters: Option<MapHandle>,
+    invalid_events: Arc<AtomicU64>,
 }
 
 impl EventChannel {
     pub fn get_channel(&self) -> &mpsc::Receiver<events::BinderEvent> {
         &self.binder_events_channel
     }
+
+    // Events lost before they reached the channel, either in BPF or while parsing the ring buffer
+    pub fn drops(&self) -> Result<CaptureDrops> {
+        let mut drops = match &self.drop_counters {
+            Some(drop_counters) => CaptureDrops::read_bpf_counters(drop_counters)?,
+            None => CaptureDrops::default(),
+        };
+        drops.invalid_events = self.invalid_events.load(Ordering::Relaxed);
+        Ok(drops)
+    }
 }
 
 impl Drop for EventChannel {
@@ -32,38 +236,55 @@ impl Drop for EventChannel {
     }
 }
 
-fn handle_binder_event(sender: &mpsc::Sender<events::BinderEvent>, data: &[u8]) -> i32 {
-    // println!("Received ringbuf data of size {}", data.len());
+fn parse_binder_event(invalid_events: &AtomicU64, data: &[u8]) -> Option<events::BinderEvent> {
     match data.try_into() {
-        Ok(event) => match sender.send(event) {
-            Ok(_) => 0,
-            Err(err) => {
-                error!("Failed to send ringbuffer event {}", err);
-                1
-            }
-        },
+        Ok(event) => Some(event),
         Err(err) => {
             warn!("Invalid event received from ring buffer: {}", err);
-            0
+            invalid_events.fetch_add(1, Ordering::Relaxed);
+            None
         }
     }
 }
 
-pub fn create_events_channel(skel: &mut BinderSkel) -> Result<EventChannel> {
-    let (sender, recv) = mpsc::channel();
+fn handle_binder_event(
+    sender: &mpsc::Sender<events::BinderEvent>,
+    invalid_events: &AtomicU64,
+    raw_dump: Option<&RawDumpWriter>,
+    data: &[u8],
+) -> i32 {
+    // println!("Received ringbuf data of size {}", data.len());
+    if let Some(raw_dump) = raw_dump {
+        raw_dump.write_event(data);
+    }
+    let Some(event) = parse_binder_event(invalid_events, data) else {
+        return 0;
+    };
+    match sender.send(event) {
+        Ok(_) => 0,
+        Err(err) => {
+            error!("Failed to send ringbuffer event {}", err);
+            1
+        }
+    }
+}
 
+fn spawn_ringbuf_consumer(
+    skel: &mut BinderSkel,
+    sender: mpsc::Sender<events::BinderEvent>,
+    invalid_events: Arc<AtomicU64>,
+    raw_dump: Option<RawDumpWriter>,
+    running: Arc<AtomicBool>,
+) -> Result<thread::JoinHandle<()>> {
     let mut events_buffer_builder = RingBufferBuilder::new();
-    let maps = &mut skel.maps;
-    let binder_events_buffer = &mut maps.binder_events_buffer;
+    let binder_events_buffer = &mut skel.maps.binder_events_buffer;
     events_buffer_builder.add(binder_events_buffer, move |data| -> i32 {
-        handle_binder_event(&sender, data)
+        handle_binder_event(&sender, &invalid_events, raw_dump.as_ref(), data)
     })?;
     let events_buffer = events_buffer_builder.build()?;
 
-    let running = Arc::new(AtomicBool::new(true));
-    let running_copy = running.clone();
-    let thread = std::thread::spawn(move || {
-        while running_copy.load(Ordering::Relaxed) {
+    Ok(std::thread::spawn(move || {
+        while running.load(Ordering::Relaxed) {
             match events_buffer.poll(Duration::from_millis(10)) {
                 Ok(_) => (),
                 Err(err) => match err.kind() {
@@ -73,11 +294,71 @@ pub fn create_events_channel(skel: &mut BinderSkel) -> Result<EventChannel> {
             }
         }
         debug!("Events thread exiting...");
-    });
-    let running_copy = running.clone();
+    }))
+}
+
+fn spawn_perf_consumer(
+    skel: &BinderSkel,
+    limits: &CaptureLimits,
+    sender: mpsc::Sender<events::BinderEvent>,
+    invalid_events: Arc<AtomicU64>,
+    raw_dump: Option<RawDumpWriter>,
+    running: Arc<AtomicBool>,
+) -> Result<thread::JoinHandle<()>> {
+    let cpus = libbpf_rs::num_possible_cpus().context("failed to get the number of cpus")?;
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+    let pages = perf_buffer_pages(limits.ring_size_bytes() as usize, cpus, page_size);
+    debug!("{} perf buffers of {} pages", cpus, pages);
+
+    let pending = Arc::new(Mutex::new(ReorderBuffer::default()));
+    let pending_copy = pending.clone();
+    let invalid_events_copy = invalid_events.clone();
+    let perf_buffer = PerfBufferBuilder::new(&skel.maps.binder_events_perf)
+        .pages(pages)
+        .sample_cb(move |_cpu, data: &[u8]| match parse_perf_record(data) {
+            Some((timestamp, record)) => pending_copy.lock().unwrap().push(timestamp, record),
+            None => {
+                warn!(
+                    "Invalid record of {} bytes received from perf buffer",
+                    data.len()
+                );
+                invalid_events_copy.fetch_add(1, Ordering::Relaxed);
+            }
+        })
+        // bpf_perf_event_output failed for them, which the drop counters already count
+        .lost_cb(|cpu, count| debug!("{} events lost on cpu {}", count, cpu))
+        .build()
+        .context("failed to create the perf buffers")?;
+
+    let send = move |records: Vec<AlignedRecord>| {
+        for record in records {
+            handle_binder_event(&sender, &invalid_events, raw_dump.as_ref(), record.bytes());
+        }
+    };
+    Ok(std::thread::spawn(move || {
+        while running.load(Ordering::Relaxed) {
+            match perf_buffer.poll(Duration::from_millis(10)) {
+                Ok(_) => (),
+                Err(err) => match err.kind() {
+                    libbpf_rs::ErrorKind::Interrupted => (),
+                    _ => panic!("error polling perf buffer: {}", err),
+                },
+            }
+            send(pending.lock().unwrap().release(perf_clock_now()));
+        }
+        // nothing is left to wait for
+        if let Err(err) = perf_buffer.consume() {
+            debug!("failed to consume the last perf buffer records: {}", err);
+        }
+        send(pending.lock().unwrap().flush());
+        debug!("Events thread exiting...");
+    }))
+}
+
+fn set_ctrlc_handler(running: Arc<AtomicBool>) -> Result<()> {
     ctrlc::set_handler(move || {
-        if running_copy.load(Ordering::Relaxed) {
-            running_copy.store(false, Ordering::Relaxed);
+        if running.load(Ordering::Relaxed) {
+            running.store(false, Ordering::Relaxed);
             // if we get sighup, eprintln! will panic
             eprintln!("Ctrl-C received, exiting cleanly...");
         } else {
@@ -85,10 +366,147 @@ pub fn create_events_channel(skel: &mut BinderSkel) -> Result<EventChannel> {
             std::process::exit(1);
         }
     })
-    .context("failed to set ctrlc handler")?;
+    .context("failed to set ctrlc handler")
+}
+
+// Every ring buffer record is also written to `raw_dump`, when set. On the perf buffer they are
+// written once they are back in order, without their padding, so a dump reads the same either way.
+pub fn create_events_channel(
+    skel: &mut BinderSkel,
+    limits: &CaptureLimits,
+    raw_dump: Option<RawDumpWriter>,
+) -> Result<EventChannel> {
+    let (sender, recv) = mpsc::channel();
+    let invalid_events = Arc::new(AtomicU64::new(0));
+    let drop_counters = MapHandle::try_from(&skel.maps.drop_counters)
+        .context("failed to get a handle to the drop counters")?;
+    let running = Arc::new(AtomicBool::new(true));
+
+    let thread = match Transport::detect() {
+        Transport::RingBuf => spawn_ringbuf_consumer(
+            skel,
+            sender,
+            invalid_events.clone(),
+            raw_dump,
+            running.clone(),
+        )?,
+        Transport::PerfBuffer => spawn_perf_consumer(
+            skel,
+            limits,
+            sender,
+            invalid_events.clone(),
+            raw_dump,
+            running.clone(),
+        )?,
+    };
+    set_ctrlc_handler(running.clone())?;
     Ok(EventChannel {
         consumer_thread: Some(thread),
         running: running,
         binder_events_channel: recv,
+        drop_counters: Some(drop_counters),
+        invalid_events,
     })
 }
+
+// Records are read ahead of the packet generation, a bounded channel keeps a long dump out of memory
+const REPLAY_CHANNEL_SIZE: usize = 4096;
+
+// Feeds the ring buffer records of a raw dump through the same parsing as a live capture. The
+// channel disconnects after the last record, which ends the capture.
+pub fn replay_events_channel(
+    records: impl Iterator<Item = Vec<u8>> + Send + 'static,
+) -> EventChannel {
+    let (sender, recv) = mpsc::sync_channel(REPLAY_CHANNEL_SIZE);
+    let invalid_events = Arc::new(AtomicU64::new(0));
+    let invalid_events_copy = invalid_events.clone();
+    // Not joined on drop: it may be blocked on a full channel until the receiver is dropped, and
+    // exits on the failed send then
+    std::thread::spawn(move || {
+        for data in records {
+            let record = AlignedRecord::new(&data);
+            let Some(event) = parse_binder_event(&invalid_events_copy, record.bytes()) else {
+                continue;
+            };
+            if sender.send(event).is_err() {
+                break;
+            }
+        }
+        debug!("Replay thread exiting...");
+    });
+    EventChannel {
+        consumer_thread: None,
+        running: Arc::new(AtomicBool::new(true)),
+        binder_events_channel: recv,
+        drop_counters: None,
+        invalid_events,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a bare binder_event header, padded like the perf buffer pads it
+    fn perf_record(tid: i32, timestamp: u64) -> Vec<u8> {
+        let mut data = vec![];
+        data.extend(0u32.to_ne_bytes());
+        data.extend(100i32.to_ne_bytes());
+        data.extend(tid.to_ne_bytes());
+        data.extend((std::mem::size_of::<binder_event>() as u32).to_ne_bytes());
+        data.extend(timestamp.to_ne_bytes());
+        data.extend([0u8; 4]);
+        data
+    }
+
+    fn tids(records: Vec<AlignedRecord>) -> Vec<(i32, u64)> {
+        records
+            .iter()
+            .map(|record| {
+                let header: &binder_event = plain::from_bytes(record.bytes()).unwrap();
+                (header.tid, header.timestamp)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn trims_the_perf_buffer_padding() {
+        let data = perf_record(1, 10);
+        let (timestamp, record) = parse_perf_record(&data).unwrap();
+        assert_eq!(timestamp, 10);
+        assert_eq!(record.bytes(), &data[..std::mem::size_of::<binder_event>()]);
+
+        let mut truncated = perf_record(1, 10);
+        truncated[12..16].copy_from_slice(&64u32.to_ne_bytes());
+        assert!(parse_perf_record(&truncated).is_none());
+        assert!(parse_perf_record(&data[..8]).is_none());
+    }
+
+    #[test]
+    fn releases_records_in_timestamp_order() {
+        let mut pending = ReorderBuffer::default();
+        // cpu 0 is read first, but the thread migrated to it from cpu 1
+        for (tid, timestamp) in [(2, 30), (1, 40), (1, 10), (2, 20), (3, 40)] {
+            let (timestamp, record) = parse_perf_record(&perf_record(tid, timestamp)).unwrap();
+            pending.push(timestamp, record);
+        }
+        assert!(pending.release(REORDER_WINDOW_NS).is_empty());
+        assert_eq!(
+            tids(pending.release(REORDER_WINDOW_NS + 30)),
+            [(1, 10), (2, 20), (2, 30)]
+        );
+        // same timestamp, in the order they were read
+        assert_eq!(tids(pending.flush()), [(1, 40), (3, 40)]);
+        assert!(pending.flush().is_empty());
+    }
+
+    #[test]
+    fn splits_the_ring_size_between_cpus() {
+        assert_eq!(perf_buffer_pages(64 << 20, 8, 4096), 2048);
+        assert_eq!(perf_buffer_pages(64 << 20, 6, 4096), 2048);
+        assert_eq!(
+            perf_buffer_pages(1 << 20, 128, 16384),
+            MIN_PERF_BUFFER_PAGES
+        );
+    }
+}
@@ -0,0 +1,57 @@
+// How much of every user buffer is copied out of the task (--snaplen, --metadata-only).
+//
+// The limits live in the BPF rodata (see src/bpf/snaplen.h), so the bytes we don't want are never
+// read in the first place. The events keep the real sizes, so the packets still show how big every
+// buffer was.
+use anyhow::{Context, Result};
+
+use super::tracepoints::binder::OpenBinderSkel;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Snaplen {
+    #[default]
+    Full,
+    // at most this many bytes of every BWR buffer, transaction data and scatter-gather payload
+    Bytes(u32),
+    // no buffer contents at all, only the sizes
+    MetadataOnly,
+}
+
+impl Snaplen {
+    // Must be called before the skeleton is loaded (rodata becomes read-only)
+    pub(crate) fn configure(&self, open_skel: &mut OpenBinderSkel<'_>) -> Result<()> {
+        let rodata = open_skel
+            .maps
+            .rodata_data
+            .as_deref_mut()
+            .context("BPF rodata section unavailable")?;
+        let (snaplen, metadata_only) = self.rodata();
+        rodata.cfg_snaplen = snaplen;
+        rodata.cfg_metadata_only = metadata_only;
+        Ok(())
+    }
+
+    fn rodata(&self) -> (u32, u32) {
+        match self {
+            Snaplen::Full => (0, 0),
+            Snaplen::Bytes(bytes) => (*bytes, 0),
+            Snaplen::MetadataOnly => (0, 1),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn full_capture_leaves_rodata_unset() {
+        assert_eq!(Snaplen::default().rodata(), (0, 0));
+    }
+
+    #[test]
+    fn rodata_follows_mode() {
+        assert_eq!(Snaplen::Bytes(256).rodata(), (256, 0));
+        assert_eq!(Snaplen::MetadataOnly.rodata(), (0, 1));
+    }
+}
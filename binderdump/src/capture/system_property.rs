@@ -2,9 +2,11 @@
 // Used to stamp the active SDK version onto each captured event so the
 // dissector picks the right per-version method table.
 
+#[cfg(target_os = "android")]
 use std::ffi::CString;
 use std::sync::OnceLock;
 
+#[cfg(target_os = "android")]
 extern "C" {
     fn __system_property_get(
         name: *const std::os::raw::c_char,
@@ -12,6 +14,7 @@ extern "C" {
     ) -> std::os::raw::c_int;
 }
 
+#[cfg(target_os = "android")]
 const PROP_VALUE_MAX: usize = 92;
 
 // ro.build.version.sdk never changes for a running device, and the FFI
@@ -22,6 +25,13 @@ pub fn read_sdk_int() -> u32 {
     *SDK_INT.get_or_init(query_sdk_int)
 }
 
+// Hosts have no system properties, e.g. when replaying a raw dump, which records the SDK itself
+#[cfg(not(target_os = "android"))]
+fn query_sdk_int() -> u32 {
+    0
+}
+
+#[cfg(target_os = "android")]
 fn query_sdk_int() -> u32 {
     let name = CString::new("ro.build.version.sdk").expect("CString");
     let mut buf = vec![0i8; PROP_VALUE_MAX];
@@ -0,0 +1,410 @@
+// The features of the capture that load independently of each other, so a program a vendor
+// kernel's verifier rejects costs that feature instead of the whole capture.
+//
+// core is the ioctl and BINDER_WRITE_READ tracking everything builds on, and has to load.
+// txn_data copies the data and offsets of transactions, and ptr_payloads their scatter-gather
+// buffers on top of that: the hooks that copy them are compiled once per tier (binder_command,
+// binder_command_data and binder_command_ptrs, see src/bpf/binder.bpf.c) and only one variant is
+// loaded. reply_stack is the raw_binder_transaction programs of reply correlation, and every other
+// feature with programs of its own (fork/exec, node translations, fds, priorities, wakeups, stacks)
+// is a tier of those programs.
+use anyhow::{Context, Result};
+use libbpf_rs::skel::OpenSkel;
+use log::{info, warn};
+
+use super::tracepoints::binder::OpenBinderSkel;
+
+// the programs with a variant per tier, suffixed with the tier they were compiled for
+const TIERED_PROGRAMS: &[&str] = &["binder_command", "binder_return", "raw_sys_exit"];
+
+// the tiers of whole programs
+const TIER_PROGRAMS: &[(Tier, &[&str])] = &[
+    (
+        Tier::ReplyStack,
+        &[
+            "raw_binder_transaction_core",
+            "raw_binder_transaction_manual",
+        ],
+    ),
+    (
+        Tier::Processes,
+        &["sched_process_fork", "sched_process_exec"],
+    ),
+    (
+        Tier::Nodes,
+        &[
+            "binder_transaction_node_to_ref",
+            "binder_transaction_ref_to_node",
+            "binder_transaction_ref_to_ref",
+        ],
+    ),
+    (
+        Tier::Fds,
+        &[
+            "binder_transaction_fd_send_core",
+            "binder_transaction_fd_send_plain",
+            "binder_transaction_fd_recv",
+        ],
+    ),
+    (
+        Tier::Priorities,
+        &["binder_transaction_prio", "binder_set_priority"],
+    ),
+    (
+        Tier::Wakeups,
+        &[
+            "binder_sched_waking",
+            "binder_sched_switch",
+            "binder_transaction_wakeup",
+        ],
+    ),
+    (Tier::Stacks, &["binder_user_stack"]),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tier {
+    Core,
+    TxnData,
+    PtrPayloads,
+    ReplyStack,
+    Processes,
+    Nodes,
+    Fds,
+    Priorities,
+    Wakeups,
+    Stacks,
+}
+
+impl Tier {
+    // core first, then in the order they are probed in
+    const ALL: [Tier; 10] = [
+        Tier::Core,
+        Tier::TxnData,
+        Tier::PtrPayloads,
+        Tier::ReplyStack,
+        Tier::Processes,
+        Tier::Nodes,
+        Tier::Fds,
+        Tier::Priorities,
+        Tier::Wakeups,
+        Tier::Stacks,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Tier::Core => "core",
+            Tier::TxnData => "txn_data",
+            Tier::PtrPayloads => "ptr_payloads",
+            Tier::ReplyStack => "reply_stack",
+            Tier::Processes => "processes",
+            Tier::Nodes => "nodes",
+            Tier::Fds => "fds",
+            Tier::Priorities => "priorities",
+            Tier::Wakeups => "wakeups",
+            Tier::Stacks => "stacks",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Tier::Core => "ioctl tracking",
+            Tier::TxnData => "transaction data",
+            Tier::PtrPayloads => "scatter-gather payloads",
+            Tier::ReplyStack => "reply correlation",
+            Tier::Processes => "fork and exec tracking",
+            Tier::Nodes => "node translations",
+            Tier::Fds => "fd tracking",
+            Tier::Priorities => "transaction priorities",
+            Tier::Wakeups => "wakeup latencies",
+            Tier::Stacks => "caller stacks",
+        }
+    }
+}
+
+// The optional tiers, core is always there
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tiers {
+    pub txn_data: bool,
+    pub ptr_payloads: bool,
+    pub reply_stack: bool,
+    pub processes: bool,
+    pub nodes: bool,
+    pub fds: bool,
+    pub priorities: bool,
+    pub wakeups: bool,
+    pub stacks: bool,
+}
+
+impl Tiers {
+    pub fn core() -> Self {
+        Self {
+            txn_data: false,
+            ptr_payloads: false,
+            reply_stack: false,
+            processes: false,
+            nodes: false,
+            fds: false,
+            priorities: false,
+            wakeups: false,
+            stacks: false,
+        }
+    }
+
+    pub fn all() -> Self {
+        Self {
+            txn_data: true,
+            ptr_payloads: true,
+            reply_stack: true,
+            processes: true,
+            nodes: true,
+            fds: true,
+            priorities: true,
+            wakeups: true,
+            stacks: true,
+        }
+    }
+
+    fn flag(&mut self, tier: Tier) -> Option<&mut bool> {
+        match tier {
+            Tier::Core => None,
+            Tier::TxnData => Some(&mut self.txn_data),
+            Tier::PtrPayloads => Some(&mut self.ptr_payloads),
+            Tier::ReplyStack => Some(&mut self.reply_stack),
+            Tier::Processes => Some(&mut self.processes),
+            Tier::Nodes => Some(&mut self.nodes),
+            Tier::Fds => Some(&mut self.fds),
+            Tier::Priorities => Some(&mut self.priorities),
+            Tier::Wakeups => Some(&mut self.wakeups),
+            Tier::Stacks => Some(&mut self.stacks),
+        }
+    }
+
+    pub fn contains(&self, tier: Tier) -> bool {
+        let mut tiers = *self;
+        tiers.flag(tier).map(|flag| *flag).unwrap_or(true)
+    }
+
+    fn with(mut self, tier: Tier) -> Self {
+        if let Some(flag) = self.flag(tier) {
+            *flag = true;
+        }
+        self
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        Tier::ALL
+            .into_iter()
+            .filter(|tier| self.contains(*tier))
+            .map(|tier| tier.name())
+            .collect()
+    }
+
+    // The variant of the tiered programs to load, payloads are only copied along with the data
+    fn program_suffix(&self) -> &'static str {
+        match (self.txn_data, self.ptr_payloads) {
+            (true, true) => "_ptrs",
+            (true, false) => "_data",
+            _ => "",
+        }
+    }
+
+    // Must be called before the skeleton is loaded, after the configure functions of the options,
+    // which it only turns programs off on top of
+    pub(crate) fn configure(&self, open_skel: &mut OpenBinderSkel<'_>) {
+        let suffix = self.program_suffix();
+        for mut prog in open_skel.open_object_mut().progs_mut() {
+            let name = prog.name().to_string_lossy().into_owned();
+            match tier_variant(&name) {
+                Some(variant) => prog.set_autoload(variant == suffix),
+                None if !self.contains(program_tier(&name)) => prog.set_autoload(false),
+                None => (),
+            }
+        }
+    }
+}
+
+// The suffix of a tiered program, None for the other programs
+fn tier_variant(name: &str) -> Option<&str> {
+    TIERED_PROGRAMS.iter().find_map(|base| {
+        let suffix = name.strip_prefix(base)?;
+        ["", "_data", "_ptrs"].contains(&suffix).then_some(suffix)
+    })
+}
+
+// The tier a program belongs to
+pub fn program_tier(name: &str) -> Tier {
+    match tier_variant(name) {
+        Some("_data") => return Tier::TxnData,
+        Some("_ptrs") => return Tier::PtrPayloads,
+        Some(_) => return Tier::Core,
+        None => (),
+    }
+    TIER_PROGRAMS
+        .iter()
+        .find(|(_, programs)| programs.contains(&name))
+        .map_or(Tier::Core, |(tier, _)| *tier)
+}
+
+// Loads the `wanted` tiers with `load`, or as many of them as the kernel accepts: when they don't
+// load together, every tier is tried on its own on top of core and the ones that fail are left out
+// with a warning. Only the programs that are kept stay loaded.
+pub fn load_tiers<S>(
+    wanted: Tiers,
+    mut load: impl FnMut(Tiers) -> Result<S>,
+) -> Result<(S, Tiers)> {
+    let err = match load(wanted) {
+        Ok(loaded) => return Ok((loaded, wanted)),
+        Err(err) => err,
+    };
+    if wanted == Tiers::core() {
+        return Err(err);
+    }
+    info!(
+        "failed to load every tier, probing them one by one: {:#}",
+        err
+    );
+
+    load(Tiers::core()).context("the core BPF programs failed to load")?;
+    let mut active = Tiers::core();
+    for tier in Tier::ALL.into_iter().skip(1) {
+        if !wanted.contains(tier) {
+            continue;
+        }
+        if tier == Tier::PtrPayloads && !active.txn_data {
+            warn!(
+                "{} disabled, they are copied along with the transaction data",
+                tier.description()
+            );
+            continue;
+        }
+        match load(active.with(tier)) {
+            Ok(_) => active = active.with(tier),
+            Err(err) => warn!(
+                "{} disabled, the kernel rejected its programs: {:#}",
+                tier.description(),
+                err
+            ),
+        }
+    }
+    let loaded = load(active).context("failed to reload the BPF programs that loaded")?;
+    Ok((loaded, active))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::bail;
+
+    // loads everything but the tiers in `rejected`, and records what it was asked to load
+    fn loader<'a>(
+        rejected: &[Tier],
+        attempts: &'a mut Vec<Tiers>,
+    ) -> impl FnMut(Tiers) -> Result<Tiers> + 'a {
+        let rejected = rejected.to_vec();
+        move |tiers| {
+            attempts.push(tiers);
+            if rejected.iter().any(|tier| tiers.contains(*tier)) {
+                bail!("Invalid argument (os error 22)");
+            }
+            Ok(tiers)
+        }
+    }
+
+    #[test]
+    fn loads_everything_at_once_when_it_can() {
+        let mut attempts = vec![];
+        let (loaded, active) = load_tiers(Tiers::all(), loader(&[], &mut attempts)).unwrap();
+        assert_eq!((loaded, active), (Tiers::all(), Tiers::all()));
+        assert_eq!(attempts.len(), 1);
+    }
+
+    #[test]
+    fn leaves_out_the_tiers_that_fail() {
+        let mut attempts = vec![];
+        let (loaded, active) =
+            load_tiers(Tiers::all(), loader(&[Tier::PtrPayloads], &mut attempts)).unwrap();
+        let expected = Tiers {
+            ptr_payloads: false,
+            ..Tiers::all()
+        };
+        assert_eq!((loaded, active), (expected, expected));
+        assert_eq!(
+            active.names(),
+            [
+                "core",
+                "txn_data",
+                "reply_stack",
+                "processes",
+                "nodes",
+                "fds",
+                "priorities",
+                "wakeups",
+                "stacks"
+            ]
+        );
+    }
+
+    #[test]
+    fn options_only_cost_their_own_tier() {
+        let mut attempts = vec![];
+        let wanted = Tiers {
+            stacks: false,
+            ..Tiers::all()
+        };
+        let (_, active) = load_tiers(wanted, loader(&[Tier::Fds], &mut attempts)).unwrap();
+        let expected = Tiers {
+            fds: false,
+            ..wanted
+        };
+        assert_eq!(active, expected);
+        // not asked for, not probed
+        assert!(!attempts.iter().any(|tiers| tiers.stacks));
+    }
+
+    #[test]
+    fn payloads_need_the_transaction_data() {
+        let mut attempts = vec![];
+        let (_, active) =
+            load_tiers(Tiers::all(), loader(&[Tier::TxnData], &mut attempts)).unwrap();
+        assert!(!active.txn_data && !active.ptr_payloads && active.reply_stack);
+        assert!(!attempts
+            .iter()
+            .any(|tiers| tiers.ptr_payloads && !tiers.txn_data));
+    }
+
+    #[test]
+    fn fails_without_core() {
+        let mut attempts = vec![];
+        assert!(load_tiers(Tiers::all(), loader(&[Tier::Core], &mut attempts)).is_err());
+        assert_eq!(attempts, [Tiers::all(), Tiers::core()]);
+    }
+
+    #[test]
+    fn picks_the_program_variant_of_the_tiers() {
+        assert_eq!(tier_variant("binder_command"), Some(""));
+        assert_eq!(tier_variant("binder_return_ptrs"), Some("_ptrs"));
+        assert_eq!(tier_variant("raw_sys_exit_data"), Some("_data"));
+        assert_eq!(tier_variant("binder_transaction"), None);
+        assert_eq!(tier_variant("binder_command_user_stack"), None);
+        assert_eq!(Tiers::all().program_suffix(), "_ptrs");
+        assert_eq!(Tiers::core().program_suffix(), "");
+    }
+
+    #[test]
+    fn knows_the_tier_of_a_program() {
+        assert_eq!(program_tier("binder_command_ptrs"), Tier::PtrPayloads);
+        assert_eq!(program_tier("raw_sys_exit_data"), Tier::TxnData);
+        assert_eq!(
+            program_tier("raw_binder_transaction_core"),
+            Tier::ReplyStack
+        );
+        assert_eq!(program_tier("binder_return"), Tier::Core);
+        assert_eq!(program_tier("binder_ioctl"), Tier::Core);
+        assert_eq!(program_tier("sched_process_exit"), Tier::Core);
+        assert_eq!(program_tier("sched_process_exec"), Tier::Processes);
+        assert_eq!(program_tier("binder_transaction_fd_send_core"), Tier::Fds);
+        assert_eq!(program_tier("binder_set_priority"), Tier::Priorities);
+        assert_eq!(program_tier("binder_sched_switch"), Tier::Wakeups);
+        assert_eq!(program_tier("binder_user_stack"), Tier::Stacks);
+    }
+}
@@ -1,9 +1,22 @@
+use std::collections::HashMap;
 use std::mem::MaybeUninit;
+use std::path::Path;
 
 use anyhow::{bail, Context, Result};
 use libbpf_rs::skel::{OpenSkel, Skel, SkelBuilder};
 use libc;
 
+use super::btf_probe::kernel_machine;
+use super::filter::CaptureFilter;
+use super::limits::CaptureLimits;
+use super::offset_cache::{KernelBuild, OffsetCache};
+use super::offset_solver::KernelLayout;
+use super::ringbuf::Transport;
+use super::snaplen::Snaplen;
+use super::tiers::{load_tiers, Tiers};
+
+const SET_PRIORITY_TRACEPOINT: &str = "/sys/kernel/tracing/events/binder/binder_set_priority";
+
 pub mod binder {
     include!(concat!(env!("OUT_DIR"), "/binder.skel.rs"));
 }
@@ -85,9 +98,13 @@ impl From<ReplyOffsets> for ReplyCorrelationMode {
 }
 
 pub fn bump_memlock_rlimit() -> Result<()> {
+    set_memlock_rlimit(128 << 20)
+}
+
+fn set_memlock_rlimit(bytes: u64) -> Result<()> {
     let rlimit = libc::rlimit {
-        rlim_cur: 128 << 20,
-        rlim_max: 128 << 20,
+        rlim_cur: bytes as _,
+        rlim_max: bytes as _,
     };
 
     if unsafe { libc::setrlimit(libc::RLIMIT_MEMLOCK, &rlimit) } != 0 {
@@ -104,8 +121,9 @@ fn enable_bpf_printk_trace() -> Result<()> {
     Ok(())
 }
 
-fn prepare_tracepoints() -> Result<()> {
-    bump_memlock_rlimit()?;
+fn prepare_tracepoints(limits: &CaptureLimits) -> Result<()> {
+    // the ring buffer, and 64MB for the other maps
+    set_memlock_rlimit((64 << 20) + limits.ring_size_bytes() as u64)?;
     enable_bpf_printk_trace()?;
     Ok(())
 }
@@ -143,6 +161,8 @@ fn configure_reply_correlation(
             rodata.cfg_off_to_thread = to_thread;
             rodata.cfg_off_transaction_stack = transaction_stack;
             rodata.cfg_off_debug_id = debug_id;
+            // to_thread and transaction_stack are read at the kernel's pointer width
+            rodata.cfg_ptr_size = KernelLayout::for_machine(&kernel_machine()?)?.ptr_size as u32;
             log::info!(
                 "reply correlation using manual offsets: to_thread={} transaction_stack={} debug_id={}",
                 to_thread,
@@ -167,17 +187,67 @@ fn configure_reply_correlation(
     Ok(())
 }
 
-pub fn attach_tracepoints<'a>(mode: ReplyCorrelationMode) -> Result<BinderSkel<'a>> {
-    prepare_tracepoints()?;
+// The path of a sent fd is resolved from kernel structs, which needs CO-RE. Without it the fds are
+// still traced, just without their path.
+fn configure_fd_paths(open_skel: &mut OpenBinderSkel<'_>) {
+    if crate::capture::btf_probe::fd_paths_supported() {
+        open_skel
+            .progs
+            .binder_transaction_fd_send_plain
+            .set_autoload(false);
+    } else {
+        open_skel
+            .progs
+            .binder_transaction_fd_send_core
+            .set_autoload(false);
+        log::info!("paths of sent fds disabled (no kernel BTF support)");
+    }
+}
+
+// The caller's priority is read from its task_struct, which needs CO-RE. binder_set_priority only
+// exists on Android common kernels, attaching it anywhere else would fail the whole capture.
+fn configure_priorities(open_skel: &mut OpenBinderSkel<'_>) {
+    if !crate::capture::btf_probe::sched_prio_supported() {
+        open_skel.progs.binder_transaction_prio.set_autoload(false);
+        log::info!("caller priorities disabled (no kernel BTF support)");
+    }
+    if !Path::new(SET_PRIORITY_TRACEPOINT).exists() {
+        open_skel.progs.binder_set_priority.set_autoload(false);
+        log::info!("priority inheritance disabled (no binder_set_priority tracepoint)");
+    }
+}
+
+// Kernels before 5.5 only have bpf_probe_read, which reads user and kernel memory alike there
+fn configure_probe_reads(open_skel: &mut OpenBinderSkel<'_>) -> Result<()> {
+    if crate::capture::btf_probe::probe_read_user_supported() {
+        return Ok(());
+    }
+    let rodata = open_skel
+        .maps
+        .rodata_data
+        .as_deref_mut()
+        .context("BPF rodata section unavailable")?;
+    rodata.cfg_probe_read_legacy = 1;
+    Ok(())
+}
 
+// Opens the skeleton with the programs of `tiers`, configured for the capture
+fn open_configured<'a>(
+    mode: ReplyCorrelationMode,
+    filter: &CaptureFilter,
+    snaplen: Snaplen,
+    limits: CaptureLimits,
+    tiers: Tiers,
+) -> Result<OpenBinderSkel<'a>> {
     let mut skel_builder = BinderSkelBuilder::default();
     // skel_builder.obj_builder.debug(true);
 
     // On kernels without BTF, hand libbpf a minimal custom BTF so it uses that
     // as the CO-RE target instead of aborting the load over a missing
-    // /sys/kernel/btf/vmlinux. The reply-correlation program (the only CO-RE
-    // user) is disabled below, so the blob's contents are never consulted -- but
-    // libbpf does parse the file, so it must be valid BTF. See write_dummy_btf.
+    // /sys/kernel/btf/vmlinux. The reply-correlation and fd path programs (the
+    // only CO-RE users) are disabled below, so the blob's contents are never
+    // consulted -- but libbpf does parse the file, so it must be valid BTF. See
+    // write_dummy_btf.
     if !crate::capture::btf_probe::kernel_btf_present() {
         let path = crate::capture::btf_probe::write_dummy_btf()?;
         skel_builder.obj_builder.btf_custom_path(&path)?;
@@ -188,11 +258,174 @@ pub fn attach_tracepoints<'a>(mode: ReplyCorrelationMode) -> Result<BinderSkel<'
     open_skel.maps.bss_data.as_deref_mut().unwrap().g_loader_pid = unsafe { libc::getpid() } as i32;
 
     configure_reply_correlation(&mut open_skel, mode)?;
+    configure_fd_paths(&mut open_skel);
+    configure_priorities(&mut open_skel);
+    configure_probe_reads(&mut open_skel)?;
+    tiers.configure(&mut open_skel);
+    filter.configure(&mut open_skel)?;
+    snaplen.configure(&mut open_skel)?;
+    // cached, the same on every load
+    let transport = Transport::detect();
+    transport.configure(&mut open_skel)?;
+    limits.configure(&mut open_skel, transport)?;
+    Ok(open_skel)
+}
+
+// Opens and loads the skeleton with the programs of `tiers`
+fn load_skel<'a>(
+    mode: ReplyCorrelationMode,
+    filter: &CaptureFilter,
+    snaplen: Snaplen,
+    limits: CaptureLimits,
+    tiers: Tiers,
+) -> Result<BinderSkel<'a>> {
+    let open_skel = open_configured(mode, filter, snaplen, limits, tiers)?;
+    open_skel.load().with_context(|| {
+        let flags = limits.non_default_flags();
+        if flags.is_empty() {
+            return "failed to load the BPF programs".to_string();
+        }
+        format!(
+            "failed to load the BPF programs with {}, try the defaults",
+            flags.join(" ")
+        )
+    })
+}
 
-    let mut skel = open_skel.load()?;
+// Without BTF, Auto falls back to the offsets offset_finder or --calibrate cached for this kernel
+fn resolve_reply_correlation(mode: ReplyCorrelationMode) -> ReplyCorrelationMode {
+    if !matches!(mode, ReplyCorrelationMode::Auto)
+        || crate::capture::btf_probe::reply_correlation_supported()
+    {
+        return mode;
+    }
+    let cache = OffsetCache::default();
+    let kernel = match KernelBuild::current() {
+        Ok(kernel) => kernel,
+        Err(err) => {
+            log::debug!("offset cache not consulted: {:#}", err);
+            return mode;
+        }
+    };
+    match cache.lookup(&kernel) {
+        Some(offsets) => {
+            log::info!(
+                "reply offsets of this kernel found in {}",
+                cache.path().display()
+            );
+            offsets.into()
+        }
+        None => mode,
+    }
+}
+
+// Returns the loaded skeleton, along with the tiers the kernel accepted
+pub fn attach_tracepoints<'a>(
+    mode: ReplyCorrelationMode,
+    filter: &CaptureFilter,
+    snaplen: Snaplen,
+    wakeup_latency: bool,
+    stacks: bool,
+    limits: CaptureLimits,
+) -> Result<(BinderSkel<'a>, Tiers)> {
+    prepare_tracepoints(&limits)?;
+
+    log::info!("events go through the {}", Transport::detect().name());
+    let mode = resolve_reply_correlation(mode);
+    let wanted = Tiers {
+        reply_stack: match mode {
+            ReplyCorrelationMode::Disabled => false,
+            ReplyCorrelationMode::Auto => crate::capture::btf_probe::reply_correlation_supported(),
+            ReplyCorrelationMode::ManualOffsets { .. } => true,
+        },
+        // sched_switch fires on every context switch of the system, and stack walks of every
+        // BC_TRANSACTION cost the callers, only hook them when asked to
+        wakeups: wakeup_latency,
+        stacks,
+        ptr_payloads: limits.copies_ptr_payloads(),
+        ..Tiers::all()
+    };
+    let (mut skel, tiers) = load_tiers(wanted, |tiers| {
+        load_skel(mode, filter, snaplen, limits, tiers)
+    })?;
+    filter.populate(&skel)?;
     skel.attach()?;
 
-    Ok(skel)
+    Ok((skel, tiers))
+}
+
+// A program of the skeleton, for `binderdump doctor`
+pub struct ProgramInfo {
+    pub name: String,
+    // tp/<category>/<name> or raw_tp/<name>
+    pub section: String,
+    // whether a capture with every option on loads it on this kernel, in some tier
+    pub loaded: bool,
+}
+
+// Opens the skeleton like a capture with every option on
+fn open_for_doctor<'a>(tiers: Tiers) -> Result<OpenBinderSkel<'a>> {
+    open_configured(
+        ReplyCorrelationMode::Auto,
+        &CaptureFilter::default(),
+        Snaplen::default(),
+        CaptureLimits::default(),
+        tiers,
+    )
+}
+
+// Every program of the skeleton. Nothing is loaded, so it doesn't need any privilege.
+pub fn capture_programs() -> Result<Vec<ProgramInfo>> {
+    let mut programs: Vec<ProgramInfo> = vec![];
+    let data_only = Tiers {
+        ptr_payloads: false,
+        ..Tiers::all()
+    };
+    // each variant of the tiered programs is only loaded in its tier
+    for tiers in [Tiers::core(), data_only, Tiers::all()] {
+        let open_skel = open_for_doctor(tiers)?;
+        for prog in open_skel.open_object().progs() {
+            let name = prog.name().to_string_lossy();
+            match programs.iter_mut().find(|program| program.name == name) {
+                Some(program) => program.loaded |= prog.autoload(),
+                None => programs.push(ProgramInfo {
+                    name: name.into_owned(),
+                    section: prog.section().to_string_lossy().into_owned(),
+                    loaded: prog.autoload(),
+                }),
+            }
+        }
+    }
+    Ok(programs)
+}
+
+// Loads the programs in `names`, with the others' autoload off. Nothing is attached.
+fn load_programs(names: &[&str]) -> Result<()> {
+    let mut open_skel = open_for_doctor(Tiers::all())?;
+    for mut prog in open_skel.open_object_mut().progs_mut() {
+        let autoload = names.contains(&&*prog.name().to_string_lossy());
+        prog.set_autoload(autoload);
+    }
+    open_skel.load().map(drop).context("failed to load")
+}
+
+// Loads the programs in `names` to see whether the verifier takes them: all of them at once, and
+// one at a time only when that fails. Returns the programs that don't load, with their error.
+pub fn dry_run_load(names: &[&str]) -> Result<HashMap<String, anyhow::Error>> {
+    set_memlock_rlimit((64 << 20) + CaptureLimits::default().ring_size_bytes() as u64)?;
+    let mut rejected = HashMap::new();
+    let Err(err) = load_programs(names) else {
+        return Ok(rejected);
+    };
+    for &name in names {
+        if let Err(err) = load_programs(&[name]) {
+            rejected.insert(name.to_string(), err);
+        }
+    }
+    if rejected.is_empty() {
+        return Err(err).context("the programs load one by one, but not together");
+    }
+    Ok(rejected)
 }
 
 #[cfg(test)]
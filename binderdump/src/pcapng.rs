@@ -2,6 +2,13 @@
 //! captured by the `capture` module and parsed by the `binder` module
 
 mod builders;
-mod capture_info;
+pub mod capture_info;
 mod events_aggregator;
+mod fds;
+pub mod flight_recorder;
+mod nodes;
 pub mod packets;
+mod pending;
+mod priorities;
+pub mod rotation;
+mod wakeups;
@@ -1,16 +1,22 @@
 use crate::capture::events::{
-    BinderEventIoctl, BinderEventWriteRead, BinderTransactionContents, BinderTransactionData,
-    BinderTransactionPtrChunk, BinderTransactionStack,
+    BinderEventIoctl, BinderEventWriteRead, BinderIoctlArg, BinderTransactionContents,
+    BinderTransactionData, BinderTransactionPtrChunk, BinderTransactionStack,
 };
-use crate::capture::process_cache::ProcessCache;
+use crate::capture::process_cache::{ProcessCache, ProcessInfo};
+use crate::capture::raw_dump::RawDumpMapping;
 use anyhow::{Context, Ok};
 use binderdump_structs::binder_types::transaction::binder_transaction_data;
 use binderdump_structs::binder_types::{binder_ioctl, BinderInterface};
 use binderdump_structs::bwr_layer::{
-    BinderWriteReadProtocol, BinderWriteReadType, PtrPayload, Transaction, TransactionProtocol,
+    BinderWriteReadProtocol, BinderWriteReadType, FdProtocol, InheritedPrioProtocol, PtrPayload,
+    SchedPrioProtocol, Transaction, TransactionProtocol, TranslationProtocol, WakeupProtocol,
 };
 pub use binderdump_structs::event_layer::EventType;
-use binderdump_structs::event_layer::{EventProtocol, IoctlProtocol};
+use binderdump_structs::event_layer::{
+    EventProtocol, ExecProtocol, IoctlProtocol, MappingProtocol, NewProcessProtocol,
+    ProcessInfoProtocol, ProcessMapsProtocol, UidInfoProtocol,
+};
+use binderdump_structs::ioctl_layer::IoctlArgProtocol;
 use std::collections::BTreeMap;
 
 #[derive(Default)]
@@ -23,6 +29,9 @@ pub struct IoctlProtocolBuilder {
     gid: u32,
     ioctl_id: u64,
     bwr: Option<BinderWriteReadProtocol>,
+    // raw, until build() knows the cmd for sure
+    arg_in: Option<Vec<u8>>,
+    arg_out: Option<Vec<u8>>,
     read_only: bool,
     non_empty: bool,
 }
@@ -46,6 +55,15 @@ impl IoctlProtocolBuilder {
         self
     }
 
+    pub fn arg(mut self, arg: BinderIoctlArg) -> Self {
+        if arg.done {
+            self.arg_out = Some(arg.data);
+        } else {
+            self.arg_in = Some(arg.data);
+        }
+        self
+    }
+
     pub fn result(mut self, result: i32) -> Self {
         self.result = result;
         // TODO - is this correct? if we only have result, we missed the start of the ioctl
@@ -54,21 +72,24 @@ impl IoctlProtocolBuilder {
     }
 
     pub fn build(self) -> Option<IoctlProtocol> {
-        if self.non_empty {
-            Some(IoctlProtocol::new(
-                self.fd,
-                self.cmd,
-                self.arg,
-                self.result,
-                self.uid,
-                self.gid,
-                self.ioctl_id,
-                self.read_only,
-                self.bwr,
-            ))
-        } else {
-            None
+        if !self.non_empty {
+            return None;
         }
+        let mut ioctl = IoctlProtocol::new(
+            self.fd,
+            self.cmd,
+            self.arg,
+            self.result,
+            self.uid,
+            self.gid,
+            self.ioctl_id,
+            self.read_only,
+            self.bwr,
+        );
+        let parse = |data: Option<Vec<u8>>| IoctlArgProtocol::parse(self.cmd, &data?);
+        ioctl.arg_in = parse(self.arg_in);
+        ioctl.arg_out = parse(self.arg_out);
+        Some(ioctl)
     }
 }
 
@@ -80,9 +101,16 @@ pub struct EventProtocolBuilder {
     comm: Option<String>,
     event_type: EventType,
     binder_interface: BinderInterface,
+    interface_id: u32,
+    partial: bool,
     android_sdk: u32,
     cmdline: Option<String>,
     ioctl_data: Option<IoctlProtocol>,
+    process_info: Option<ProcessInfoProtocol>,
+    uid_info: Option<UidInfoProtocol>,
+    new_process: Option<NewProcessProtocol>,
+    exec: Option<ExecProtocol>,
+    maps: Option<ProcessMapsProtocol>,
 }
 
 impl EventProtocolBuilder {
@@ -111,6 +139,16 @@ impl EventProtocolBuilder {
         self
     }
 
+    pub fn interface_id(mut self, interface_id: u32) -> Self {
+        self.interface_id = interface_id;
+        self
+    }
+
+    pub fn partial(mut self, partial: bool) -> Self {
+        self.partial = partial;
+        self
+    }
+
     pub fn cmdline(mut self, cmdline: String) -> Self {
         self.cmdline = Some(cmdline);
         self
@@ -121,6 +159,64 @@ impl EventProtocolBuilder {
         self
     }
 
+    // The metadata of the thread, for ProcessInfo and Dead packets
+    pub fn process(mut self, info: &ProcessInfo) -> Self {
+        self.comm = Some(info.get_comm().into());
+        self.cmdline = Some(info.get_cmdline().into());
+        self.process_info = Some(ProcessInfoProtocol {
+            uid: info.get_uid(),
+            start_time: info.get_start_time(),
+            package: info.get_package().as_bytes().to_vec(),
+            selinux: info.get_selinux().as_bytes().to_vec(),
+        });
+        self
+    }
+
+    // The packages of `uid`, for UidInfo packets
+    pub fn uid_info(mut self, uid: u32, packages: &[String]) -> Self {
+        self.uid_info = Some(UidInfoProtocol {
+            uid,
+            packages: packages.join(",").into_bytes(),
+        });
+        self
+    }
+
+    // The thread that forked, for NewProcess packets
+    pub fn new_process(mut self, parent_pid: i32, parent_tid: i32) -> Self {
+        self.new_process = Some(NewProcessProtocol {
+            parent_pid,
+            parent_tid,
+        });
+        self
+    }
+
+    // What was exec'd, for Exec packets
+    pub fn exec(mut self, old_tid: i32, filename: &str, argv: &[String]) -> Self {
+        self.exec = Some(ExecProtocol {
+            old_tid,
+            filename: filename.as_bytes().to_vec(),
+            argv: argv.join(" ").into_bytes(),
+        });
+        self
+    }
+
+    // The executable mappings of the process, for ProcessMaps packets
+    pub fn maps(mut self, mappings: &[RawDumpMapping]) -> Self {
+        self.maps = Some(ProcessMapsProtocol {
+            mappings: mappings
+                .iter()
+                .map(|mapping| MappingProtocol {
+                    start: mapping.start,
+                    end: mapping.end,
+                    offset: mapping.offset,
+                    build_id: mapping.build_id.clone(),
+                    path: mapping.path.as_bytes().to_vec(),
+                })
+                .collect(),
+        });
+        self
+    }
+
     pub fn build(self) -> anyhow::Result<EventProtocol> {
         let mut comm_vec = self.comm.unwrap_or_default().into_bytes();
         comm_vec.resize(16, 0);
@@ -128,7 +224,7 @@ impl EventProtocolBuilder {
             "failed to convert comm String to [u8; 16]"
         )))?;
 
-        Ok(EventProtocol::new(
+        let mut event = EventProtocol::new(
             self.timestamp,
             self.pid,
             self.tid,
@@ -138,7 +234,15 @@ impl EventProtocolBuilder {
             self.android_sdk,
             self.cmdline.map(|s| s.into_bytes()).unwrap_or_default(),
             self.ioctl_data,
-        ))
+        );
+        event.interface_id = self.interface_id;
+        event.partial = self.partial;
+        event.process_info = self.process_info;
+        event.uid_info = self.uid_info;
+        event.new_process = self.new_process;
+        event.exec = self.exec;
+        event.maps = self.maps;
+        Ok(event)
     }
 }
 
@@ -209,6 +313,13 @@ pub struct TransactionProtocolBuilder {
     // (one entry in `offsets` may span multiple chunks if its `length` exceeds
     // MAX_PTR_PAYLOAD); inner BTreeMap is keyed by chunk_index to keep ordering.
     ptr_payloads: BTreeMap<u32, PtrPayloadAccum>,
+    translations: Vec<TranslationProtocol>,
+    fds: Vec<FdProtocol>,
+    secctx: String,
+    caller_prio: Option<SchedPrioProtocol>,
+    inherited_prio: Option<InheritedPrioProtocol>,
+    wakeup: Option<WakeupProtocol>,
+    user_stack: Vec<u64>,
 }
 
 #[derive(Default)]
@@ -225,12 +336,15 @@ impl TransactionProtocolBuilder {
 
     pub fn build(self) -> Option<TransactionProtocol> {
         let mut txn = self.txn?;
+        let has_contents = self.data.is_some() || self.offsets.is_some();
 
         if let Some(data) = self.data {
+            txn.data_size = data.total_size as u64;
             txn.data = data.data;
         }
 
         if let Some(offsets) = self.offsets {
+            txn.offsets_size = offsets.total_size as u64;
             txn.offsets = offsets.data;
         }
 
@@ -253,9 +367,25 @@ impl TransactionProtocolBuilder {
             txn.cookie = cmd.cookie;
             txn.sender_pid = cmd.sender_pid;
             txn.sender_euid = cmd.sender_euid;
+            // without the txn_data tier only the command has the sizes
+            if !has_contents {
+                txn.data_size = cmd.data_size;
+                txn.offsets_size = cmd.offsets_size;
+            }
         }
 
         txn.is_compat = self.is_compat;
+        txn.translations = self.translations;
+        txn.fds = self.fds;
+        txn.secctx = self.secctx.into_bytes();
+        txn.caller_prio = self.caller_prio;
+        txn.inherited_prio = self.inherited_prio;
+        txn.wakeup = self.wakeup;
+        txn.user_stack = self
+            .user_stack
+            .iter()
+            .flat_map(|ip| ip.to_le_bytes())
+            .collect();
 
         // move the accumulated scatter-gather payloads onto the wire struct;
         // concatenate each entry's chunks in chunk_index order.
@@ -286,6 +416,41 @@ impl TransactionProtocolBuilder {
         self
     }
 
+    pub fn translations(mut self, translations: Vec<TranslationProtocol>) -> Self {
+        self.translations = translations;
+        self
+    }
+
+    pub fn fds(mut self, fds: Vec<FdProtocol>) -> Self {
+        self.fds = fds;
+        self
+    }
+
+    pub fn secctx(mut self, secctx: String) -> Self {
+        self.secctx = secctx;
+        self
+    }
+
+    pub fn priorities(
+        mut self,
+        caller_prio: Option<SchedPrioProtocol>,
+        inherited_prio: Option<InheritedPrioProtocol>,
+    ) -> Self {
+        self.caller_prio = caller_prio;
+        self.inherited_prio = inherited_prio;
+        self
+    }
+
+    pub fn wakeup(mut self, wakeup: Option<WakeupProtocol>) -> Self {
+        self.wakeup = wakeup;
+        self
+    }
+
+    pub fn user_stack(mut self, ips: Vec<u64>) -> Self {
+        self.user_stack = ips;
+        self
+    }
+
     pub fn command_data(mut self, data: binder_transaction_data) -> Self {
         self.command_data = Some(data);
         self
@@ -305,6 +470,7 @@ impl TransactionProtocolBuilder {
         } else {
             txn.to_proc
         };
+        // also makes sure the target's metadata is written before this packet
         let proc_info = procs
             .get_proc(txn.to_proc, to_thread, None)
             .context(format!("failed to get target process for txn: {:?}", txn))?;
@@ -326,7 +492,8 @@ impl TransactionProtocolBuilder {
             code: txn.code,
             flags: txn.flags,
             target_comm: comm,
-            target_cmdline: proc_info.get_cmdline().to_string().into_bytes(),
+            // filled in by the dissector from the target's ProcessInfo packet
+            target_cmdline: vec![],
             ..Default::default()
         });
         self.validate_transaction_stack()?;
@@ -4,6 +4,12 @@ use android_system_properties::AndroidSystemProperties;
 use anyhow::{Context, Result};
 use nix;
 
+use crate::capture::{
+    devices::BinderDevices,
+    raw_dump::{RawDumpHeader, RawDumpReader},
+    system_property,
+};
+
 #[derive(Debug)]
 pub struct CaptureInfo {
     model: String,
@@ -12,8 +18,13 @@ pub struct CaptureInfo {
     kernel_version: String,
     capture_app: &'static str,
     timeshift: Duration,
+    android_sdk: u32,
+    // the feature tiers the capture loaded, empty when not known
+    tiers: Vec<String>,
 }
 
+const CAPTURE_APP: &str = concat!("binderdump (version ", env!("CARGO_PKG_VERSION"), ")");
+
 impl CaptureInfo {
     pub fn new() -> Result<Self> {
         let properties = AndroidSystemProperties::new();
@@ -35,8 +46,6 @@ impl CaptureInfo {
             .context("Failed to open /proc/version")?
             .read_to_string(&mut kernel_version)?;
 
-        let capture_app = concat!("binderdump (version ", env!("CARGO_PKG_VERSION"), ")");
-
         let real_clock = nix::time::clock_gettime(nix::time::ClockId::CLOCK_REALTIME)
             .context("failed to get CLOCK_REALTIME")?;
         let boot_clock = nix::time::clock_gettime(nix::time::ClockId::CLOCK_BOOTTIME)
@@ -49,11 +58,46 @@ impl CaptureInfo {
             os,
             fingerprint,
             kernel_version,
-            capture_app,
+            capture_app: CAPTURE_APP,
             timeshift: timeshift.into(),
+            android_sdk: system_property::read_sdk_int(),
+            tiers: vec![],
         })
     }
 
+    pub fn with_tiers(mut self, tiers: &[&str]) -> Self {
+        self.tiers = tiers.iter().map(|tier| tier.to_string()).collect();
+        self
+    }
+
+    // The device a raw dump was recorded on
+    pub fn from_raw_dump(dump: &RawDumpReader) -> Self {
+        let header = dump.header();
+        Self {
+            model: header.model.clone(),
+            os: header.os.clone(),
+            fingerprint: header.fingerprint.clone(),
+            kernel_version: header.kernel_version.clone(),
+            capture_app: CAPTURE_APP,
+            timeshift: Duration::from_nanos(header.timeshift_ns),
+            android_sdk: header.android_sdk,
+            tiers: header.tiers.clone(),
+        }
+    }
+
+    pub fn raw_dump_header(&self, devices: &BinderDevices) -> RawDumpHeader {
+        RawDumpHeader {
+            model: self.model.clone(),
+            os: self.os.clone(),
+            fingerprint: self.fingerprint.clone(),
+            kernel_version: self.kernel_version.clone(),
+            timeshift_ns: self.timeshift.as_nanos() as u64,
+            android_sdk: self.android_sdk,
+            devices: RawDumpHeader::dump_devices(devices),
+            tiers: self.tiers.clone(),
+        }
+    }
+
     pub fn get_model(&self) -> &str {
         &self.model
     }
@@ -77,4 +121,12 @@ impl CaptureInfo {
     pub fn get_timeshift(&self) -> &Duration {
         &self.timeshift
     }
+
+    pub fn get_android_sdk(&self) -> u32 {
+        self.android_sdk
+    }
+
+    pub fn get_tiers(&self) -> &[String] {
+        &self.tiers
+    }
 }
@@ -15,10 +15,16 @@ pub struct EventsAggregator {
     channel: EventChannel,
     ongoing_events: HashMap<i32, OngoingEvent>,
     current_ioctl_id: u64,
+    // number of event vectors we produced without the ioctl they belong to
+    orphan_events: u64,
     // finish the iteration if `timeout` passed without a new event (useful for testing to ensure we don't block indefinitly)
     timeout: Option<Duration>,
     // finish the iteration once this absolute instant is reached
     deadline: Option<Instant>,
+    // yield an empty vector every `tick` without events, so the consumer's timers run on an idle
+    // device too
+    tick: Option<Duration>,
+    last_event: Instant,
 }
 
 struct OngoingEvent {
@@ -56,8 +62,11 @@ impl EventsAggregator {
             channel,
             ongoing_events: HashMap::new(),
             current_ioctl_id: 0,
+            orphan_events: 0,
             timeout: None,
             deadline: None,
+            tick: None,
+            last_event: Instant::now(),
         }
     }
 
@@ -66,6 +75,64 @@ impl EventsAggregator {
         self.deadline = Some(deadline);
     }
 
+    pub fn set_tick(&mut self, tick: Duration) {
+        self.tick = Some(tick);
+    }
+
+    // A timeout that is neither the deadline nor the end of `timeout`
+    fn is_tick(&self) -> bool {
+        self.tick.is_some()
+            && !self.deadline.is_some_and(|d| Instant::now() >= d)
+            && !self.timeout.is_some_and(|t| self.last_event.elapsed() >= t)
+    }
+
+    pub fn channel(&self) -> &EventChannel {
+        &self.channel
+    }
+
+    pub fn orphan_events(&self) -> u64 {
+        self.orphan_events
+    }
+
+    // An orphan is anything that should have started with a BinderIoctl event but didn't, e.g.
+    // because the ring buffer was full when the ioctl started. Process exits and lone
+    // invalidations come from BPF on their own.
+    fn is_orphan(events: &[BinderEvent]) -> bool {
+        match events.first().map(|event| &event.data) {
+            Some(BinderEventData::BinderIoctl(_))
+            | Some(BinderEventData::BinderInvalidateProcess)
+            | Some(BinderEventData::BinderNewProcess(_))
+            | Some(BinderEventData::BinderExec(_))
+            | Some(BinderEventData::BinderTransactionTranslation(_))
+            | Some(BinderEventData::BinderTransactionFd(_))
+            | Some(BinderEventData::BinderTransactionPrio(_))
+            | Some(BinderEventData::BinderSetPriority(_))
+            | Some(BinderEventData::BinderTransactionWakeup(_))
+            | None => false,
+            Some(BinderEventData::BinderInvalidate) => events.len() > 1,
+            Some(_) => true,
+        }
+    }
+
+    // Takes every ioctl that is still in flight, oldest first. Called once the iteration is over,
+    // so the calls that were blocked when the capture stopped still make it to the pcapng.
+    pub fn drain_ongoing(&mut self) -> Vec<Vec<BinderEvent>> {
+        let mut ongoing = vec![];
+        for (_, ongoing_event) in self.ongoing_events.drain() {
+            let events = ongoing_event.events;
+            if events.is_empty() {
+                continue;
+            }
+            if Self::is_orphan(&events) {
+                self.orphan_events += 1;
+                continue;
+            }
+            ongoing.push(events);
+        }
+        ongoing.sort_by_key(|events| events[0].timestamp);
+        ongoing
+    }
+
     fn get_event(&mut self) -> Result<BinderEvent, RecvTimeoutError> {
         let channel = self.channel.get_channel();
         let remaining = match self.deadline {
@@ -75,12 +142,16 @@ impl EventsAggregator {
                 _ => return Err(RecvTimeoutError::Timeout),
             },
         };
-        match (self.timeout, remaining) {
-            (None, None) => channel.recv().map_err(|err| err.into()),
-            (Some(t), None) => channel.recv_timeout(t),
-            (None, Some(r)) => channel.recv_timeout(r),
-            (Some(t), Some(r)) => channel.recv_timeout(t.min(r)),
-        }
+        let wait = [self.timeout, remaining, self.tick]
+            .into_iter()
+            .flatten()
+            .min();
+        let event = match wait {
+            None => channel.recv().map_err(|err| err.into()),
+            Some(wait) => channel.recv_timeout(wait),
+        }?;
+        self.last_event = Instant::now();
+        Ok(event)
     }
 
     fn split_events(&mut self, tid: i32) -> Option<Vec<BinderEvent>> {
@@ -109,6 +180,24 @@ impl EventsAggregator {
         mut event: BinderEvent,
     ) -> anyhow::Result<Option<Vec<BinderEvent>>> {
         let tid = event.tid;
+        // not part of any ioctl, a thread can't fork or exec while it's in one. Translations and
+        // fds are part of the sender's ioctl, but they are traced after the BinderTransaction that
+        // split it and only matter to the receiving side, so they don't wait for the rest of it.
+        // The receiving side's fds are traced before its BinderTransactionReceived, so they are
+        // recorded before the packet they belong to is written. The same goes for the caller's
+        // priority and the changes binder makes to the receiving thread's, which aren't even
+        // traced from its context. The wakeup of the receiving thread is traced along with its
+        // BinderTransactionReceived, and recorded before the rest of its ioctl is done.
+        if let BinderEventData::BinderNewProcess(_)
+        | BinderEventData::BinderExec(_)
+        | BinderEventData::BinderTransactionTranslation(_)
+        | BinderEventData::BinderTransactionFd(_)
+        | BinderEventData::BinderTransactionPrio(_)
+        | BinderEventData::BinderSetPriority(_)
+        | BinderEventData::BinderTransactionWakeup(_) = event.data
+        {
+            return Ok(Some(vec![event]));
+        }
         let events = self
             .ongoing_events
             .entry(tid)
@@ -155,6 +244,9 @@ impl EventsAggregator {
             BinderEventData::BinderIoctl(ref mut ioctl) => {
                 ioctl.ioctl_id = self.current_ioctl_id;
                 self.current_ioctl_id += 1;
+                // A thread that was already blocked reading when the capture started is only
+                // announced at sys_exit, after its BinderTransactionReceived, so move the ioctl
+                // to the front to keep the fragment from being counted as an orphan
                 if ioctl.read_only {
                     events.insert(event);
                 } else {
@@ -177,12 +269,17 @@ impl Iterator for EventsAggregator {
     // events between (and including) BinderIoctl and BinderWriteRead(BinderEventWriteRead::BinderEventWrite(...)), will be returned first,
     // and a copy of the first ioctl event, along with all the remaining events until BinderIoctlDone will be produced as a seperate vector.
     //
-    // If a BinderInvalidate or BinderInvalidateProcess events are received, they are sent immediatly.
+    // If a BinderInvalidate, BinderInvalidateProcess, BinderNewProcess, BinderExec,
+    // BinderTransactionTranslation, BinderTransactionFd, BinderTransactionPrio,
+    // BinderSetPriority or BinderTransactionWakeup events are received, they are sent immediatly.
+    //
+    // With a tick set, an empty vector is produced every tick without events.
     fn next(&mut self) -> Option<Self::Item> {
         loop {
             let event = self.get_event();
             let event = match event {
                 Ok(event) => event,
+                Err(RecvTimeoutError::Timeout) if self.is_tick() => return Some(vec![]),
                 Err(RecvTimeoutError::Timeout) if self.deadline.is_some() => return None,
                 Err(err) => {
                     error!("Events channel error: {}", err);
@@ -191,7 +288,12 @@ impl Iterator for EventsAggregator {
             };
             match self.handle_new_event(event) {
                 Ok(events) => match events {
-                    Some(events) => return Some(events),
+                    Some(events) => {
+                        if Self::is_orphan(&events) {
+                            self.orphan_events += 1;
+                        }
+                        return Some(events);
+                    }
                     None => continue,
                 },
                 Err(err) => error!("error handling new event: {:#?}", err),
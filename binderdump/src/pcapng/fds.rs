@@ -0,0 +1,193 @@
+// The fds of the transactions in flight, with the file behind them.
+//
+// The kernel traces every fd of a FD or FDA object twice: from the sender's context while it
+// translates the transaction, with the sender's fd (BPF resolves the file right then, the sender
+// usually closes it as soon as the transaction is sent), and from the target's context once it
+// installed its own fd, right before it reads the transaction. Both carry the offset of the fd in
+// the transaction buffer, which pairs them, and are kept until the target's packet is written.
+use super::pending::Pending;
+use crate::capture::events::{BinderFdFile, BinderTransactionFd};
+use binderdump_structs::bwr_layer::FdProtocol;
+
+// linux/magic.h
+const SOCKFS_MAGIC: u64 = 0x534f434b;
+const PIPEFS_MAGIC: u64 = 0x50495045;
+const ANON_INODE_FS_MAGIC: u64 = 0x09041934;
+const DMA_BUF_MAGIC: u64 = 0x444d4142;
+
+const S_IFMT: u32 = 0o170000;
+const S_IFSOCK: u32 = 0o140000;
+
+// The file as /proc/<pid>/fd names it. Files of pseudo filesystems get their type and inode
+// instead of a path; memfds and ashmem regions live at the root of the internal shmem mount, under
+// the name they were created with (`memfd:<name>`, `dev/ashmem/<name>`).
+pub fn file_path(file: &BinderFdFile) -> String {
+    let name = file.names.first().map(String::as_str).unwrap_or_default();
+    if file.magic == SOCKFS_MAGIC || file.mode & S_IFMT == S_IFSOCK {
+        return format!("socket:[{}]", file.ino);
+    }
+    match file.magic {
+        PIPEFS_MAGIC => return format!("pipe:[{}]", file.ino),
+        ANON_INODE_FS_MAGIC => return format!("anon_inode:{}", name),
+        DMA_BUF_MAGIC => return format!("dmabuf:[{}]", file.ino),
+        _ => (),
+    }
+    if let [name] = file.names.as_slice() {
+        if name.starts_with("memfd:") {
+            return name.clone();
+        }
+    }
+    let path: String = file
+        .names
+        .iter()
+        .rev()
+        .map(|name| format!("/{}", name))
+        .collect();
+    match (file.truncated, path.is_empty()) {
+        (true, _) => format!("...{}", path),
+        (false, true) => "/".to_string(),
+        (false, false) => path,
+    }
+}
+
+#[derive(Default)]
+pub struct Fds {
+    // transaction debug id -> its fds, until its target reads it
+    pending: Pending<Vec<FdProtocol>>,
+}
+
+impl Fds {
+    pub fn record(&mut self, fd: BinderTransactionFd) {
+        let fds = self.pending.entry(fd.debug_id);
+        let index = match fds.iter().position(|pending| pending.offset == fd.offset) {
+            Some(index) => index,
+            None => {
+                fds.push(FdProtocol {
+                    offset: fd.offset,
+                    sender_fd: -1,
+                    receiver_fd: -1,
+                    path: vec![],
+                });
+                fds.len() - 1
+            }
+        };
+        let pending = &mut fds[index];
+        if fd.received {
+            pending.receiver_fd = fd.fd;
+            return;
+        }
+        pending.sender_fd = fd.fd;
+        if let Some(file) = &fd.file {
+            pending.path = file_path(file).into_bytes();
+        }
+    }
+
+    // The fds of transaction `debug_id`, which its target just read
+    pub fn take(&mut self, debug_id: i32) -> Vec<FdProtocol> {
+        self.pending.take(debug_id).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(magic: u64, mode: u32, ino: u64, names: &[&str]) -> BinderFdFile {
+        BinderFdFile {
+            magic,
+            mode,
+            ino,
+            names: names.iter().map(|name| name.to_string()).collect(),
+            truncated: false,
+        }
+    }
+
+    #[test]
+    fn paths_follow_proc() {
+        const EXT4_SUPER_MAGIC: u64 = 0xef53;
+        const TMPFS_MAGIC: u64 = 0x01021994;
+        assert_eq!(
+            file_path(&file(
+                EXT4_SUPER_MAGIC,
+                0o100644,
+                12,
+                &["base.apk", "com.example.app-1", "app", "data"]
+            )),
+            "/data/app/com.example.app-1/base.apk"
+        );
+        assert_eq!(
+            file_path(&file(TMPFS_MAGIC, 0o100600, 3, &["memfd:jit-cache"])),
+            "memfd:jit-cache"
+        );
+        assert_eq!(
+            file_path(&file(
+                TMPFS_MAGIC,
+                0o100600,
+                4,
+                &["dev/ashmem/CursorWindow"]
+            )),
+            "/dev/ashmem/CursorWindow"
+        );
+        assert_eq!(
+            file_path(&file(SOCKFS_MAGIC, 0o140777, 81234, &[""])),
+            "socket:[81234]"
+        );
+        assert_eq!(
+            file_path(&file(PIPEFS_MAGIC, 0o010600, 5678, &[""])),
+            "pipe:[5678]"
+        );
+        assert_eq!(
+            file_path(&file(ANON_INODE_FS_MAGIC, 0o600, 1, &["sync_file"])),
+            "anon_inode:sync_file"
+        );
+        assert_eq!(
+            file_path(&file(DMA_BUF_MAGIC, 0o100644, 99, &["dmabuf"])),
+            "dmabuf:[99]"
+        );
+
+        let mut deep = file(EXT4_SUPER_MAGIC, 0o100644, 1, &["c", "b"]);
+        deep.truncated = true;
+        assert_eq!(file_path(&deep), ".../b/c");
+    }
+
+    fn fd(debug_id: i32, offset: u64, fd: i32, received: bool) -> BinderTransactionFd {
+        BinderTransactionFd {
+            debug_id,
+            fd,
+            offset,
+            received,
+            file: (!received).then(|| file(SOCKFS_MAGIC, 0o140777, fd as u64, &[""])),
+        }
+    }
+
+    #[test]
+    fn sends_pair_with_receives_by_offset() {
+        let mut fds = Fds::default();
+        // a FD object at 0x50 and a FDA of two fds in the buffer after the data
+        fds.record(fd(1, 0x58, 10, false));
+        fds.record(fd(1, 0x100, 11, false));
+        fds.record(fd(1, 0x104, 12, false));
+        fds.record(fd(1, 0x58, 40, true));
+        fds.record(fd(1, 0x104, 42, true));
+        fds.record(fd(1, 0x100, 41, true));
+
+        let taken = fds.take(1);
+        let pairs: Vec<_> = taken
+            .iter()
+            .map(|fd| (fd.offset, fd.sender_fd, fd.receiver_fd))
+            .collect();
+        assert_eq!(pairs, [(0x58, 10, 40), (0x100, 11, 41), (0x104, 12, 42)]);
+        assert_eq!(taken[0].path, b"socket:[10]");
+        assert!(fds.take(1).is_empty());
+    }
+
+    #[test]
+    fn receives_without_a_send_keep_the_receiver_fd() {
+        let mut fds = Fds::default();
+        fds.record(fd(2, 0x58, 40, true));
+        let taken = fds.take(2);
+        assert_eq!(taken[0].sender_fd, -1);
+        assert_eq!(taken[0].receiver_fd, 40);
+        assert!(taken[0].path.is_empty());
+    }
+}
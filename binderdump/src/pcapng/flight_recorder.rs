@@ -0,0 +1,420 @@
+// Flight recorder mode (--flight-recorder-secs / --flight-recorder-mb). Packets are kept in an
+// in-memory window instead of being written, and the window is dumped to the pcapng when one of
+// the triggers fires, so rare stalls can be caught without storing hours of uneventful traffic.
+use anyhow::{bail, Context, Result};
+use binderdump_structs::{
+    binder_types::{binder_return::BinderReturn, bwr_trait::Bwr, BinderInterface},
+    bwr_layer::TransactionProtocol,
+    event_layer::{EventProtocol, EventType},
+};
+use log::warn;
+use pcap_file::pcapng::blocks::enhanced_packet::EnhancedPacketBlock;
+use std::collections::{HashMap, VecDeque};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+// Requests we are still waiting on a reply for, when --trigger-slow-reply is set. Replies can be
+// missed (filtered peer, dropped event), so don't let the map grow forever.
+const MAX_PENDING_REQUESTS: usize = 16384;
+
+static DUMP_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn request_dump(_: libc::c_int) {
+    DUMP_REQUESTED.store(true, Ordering::Relaxed);
+}
+
+// --trigger-txn INTERFACE[:CODE]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransactionTrigger {
+    pub interface: String,
+    pub code: Option<u32>,
+}
+
+impl FromStr for TransactionTrigger {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (interface, code) = match s.rsplit_once(':') {
+            // HIDL descriptors contain '::' (android.hardware.foo@1.0::IFoo), only split on a
+            // trailing code
+            Some((interface, code)) if !interface.ends_with(':') && !code.is_empty() => {
+                let code = match code.strip_prefix("0x") {
+                    Some(hex) => u32::from_str_radix(hex, 16),
+                    None => code.parse(),
+                }
+                .with_context(|| format!("invalid transaction code: {}", code))?;
+                (interface, Some(code))
+            }
+            _ => (s, None),
+        };
+        if interface.is_empty() {
+            bail!("expected INTERFACE[:CODE]");
+        }
+        Ok(Self {
+            interface: interface.to_string(),
+            code,
+        })
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Triggers {
+    pub transactions: Vec<TransactionTrigger>,
+    // BR_FAILED_REPLY / BR_DEAD_REPLY
+    pub failed_replies: bool,
+    pub slow_reply: Option<Duration>,
+}
+
+#[derive(PartialEq, Eq, Hash)]
+enum Metadata {
+    Process(i32),
+    Uid(u32),
+    Maps(i32),
+}
+
+pub struct FlightRecorder {
+    max_age: Option<Duration>,
+    max_bytes: Option<u64>,
+    triggers: Triggers,
+    packets: VecDeque<EnhancedPacketBlock<'static>>,
+    bytes: u64,
+    // the last ProcessInfo packet of every live process, the UidInfo packets and the ProcessMaps
+    // packets, written at the start of every dump
+    metadata: HashMap<Metadata, EnhancedPacketBlock<'static>>,
+    // debug_id -> timestamp of the request
+    pending_requests: HashMap<i32, u64>,
+}
+
+impl FlightRecorder {
+    // Also makes SIGUSR1 dump the window
+    pub fn new(
+        max_age: Option<Duration>,
+        max_bytes: Option<u64>,
+        triggers: Triggers,
+    ) -> Result<Self> {
+        let handler = request_dump as extern "C" fn(libc::c_int);
+        if unsafe { libc::signal(libc::SIGUSR1, handler as libc::sighandler_t) } == libc::SIG_ERR {
+            bail!("failed to set SIGUSR1 handler");
+        }
+        Ok(Self::without_signal(max_age, max_bytes, triggers))
+    }
+
+    fn without_signal(
+        max_age: Option<Duration>,
+        max_bytes: Option<u64>,
+        triggers: Triggers,
+    ) -> Self {
+        Self {
+            max_age,
+            max_bytes,
+            triggers,
+            packets: VecDeque::new(),
+            bytes: 0,
+            metadata: HashMap::new(),
+            pending_requests: HashMap::new(),
+        }
+    }
+
+    pub(super) fn len(&self) -> usize {
+        self.packets.len()
+    }
+
+    pub(super) fn record(&mut self, packet: EnhancedPacketBlock<'static>) {
+        self.bytes += packet.data.len() as u64;
+        let newest = packet.timestamp;
+        self.packets.push_back(packet);
+
+        while let Some(oldest) = self.packets.front() {
+            let too_old = self
+                .max_age
+                .is_some_and(|max| newest.saturating_sub(oldest.timestamp) > max);
+            let too_big = self.max_bytes.is_some_and(|max| self.bytes > max);
+            // always keep the packet we just got
+            if !(too_old || too_big) || self.packets.len() == 1 {
+                break;
+            }
+            let oldest = self.packets.pop_front().unwrap();
+            self.bytes -= oldest.data.len() as u64;
+        }
+    }
+
+    // ProcessInfo, UidInfo and ProcessMaps packets
+    pub(super) fn record_metadata(
+        &mut self,
+        proto: &EventProtocol,
+        packet: EnhancedPacketBlock<'static>,
+    ) {
+        let key = match (&proto.uid_info, &proto.maps) {
+            (Some(uid_info), _) => Metadata::Uid(uid_info.uid),
+            (None, Some(_)) => Metadata::Maps(proto.pid),
+            (None, None) => Metadata::Process(proto.pid),
+        };
+        self.metadata.insert(key, packet);
+    }
+
+    // The Dead packets carry the metadata themselves, and an Exec replaces the metadata of the
+    // whole process
+    pub(super) fn forget_dead(&mut self, proto: &EventProtocol) {
+        if matches!(proto.event_type, EventType::DeadProcess | EventType::Exec) {
+            self.metadata.retain(|key, _| {
+                !matches!(key, Metadata::Process(pid) | Metadata::Maps(pid) if *pid == proto.pid)
+            });
+        }
+    }
+
+    pub(super) fn take_window(&mut self) -> VecDeque<EnhancedPacketBlock<'static>> {
+        self.bytes = 0;
+        let mut window = std::mem::take(&mut self.packets);
+        let mut metadata: Vec<_> = self.metadata.values().cloned().collect();
+        metadata.sort_by_key(|packet| packet.timestamp);
+        for packet in metadata.into_iter().rev() {
+            window.push_front(packet);
+        }
+        window
+    }
+
+    // Checked before every packet and while idle, a dump can be requested on a quiet device
+    pub(super) fn dump_requested(&self) -> Option<String> {
+        DUMP_REQUESTED
+            .swap(false, Ordering::Relaxed)
+            .then(|| "SIGUSR1 received".to_string())
+    }
+
+    // Returns why the window should be dumped after this packet, if it should
+    pub(super) fn check(&mut self, proto: &EventProtocol) -> Option<String> {
+        if let Some(reason) = self.dump_requested() {
+            return Some(reason);
+        }
+        let bwr = proto.ioctl_data.as_ref()?.bwr.as_ref()?;
+        if self.triggers.failed_replies && bwr.is_read() {
+            if let Some(reason) = failed_reply(&bwr.data) {
+                return Some(format!("{} returned to {}", reason, proto.tid));
+            }
+        }
+        let txn = bwr.transaction.as_ref()?;
+        if txn.reply == 0 {
+            if let Some(trigger) = self.transaction_trigger(proto, txn) {
+                return Some(format!("transaction to {}", trigger));
+            }
+        }
+        self.slow_reply(proto.timestamp(), txn)
+            .map(|latency| format!("reply to {} took {:?}", txn.in_reply_to_debug_id, latency))
+    }
+
+    fn transaction_trigger(
+        &self,
+        proto: &EventProtocol,
+        txn: &TransactionProtocol,
+    ) -> Option<String> {
+        if self.triggers.transactions.is_empty() {
+            return None;
+        }
+        let interface = match proto.binder_interface() {
+            BinderInterface::HWBINDER => parse_hidl_token(&txn.data),
+            _ => parse_aidl_token(&txn.data, proto.android_sdk()),
+        }?;
+        self.triggers
+            .transactions
+            .iter()
+            .find(|trigger| {
+                trigger.interface == interface && trigger.code.map_or(true, |code| code == txn.code)
+            })
+            .map(|_| format!("{}:{}", interface, txn.code))
+    }
+
+    fn slow_reply(&mut self, timestamp: u64, txn: &TransactionProtocol) -> Option<Duration> {
+        let threshold = self.triggers.slow_reply?;
+        if txn.reply == 0 {
+            if txn.flags & binderdump_sys::transaction_flags_TF_ONE_WAY == 0 {
+                if self.pending_requests.len() >= MAX_PENDING_REQUESTS {
+                    warn!("too many requests without a reply, forgetting them");
+                    self.pending_requests.clear();
+                }
+                // both the sending and the receiving side carry the transaction, keep the first
+                self.pending_requests
+                    .entry(txn.debug_id)
+                    .or_insert(timestamp);
+            }
+            return None;
+        }
+        // in_reply_to_debug_id is only known with reply correlation
+        let sent = self.pending_requests.remove(&txn.in_reply_to_debug_id)?;
+        let latency = Duration::from_nanos(timestamp.saturating_sub(sent));
+        (latency >= threshold).then_some(latency)
+    }
+}
+
+fn failed_reply(data: &[u8]) -> Option<&'static str> {
+    let mut pos = 0;
+    while pos < data.len() {
+        let br = BinderReturn::from_bytes(&data[pos..]).ok()?;
+        match br {
+            BinderReturn::FailedReply => return Some("BR_FAILED_REPLY"),
+            BinderReturn::DeadReply => return Some("BR_DEAD_REPLY"),
+            _ => pos += br.size(),
+        }
+    }
+    None
+}
+
+// Same layout as binderdump-aidl's token parser, which is host-only: policy, work_source_uid
+// (sdk >= 29) and the 'SYS\0' marker (sdk >= 30), followed by the String16 descriptor.
+fn parse_aidl_token(data: &[u8], android_sdk: u32) -> Option<String> {
+    let mut off = 4;
+    if android_sdk >= 29 {
+        off += 4;
+    }
+    if android_sdk >= 30 {
+        off += 4;
+    }
+    let len = i32::from_le_bytes(data.get(off..off + 4)?.try_into().ok()?);
+    let len = usize::try_from(len).ok()?;
+    off += 4;
+    let units: Vec<u16> = data
+        .get(off..off + len.checked_mul(2)?)?
+        .chunks_exact(2)
+        .map(|chunk| u16::from_le_bytes([chunk[0], chunk[1]]))
+        .collect();
+    String::from_utf16(&units).ok()
+}
+
+// HIDL writes the descriptor as a NUL-terminated string
+fn parse_hidl_token(data: &[u8]) -> Option<String> {
+    let end = data.iter().position(|&b| b == 0)?;
+    if end == 0 {
+        return None;
+    }
+    std::str::from_utf8(&data[..end]).ok().map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet(timestamp_ms: u64, len: usize) -> EnhancedPacketBlock<'static> {
+        EnhancedPacketBlock {
+            interface_id: 0,
+            timestamp: Duration::from_millis(timestamp_ms),
+            original_len: len as u32,
+            data: vec![0; len].into(),
+            options: vec![],
+        }
+    }
+
+    #[test]
+    fn window_keeps_last_seconds() {
+        let mut recorder =
+            FlightRecorder::without_signal(Some(Duration::from_secs(1)), None, Triggers::default());
+        for ms in [0, 500, 1200, 1600] {
+            recorder.record(packet(ms, 10));
+        }
+        let window: Vec<_> = recorder.take_window().iter().map(|p| p.timestamp).collect();
+        assert_eq!(
+            window,
+            [Duration::from_millis(1200), Duration::from_millis(1600)]
+        );
+        assert_eq!(recorder.len(), 0);
+    }
+
+    #[test]
+    fn window_keeps_last_bytes() {
+        let mut recorder = FlightRecorder::without_signal(None, Some(25), Triggers::default());
+        for ms in 0..4 {
+            recorder.record(packet(ms, 10));
+        }
+        assert_eq!(recorder.len(), 2);
+        // a single packet larger than the window is still kept
+        recorder.record(packet(4, 100));
+        assert_eq!(recorder.len(), 1);
+    }
+
+    #[test]
+    fn dumps_start_with_the_metadata() {
+        let mut recorder = FlightRecorder::without_signal(None, Some(10), Triggers::default());
+        let mut proto = EventProtocol {
+            pid: 100,
+            tid: 101,
+            ..Default::default()
+        };
+        recorder.record_metadata(&proto, packet(0, 100));
+        let maps = EventProtocol {
+            pid: 100,
+            tid: 100,
+            maps: Some(Default::default()),
+            ..Default::default()
+        };
+        recorder.record_metadata(&maps, packet(0, 100));
+        recorder.record(packet(1, 10));
+        recorder.record(packet(2, 10));
+        let window: Vec<_> = recorder.take_window().iter().map(|p| p.timestamp).collect();
+        assert_eq!(
+            window,
+            [
+                Duration::from_millis(0),
+                Duration::from_millis(0),
+                Duration::from_millis(2)
+            ]
+        );
+
+        // the ProcessInfo is the process's, not the thread's
+        proto.event_type = EventType::DeadThread;
+        recorder.forget_dead(&proto);
+        assert_eq!(recorder.take_window().len(), 2);
+
+        proto.event_type = EventType::DeadProcess;
+        recorder.forget_dead(&proto);
+        assert!(recorder.take_window().is_empty());
+    }
+
+    #[test]
+    fn parses_transaction_triggers() {
+        let trigger: TransactionTrigger = "android.os.IServiceManager:0x2".parse().unwrap();
+        assert_eq!(trigger.interface, "android.os.IServiceManager");
+        assert_eq!(trigger.code, Some(2));
+
+        let trigger: TransactionTrigger =
+            "android.hidl.manager@1.0::IServiceManager".parse().unwrap();
+        assert_eq!(
+            trigger.interface,
+            "android.hidl.manager@1.0::IServiceManager"
+        );
+        assert_eq!(trigger.code, None);
+
+        let trigger: TransactionTrigger = "android.hidl.manager@1.0::IServiceManager:1"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            trigger.interface,
+            "android.hidl.manager@1.0::IServiceManager"
+        );
+        assert_eq!(trigger.code, Some(1));
+
+        assert!("IFoo:bar".parse::<TransactionTrigger>().is_err());
+    }
+
+    #[test]
+    fn finds_failed_reply_among_returns() {
+        let mut data = binderdump_sys::binder_driver_return_protocol_BR_NOOP
+            .to_ne_bytes()
+            .to_vec();
+        data.extend_from_slice(
+            &binderdump_sys::binder_driver_return_protocol_BR_DEAD_REPLY.to_ne_bytes(),
+        );
+        assert_eq!(failed_reply(&data), Some("BR_DEAD_REPLY"));
+        assert_eq!(failed_reply(&data[..4]), None);
+    }
+
+    #[test]
+    fn parses_aidl_descriptor() {
+        let mut data = vec![0u8; 12];
+        let descriptor: Vec<u16> = "IFoo".encode_utf16().collect();
+        data.extend_from_slice(&(descriptor.len() as i32).to_le_bytes());
+        for unit in descriptor {
+            data.extend_from_slice(&unit.to_le_bytes());
+        }
+        data.extend_from_slice(&[0, 0]);
+        assert_eq!(parse_aidl_token(&data, 30).as_deref(), Some("IFoo"));
+        assert_eq!(parse_aidl_token(&data[..16], 30), None);
+    }
+}
@@ -0,0 +1,118 @@
+// The binder objects of the transactions in flight, and the process that owns every node the
+// capture saw crossing a process boundary.
+//
+// The kernel traces the translations from the sender's context, right after the transaction, so
+// they are kept until the target reads the transaction and attached to its packet. A node is
+// owned by the process that first sent it (NodeToRef), or that it was sent back to (RefToNode).
+use super::pending::Pending;
+use crate::capture::events::BinderTransactionTranslation;
+use binderdump_structs::bwr_layer::{TranslationProtocol, TranslationType};
+use std::collections::HashMap;
+
+#[derive(Default)]
+pub struct Nodes {
+    // transaction debug id -> its translations, until its target reads it
+    pending: Pending<Vec<BinderTransactionTranslation>>,
+    // node debug id -> pid of the process that owns it
+    owners: HashMap<i32, i32>,
+}
+
+impl Nodes {
+    // `sender_pid` sent the transaction that carried the object
+    pub fn record(&mut self, sender_pid: i32, translation: BinderTransactionTranslation) {
+        if translation.translation_type == TranslationType::NodeToRef {
+            self.owners.insert(translation.node_debug_id, sender_pid);
+        }
+        self.pending.entry(translation.debug_id).push(translation);
+    }
+
+    // The translations of transaction `debug_id`, which `target_pid` just read
+    pub fn take(&mut self, debug_id: i32, target_pid: i32) -> Vec<TranslationProtocol> {
+        let Some(translations) = self.pending.take(debug_id) else {
+            return vec![];
+        };
+        translations
+            .into_iter()
+            .map(|translation| {
+                if translation.translation_type == TranslationType::RefToNode {
+                    self.owners.insert(translation.node_debug_id, target_pid);
+                }
+                TranslationProtocol {
+                    translation_type: translation.translation_type,
+                    node_debug_id: translation.node_debug_id,
+                    owner_pid: self
+                        .owners
+                        .get(&translation.node_debug_id)
+                        .copied()
+                        .unwrap_or_default(),
+                    src: translation.src,
+                    dest: translation.dest,
+                }
+            })
+            .collect()
+    }
+
+    // the nodes of a process die with it
+    pub fn forget_process(&mut self, pid: i32) {
+        self.owners.retain(|_, owner| *owner != pid);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn translation(
+        debug_id: i32,
+        translation_type: TranslationType,
+        node_debug_id: i32,
+        src: u64,
+        dest: u64,
+    ) -> BinderTransactionTranslation {
+        BinderTransactionTranslation {
+            debug_id,
+            translation_type,
+            node_debug_id,
+            src,
+            dest,
+        }
+    }
+
+    #[test]
+    fn owners_follow_the_node_across_processes() {
+        let mut nodes = Nodes::default();
+        // 100 registers a callback with 200, then 200 hands it to 300
+        nodes.record(
+            100,
+            translation(1, TranslationType::NodeToRef, 7, 0x7000, 3),
+        );
+        let received = nodes.take(1, 200);
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].owner_pid, 100);
+        assert_eq!(received[0].dest, 3);
+
+        nodes.record(200, translation(2, TranslationType::RefToRef, 7, 3, 5));
+        assert_eq!(nodes.take(2, 300)[0].owner_pid, 100);
+        // already taken
+        assert!(nodes.take(2, 300).is_empty());
+    }
+
+    #[test]
+    fn nodes_sent_back_are_owned_by_the_target() {
+        let mut nodes = Nodes::default();
+        // a node that crossed before the capture started
+        nodes.record(200, translation(1, TranslationType::RefToRef, 9, 4, 6));
+        assert_eq!(nodes.take(1, 300)[0].owner_pid, 0);
+        nodes.record(
+            300,
+            translation(2, TranslationType::RefToNode, 9, 6, 0x8000),
+        );
+        assert_eq!(nodes.take(2, 100)[0].owner_pid, 100);
+        nodes.record(200, translation(3, TranslationType::RefToRef, 9, 4, 8));
+        assert_eq!(nodes.take(3, 400)[0].owner_pid, 100);
+
+        nodes.forget_process(100);
+        nodes.record(200, translation(4, TranslationType::RefToRef, 9, 4, 8));
+        assert_eq!(nodes.take(4, 400)[0].owner_pid, 0);
+    }
+}
@@ -4,11 +4,24 @@ use super::builders::{
 };
 use super::capture_info::CaptureInfo;
 use super::events_aggregator::EventsAggregator;
+use super::fds::Fds;
+use super::flight_recorder::FlightRecorder;
+use super::nodes::Nodes;
+use super::priorities::Priorities;
+use super::rotation::Rotation;
+use super::wakeups::Wakeups;
 use crate::capture::{
-    events::{BinderEvent, BinderEventData, BinderEventWriteRead},
-    process_cache::ProcessCache,
+    devices::BinderDevices,
+    drops::CaptureDrops,
+    events::{
+        BinderEvent, BinderEventData, BinderEventExec, BinderEventNewProcess, BinderEventWriteRead,
+        TransactionCommand,
+    },
+    packages,
+    proc_maps::ProcessMaps,
+    process_cache::{ProcessCache, ProcessInfo},
+    raw_dump::RawDumpMapping,
     ringbuf::EventChannel,
-    system_property,
 };
 use anyhow::{Context, Result};
 use binderdump_structs::bwr_layer::Transaction;
@@ -22,8 +35,9 @@ use log::warn;
 use pcap_file::{
     pcapng::{
         blocks::{
-            enhanced_packet::EnhancedPacketBlock,
+            enhanced_packet::{EnhancedPacketBlock, EnhancedPacketOption},
             interface_description::{InterfaceDescriptionBlock, InterfaceDescriptionOption},
+            interface_statistics::{InterfaceStatisticsBlock, InterfaceStatisticsOption},
             section_header::{SectionHeaderBlock, SectionHeaderOption},
             PcapNgBlock,
         },
@@ -31,29 +45,102 @@ use pcap_file::{
     },
     DataLink,
 };
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::io::{Cursor, Write};
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use yansi::Paint;
 
 pub struct PacketGenerator<W: Write> {
     pcap_writer: PcapNgWriter<W>,
+    // kept to start every rotated file with the same framing
+    section_header: SectionHeaderBlock<'static>,
+    // one per binder device, the interface id of every packet is its EventProtocol::interface_id
+    interface_blocks: Vec<InterfaceDescriptionBlock<'static>>,
+    rotation: Option<Rotation<W>>,
+    flight_recorder: Option<FlightRecorder>,
     process_cache: ProcessCache,
+    // only with --stacks, or when replaying a dump of such a capture
+    process_maps: Option<ProcessMaps>,
+    // app ids of the uids a UidInfo packet was written for, with the uid it was written for
+    announced_uids: HashMap<u32, u32>,
     events_aggregator: Option<EventsAggregator>,
     ongoing_txn: HashMap<i32, Transaction>,
+    nodes: Nodes,
+    fds: Fds,
+    priorities: Priorities,
+    wakeups: Wakeups,
     timeshift: Duration,
     android_sdk: u32,
     // flush the underlying writer after the header and after every packet, so a
     // consumer reading the stream live (e.g. Wireshark on a pipe) sees packets as
     // they happen instead of waiting for the block buffer to fill.
     flush_each: bool,
+    // for the InterfaceStatisticsBlocks written at the end of the capture
+    start_time: u64,
+    packets_per_interface: Vec<u64>,
+    unhandled_events: u64,
+}
+
+// Bytes of the buffers in `proto` that weren't captured (--snaplen, --metadata-only, or a buffer
+// larger than what BPF copies), so the EPB original_len is the size the packet would have had
+fn missing_bytes(proto: &EventProtocol) -> u64 {
+    let Some(bwr) = proto
+        .ioctl_data
+        .as_ref()
+        .and_then(|ioctl| ioctl.bwr.as_ref())
+    else {
+        return 0;
+    };
+    let bwr_size = if bwr.is_read() {
+        bwr.read_consumed
+    } else {
+        bwr.write_size
+    };
+    let mut missing = bwr_size.saturating_sub(bwr.data.len() as u64);
+    if let Some(txn) = &bwr.transaction {
+        missing += txn.data_size.saturating_sub(txn.data.len() as u64);
+        missing += txn.offsets_size.saturating_sub(txn.offsets.len() as u64);
+        for payload in &txn.ptr_payloads {
+            missing += payload.total_size.saturating_sub(payload.data.len() as u64);
+        }
+    }
+    missing
+}
+
+// How often the timers (-G, SIGUSR1) run when no binder traffic comes in
+const IDLE_TICK: Duration = Duration::from_millis(250);
+
+// pcapng timestamps, in the nanosecond resolution we declare in every IDB
+fn pcapng_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64
 }
 
 impl<W: Write> PacketGenerator<W> {
     pub fn new(channel: EventChannel, writer: W, flush_each: bool) -> Result<Self> {
-        let capture_info = CaptureInfo::new()?;
+        let process_cache = ProcessCache::new(BinderDevices::discover());
+        Self::from_sources(
+            channel,
+            writer,
+            flush_each,
+            CaptureInfo::new()?,
+            process_cache,
+        )
+    }
+
+    // Like `new`, with the device and process information coming from somewhere else than the
+    // running system (e.g. a raw dump)
+    pub fn from_sources(
+        channel: EventChannel,
+        writer: W,
+        flush_each: bool,
+        capture_info: CaptureInfo,
+        process_cache: ProcessCache,
+    ) -> Result<Self> {
         let version_comment = format!("binderdump-version={}", env!("CARGO_PKG_VERSION"));
-        let options = vec![
+        let mut options = vec![
             SectionHeaderOption::OS(capture_info.get_os().to_string().into()),
             SectionHeaderOption::Hardware(capture_info.get_model().to_string().into()),
             SectionHeaderOption::UserApplication(capture_info.get_capture_app().to_string().into()),
@@ -61,47 +148,252 @@ impl<W: Write> PacketGenerator<W> {
             SectionHeaderOption::Comment(capture_info.get_fingerprint().to_string().into()),
             SectionHeaderOption::Comment(capture_info.get_kernel_version().to_string().into()),
         ];
+        if !capture_info.get_tiers().is_empty() {
+            let tiers_comment = format!("binderdump-tiers={}", capture_info.get_tiers().join(","));
+            options.push(SectionHeaderOption::Comment(tiers_comment.into()));
+        }
 
         let mut header = SectionHeaderBlock::default();
         header.options = options;
 
-        let mut pcap_writer = PcapNgWriter::with_section_header(writer, header)?;
-
+        let devices = process_cache.devices();
         let idb_description = format!("binderdump-version={}", env!("CARGO_PKG_VERSION"));
-        for interface in ["/dev/binder", "/dev/hwbinder", "/dev/vndbinder"] {
-            let interface_block = InterfaceDescriptionBlock {
+        let interface_blocks = devices
+            .iter()
+            .map(|device| InterfaceDescriptionBlock {
                 linktype: DataLink::WIRESHARK_UPPER_PDU,
                 snaplen: 0,
                 options: vec![
-                    InterfaceDescriptionOption::IfName(interface.into()),
+                    InterfaceDescriptionOption::IfName(
+                        device.path().to_string_lossy().into_owned().into(),
+                    ),
                     InterfaceDescriptionOption::IfDescription(idb_description.clone().into()),
                     // seems like the pcap-file library implicitly uses nanoseconds when writing Duration to a packet block,
                     // so we tell wireshark about it
                     // this seems to be fixed pcap-file 3.0.0-rc1
                     InterfaceDescriptionOption::IfTsResol(0x9),
                 ],
-            };
+            })
+            .collect::<Vec<_>>();
 
-            pcap_writer.write_pcapng_block(interface_block)?;
-        }
-
-        // Push the section header + interface blocks out immediately, so a live
-        // reader has the framing before the first packet arrives.
-        if flush_each {
-            pcap_writer.get_mut().flush()?;
-        }
+        let start_time = pcapng_now();
+        let pcap_writer = Self::start_output(writer, &header, &interface_blocks, flush_each)?;
 
         Ok(Self {
             pcap_writer,
-            process_cache: ProcessCache::new(),
+            section_header: header,
+            interface_blocks,
+            rotation: None,
+            flight_recorder: None,
+            packets_per_interface: vec![0; devices.len()],
+            process_cache,
+            process_maps: None,
+            announced_uids: HashMap::new(),
             events_aggregator: Some(EventsAggregator::new(channel)),
             ongoing_txn: HashMap::new(),
+            nodes: Nodes::default(),
+            fds: Fds::default(),
+            priorities: Priorities::default(),
+            wakeups: Wakeups::default(),
             timeshift: capture_info.get_timeshift().clone(),
-            android_sdk: system_property::read_sdk_int(),
+            android_sdk: capture_info.get_android_sdk(),
             flush_each,
+            start_time,
+            unhandled_events: 0,
         })
     }
 
+    // Start a new file every time `rotation` says the current one is due
+    pub fn with_rotation(mut self, rotation: Rotation<W>) -> Self {
+        self.rotation = Some(rotation);
+        self
+    }
+
+    // Write a ProcessMaps packet for every process before its first stack
+    pub fn with_process_maps(mut self, process_maps: ProcessMaps) -> Self {
+        self.process_maps = Some(process_maps);
+        self
+    }
+
+    // Keep packets in memory, and only write them when the recorder triggers
+    pub fn with_flight_recorder(mut self, flight_recorder: FlightRecorder) -> Self {
+        self.flight_recorder = Some(flight_recorder);
+        self
+    }
+
+    // Writes the section header and the interface blocks, so every output opens standalone
+    fn start_output(
+        writer: W,
+        section_header: &SectionHeaderBlock<'static>,
+        interface_blocks: &[InterfaceDescriptionBlock<'static>],
+        flush_each: bool,
+    ) -> Result<PcapNgWriter<W>> {
+        let mut pcap_writer = PcapNgWriter::with_section_header(writer, section_header.clone())?;
+        for interface_block in interface_blocks {
+            pcap_writer.write_pcapng_block(interface_block.clone())?;
+        }
+
+        // Push the section header + interface blocks out immediately, so a live
+        // reader has the framing before the first packet arrives.
+        if flush_each {
+            pcap_writer.get_mut().flush()?;
+        }
+        Ok(pcap_writer)
+    }
+
+    // `timestamp` is the one of the packet about to be written, or the current time when idle
+    fn rotate_if_due(&mut self, timestamp: Duration) -> Result<()> {
+        let Some(rotation) = self.rotation.as_mut() else {
+            return Ok(());
+        };
+        if !rotation.is_due() {
+            return Ok(());
+        }
+        let writer = rotation.open_next()?;
+        let pcap_writer = Self::start_output(
+            writer,
+            &self.section_header,
+            &self.interface_blocks,
+            self.flush_each,
+        )?;
+        let mut previous = std::mem::replace(&mut self.pcap_writer, pcap_writer);
+        previous
+            .get_mut()
+            .flush()
+            .context("failed to flush the previous output")?;
+        // the statistics of every file are its own
+        self.start_time = pcapng_now();
+        self.packets_per_interface.fill(0);
+
+        // the UidInfo, ProcessInfo and ProcessMaps packets we already wrote went to the previous
+        // files
+        let link_layer = link_layer::get_pdu_header();
+        let timestamp = timestamp.saturating_sub(self.timeshift).as_nanos() as u64;
+        let mut protos = vec![];
+        for uid in self.announced_uids.values().copied().collect::<Vec<_>>() {
+            let packages = self.process_cache.packages(uid).to_vec();
+            protos.push(self.uid_info_proto(timestamp, uid, &packages)?);
+        }
+        for (pid, tid, info) in self.process_cache.processes() {
+            protos.push(self.process_info_proto(timestamp, pid, tid, info)?);
+        }
+        for (pid, mappings) in self.process_maps.iter().flat_map(|maps| maps.iter()) {
+            protos.push(self.process_maps_proto(timestamp, pid, mappings)?);
+        }
+        for proto in protos {
+            let packet = self.packet_block(&proto, &link_layer)?;
+            let written = self.pcap_writer.write_block(&packet.into_block())?;
+            if let Some(rotation) = self.rotation.as_mut() {
+                rotation.record(written);
+            }
+        }
+        Ok(())
+    }
+
+    fn process_info_proto(
+        &self,
+        timestamp: u64,
+        pid: i32,
+        tid: i32,
+        info: &ProcessInfo,
+    ) -> Result<EventProtocol> {
+        EventProtocolBuilder::new(timestamp, pid, tid, self.android_sdk)
+            .event_type(EventType::ProcessInfo)
+            .process(info)
+            .build()
+    }
+
+    fn process_maps_proto(
+        &self,
+        timestamp: u64,
+        pid: i32,
+        mappings: &[RawDumpMapping],
+    ) -> Result<EventProtocol> {
+        EventProtocolBuilder::new(timestamp, pid, pid, self.android_sdk)
+            .event_type(EventType::ProcessMaps)
+            .maps(mappings)
+            .build()
+    }
+
+    fn uid_info_proto(
+        &self,
+        timestamp: u64,
+        uid: u32,
+        packages: &[String],
+    ) -> Result<EventProtocol> {
+        EventProtocolBuilder::new(timestamp, 0, 0, self.android_sdk)
+            .event_type(EventType::UidInfo)
+            .uid_info(uid, packages)
+            .build()
+    }
+
+    // UidInfo packets for the uids of `protos` whose packages weren't written yet
+    fn new_uid_infos<'a>(
+        &mut self,
+        timestamp: u64,
+        protos: impl Iterator<Item = &'a EventProtocol>,
+    ) -> Result<Vec<EventProtocol>> {
+        let mut uids = HashSet::new();
+        for proto in protos {
+            if let Some(ioctl) = &proto.ioctl_data {
+                uids.insert(ioctl.uid);
+                let txn = ioctl.bwr.as_ref().and_then(|bwr| bwr.transaction.as_ref());
+                if let Some(txn) = txn {
+                    uids.insert(txn.sender_euid);
+                }
+            }
+            if let Some(process_info) = &proto.process_info {
+                uids.insert(process_info.uid);
+            }
+        }
+        let mut uid_infos = vec![];
+        for uid in uids {
+            let app_id = packages::app_id(uid);
+            if self.announced_uids.contains_key(&app_id) {
+                continue;
+            }
+            // not marked as written, the package may be installed later
+            let packages = self.process_cache.packages(uid).to_vec();
+            if packages.is_empty() {
+                continue;
+            }
+            self.announced_uids.insert(app_id, uid);
+            uid_infos.push(self.uid_info_proto(timestamp, uid, &packages)?);
+        }
+        Ok(uid_infos)
+    }
+
+    // ProcessInfo packets for the processes the cache learned about since the last call
+    fn new_process_infos(&mut self, timestamp: u64) -> Result<Vec<EventProtocol>> {
+        let new_entries = self.process_cache.take_new_entries();
+        let mut protos = vec![];
+        for (pid, tid) in new_entries {
+            if let Some(info) = self.process_cache.get(pid, tid) {
+                protos.push(self.process_info_proto(timestamp, pid, tid, info)?);
+            }
+        }
+        Ok(protos)
+    }
+
+    // The ProcessMaps packet of the sender of `proto`, before the first stack of the process
+    fn new_process_maps(&mut self, proto: &EventProtocol) -> Result<Option<EventProtocol>> {
+        let has_stack = proto
+            .ioctl_data
+            .as_ref()
+            .and_then(|ioctl| ioctl.bwr.as_ref())
+            .and_then(|bwr| bwr.transaction.as_ref())
+            .is_some_and(|txn| !txn.user_stack.is_empty());
+        let Some(process_maps) = self.process_maps.as_mut().filter(|_| has_stack) else {
+            return Ok(None);
+        };
+        let Some(mappings) = process_maps.snapshot_once(proto.pid) else {
+            return Ok(None);
+        };
+        let mappings = mappings.to_vec();
+        self.process_maps_proto(proto.timestamp(), proto.pid, &mappings)
+            .map(Some)
+    }
+
     #[allow(unused)]
     fn print_events(events: &Vec<BinderEvent>) -> Result<()> {
         for event in events {
@@ -129,6 +421,15 @@ impl<W: Write> PacketGenerator<W> {
 
     fn handle_invalidate_process(&mut self, event: &BinderEvent) -> Result<EventProtocol> {
         let info = self.process_cache.invalidate_proc(event.pid, event.tid);
+        if event.pid == event.tid {
+            self.nodes.forget_process(event.pid);
+            self.priorities.forget_process(event.pid);
+            if let Some(process_maps) = self.process_maps.as_mut() {
+                process_maps.forget(event.pid);
+            }
+        }
+        // carries the metadata too, so a window or a file without the ProcessInfo packet still
+        // knows who died
         let mut builder =
             EventProtocolBuilder::new(event.timestamp, event.pid, event.tid, self.android_sdk)
                 .event_type(if event.pid == event.tid {
@@ -137,21 +438,64 @@ impl<W: Write> PacketGenerator<W> {
                     EventType::DeadThread
                 });
         if let Some(info) = info {
-            builder = builder
-                .cmdline(info.get_cmdline().into())
-                .comm(info.get_comm().into());
+            builder = builder.process(&info);
         };
         builder.build()
     }
 
+    // The child's packet, with what it inherited from the parent
+    fn handle_new_process(
+        &mut self,
+        event: &BinderEvent,
+        new_process: &BinderEventNewProcess,
+    ) -> Result<EventProtocol> {
+        let child_pid = new_process.child_pid;
+        let info = self.process_cache.fork(
+            event.pid,
+            event.tid,
+            child_pid,
+            &new_process.comm,
+            new_process.uid,
+            event.timestamp,
+        );
+        EventProtocolBuilder::new(event.timestamp, child_pid, child_pid, self.android_sdk)
+            .event_type(EventType::NewProcess)
+            .process(&info)
+            .new_process(event.pid, event.tid)
+            .build()
+    }
+
+    fn handle_exec(
+        &mut self,
+        event: &BinderEvent,
+        exec: &BinderEventExec,
+    ) -> Result<EventProtocol> {
+        let info = self.process_cache.exec(event.pid, exec.old_tid, exec);
+        if let Some(process_maps) = self.process_maps.as_mut() {
+            process_maps.forget(event.pid);
+        }
+        EventProtocolBuilder::new(event.timestamp, event.pid, event.tid, self.android_sdk)
+            .event_type(EventType::Exec)
+            .process(&info)
+            .exec(exec.old_tid, &exec.filename, &exec.argv)
+            .build()
+    }
+
     pub fn handle_events(&mut self, events: Vec<BinderEvent>) -> Result<EventProtocol> {
         let last_event = events.last().context("empty events vector")?;
         let timestamp = last_event.timestamp;
         let pid = last_event.pid;
         let tid = last_event.tid;
 
-        if last_event.is_invalidate_process() {
-            return self.handle_invalidate_process(last_event);
+        match &last_event.data {
+            BinderEventData::BinderInvalidateProcess => {
+                return self.handle_invalidate_process(last_event)
+            }
+            BinderEventData::BinderNewProcess(new_process) => {
+                return self.handle_new_process(last_event, new_process)
+            }
+            BinderEventData::BinderExec(exec) => return self.handle_exec(last_event, exec),
+            _ => (),
         }
 
         let mut builder = EventProtocolBuilder::new(timestamp, pid, tid, self.android_sdk);
@@ -159,6 +503,10 @@ impl<W: Write> PacketGenerator<W> {
         let mut bwr_builder = BinderWriteReadProtocolBuilder::new();
         let mut txn_builder = TransactionProtocolBuilder::new();
         let mut comm: Option<String> = None;
+        let mut read_only = false;
+        let mut command: Option<TransactionCommand> = None;
+        // a transaction received before the capture saw it being sent
+        let mut unseen_txn: Option<i32> = None;
 
         for event in events {
             match event.data {
@@ -167,6 +515,7 @@ impl<W: Write> PacketGenerator<W> {
                     break;
                 }
                 BinderEventData::BinderIoctl(ioctl) => {
+                    read_only = ioctl.read_only;
                     ioctl_builder = ioctl_builder.with_ioctl_event(&ioctl);
                     txn_builder = txn_builder.is_compat(ioctl.is_compat);
                     comm = Some(
@@ -189,9 +538,10 @@ impl<W: Write> PacketGenerator<W> {
                     // TransactionProtocol layer so the dissector shows them
                     // alongside the resolved/reassembled fields, not buried
                     // under the Commands array.
-                    match bwr_event.first_transaction_command_data() {
-                        Ok(Some(cmd_data)) => {
-                            txn_builder = txn_builder.command_data(cmd_data);
+                    match bwr_event.first_transaction_command() {
+                        Ok(Some(cmd)) => {
+                            txn_builder = txn_builder.command_data(cmd.data);
+                            command = Some(cmd);
                         }
                         Ok(None) => {}
                         Err(e) => {
@@ -199,13 +549,16 @@ impl<W: Write> PacketGenerator<W> {
                             // version added a new command that our enum doesn't model.
                             // The cmd_data plumbing is informational; the rest of the
                             // packet is still good.
-                            warn!("first_transaction_command_data: {:#}", e);
+                            warn!("first_transaction_command: {:#}", e);
                         }
                     }
                     bwr_builder = bwr_builder
                         .bwr_event(bwr_event)
                         .context("failed to parse bwr event")?
                 }
+                BinderEventData::BinderIoctlArg(arg) => {
+                    ioctl_builder = ioctl_builder.arg(arg);
+                }
                 BinderEventData::BinderIoctlDone(result) => {
                     ioctl_builder = ioctl_builder.result(result);
                     builder = builder.event_type(EventType::FinishedIoctl);
@@ -231,6 +584,7 @@ impl<W: Write> PacketGenerator<W> {
                         code: txn.code,
                         flags: txn.flags,
                     };
+                    self.wakeups.record_enqueue(txn.debug_id, event.timestamp);
                     if let Some(prev) = self.ongoing_txn.insert(txn.debug_id, recv_txn) {
                         return Err(anyhow::anyhow!(format!(
                             "Transaction {} is already ongoing",
@@ -242,8 +596,19 @@ impl<W: Write> PacketGenerator<W> {
                 BinderEventData::BinderTransactionReceived(txn_id) => {
                     match self.ongoing_txn.remove(&txn_id) {
                         Some(txn) => {
-                            txn_builder = txn_builder.transaction(txn, &mut self.process_cache)?
+                            txn_builder = txn_builder
+                                .transaction(txn, &mut self.process_cache)?
+                                .translations(self.nodes.take(txn_id, pid))
+                                .fds(self.fds.take(txn_id))
+                                .priorities(
+                                    self.priorities.take_caller(txn_id),
+                                    self.priorities.take_change(tid),
+                                )
+                                .wakeup(self.wakeups.take(txn_id))
                         }
+                        // A thread that was already waiting for work when the capture started,
+                        // rebuilt from the BR_ command once the whole read was seen
+                        None if read_only => unseen_txn = Some(txn_id),
                         None => {
                             // The matching `binder_transaction` was never observed by the
                             // BPF program. Most common cause: capture started after the
@@ -267,53 +632,212 @@ impl<W: Write> PacketGenerator<W> {
                 BinderEventData::BinderTransactionPtrData(chunk) => {
                     txn_builder = txn_builder.ptr_payload_chunk(chunk);
                 }
+                BinderEventData::BinderTransactionSecCtx(secctx) => {
+                    txn_builder = txn_builder.secctx(secctx);
+                }
+                BinderEventData::BinderTransactionUserStack(ips) => {
+                    txn_builder = txn_builder.user_stack(ips);
+                }
 
-                BinderEventData::BinderInvalidateProcess => unreachable!(),
+                BinderEventData::BinderInvalidateProcess
+                | BinderEventData::BinderNewProcess(_)
+                | BinderEventData::BinderExec(_)
+                | BinderEventData::BinderTransactionTranslation(_)
+                | BinderEventData::BinderTransactionFd(_)
+                | BinderEventData::BinderTransactionPrio(_)
+                | BinderEventData::BinderSetPriority(_)
+                | BinderEventData::BinderTransactionWakeup(_) => unreachable!(),
             }
         }
 
-        let info = self.process_cache.get_proc(pid, tid, comm.as_deref())?;
+        // the cmdline is in the process's ProcessInfo packet, written before this one
+        self.process_cache.get_proc(pid, tid, comm.as_deref())?;
 
         if let Some(comm) = comm {
             builder = builder.comm(comm);
         };
 
+        if let (Some(debug_id), Some(cmd)) = (unseen_txn, command) {
+            let txn = Transaction {
+                debug_id,
+                in_reply_to_debug_id: 0,
+                target_node: 0,
+                to_proc: pid,
+                to_thread: tid,
+                reply: cmd.is_reply as i32,
+                code: cmd.data.code,
+                flags: cmd.data.flags,
+            };
+            txn_builder = txn_builder.transaction(txn, &mut self.process_cache)?;
+        }
+
         let bwr = bwr_builder.transaction(txn_builder.build())?.build();
 
         let ioctl = ioctl_builder.bwr(bwr).build();
         if let Some(ioctl_data) = &ioctl {
-            if let Some(binder_interface) = info.get_binder_interface(ioctl_data.fd()) {
-                builder = builder.binder_interface(binder_interface);
+            let interface_id = self
+                .process_cache
+                .get_interface_id(pid, tid, ioctl_data.fd());
+            let device = interface_id.and_then(|id| self.process_cache.devices().get(id));
+            if let (Some(interface_id), Some(device)) = (interface_id, device) {
+                builder = builder
+                    .binder_interface(device.interface())
+                    .interface_id(interface_id);
             }
         }
 
-        builder
-            .cmdline(info.get_cmdline().into())
-            .ioctl_data(ioctl)
-            .build()
+        builder.partial(read_only).ioctl_data(ioctl).build()
     }
 
-    fn write_packet(&mut self, proto: EventProtocol, link: &[u8]) -> Result<()> {
+    fn packet_block(
+        &self,
+        proto: &EventProtocol,
+        link: &[u8],
+    ) -> Result<EnhancedPacketBlock<'static>> {
         let mut cursor = Cursor::new(Vec::new());
         cursor.write_all(link)?;
-        binder_serde::write(&mut cursor, &proto)?;
+        binder_serde::write(&mut cursor, proto)?;
         let data = cursor.into_inner();
+        let original_len = data.len() as u64 + missing_bytes(proto);
 
-        let packet = EnhancedPacketBlock {
-            interface_id: proto.binder_interface() as u32,
+        Ok(EnhancedPacketBlock {
+            interface_id: proto.interface_id(),
             timestamp: Duration::from_nanos(proto.timestamp()) + self.timeshift,
-            original_len: data.len() as u32,
+            original_len: u32::try_from(original_len).unwrap_or(u32::MAX),
             data: data.into(),
             options: vec![],
+        })
+    }
+
+    fn write_packet(&mut self, proto: EventProtocol, link: &[u8]) -> Result<()> {
+        let mut packet = self.packet_block(&proto, link)?;
+        let is_metadata = matches!(
+            proto.event_type,
+            EventType::ProcessInfo | EventType::UidInfo | EventType::ProcessMaps
+        );
+        // not traffic of the interface
+        if !is_metadata {
+            let interface_id = proto.interface_id() as usize;
+            if let Some(count) = self.packets_per_interface.get_mut(interface_id) {
+                *count += 1;
+            }
+        }
+
+        let Some(flight_recorder) = self.flight_recorder.as_mut() else {
+            return self.write_block(packet);
+        };
+        // kept outside of the window, every dump needs the metadata of its threads
+        if is_metadata {
+            flight_recorder.record_metadata(&proto, packet);
+            return Ok(());
+        }
+        flight_recorder.forget_dead(&proto);
+        let trigger = flight_recorder.check(&proto);
+        if let Some(trigger) = &trigger {
+            // mark the packet that fired, so it is easy to find in the dump
+            let comment = format!("flight recorder trigger: {}", trigger);
+            packet
+                .options
+                .push(EnhancedPacketOption::Comment(comment.into()));
+        }
+        flight_recorder.record(packet);
+        match trigger {
+            Some(trigger) => self.dump_window(&trigger),
+            None => Ok(()),
+        }
+    }
+
+    fn dump_window(&mut self, trigger: &str) -> Result<()> {
+        let Some(flight_recorder) = self.flight_recorder.as_mut() else {
+            return Ok(());
         };
-        self.pcap_writer.write_block(&packet.into_block())?;
+        eprintln!(
+            "{}, writing the last {} packets",
+            trigger,
+            flight_recorder.len()
+        );
+        for packet in flight_recorder.take_window() {
+            self.write_block(packet)?;
+        }
+        Ok(())
+    }
+
+    fn write_block(&mut self, packet: EnhancedPacketBlock<'static>) -> Result<()> {
+        self.rotate_if_due(packet.timestamp)?;
+
+        let written = self.pcap_writer.write_block(&packet.into_block())?;
+        if let Some(rotation) = self.rotation.as_mut() {
+            rotation.record(written);
+        }
         if self.flush_each {
             self.pcap_writer.get_mut().flush()?;
         }
         Ok(())
     }
 
-    pub fn capture(&mut self, duration: Option<Duration>) -> Result<()> {
+    // Reports the events lost during the capture, as InterfaceStatisticsBlocks (Wireshark shows
+    // them in Statistics > Capture File Properties) and on stderr.
+    fn write_statistics(&mut self, events_aggregator: &EventsAggregator) -> Result<()> {
+        let mut drops = match events_aggregator.channel().drops() {
+            Ok(drops) => drops,
+            Err(err) => {
+                warn!("failed to read the BPF drop counters: {:#}", err);
+                CaptureDrops::default()
+            }
+        };
+        drops.orphan_events = events_aggregator.orphan_events();
+        drops.unhandled_events = self.unhandled_events;
+
+        let end_time = pcapng_now();
+        for (interface_id, received) in self.packets_per_interface.iter().enumerate() {
+            let mut options = vec![
+                InterfaceStatisticsOption::IsbStartTime(self.start_time),
+                InterfaceStatisticsOption::IsbEndTime(end_time),
+                InterfaceStatisticsOption::IsbIfRecv(*received),
+            ];
+            // Most drops happen before we know which binder device the event belonged to, so
+            // they are all reported on the first interface. That keeps Wireshark's total right.
+            if interface_id == 0 {
+                options.push(InterfaceStatisticsOption::IsbIfDrop(drops.lost()));
+                options.push(InterfaceStatisticsOption::Comment(drops.to_string().into()));
+            } else {
+                options.push(InterfaceStatisticsOption::IsbIfDrop(0));
+            }
+            let block = InterfaceStatisticsBlock {
+                interface_id: interface_id as u32,
+                timestamp: end_time,
+                options,
+            };
+            self.pcap_writer.write_pcapng_block(block)?;
+        }
+        if self.flush_each {
+            self.pcap_writer.get_mut().flush()?;
+        }
+
+        eprintln!("{}", drops);
+        Ok(())
+    }
+
+    // No event for IDLE_TICK, a capture without traffic still rotates on time and dumps the flight
+    // recorder on SIGUSR1
+    fn on_idle(&mut self) -> Result<()> {
+        self.rotate_if_due(Duration::from_nanos(pcapng_now()))?;
+        let requested = self
+            .flight_recorder
+            .as_ref()
+            .and_then(|flight_recorder| flight_recorder.dump_requested());
+        if let Some(trigger) = requested {
+            self.dump_window(&trigger)?;
+        }
+        if self.flush_each {
+            self.pcap_writer.get_mut().flush()?;
+        }
+        Ok(())
+    }
+
+    // Stops after `duration`, after `max_packets` packets, or on SIGINT/SIGTERM. Except for the
+    // packet limit, the ioctls still in flight at that point are written as TruncatedIoctl packets.
+    pub fn capture(&mut self, duration: Option<Duration>, max_packets: Option<u64>) -> Result<()> {
         let link_layer = link_layer::get_pdu_header();
         let events_aggregator = self.events_aggregator.take();
         let mut events_aggregator = match events_aggregator {
@@ -327,19 +851,89 @@ impl<W: Write> PacketGenerator<W> {
         if let Some(d) = duration {
             events_aggregator.set_deadline(Instant::now() + d);
         }
-        for events in events_aggregator {
-            let str = format!("{:#?}", events);
-            let proto = match self.handle_events(events) {
-                Ok(proto) => proto,
-                Err(err) => {
-                    // diagnostics go to stderr so they never corrupt a pcapng stream on stdout.
-                    eprintln!("Failed to handle events: {}", err);
-                    eprintln!("events: {}", str);
-                    continue;
-                }
-            };
-            self.write_packet(proto, &link_layer)?;
+        events_aggregator.set_tick(IDLE_TICK);
+        let mut packets = 0;
+        for events in &mut events_aggregator {
+            if events.is_empty() {
+                self.on_idle()?;
+                continue;
+            }
+            if self.handle_and_write(events, &link_layer, false)? {
+                packets += 1;
+            }
+            if max_packets.is_some_and(|max| packets >= max) {
+                break;
+            }
         }
-        Ok(())
+        if max_packets.map_or(true, |max| packets < max) {
+            for events in events_aggregator.drain_ongoing() {
+                self.handle_and_write(events, &link_layer, true)?;
+            }
+        }
+        self.write_statistics(&events_aggregator)
+    }
+
+    // Returns whether a packet was written
+    // The events traced apart from the transaction they belong to are kept for the packet of the
+    // receiving side. false for the others.
+    fn keep_for_receiver(&mut self, event: &BinderEvent) -> bool {
+        match &event.data {
+            BinderEventData::BinderTransactionTranslation(translation) => {
+                self.nodes.record(event.pid, translation.clone())
+            }
+            BinderEventData::BinderTransactionFd(fd) => self.fds.record(fd.clone()),
+            BinderEventData::BinderTransactionPrio(prio) => {
+                self.priorities.record_caller(prio.clone())
+            }
+            BinderEventData::BinderSetPriority(change) => {
+                self.priorities
+                    .record_change(event.pid, event.tid, change.clone())
+            }
+            BinderEventData::BinderTransactionWakeup(wakeup) => {
+                self.wakeups.record_wakeup(wakeup.clone())
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    fn handle_and_write(
+        &mut self,
+        events: Vec<BinderEvent>,
+        link_layer: &[u8],
+        truncated: bool,
+    ) -> Result<bool> {
+        if let [event] = events.as_slice() {
+            if self.keep_for_receiver(event) {
+                return Ok(false);
+            }
+        }
+        let str = format!("{:#?}", events);
+        let mut proto = match self.handle_events(events) {
+            Ok(proto) => proto,
+            Err(err) => {
+                // diagnostics go to stderr so they never corrupt a pcapng stream on stdout.
+                eprintln!("Failed to handle events: {}", err);
+                eprintln!("events: {}", str);
+                self.unhandled_events += 1;
+                return Ok(false);
+            }
+        };
+        if truncated {
+            proto.event_type = EventType::TruncatedIoctl;
+        }
+        let process_infos = self.new_process_infos(proto.timestamp())?;
+        let uid_infos =
+            self.new_uid_infos(proto.timestamp(), process_infos.iter().chain([&proto]))?;
+        let process_maps = self.new_process_maps(&proto)?;
+        for metadata in uid_infos
+            .into_iter()
+            .chain(process_infos)
+            .chain(process_maps)
+        {
+            self.write_packet(metadata, link_layer)?;
+        }
+        self.write_packet(proto, link_layer)?;
+        Ok(true)
     }
 }
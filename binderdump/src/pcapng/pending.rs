@@ -0,0 +1,69 @@
+// What the capture learned about a transaction in flight before its target read it, by debug id.
+//
+// Transactions that are never read (dead targets, failed transactions) don't keep theirs forever:
+// past MAX_PENDING transactions, the oldest is dropped. Debug ids only grow, the smallest is the
+// oldest.
+use std::collections::HashMap;
+
+const MAX_PENDING: usize = 4096;
+
+pub struct Pending<T> {
+    by_debug_id: HashMap<i32, T>,
+}
+
+impl<T> Default for Pending<T> {
+    fn default() -> Self {
+        Self {
+            by_debug_id: HashMap::new(),
+        }
+    }
+}
+
+impl<T> Pending<T> {
+    fn make_room(&mut self, debug_id: i32) {
+        if self.by_debug_id.contains_key(&debug_id) || self.by_debug_id.len() < MAX_PENDING {
+            return;
+        }
+        if let Some(&oldest) = self.by_debug_id.keys().min() {
+            self.by_debug_id.remove(&oldest);
+        }
+    }
+
+    pub fn insert(&mut self, debug_id: i32, value: T) {
+        self.make_room(debug_id);
+        self.by_debug_id.insert(debug_id, value);
+    }
+
+    // The value of transaction `debug_id`, for the sides traced in more than one event
+    pub fn entry(&mut self, debug_id: i32) -> &mut T
+    where
+        T: Default,
+    {
+        self.make_room(debug_id);
+        self.by_debug_id.entry(debug_id).or_default()
+    }
+
+    // Transaction `debug_id` was read, it is not pending anymore
+    pub fn take(&mut self, debug_id: i32) -> Option<T> {
+        self.by_debug_id.remove(&debug_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drops_the_oldest_transaction() {
+        let mut pending = Pending::default();
+        for debug_id in 1..=MAX_PENDING as i32 {
+            *pending.entry(debug_id) = debug_id;
+        }
+        // already pending, nothing is dropped
+        *pending.entry(2) += 10;
+        *pending.entry(MAX_PENDING as i32 + 1) = 0;
+        assert_eq!(pending.take(1), None);
+        assert_eq!(pending.take(2), Some(12));
+        assert_eq!(pending.take(MAX_PENDING as i32 + 1), Some(0));
+    }
+}
@@ -0,0 +1,112 @@
+// The scheduling side of the transactions in flight.
+//
+// The scheduling class of the caller is traced from its context when it sends the transaction.
+// Binder then hands the caller's priority to the thread that takes it, either right away from the
+// caller's context or once the thread picks the transaction up, and restores the thread's own
+// priority after it replied; every change is traced with the thread it was made to. Both are kept
+// until the receiving side's packet is written.
+use super::pending::Pending;
+use crate::capture::events::{BinderSetPriority, BinderTransactionPrio};
+use binderdump_structs::bwr_layer::{InheritedPrioProtocol, SchedPolicy, SchedPrioProtocol};
+use num::FromPrimitive;
+use std::collections::HashMap;
+
+#[derive(Default)]
+pub struct Priorities {
+    // transaction debug id -> the caller's, until its target reads it
+    callers: Pending<SchedPrioProtocol>,
+    // tid -> the pid of the thread and the last change binder made to its priority
+    changes: HashMap<i32, (i32, InheritedPrioProtocol)>,
+}
+
+impl Priorities {
+    pub fn record_caller(&mut self, prio: BinderTransactionPrio) {
+        // SCHED_EXT and whatever comes after it
+        let Some(policy) = SchedPolicy::from_u32(prio.policy) else {
+            return;
+        };
+        self.callers.insert(
+            prio.debug_id,
+            SchedPrioProtocol {
+                policy,
+                prio: prio.prio,
+            },
+        );
+    }
+
+    pub fn record_change(&mut self, pid: i32, tid: i32, change: BinderSetPriority) {
+        let change = InheritedPrioProtocol {
+            old_prio: change.old_prio,
+            desired_prio: change.desired_prio,
+            new_prio: change.new_prio,
+        };
+        self.changes.insert(tid, (pid, change));
+    }
+
+    // The caller of transaction `debug_id`, which its target just read
+    pub fn take_caller(&mut self, debug_id: i32) -> Option<SchedPrioProtocol> {
+        self.callers.take(debug_id)
+    }
+
+    // The priority thread `tid` read its transaction with. A thread that wasn't changed since it
+    // read the previous one keeps what it had, which is not a change of its own.
+    pub fn take_change(&mut self, tid: i32) -> Option<InheritedPrioProtocol> {
+        self.changes.remove(&tid).map(|(_, change)| change)
+    }
+
+    pub fn forget_process(&mut self, pid: i32) {
+        self.changes.retain(|_, (change_pid, _)| *change_pid != pid);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn change(old_prio: u32, desired_prio: u32, new_prio: u32) -> BinderSetPriority {
+        BinderSetPriority {
+            old_prio,
+            desired_prio,
+            new_prio,
+        }
+    }
+
+    #[test]
+    fn the_last_change_is_the_inherited_one() {
+        let mut priorities = Priorities::default();
+        priorities.record_caller(BinderTransactionPrio {
+            debug_id: 7,
+            policy: 1,
+            prio: 97,
+        });
+        // restored when the thread went back to waiting, then given the caller's
+        priorities.record_change(100, 101, change(110, 120, 120));
+        priorities.record_change(100, 101, change(120, 97, 97));
+
+        let caller = priorities.take_caller(7).unwrap();
+        assert_eq!(caller.policy, SchedPolicy::SCHED_FIFO);
+        assert_eq!(caller.prio, 97);
+        let inherited = priorities.take_change(101).unwrap();
+        assert_eq!(inherited.old_prio, 120);
+        assert_eq!(inherited.new_prio, 97);
+        assert!(!inherited.is_below(&caller));
+
+        assert!(priorities.take_caller(7).is_none());
+        assert!(priorities.take_change(101).is_none());
+    }
+
+    #[test]
+    fn unknown_policies_and_dead_processes_are_dropped() {
+        let mut priorities = Priorities::default();
+        priorities.record_caller(BinderTransactionPrio {
+            debug_id: 8,
+            policy: 7,
+            prio: 120,
+        });
+        assert!(priorities.take_caller(8).is_none());
+
+        priorities.record_change(100, 101, change(120, 97, 97));
+        priorities.forget_process(100);
+        assert!(priorities.take_change(101).is_none());
+    }
+}
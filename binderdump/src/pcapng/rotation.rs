@@ -0,0 +1,154 @@
+// Output rotation for long captures (-C, -G and -W). `PacketGenerator` asks `Rotation` whether the
+// current output is due before every packet and while idle, and starts a new standalone pcapng
+// when it is.
+use anyhow::{Context, Result};
+use log::warn;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+pub struct Rotation<W> {
+    max_bytes: Option<u64>,
+    max_age: Option<Duration>,
+    open_next: Box<dyn FnMut() -> Result<W>>,
+    bytes_written: u64,
+    opened_at: Instant,
+}
+
+impl<W> Rotation<W> {
+    // `open_next` is called every time the current output is due, and must return the next one
+    pub fn new(
+        max_bytes: Option<u64>,
+        max_age: Option<Duration>,
+        open_next: impl FnMut() -> Result<W> + 'static,
+    ) -> Self {
+        Self {
+            max_bytes,
+            max_age,
+            open_next: Box::new(open_next),
+            bytes_written: 0,
+            opened_at: Instant::now(),
+        }
+    }
+
+    pub(super) fn is_due(&self) -> bool {
+        self.max_bytes.is_some_and(|max| self.bytes_written >= max)
+            || self
+                .max_age
+                .is_some_and(|max| self.opened_at.elapsed() >= max)
+    }
+
+    pub(super) fn record(&mut self, bytes: usize) {
+        self.bytes_written += bytes as u64;
+    }
+
+    pub(super) fn open_next(&mut self) -> Result<W> {
+        let writer = (self.open_next)()?;
+        self.bytes_written = 0;
+        self.opened_at = Instant::now();
+        Ok(writer)
+    }
+}
+
+// Names the rotated files after the -w path (out.pcapng -> out_00000.pcapng, out_00001.pcapng,
+// ...), and keeps only the newest `max_files` of them when set.
+pub struct FileRing {
+    path: PathBuf,
+    max_files: Option<usize>,
+    next_index: u64,
+    files: VecDeque<PathBuf>,
+}
+
+impl FileRing {
+    pub fn new(path: impl Into<PathBuf>, max_files: Option<usize>) -> Self {
+        Self {
+            path: path.into(),
+            max_files,
+            next_index: 0,
+            files: VecDeque::new(),
+        }
+    }
+
+    fn file_path(path: &Path, index: u64) -> PathBuf {
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let name = match path.extension() {
+            Some(ext) => format!("{}_{:05}.{}", stem, index, ext.to_string_lossy()),
+            None => format!("{}_{:05}", stem, index),
+        };
+        path.with_file_name(name)
+    }
+
+    pub fn open_next(&mut self) -> Result<File> {
+        let path = Self::file_path(&self.path, self.next_index);
+        let file = File::create(&path)
+            .with_context(|| format!("failed to open output file: {}", path.display()))?;
+        self.next_index += 1;
+        self.files.push_back(path);
+
+        if let Some(max_files) = self.max_files {
+            while self.files.len() > max_files.max(1) {
+                let oldest = self.files.pop_front().unwrap();
+                if let Err(err) = std::fs::remove_file(&oldest) {
+                    warn!("failed to remove {}: {}", oldest.display(), err);
+                }
+            }
+        }
+        Ok(file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_files_after_the_output_path() {
+        let path = Path::new("/data/local/tmp/out.pcapng");
+        assert_eq!(
+            FileRing::file_path(path, 3),
+            Path::new("/data/local/tmp/out_00003.pcapng")
+        );
+        assert_eq!(
+            FileRing::file_path(Path::new("capture"), 12),
+            Path::new("capture_00012")
+        );
+    }
+
+    #[test]
+    fn ring_keeps_newest_files() {
+        let dir = std::env::temp_dir().join(format!("binderdump_ring_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut ring = FileRing::new(dir.join("out.pcapng"), Some(2));
+        for _ in 0..4 {
+            ring.open_next().unwrap();
+        }
+        let mut names: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["out_00002.pcapng", "out_00003.pcapng"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn due_on_size() {
+        let mut rotation = Rotation::new(Some(100), None, || Ok(()));
+        assert!(!rotation.is_due());
+        rotation.record(60);
+        assert!(!rotation.is_due());
+        rotation.record(40);
+        assert!(rotation.is_due());
+        rotation.open_next().unwrap();
+        assert!(!rotation.is_due());
+    }
+
+    #[test]
+    fn due_on_age() {
+        let rotation = Rotation::new(None, Some(Duration::ZERO), || Ok(()));
+        assert!(rotation.is_due());
+        let rotation = Rotation::new(None, Some(Duration::from_secs(3600)), || Ok(()));
+        assert!(!rotation.is_due());
+    }
+}
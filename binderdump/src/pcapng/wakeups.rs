@@ -0,0 +1,83 @@
+// When the transactions in flight were sent, and when the thread that reads them was woken and got
+// on a CPU (--wakeup-latency).
+//
+// The sender's BinderTransaction and the receiving thread's wakeup come from different contexts
+// and may be handled in any order, both are kept until the receiving side's packet is written.
+// BPF only knows that a thread waiting for work was woken, not what for: a thread woken for
+// something else that finds the transaction without sleeping again reports the older wakeup,
+// which is dropped here.
+use super::pending::Pending;
+use crate::capture::events::BinderTransactionWakeup;
+use binderdump_structs::bwr_layer::WakeupProtocol;
+
+#[derive(Default)]
+pub struct Wakeups {
+    // transaction debug id -> the timestamp of its BinderTransaction
+    enqueued: Pending<u64>,
+    // transaction debug id -> the wakeup of the thread that read it
+    woken: Pending<BinderTransactionWakeup>,
+}
+
+impl Wakeups {
+    pub fn record_enqueue(&mut self, debug_id: i32, timestamp: u64) {
+        self.enqueued.insert(debug_id, timestamp);
+    }
+
+    pub fn record_wakeup(&mut self, wakeup: BinderTransactionWakeup) {
+        self.woken.insert(wakeup.debug_id, wakeup);
+    }
+
+    // The wakeup of the thread that just read transaction `debug_id`
+    pub fn take(&mut self, debug_id: i32) -> Option<WakeupProtocol> {
+        let enqueued = self.enqueued.take(debug_id);
+        let wakeup = self.woken.take(debug_id)?;
+        let enqueued = enqueued?;
+        if wakeup.woken < enqueued {
+            return None;
+        }
+        Some(WakeupProtocol {
+            enqueued,
+            woken: wakeup.woken,
+            on_cpu: wakeup.on_cpu,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wakeup(debug_id: i32, woken: u64, on_cpu: u64) -> BinderTransactionWakeup {
+        BinderTransactionWakeup {
+            debug_id,
+            woken,
+            on_cpu,
+        }
+    }
+
+    #[test]
+    fn joins_the_send_and_the_wakeup() {
+        let mut wakeups = Wakeups::default();
+        // the receiving side's wakeup can be handled before the sender's packet
+        wakeups.record_wakeup(wakeup(7, 1_500, 3_500));
+        wakeups.record_enqueue(7, 1_000);
+
+        let wakeup = wakeups.take(7).unwrap();
+        assert_eq!(wakeup.queued_ns(), 500);
+        assert_eq!(wakeup.runnable_ns(), 2_000);
+        assert!(wakeups.take(7).is_none());
+    }
+
+    #[test]
+    fn drops_wakeups_from_before_the_send() {
+        let mut wakeups = Wakeups::default();
+        wakeups.record_enqueue(8, 2_000);
+        wakeups.record_wakeup(wakeup(8, 1_500, 1_600));
+        assert!(wakeups.take(8).is_none());
+
+        // a thread that never slept has no wakeup, the send doesn't stay behind
+        wakeups.record_enqueue(9, 3_000);
+        assert!(wakeups.take(9).is_none());
+        assert!(wakeups.enqueued.take(9).is_none());
+    }
+}
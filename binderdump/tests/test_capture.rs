@@ -7,14 +7,24 @@ use std::path::PathBuf;
 use std::time::{Duration, Instant};
 
 use anyhow::Result;
+use binderdump::capture::filter::CaptureFilter;
+use binderdump::capture::limits::CaptureLimits;
 use binderdump::capture::ringbuf::create_events_channel;
+use binderdump::capture::snaplen::Snaplen;
 use binderdump::capture::tracepoints::{attach_tracepoints, ReplyCorrelationMode};
 use binderdump::pcapng::packets::PacketGenerator;
 
 #[test]
 fn capture_for_one_second_terminates() -> Result<()> {
-    let mut binder_skel = attach_tracepoints(ReplyCorrelationMode::Auto)?;
-    let event_channel = create_events_channel(&mut binder_skel)?;
+    let (mut binder_skel, _) = attach_tracepoints(
+        ReplyCorrelationMode::Auto,
+        &CaptureFilter::default(),
+        Snaplen::default(),
+        false,
+        false,
+        CaptureLimits::default(),
+    )?;
+    let event_channel = create_events_channel(&mut binder_skel, &CaptureLimits::default(), None)?;
 
     let path = PathBuf::from("/data/local/tmp/binderdump_capture_test.pcapng");
     let output = std::fs::File::create(&path)?;
@@ -22,7 +32,7 @@ fn capture_for_one_second_terminates() -> Result<()> {
     let mut packets = PacketGenerator::new(event_channel, output, false)?;
 
     let start = Instant::now();
-    packets.capture(Some(Duration::from_secs(1)))?;
+    packets.capture(Some(Duration::from_secs(1)), None)?;
     let elapsed = start.elapsed();
 
     assert!(
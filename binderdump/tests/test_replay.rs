@@ -0,0 +1,169 @@
+// Replays a small raw dump through the capture pipeline, without BPF, so it runs on any host.
+use anyhow::Result;
+use binderdump::capture::common_types::{
+    binder_event, binder_event_ioctl, binder_event_ioctl_done, binder_process_state_t,
+    binder_process_state_t_BINDER_IOCTL, binder_process_state_t_BINDER_IOCTL_DONE,
+};
+use binderdump::capture::packages::Packages;
+use binderdump::capture::process_cache::ProcessCache;
+use binderdump::capture::raw_dump::{
+    RawDumpDevice, RawDumpFd, RawDumpHeader, RawDumpPackage, RawDumpProcess, RawDumpReader,
+    RawDumpWriter,
+};
+use binderdump::capture::ringbuf::replay_events_channel;
+use binderdump::pcapng::capture_info::CaptureInfo;
+use binderdump::pcapng::packets::PacketGenerator;
+use binderdump_structs::binder_types::binder_ioctl;
+use pcap_file::pcapng::{blocks::section_header::SectionHeaderOption, Block, PcapNgReader};
+
+// A struct binder_event, followed by the struct of its type, the way the BPF program writes them
+fn event<T>(kind: binder_process_state_t, pid: i32, tid: i32, timestamp: u64, body: &T) -> Vec<u8> {
+    let header = binder_event {
+        type_: kind,
+        pid,
+        tid,
+        size: 0,
+        timestamp,
+    };
+    let mut data = unsafe { plain::as_bytes(&header) }.to_vec();
+    data.extend_from_slice(unsafe { plain::as_bytes(body) });
+    data
+}
+
+fn ioctl_event(pid: i32, tid: i32, timestamp: u64, fd: i32, cmd: u32) -> Vec<u8> {
+    let mut comm = [0; 16];
+    for (dst, src) in comm.iter_mut().zip(b"servicem") {
+        *dst = *src as _;
+    }
+    let ioctl = binder_event_ioctl {
+        fd,
+        comm,
+        uid: 1000,
+        gid: 1000,
+        cmd,
+        arg: 0,
+        read_only: 0,
+        is_compat: 0,
+    };
+    event(
+        binder_process_state_t_BINDER_IOCTL,
+        pid,
+        tid,
+        timestamp,
+        &ioctl,
+    )
+}
+
+fn ioctl_done_event(pid: i32, tid: i32, timestamp: u64) -> Vec<u8> {
+    let done = binder_event_ioctl_done { ret: 0 };
+    event(
+        binder_process_state_t_BINDER_IOCTL_DONE,
+        pid,
+        tid,
+        timestamp,
+        &done,
+    )
+}
+
+#[test]
+fn replays_a_raw_dump() -> Result<()> {
+    let dir = std::env::temp_dir().join(format!("binderdump_replay_{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+    let dump_path = dir.join("capture.raw");
+    let output_path = dir.join("capture.pcapng");
+
+    let header = RawDumpHeader {
+        model: "Pixel 7".into(),
+        os: "Android 14".into(),
+        fingerprint: "google/panther/panther:14/UQ1A".into(),
+        kernel_version: "Linux version 5.10.157".into(),
+        timeshift_ns: 1_700_000_000_000_000_000,
+        android_sdk: 34,
+        devices: vec![
+            RawDumpDevice {
+                path: "/dev/binder".into(),
+                aliases: vec![],
+                rdev: None,
+            },
+            RawDumpDevice {
+                path: "/dev/hwbinder".into(),
+                aliases: vec![],
+                rdev: None,
+            },
+        ],
+        tiers: vec!["core".into(), "txn_data".into(), "reply_stack".into()],
+    };
+    let writer = RawDumpWriter::create(&dump_path, &header)?;
+    let cmd = binder_ioctl::BINDER_SET_MAX_THREADS as u32;
+    writer.write_event(&ioctl_event(100, 100, 1_000, 7, cmd));
+    writer.write_event(&ioctl_done_event(100, 100, 2_000));
+    writer.write_process(&RawDumpProcess {
+        pid: 100,
+        tid: 100,
+        cmdline: "/system/bin/servicemanager".into(),
+        comm: "servicem".into(),
+        uid: 1000,
+        start_time: 500,
+        binder_fds: vec![RawDumpFd {
+            fd: 7,
+            interface_id: 1,
+        }],
+        selinux: "u:r:servicemanager:s0".into(),
+    });
+    writer.write_packages(&[RawDumpPackage {
+        name: "android".into(),
+        app_id: 1000,
+    }]);
+    writer.flush()?;
+
+    let dump = RawDumpReader::open(&dump_path)?;
+    let process_cache =
+        ProcessCache::from_raw_dump(dump.header().binder_devices(), dump.processes()?)
+            .with_packages(Packages::from_list(&dump.packages()?));
+    let mut packets = PacketGenerator::from_sources(
+        replay_events_channel(dump.events()?),
+        std::fs::File::create(&output_path)?,
+        false,
+        CaptureInfo::from_raw_dump(&dump),
+        process_cache,
+    )?;
+    packets.capture(None, None)?;
+    drop(packets);
+
+    let mut reader = PcapNgReader::new(std::fs::File::open(&output_path)?)?;
+    let hardware = reader
+        .section()
+        .options
+        .iter()
+        .find_map(|option| match option {
+            SectionHeaderOption::Hardware(model) => Some(model.to_string()),
+            _ => None,
+        });
+    assert_eq!(hardware.as_deref(), Some("Pixel 7"));
+    let tiers = reader
+        .section()
+        .options
+        .iter()
+        .find_map(|option| match option {
+            SectionHeaderOption::Comment(comment) => comment.strip_prefix("binderdump-tiers="),
+            _ => None,
+        })
+        .map(str::to_string);
+    assert_eq!(tiers.as_deref(), Some("core,txn_data,reply_stack"));
+    let mut interfaces = 0;
+    let mut packet_interfaces = vec![];
+    while let Some(block) = reader.next_block() {
+        match block? {
+            Block::InterfaceDescription(_) => interfaces += 1,
+            Block::EnhancedPacket(packet) => packet_interfaces.push(packet.interface_id),
+            _ => (),
+        }
+    }
+    assert_eq!(interfaces, 2);
+    // the UidInfo packet of uid 1000 and the ProcessInfo packet of servicemanager, then its ioctl
+    // on fd 7, which was open on /dev/hwbinder
+    assert_eq!(packet_interfaces, [0, 0, 1]);
+
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
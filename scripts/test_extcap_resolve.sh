@@ -44,6 +44,12 @@ case "${1:-}" in
     case "$cmd" in
       *"id -u"*) echo 0 ;;
       *"[ -x"*) exit 0 ;;
+      *" doctor"*)
+        if [ "${DOCTOR_FAIL:-0}" = "1" ]; then
+          printf 'ok\tringbuf\tBPF ring buffers supported\n'
+          printf 'fail\ttracepoint binder_ioctl\tmissing\n'
+          printf 'fail\tdoctor\t2 checks, 1 failed, 0 warnings\n'
+        fi ;;
       *) printf 'pcapbytes' ;;
     esac ;;
 esac
@@ -74,7 +80,18 @@ else
     echo "FAIL: extcap did not print the expected error message"; echo "stderr:"; cat "$stderr_file"; exit 1
 fi
 
-if [ "${pass1:-0}" = 1 ] && [ "${pass2:-0}" = 1 ]; then
+# Scenario 3: binderdump doctor reports a failed check; extcap should show it and not capture
+if DOCTOR_FAIL=1 PATH="$stub:$PATH" "$repo_root/extcap/binderdump-extcap" \
+    --capture --extcap-interface binder-emu --fifo "$fifo" 2>"$stderr_file"; then
+    echo "FAIL: extcap should have exited non-zero when doctor fails"; exit 1
+fi
+if grep -qF "tracepoint binder_ioctl: missing" "$stderr_file"; then
+    pass3=1
+else
+    echo "FAIL: extcap did not show the failed doctor check"; echo "stderr:"; cat "$stderr_file"; exit 1
+fi
+
+if [ "${pass1:-0}" = 1 ] && [ "${pass2:-0}" = 1 ] && [ "${pass3:-0}" = 1 ]; then
     echo "PASS"
 else
     echo "FAIL: one or more scenarios did not pass"; exit 1